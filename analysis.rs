@@ -0,0 +1,448 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use image::{imageops, imageops::FilterType, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::format::PixelFormat;
+
+/// Number of pixels that differ in any channel. Both images must have the same dimensions.
+pub fn differing_pixels(a: &RgbaImage, b: &RgbaImage) -> usize {
+    a.pixels().zip(b.pixels()).filter(|(a, b)| a != b).count()
+}
+
+/// Peak signal-to-noise ratio over all four channels in dB, infinite for identical images.
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+fn luma(img: &RgbaImage) -> Vec<f64> {
+    img.pixels()
+        .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+        .collect()
+}
+
+/// Mean structural similarity of the luma channels, computed over 8x8 windows with a stride of
+/// 4 pixels. 1.0 means identical.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const STRIDE: u32 = 4;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    let (luma_a, luma_b) = (luma(a), luma(b));
+    let (window_width, window_height) = (WINDOW.min(width), WINDOW.min(height));
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height.saturating_sub(window_height)).step_by(STRIDE as usize) {
+        for x in (0..=width.saturating_sub(window_width)).step_by(STRIDE as usize) {
+            let mut samples = Vec::with_capacity((window_width * window_height) as usize);
+            for wy in y..y + window_height {
+                for wx in x..x + window_width {
+                    let i = (wy * width + wx) as usize;
+                    samples.push((luma_a[i], luma_b[i]));
+                }
+            }
+            if samples.is_empty() {
+                continue;
+            }
+
+            let n = samples.len() as f64;
+            let mean_a = samples.iter().map(|s| s.0).sum::<f64>() / n;
+            let mean_b = samples.iter().map(|s| s.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (sa, sb) in &samples {
+                var_a += (sa - mean_a).powi(2);
+                var_b += (sb - mean_b).powi(2);
+                covariance += (sa - mean_a) * (sb - mean_b);
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// Renders where `a` and `b` differ: a faded grayscale copy of `a` with every differing pixel
+/// tinted red, stronger the larger the difference.
+pub fn diff_image(a: &RgbaImage, b: &RgbaImage) -> RgbaImage {
+    let mut out = RgbaImage::new(a.width(), a.height());
+    for ((pa, pb), out) in a.pixels().zip(b.pixels()).zip(out.pixels_mut()) {
+        let luma = 0.299 * pa[0] as f32 + 0.587 * pa[1] as f32 + 0.114 * pa[2] as f32;
+        let faded = 160.0 + luma * (95.0 / 255.0);
+
+        let difference =
+            pa.0.iter()
+                .zip(&pb.0)
+                .map(|(&a, &b)| a.abs_diff(b))
+                .max()
+                .unwrap();
+        let [r, g, b] = if difference == 0 {
+            [faded; 3]
+        } else {
+            let t = 0.25 + 0.75 * difference as f32 / 255.0;
+            [
+                faded + (255.0 - faded) * t,
+                faded * (1.0 - t),
+                faded * (1.0 - t),
+            ]
+        };
+
+        *out = Rgba([r.round() as u8, g.round() as u8, b.round() as u8, 255]);
+    }
+    out
+}
+
+fn gray_samples(img: &RgbaImage, width: u32, height: u32) -> Vec<f64> {
+    let gray = imageops::grayscale(img);
+    imageops::resize(&gray, width, height, FilterType::Triangle)
+        .into_raw()
+        .into_iter()
+        .map(f64::from)
+        .collect()
+}
+
+fn bits_to_hash(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+/// Average hash: an 8x8 grayscale thumbnail where every bit says whether that pixel is at least
+/// as bright as the mean.
+pub fn ahash(img: &RgbaImage) -> u64 {
+    let samples = gray_samples(img, 8, 8);
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    bits_to_hash(samples.iter().map(|&s| s >= mean))
+}
+
+/// Difference hash: whether each pixel of a 9x8 grayscale thumbnail is darker than its right
+/// neighbour.
+pub fn dhash(img: &RgbaImage) -> u64 {
+    let samples = gray_samples(img, 9, 8);
+    bits_to_hash(
+        (0..8)
+            .flat_map(|y| (0..8).map(move |x| (y, x)))
+            .map(|(y, x)| {
+                let i = y * 9 + x;
+                samples[i] < samples[i + 1]
+            }),
+    )
+}
+
+/// Perceptual hash: the lowest 8x8 frequencies of a DCT over a 32x32 grayscale thumbnail,
+/// compared against their median.
+pub fn phash(img: &RgbaImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let samples = gray_samples(img, SIZE as u32, SIZE as u32);
+
+    let cosines: Vec<f64> = (0..LOW)
+        .flat_map(|u| {
+            (0..SIZE).map(move |x| {
+                (std::f64::consts::PI / SIZE as f64 * (x as f64 + 0.5) * u as f64).cos()
+            })
+        })
+        .collect();
+
+    let mut coefficients = Vec::with_capacity(LOW * LOW);
+    for v in 0..LOW {
+        for u in 0..LOW {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    sum += samples[y * SIZE + x] * cosines[u * SIZE + x] * cosines[v * SIZE + y];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    // The DC term only says how bright the image is, so leave it out of the median.
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    bits_to_hash(coefficients.iter().map(|&c| c > median))
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Fingerprint of the exact pixel content, for finding identical images regardless of how they
+/// were encoded.
+pub fn content_hash(img: &RgbaImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    img.dimensions().hash(&mut hasher);
+    img.as_raw().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What `stats` reports about one channel: 0 to 255 for 8 bit images, the floats themselves
+/// for float ones.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChannelStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Shannon entropy of its values in bits, at most 8 for an 8 bit channel.
+    pub entropy: f64,
+}
+
+/// What `stats` reports about an image, to pick a pixel format and compression by.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub red: ChannelStats,
+    pub green: ChannelStats,
+    pub blue: ChannelStats,
+    pub alpha: ChannelStats,
+    /// Share of the pixels with an alpha of 0.
+    pub transparent: f64,
+    /// Share of the pixels with an alpha of 1.
+    pub opaque: f64,
+    pub unique_colors: usize,
+    /// Shannon entropy of the colors in bits per pixel, a lower bound for what lossless
+    /// compression that only looks at single pixels can get the image down to.
+    pub entropy: f64,
+    /// The smallest 8 bit pixel format that holds every pixel exactly, `None` for float images.
+    pub smallest_format: Option<&'static str>,
+}
+
+/// Entropy in bits of values that were seen `counts` times out of `total`.
+fn entropy(counts: impl Iterator<Item = usize>, total: usize) -> f64 {
+    counts
+        .map(|count| {
+            let p = count as f64 / total as f64;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// Statistics of straight RGBA pixels with a float per channel, 0 to 1 for 8 bit images or
+/// brighter for HDR, which `float` says they are. Values count as the same color only if they
+/// are exactly equal.
+pub fn stats(rgba: &[f32], float: bool) -> Stats {
+    let total = rgba.len() / 4;
+    let scale = if float { 1.0 } else { 255.0 };
+    let channel = |index: usize| {
+        let values = rgba
+            .iter()
+            .skip(index)
+            .step_by(4)
+            .map(|&c| c as f64 * scale);
+        let (min, max, sum) = values.clone().fold(
+            (f64::INFINITY, f64::NEG_INFINITY, 0.0),
+            |(min, max, sum), c| (min.min(c), max.max(c), sum + c),
+        );
+        let mean = sum / total.max(1) as f64;
+        let variance = values.map(|c| (c - mean).powi(2)).sum::<f64>() / total.max(1) as f64;
+        let mut counts = HashMap::new();
+        for c in rgba.iter().skip(index).step_by(4) {
+            *counts.entry(c.to_bits()).or_insert(0usize) += 1;
+        }
+        ChannelStats {
+            min: if total == 0 { 0.0 } else { min },
+            max: if total == 0 { 0.0 } else { max },
+            mean,
+            stddev: variance.sqrt(),
+            entropy: entropy(counts.into_values(), total),
+        }
+    };
+
+    let mut colors = HashMap::new();
+    let (mut transparent, mut opaque, mut gray) = (0, 0, true);
+    for pixel in rgba.chunks_exact(4) {
+        let color: [u32; 4] = std::array::from_fn(|i| pixel[i].to_bits());
+        *colors.entry(color).or_insert(0usize) += 1;
+        transparent += (pixel[3] <= 0.0) as usize;
+        opaque += (pixel[3] >= 1.0) as usize;
+        gray &= pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[3] >= 1.0;
+    }
+    let share = |count: usize| count as f64 / total.max(1) as f64;
+    let smallest_format = (!float).then(|| {
+        let format = if gray {
+            PixelFormat::Gray8
+        } else if colors.len() <= 256 {
+            PixelFormat::Palette8
+        } else if opaque == total {
+            PixelFormat::Rgb8
+        } else {
+            PixelFormat::Rgba8
+        };
+        format.name()
+    });
+    Stats {
+        red: channel(0),
+        green: channel(1),
+        blue: channel(2),
+        alpha: channel(3),
+        transparent: share(transparent),
+        opaque: share(opaque),
+        unique_colors: colors.len(),
+        entropy: entropy(colors.into_values(), total),
+        smallest_format,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `pixels` as the floats `stats` takes for 8 bit images.
+    fn unit(pixels: &[[u8; 4]]) -> Vec<f32> {
+        pixels.concat().iter().map(|&c| c as f32 / 255.0).collect()
+    }
+
+    #[test]
+    fn channels_are_measured_in_bytes() {
+        let stats = stats(&unit(&[[0, 10, 20, 255], [255, 10, 40, 0]]), false);
+        assert_eq!((stats.red.min, stats.red.max), (0.0, 255.0));
+        assert!((stats.red.mean - 127.5).abs() < 1e-3);
+        assert!((stats.red.stddev - 127.5).abs() < 1e-3);
+        assert!((stats.blue.mean - 30.0).abs() < 1e-3);
+        assert_eq!(stats.green.stddev, 0.0);
+        // Two values seen equally often are one bit, a single one none.
+        assert!((stats.red.entropy - 1.0).abs() < 1e-9);
+        assert_eq!(stats.green.entropy, 0.0);
+        assert_eq!((stats.transparent, stats.opaque), (0.5, 0.5));
+        assert_eq!(stats.unique_colors, 2);
+        assert!((stats.entropy - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn floats_stay_as_they_are() {
+        let stats = stats(&[2.5, 0.0, 0.0, 1.0, 0.5, 0.0, 0.0, 1.0], true);
+        assert_eq!((stats.red.min, stats.red.max), (0.5, 2.5));
+        assert_eq!(stats.smallest_format, None);
+    }
+
+    #[test]
+    fn the_smallest_format_holds_every_pixel() {
+        let smallest = |pixels: &[[u8; 4]]| stats(&unit(pixels), false).smallest_format;
+        assert_eq!(smallest(&[[7, 7, 7, 255], [9, 9, 9, 255]]), Some("Gray8"));
+        assert_eq!(smallest(&[[1, 2, 3, 255], [1, 2, 3, 0]]), Some("Palette8"));
+        let many: Vec<[u8; 4]> = (0..=256u32)
+            .map(|i| [i as u8, (i >> 8) as u8, 0, 255])
+            .collect();
+        assert_eq!(smallest(&many), Some("RGB8"));
+        let mut transparent = many;
+        transparent[0][3] = 0;
+        assert_eq!(smallest(&transparent), Some("RGBA8"));
+    }
+
+    #[test]
+    fn empty_images_have_no_statistics() {
+        let stats = stats(&[], false);
+        assert_eq!(
+            (stats.red.min, stats.red.max, stats.red.mean),
+            (0.0, 0.0, 0.0)
+        );
+        assert_eq!((stats.unique_colors, stats.entropy), (0, 0.0));
+    }
+
+    /// A 64x64 picture with some structure: a diagonal gradient with a bright square in it.
+    fn picture() -> RgbaImage {
+        RgbaImage::from_fn(64, 64, |x, y| {
+            let value = if (16..40).contains(&x) && (8..24).contains(&y) {
+                250
+            } else {
+                (x + y) as u8 * 2
+            };
+            Rgba([value, value / 2, 255 - value, 255])
+        })
+    }
+
+    #[test]
+    fn identical_images_compare_as_identical() {
+        let img = picture();
+        assert_eq!(differing_pixels(&img, &img), 0);
+        assert_eq!(psnr(&img, &img), f64::INFINITY);
+        assert!((ssim(&img, &img) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn worse_copies_score_lower() {
+        let img = picture();
+        let noisy = |amount: u8| {
+            let mut copy = img.clone();
+            for (i, pixel) in copy.pixels_mut().enumerate() {
+                if i % 3 == 0 {
+                    pixel[0] = pixel[0].saturating_add(amount);
+                }
+            }
+            copy
+        };
+        let (slightly, badly) = (noisy(4), noisy(60));
+        assert!(differing_pixels(&img, &slightly) > 0);
+        assert!(psnr(&img, &slightly) > psnr(&img, &badly));
+        assert!(ssim(&img, &slightly) > ssim(&img, &badly));
+        assert!(ssim(&img, &badly) < 1.0);
+
+        // One channel of every pixel off by 255 out of four channels: 10 log10(4) dB.
+        let black = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        let red = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        assert!((psnr(&black, &red) - 6.0206).abs() < 1e-3);
+    }
+
+    #[test]
+    fn diffs_tint_only_what_changed() {
+        let a = RgbaImage::from_pixel(3, 1, Rgba([255, 255, 255, 255]));
+        let mut b = a.clone();
+        b.put_pixel(1, 0, Rgba([0, 255, 255, 255]));
+        b.put_pixel(2, 0, Rgba([250, 255, 255, 255]));
+        let diff = diff_image(&a, &b);
+        assert_eq!(diff.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        let (big, small) = (diff.get_pixel(1, 0), diff.get_pixel(2, 0));
+        assert!(big[0] == 255 && big[1] == 0, "{:?}", big);
+        assert!(small[1] < 255 && small[1] > big[1], "{:?}", small);
+    }
+
+    #[test]
+    fn perceptual_hashes_survive_resizing_but_not_other_pictures() {
+        let img = picture();
+        let smaller = imageops::resize(&img, 48, 48, FilterType::Triangle);
+        let other = imageops::rotate90(&img);
+        for hash in [ahash, dhash, phash] {
+            let distance = hamming_distance(hash(&img), hash(&smaller));
+            assert!(distance <= 6, "{}", distance);
+            assert!(hamming_distance(hash(&img), hash(&other)) > distance);
+        }
+        assert_eq!(hamming_distance(0b1011, 0b0110), 3);
+    }
+
+    #[test]
+    fn content_hashes_are_of_the_exact_pixels() {
+        let img = picture();
+        assert_eq!(content_hash(&img), content_hash(&img.clone()));
+        let mut changed = img.clone();
+        changed.get_pixel_mut(63, 63)[3] = 254;
+        assert_ne!(content_hash(&img), content_hash(&changed));
+        // The same bytes in another shape are another image.
+        let row = RgbaImage::from_raw(1, 4, vec![0; 16]).unwrap();
+        let column = RgbaImage::from_raw(4, 1, vec![0; 16]).unwrap();
+        assert_ne!(content_hash(&row), content_hash(&column));
+    }
+}
@@ -0,0 +1,591 @@
+//! Images inside zip and tar archives, named like `assets.zip!sprites/hero.bruh`, so they can be
+//! read without extracting them first. Only the member asked for is read: zip archives are
+//! looked up in their central directory and the member is read from where it starts, tar
+//! archives skip over the members in front of it. Compressed members are inflated as they are
+//! read, so the viewer can stream them into `ProgressiveDecoder` like files on disk.
+//!
+//! Zip members can be stored or deflated, tar archives plain or gzipped (`.tar.gz`, `.tgz`).
+//! Zip64 and encrypted zip archives aren't supported.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use miniz_oxide::{
+    inflate::stream::{inflate, InflateState},
+    DataFormat, MZError, MZFlush, MZStatus,
+};
+
+/// The extensions of the archives a path can reach into.
+const EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+/// How far from its end a zip archive has its end of central directory record: the 22 bytes of
+/// the record and a comment of at most 64 KiB.
+const END_SEARCH: u64 = 22 + 65535;
+
+/// The largest tar member that can be read, as big as the largest image `max_pixels` lets
+/// through by default in 32 bit floats. Zip members can't be bigger without Zip64, and stop
+/// inflating at the size they say they are.
+const MAX_MEMBER: u64 = 4 << 30;
+
+/// The largest GNU long name or pax record of a tar archive.
+const MAX_RECORD: u64 = 1 << 20;
+
+/// How much a gzipped tar archive may inflate to, so a small archive can't keep a reader busy
+/// for hours skipping to its member.
+const MAX_INFLATED: u64 = 64 << 30;
+
+/// The archive and the member within it if `path` is `archive!member` for one of the archives
+/// in `EXTENSIONS`. Members are always separated by `/`, whatever the platform.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let path = path.to_str()?;
+    path.match_indices('!').find_map(|(at, _)| {
+        let (archive, member) = (&path[..at], &path[at + 1..]);
+        let lowercase = archive.to_ascii_lowercase();
+        (!member.is_empty() && EXTENSIONS.iter().any(|ext| lowercase.ends_with(ext))).then(|| {
+            (
+                PathBuf::from(archive),
+                member.trim_start_matches('/').to_owned(),
+            )
+        })
+    })
+}
+
+/// Reads the `member` of `archive`, decompressing it as it goes.
+pub fn open(archive: &Path, member: &str) -> io::Result<Box<dyn Read + Send>> {
+    let lowercase = archive.to_string_lossy().to_ascii_lowercase();
+    let file = File::open(archive)?;
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no {}", archive.display(), member),
+        )
+    };
+    if lowercase.ends_with(".zip") {
+        return open_zip(file, member)?.ok_or_else(not_found);
+    }
+    if lowercase.ends_with(".tar") {
+        return open_tar(BufReader::new(file), member)?.ok_or_else(not_found);
+    }
+    let mut file = BufReader::new(file);
+    skip_gzip_header(&mut file)?;
+    open_tar(Inflater::new(file, MAX_INFLATED), member)?.ok_or_else(not_found)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as u64
+}
+
+fn open_zip(mut file: File, member: &str) -> io::Result<Option<Box<dyn Read + Send>>> {
+    let size = file.seek(SeekFrom::End(0))?;
+    let start = size.saturating_sub(END_SEARCH);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    (&mut file).take(END_SEARCH).read_to_end(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| tail[at..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| invalid("Not a zip archive, its end is missing"))?;
+    let (directory_size, directory_at) = (u32_at(&tail, end + 12), u32_at(&tail, end + 16));
+    if directory_at == 0xffff_ffff {
+        return Err(invalid("Zip64 archives aren't supported"));
+    }
+
+    if directory_at + directory_size > size {
+        return Err(invalid(
+            "Zip central directory runs past the end of the archive",
+        ));
+    }
+    let mut directory = vec![0; directory_size as usize];
+    file.seek(SeekFrom::Start(directory_at))?;
+    file.read_exact(&mut directory)?;
+    let mut at = 0;
+    while directory.len() >= at + 46 && directory[at..].starts_with(b"PK\x01\x02") {
+        let entry = &directory[at..];
+        let name_len = u16_at(entry, 28);
+        let next = at + 46 + name_len + u16_at(entry, 30) + u16_at(entry, 32);
+        let name = entry
+            .get(46..46 + name_len)
+            .ok_or_else(|| invalid("Zip central directory is cut off"))?;
+        if name != member.as_bytes() {
+            at = next;
+            continue;
+        }
+
+        if u16_at(entry, 8) & 1 != 0 {
+            return Err(invalid("Encrypted zip members aren't supported"));
+        }
+        let (method, compressed, uncompressed) =
+            (u16_at(entry, 10), u32_at(entry, 20), u32_at(entry, 24));
+        // The local header repeats the name, and its extra field can differ from the one in the
+        // central directory.
+        let mut local = [0; 30];
+        file.seek(SeekFrom::Start(u32_at(entry, 42)))?;
+        file.read_exact(&mut local)?;
+        if !local.starts_with(b"PK\x03\x04") {
+            return Err(invalid("Zip member has no local header"));
+        }
+        let skip = u16_at(&local, 26) + u16_at(&local, 28);
+        file.seek(SeekFrom::Current(skip as i64))?;
+        let data = BufReader::new(file).take(compressed);
+        return match method {
+            0 => Ok(Some(Box::new(data))),
+            8 => Ok(Some(Box::new(Inflater::new(data, uncompressed)))),
+            _ => Err(invalid(&format!(
+                "Zip members compressed with method {} aren't supported, only stored and deflated",
+                method
+            ))),
+        };
+    }
+    Ok(None)
+}
+
+/// The member of a tar archive, reading past the headers and contents of those before it.
+fn open_tar<R: Read + Send + 'static>(
+    mut tar: R,
+    member: &str,
+) -> io::Result<Option<Box<dyn Read + Send>>> {
+    let mut long_name = None;
+    loop {
+        let mut header = [0; 512];
+        if !read_block(&mut tar, &mut header)? || header.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        let octal = String::from_utf8_lossy(&header[124..136]);
+        let size = u64::from_str_radix(octal.trim_matches(|c: char| c == '\0' || c == ' '), 8)
+            .map_err(|_| invalid("Tar header has no size"))?;
+        let padded = size.div_ceil(512) * 512;
+
+        match header[156] {
+            // GNU keeps names longer than 100 bytes in an entry of their own before the member,
+            // pax in a `path` record.
+            b'L' | b'x' => {
+                if size > MAX_RECORD {
+                    return Err(invalid("Tar name record is too long"));
+                }
+                let mut data = Vec::new();
+                (&mut tar).take(padded).read_to_end(&mut data)?;
+                data.truncate(size as usize);
+                long_name = if header[156] == b'L' {
+                    Some(
+                        String::from_utf8_lossy(&data)
+                            .trim_end_matches('\0')
+                            .to_owned(),
+                    )
+                } else {
+                    pax_path(&data).or(long_name)
+                };
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = long_name.take().unwrap_or_else(|| {
+            let field = |range: std::ops::Range<usize>| {
+                let field = &header[range];
+                let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+                String::from_utf8_lossy(&field[..end]).into_owned()
+            };
+            let (prefix, name) = (field(345..500), field(0..100));
+            if header[257..262] == *b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+        let regular = matches!(header[156], 0 | b'0');
+        if regular && name.trim_start_matches("./") == member {
+            if size > MAX_MEMBER {
+                return Err(invalid("Tar members over 4 GiB aren't supported"));
+            }
+            return Ok(Some(Box::new(tar.take(size))));
+        }
+        io::copy(&mut (&mut tar).take(padded), &mut io::sink())?;
+    }
+}
+
+/// Fills `block`, returning false if the archive ended right before it. Archives that end in
+/// the middle of it are cut off.
+fn read_block(reader: &mut impl Read, block: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the archive ends in the middle of a header",
+                ))
+            }
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// The `path` of pax records, which are `length key=value\n` each.
+fn pax_path(records: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(records).lines().find_map(|record| {
+        let (_, record) = record.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_owned)
+    })
+}
+
+/// Reads past the header gzip puts in front of the deflated data.
+fn skip_gzip_header(reader: &mut impl Read) -> io::Result<()> {
+    let mut header = [0; 10];
+    reader.read_exact(&mut header)?;
+    if header[..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid("Not a gzipped tar archive"));
+    }
+    let flags = header[3];
+    if flags & 4 != 0 {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        io::copy(
+            &mut reader.take(u16::from_le_bytes(len) as u64),
+            &mut io::sink(),
+        )?;
+    }
+    // The file name and the comment end with a NUL.
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            let mut byte = [1];
+            while byte[0] != 0 {
+                reader.read_exact(&mut byte)?;
+            }
+        }
+    }
+    if flags & 2 != 0 {
+        reader.read_exact(&mut [0; 2])?;
+    }
+    Ok(())
+}
+
+/// Inflates raw deflate data from `inner` as it is read, refusing to give more than `limit`
+/// bytes.
+struct Inflater<R> {
+    inner: R,
+    state: Box<InflateState>,
+    limit: u64,
+    input: Vec<u8>,
+    start: usize,
+    end: usize,
+    done: bool,
+}
+
+impl<R: Read> Inflater<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            state: InflateState::new_boxed(DataFormat::Raw),
+            limit,
+            input: vec![0; 64 * 1024],
+            start: 0,
+            end: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done && !buf.is_empty() {
+            let mut ended = false;
+            if self.start == self.end {
+                self.start = 0;
+                self.end = self.inner.read(&mut self.input)?;
+                ended = self.end == 0;
+            }
+            let flush = if ended {
+                MZFlush::Finish
+            } else {
+                MZFlush::None
+            };
+            let result = inflate(
+                &mut self.state,
+                &self.input[self.start..self.end],
+                buf,
+                flush,
+            );
+            self.start += result.bytes_consumed;
+            // Everything before was inflated without complaint, so failing to finish means the
+            // data stops early, whatever inflate calls it.
+            let status = match result.status {
+                Err(MZError::Data) if ended => Err(MZError::Buf),
+                status => status,
+            };
+            match status {
+                Ok(MZStatus::StreamEnd) => self.done = true,
+                Ok(_) | Err(MZError::Buf) if !ended || result.bytes_written > 0 => {}
+                Ok(_) | Err(MZError::Buf) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the archive ends in the middle of a member",
+                    ))
+                }
+                Err(_) => return Err(invalid("Damaged deflate data in the archive")),
+            }
+            if result.bytes_written > 0 {
+                self.limit = self
+                    .limit
+                    .checked_sub(result.bytes_written as u64)
+                    .ok_or_else(|| invalid("The archive inflates to more than it should"))?;
+                return Ok(result.bytes_written);
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Cursor};
+
+    use miniz_oxide::deflate::compress_to_vec;
+
+    use super::*;
+
+    const HERO: &[u8] = b"the pixels of the hero, the pixels of the hero, the pixels of the hero";
+
+    /// A zip archive of `(name, method, contents)` members, stored (0) or deflated (8).
+    fn zip(members: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let (mut archive, mut directory) = (Vec::new(), Vec::new());
+        for &(name, method, contents) in members {
+            let data = match method {
+                8 => compress_to_vec(contents, 6),
+                _ => contents.to_vec(),
+            };
+            let offset = archive.len() as u32;
+            let crc = crc32fast::hash(contents);
+            let fields = |header: &mut Vec<u8>| {
+                header.extend_from_slice(&[20, 0, 0, 0]);
+                header.extend_from_slice(&method.to_le_bytes());
+                header.extend_from_slice(&[0; 4]);
+                header.extend_from_slice(&crc.to_le_bytes());
+                header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            };
+
+            archive.extend_from_slice(b"PK\x03\x04");
+            fields(&mut archive);
+            archive.extend_from_slice(&[0; 2]);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&data);
+
+            directory.extend_from_slice(b"PK\x01\x02\x14\x00");
+            fields(&mut directory);
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let directory_at = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(b"PK\x05\x06\0\0\0\0");
+        let count = (members.len() as u16).to_le_bytes();
+        archive.extend_from_slice(&[count, count].concat());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&directory_at.to_le_bytes());
+        archive.extend_from_slice(&[0; 2]);
+        archive
+    }
+
+    /// A tar entry of `kind` with its header and contents padded to whole blocks.
+    fn tar_entry(name: &str, kind: u8, contents: &[u8]) -> Vec<u8> {
+        let mut header = [0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        let mut entry = header.to_vec();
+        entry.extend_from_slice(contents);
+        entry.resize(entry.len().div_ceil(512) * 512, 0);
+        entry
+    }
+
+    fn read_member(archive: &[u8], extension: &str, member: &str) -> io::Result<Vec<u8>> {
+        let path = env::temp_dir().join(format!(
+            "bruh-archive-{}-{}{}",
+            std::process::id(),
+            member.replace('/', "-"),
+            extension
+        ));
+        fs::write(&path, archive).unwrap();
+        let read = open(&path, member).and_then(|mut reader| {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            Ok(contents)
+        });
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn members_are_split_from_archive_paths() {
+        assert_eq!(
+            split(Path::new("assets.zip!/sprites/hero.bruh")),
+            Some((PathBuf::from("assets.zip"), "sprites/hero.bruh".to_owned()))
+        );
+        assert_eq!(
+            split(Path::new("Backup.TAR.GZ!cat.png")),
+            Some((PathBuf::from("Backup.TAR.GZ"), "cat.png".to_owned()))
+        );
+        assert_eq!(split(Path::new("wow!.bruh")), None);
+        assert_eq!(split(Path::new("assets.zip!")), None);
+    }
+
+    #[test]
+    fn stored_and_deflated_zip_members_are_read() {
+        let archive = zip(&[
+            ("stored.bruh", 0, b"stored"),
+            ("sprites/hero.bruh", 8, HERO),
+        ]);
+        assert_eq!(
+            read_member(&archive, ".zip", "stored.bruh").unwrap(),
+            b"stored"
+        );
+        assert_eq!(
+            read_member(&archive, ".zip", "sprites/hero.bruh").unwrap(),
+            HERO
+        );
+    }
+
+    #[test]
+    fn missing_members_are_not_found() {
+        let archive = zip(&[("stored.bruh", 0, b"stored")]);
+        let e = read_member(&archive, ".zip", "other.bruh").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        let tar = tar_entry("first.png", b'0', b"png");
+        let e = read_member(&tar, ".tar", "other.png").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn truncated_central_directories_are_refused() {
+        let archive = zip(&[("sprites/hero.bruh", 8, HERO)]);
+        let end = archive.len() - 22;
+
+        // A name running past the end of the directory.
+        let mut long_name = archive.clone();
+        let directory_at = u32_at(&archive, end + 16) as usize;
+        long_name[directory_at + 28] = 200;
+        let e = read_member(&long_name, ".zip", "sprites/hero.bruh").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // A directory said to be longer than what is left of the file.
+        for directory_size in [10_000, u32::MAX] {
+            let mut cut = archive.clone();
+            cut[end + 12..end + 16].copy_from_slice(&directory_size.to_le_bytes());
+            let e = read_member(&cut, ".zip", "sprites/hero.bruh").unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let e = read_member(&archive[..end], ".zip", "sprites/hero.bruh").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn long_tar_names_are_found() {
+        let gnu_name = format!("d/{}.bruh", "long".repeat(40));
+        let pax_name = format!("p/{}.bruh", "name".repeat(40));
+        let pax_record = format!("path={}\n", pax_name);
+        let pax_record = format!("{} {}", pax_record.len() + 4, pax_record);
+        let tar = [
+            tar_entry("first.png", b'0', b"first"),
+            tar_entry("././@LongLink", b'L', format!("{}\0", gnu_name).as_bytes()),
+            tar_entry(&gnu_name[..100], b'0', b"gnu"),
+            tar_entry("PaxHeaders/x", b'x', pax_record.as_bytes()),
+            tar_entry(&pax_name[..100], b'0', HERO),
+            vec![0; 1024],
+        ]
+        .concat();
+
+        let tar_reader = |member: &str| {
+            let mut contents = Vec::new();
+            open_tar(Cursor::new(tar.clone()), member)
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert_eq!(tar_reader("first.png"), b"first");
+        assert_eq!(tar_reader(&gnu_name), b"gnu");
+        assert_eq!(tar_reader(&pax_name), HERO);
+        // The long names replace the truncated ones in the headers.
+        assert!(open_tar(Cursor::new(tar.clone()), &gnu_name[..100])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn gzipped_tar_members_are_inflated() {
+        let tar = [tar_entry("hero.bruh", b'0', HERO), vec![0; 1024]].concat();
+        let mut tgz = vec![0x1f, 0x8b, 8, 8, 0, 0, 0, 0, 0, 3];
+        tgz.extend_from_slice(b"hero.tar\0");
+        tgz.extend(compress_to_vec(&tar, 6));
+        tgz.extend_from_slice(&crc32fast::hash(&tar).to_le_bytes());
+        tgz.extend_from_slice(&(tar.len() as u32).to_le_bytes());
+        assert_eq!(read_member(&tgz, ".tgz", "hero.bruh").unwrap(), HERO);
+
+        let e = read_member(&tgz[..40], ".tgz", "hero.bruh").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn members_bigger_than_they_say_or_than_allowed_are_refused() {
+        // A deflated member inflating to more than its uncompressed size.
+        let mut archive = zip(&[("hero.bruh", 8, HERO)]);
+        let directory_at = u32_at(&archive, archive.len() - 22 + 16) as usize;
+        archive[directory_at + 24..directory_at + 28].copy_from_slice(&10u32.to_le_bytes());
+        let e = read_member(&archive, ".zip", "hero.bruh").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // A tar member said to be bigger than any can be.
+
+        let mut huge = tar_entry("huge.bruh", b'0', b"");
+        huge[124..135].copy_from_slice(format!("{:011o}", MAX_MEMBER + 1).as_bytes());
+        let e = open_tar(Cursor::new(huge), "huge.bruh").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn long_tar_name_records_are_capped() {
+        let mut record = tar_entry("././@LongLink", b'L', b"");
+        record[124..135].copy_from_slice(format!("{:011o}", MAX_RECORD + 1).as_bytes());
+        let tar = [record, vec![0; 1024]].concat();
+        let e = open_tar(Cursor::new(tar), "hero.bruh").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn inflating_stops_at_the_limit() {
+        let deflated = compress_to_vec(&[0; 100_000], 6);
+        let mut inflated = Vec::new();
+        let e = Inflater::new(Cursor::new(&deflated), 1000)
+            .read_to_end(&mut inflated)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        inflated.clear();
+        Inflater::new(Cursor::new(&deflated), 100_000)
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated.len(), 100_000);
+    }
+}
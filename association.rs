@@ -0,0 +1,173 @@
+//! `bruh register` and `bruh unregister`: associating .bruh files with the viewer on Windows, so
+//! double-clicking one in Explorer opens it. The entries go under `HKEY_CURRENT_USER`, which
+//! needs no administrator rights, and are written by the `reg` program that comes with Windows.
+//!
+//! `bruh install-desktop` does the same for Linux desktops: it installs the shared-mime-info
+//! type `image/x-bruh`, a desktop entry for the viewer and the thumbnailer from `data/` into
+//! the user's data directory and refreshes the caches desktops read them from.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::error::BruhError;
+
+const CLASSES: &str = r"HKCU\Software\Classes";
+/// The file type .bruh points to, holding the icon and the command that opens it.
+const PROG_ID: &str = "Bruh.Image";
+
+/// Makes the viewer at `exe` open .bruh files.
+pub fn register(exe: &Path) -> Result<(), BruhError> {
+    let exe = exe.display();
+    let entries = [
+        (format!(r"{}\.bruh", CLASSES), PROG_ID.to_owned()),
+        (format!(r"{}\{}", CLASSES, PROG_ID), "BRUH image".to_owned()),
+        (
+            format!(r"{}\{}\DefaultIcon", CLASSES, PROG_ID),
+            format!("\"{}\",0", exe),
+        ),
+        (
+            format!(r"{}\{}\shell\open\command", CLASSES, PROG_ID),
+            format!("\"{}\" \"%1\"", exe),
+        ),
+    ];
+    for (key, value) in &entries {
+        reg(&["add", key, "/ve", "/d", value, "/f"])?;
+    }
+    Ok(())
+}
+
+/// Removes what `register` wrote, returning whether there was anything. `.bruh` is only removed
+/// if it still points to the viewer, not if another program took it over since.
+pub fn unregister() -> Result<bool, BruhError> {
+    let extension = format!(r"{}\.bruh", CLASSES);
+    let registered = exists(&format!(r"{}\{}", CLASSES, PROG_ID));
+    if registered {
+        reg(&["delete", &format!(r"{}\{}", CLASSES, PROG_ID), "/f"])?;
+    }
+
+    let ours = Command::new("reg")
+        .args(["query", &extension, "/ve"])
+        .stdin(Stdio::null())
+        .output()
+        .is_ok_and(|output| {
+            output.status.success() && String::from_utf8_lossy(&output.stdout).contains(PROG_ID)
+        });
+    if ours {
+        reg(&["delete", &extension, "/f"])?;
+    }
+    Ok(registered || ours)
+}
+
+const MIME_TYPE: &str = "image/x-bruh";
+const DESKTOP_ENTRY: &str = "bruh.desktop";
+
+/// Installs what a desktop needs to recognize, open and preview .bruh files with the viewer at
+/// `exe`, returning the files written and the programs updating the desktop's databases that
+/// couldn't be run, so the desktop only notices later.
+pub fn install_desktop(exe: &Path) -> Result<(Vec<PathBuf>, Vec<String>), BruhError> {
+    let data = data_dir().ok_or_else(|| {
+        BruhError::Failed("Can't find the data directory, set XDG_DATA_HOME or HOME".to_owned())
+    })?;
+    let files = [
+        (
+            data.join("mime/packages/bruh.xml"),
+            include_str!("data/bruh-mime.xml").to_owned(),
+        ),
+        (
+            data.join("applications").join(DESKTOP_ENTRY),
+            with_exe(include_str!("data/bruh.desktop"), exe),
+        ),
+        (
+            data.join("thumbnailers/bruh.thumbnailer"),
+            with_exe(include_str!("data/bruh.thumbnailer"), exe),
+        ),
+    ];
+    for (path, contents) in &files {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| BruhError::io(dir, e))?;
+        }
+        fs::write(path, contents).map_err(|e| BruhError::io(path, e))?;
+    }
+
+    let updates = [
+        (
+            "update-mime-database",
+            vec![data.join("mime").into_os_string()],
+        ),
+        (
+            "update-desktop-database",
+            vec![data.join("applications").into_os_string()],
+        ),
+        (
+            "xdg-mime",
+            vec!["default".into(), DESKTOP_ENTRY.into(), MIME_TYPE.into()],
+        ),
+    ];
+    let failed = updates
+        .into_iter()
+        .filter(|(program, args)| {
+            !Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+        .map(|(program, _)| program.to_owned())
+        .collect();
+    Ok((files.into_iter().map(|(path, _)| path).collect(), failed))
+}
+
+/// `$XDG_DATA_HOME`, or `~/.local/share` if that isn't set.
+fn data_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => Some(PathBuf::from(std::env::var_os("HOME")?).join(".local/share")),
+    }
+}
+
+/// A file from `data/` running `exe` instead of whatever `bruh` is on the `PATH`.
+fn with_exe(entry: &str, exe: &Path) -> String {
+    // Quoted as the desktop entry specification wants it, escaped once more for the string.
+    let mut quoted = "\"".to_owned();
+    for c in exe.to_string_lossy().chars() {
+        match c {
+            '"' | '`' | '$' => quoted.push_str(&format!("\\\\{}", c)),
+            '\\' => quoted.push_str("\\\\\\\\"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    entry
+        .replace("TryExec=bruh", &format!("TryExec={}", exe.display()))
+        .replace("Exec=bruh ", &format!("Exec={} ", quoted))
+}
+
+fn exists(key: &str) -> bool {
+    Command::new("reg")
+        .args(["query", key])
+        .stdin(Stdio::null())
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn reg(args: &[&str]) -> Result<(), BruhError> {
+    let output = Command::new("reg")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| BruhError::io(Path::new("reg"), e))?;
+    if !output.status.success() {
+        return Err(BruhError::Failed(format!(
+            "reg {} {} failed: {}",
+            args[0],
+            args[1],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! `bruh bench`: how fast an image encodes and decodes with every compression and how much
+//! memory that takes, to pick a compression and to catch performance regressions. Memory is
+//! counted by `CountingAllocator`, which the binary installs as its global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::format::{self, BruhFile, Compression, DecodeError};
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, keeping track of the most bytes in use at once.
+pub struct CountingAllocator;
+
+// SAFETY: everything is passed on to `System` unchanged, only the counters are extra.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new
+    }
+}
+
+fn grow(size: usize) {
+    let in_use = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(in_use, Ordering::Relaxed);
+}
+
+/// Runs `f`, returning its result and the most memory it had allocated at once on top of what
+/// was in use before.
+fn with_peak_memory<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed).saturating_sub(before))
+}
+
+/// How one compression did.
+pub struct Measurement {
+    pub compression: Compression,
+    /// Bytes of the encoded file.
+    pub size: usize,
+    /// The median time of every run.
+    pub encode: Duration,
+    pub decode: Duration,
+    /// The most memory a single run needed, in bytes.
+    pub encode_memory: usize,
+    pub decode_memory: usize,
+}
+
+/// Encodes and decodes the RGBA pixels `iterations` times with every compression.
+pub fn run(
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    iterations: usize,
+    max_pixels: u64,
+) -> Result<Vec<Measurement>, DecodeError> {
+    let mut measurements = Vec::new();
+    for compression in Compression::all() {
+        let (mut encode_times, mut decode_times) = (Vec::new(), Vec::new());
+        let (mut encode_memory, mut decode_memory) = (0, 0);
+        let mut size = 0;
+        for _ in 0..iterations {
+            let started = Instant::now();
+            let (bytes, memory) = with_peak_memory(|| {
+                BruhFile::from_rgba(width, height, rgba, compression).to_bytes()
+            });
+            encode_times.push(started.elapsed());
+            encode_memory = encode_memory.max(memory);
+            size = bytes.len();
+
+            let started = Instant::now();
+            let (decoded, memory) = with_peak_memory(|| format::decode(&bytes, max_pixels));
+            decode_times.push(started.elapsed());
+            decode_memory = decode_memory.max(memory);
+            decoded?;
+        }
+        measurements.push(Measurement {
+            compression,
+            size,
+            encode: median(&mut encode_times),
+            decode: median(&mut decode_times),
+            encode_memory,
+            decode_memory,
+        });
+    }
+    Ok(measurements)
+}
+
+fn median(times: &mut [Duration]) -> Duration {
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// Megabytes of RGBA pixels handled per second.
+pub fn throughput(rgba_size: usize, time: Duration) -> f64 {
+    rgba_size as f64 / 1_000_000.0 / time.as_secs_f64().max(f64::MIN_POSITIVE)
+}
@@ -0,0 +1,479 @@
+//! Shell completion scripts for `bruh completions <shell>`.
+//!
+//! The scripts are generated from `COMMANDS`, so a new subcommand or flag only has to be added
+//! there to become completable. A test checks that every command `run` knows is there.
+
+struct Command {
+    name: &'static str,
+    flags: &'static [&'static str],
+    /// Words that can follow the command directly, like the operation of `meta`.
+    values: &'static [&'static str],
+}
+
+const fn command(
+    name: &'static str,
+    flags: &'static [&'static str],
+    values: &'static [&'static str],
+) -> Command {
+    Command {
+        name,
+        flags,
+        values,
+    }
+}
+
+const COMMANDS: &[Command] = &[
+    command(
+        "compile",
+        &[
+            "--encrypt",
+            "--embed-thumbnail",
+            "--mipmaps",
+            "--linear",
+            "--no-auto-orient",
+            "--channel-order",
+            "--float",
+            "--lossy",
+            "--quality",
+            "--async",
+            "--manifest",
+        ],
+        &[],
+    ),
+    command("copy", &[], &[]),
+    command("from-clipboard", &[], &[]),
+    command("thumbnail", &["--size"], &[]),
+    command("resize", &["--to", "--filter", "--no-linear", "-o"], &[]),
+    command("crop", &["--rect", "-o"], &[]),
+    command("rotate", &["--degrees", "-o"], &[]),
+    command("flip", &["--horizontal", "--vertical", "-o"], &[]),
+    command("fx", &["-o"], &["grayscale", "invert", "sepia"]),
+    command(
+        "adjust",
+        &[
+            "--brightness",
+            "--contrast",
+            "--gamma",
+            "--linear",
+            "--auto-levels",
+            "--equalize",
+            "-o",
+        ],
+        &[],
+    ),
+    command("filter", &["--blur", "--sharpen", "-o"], &[]),
+    command(
+        "apply",
+        &["--list", "-o"],
+        &[
+            "grayscale",
+            "invert",
+            "sepia",
+            "adjust",
+            "auto-levels",
+            "equalize",
+            "blur",
+            "sharpen",
+            "rotate",
+            "flip",
+        ],
+    ),
+    command("pipe", &["--ops", "-o"], &[]),
+    command("script", &[], &[]),
+    command("composite", &["--at", "--blend", "--no-linear", "-o"], &[]),
+    command(
+        "annotate",
+        &[
+            "--text",
+            "--at",
+            "--size",
+            "--color",
+            "--font",
+            "--no-linear",
+            "-o",
+        ],
+        &[],
+    ),
+    command(
+        "watermark",
+        &[
+            "--image",
+            "--position",
+            "--tile",
+            "--opacity",
+            "--margin",
+            "--no-linear",
+            "-o",
+        ],
+        &[],
+    ),
+    command(
+        "montage",
+        &["--columns", "--gap", "--background", "-o"],
+        &[],
+    ),
+    command(
+        "slice",
+        &["--tile", "--margin", "--spacing", "--to", "-o"],
+        &[],
+    ),
+    command("pack", &["--max-width", "--map", "-o"], &[]),
+    command("quantize", &["--colors", "--dither", "-o"], &[]),
+    command(
+        "convert-depth",
+        &["--to", "--dither", "--background", "-o"],
+        &[],
+    ),
+    command("palette", &["--colors", "--format", "-o"], &[]),
+    command("flatten", &["--background", "--no-linear", "-o"], &[]),
+    command("stats", &["--format"], &[]),
+    command("compare", &["--min-psnr", "--min-ssim", "--diff-out"], &[]),
+    command("bench", &["--iterations"], &[]),
+    command("hash", &["--algorithm"], &[]),
+    command("dedupe", &["--threshold", "--delete", "--hardlink"], &[]),
+    command("strip", &["-o"], &[]),
+    command("repair", &["-o"], &[]),
+    command("meta", &["-o"], &["list", "get", "set", "remove"]),
+    command("add", &[], &[]),
+    command("list", &[], &[]),
+    command("extract", &["--index", "-o"], &[]),
+    command("stego", &["-o"], &["embed", "extract"]),
+    command(
+        "layer",
+        &["--name", "--at", "--blend", "--opacity", "-o"],
+        &["list", "add", "extract", "hide", "show"],
+    ),
+    command("sign", &["--key", "--new-key", "-o"], &[]),
+    command("verify-sig", &["--key"], &[]),
+    command("inspect", &["--hex", "--bytes"], &[]),
+    command("identify", &["--format"], &[]),
+    command("upgrade", &[], &[]),
+    command("optimize", &[], &[]),
+    command("tile", &["--size", "-o"], &[]),
+    command("interlace", &["-o"], &[]),
+    command("align", &["-o"], &[]),
+    command(
+        "decode",
+        &["--format", "--level", "--tone-map", "--exposure", "-o"],
+        &[],
+    ),
+    command(
+        "show",
+        &["--sixel", "--kitty", "--iterm", "--blocks", "--size"],
+        &[],
+    ),
+    command("ascii", &["--width", "--color"], &[]),
+    command("serve", &["--port", "--host"], &[]),
+    command("watch", &["--to", "--interval"], &[]),
+    command("mount", &[], &[]),
+    command("from-video", &["--fps", "--animated"], &[]),
+    command("append-frame", &["--delay"], &[]),
+    command("extract-frames", &["--format", "--range", "-o"], &[]),
+    command("export-raw", &["--format"], &[]),
+    command("from-raw", &["--size", "--format"], &[]),
+    command(
+        "generate",
+        &[
+            "--pattern",
+            "--size",
+            "--color",
+            "--background",
+            "--cell",
+            "--seed",
+        ],
+        &[],
+    ),
+    command("screenshot", &["--region"], &[]),
+    command("thumbnailer", &["-s", "--cache"], &[]),
+    command("register", &[], &[]),
+    command("unregister", &[], &[]),
+    command("install-desktop", &[], &[]),
+    command("completions", &[], &SHELLS),
+];
+
+/// Flags every command understands.
+const GLOBAL_FLAGS: &[&str] = &[
+    "-v",
+    "-vv",
+    "-q",
+    "--quiet",
+    "--config",
+    "--compression",
+    "--deterministic",
+    "-j",
+    "--jobs",
+];
+
+pub const SHELLS: [&str; 4] = ["bash", "zsh", "fish", "powershell"];
+
+fn command_names() -> String {
+    COMMANDS
+        .iter()
+        .map(|c| c.name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The completion script for `shell`, or `None` if it isn't one of `SHELLS`.
+pub fn script(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        "powershell" => Some(powershell()),
+        _ => None,
+    }
+}
+
+fn bash() -> String {
+    let mut cases = String::new();
+    for c in COMMANDS {
+        cases += &format!(
+            "        {}) flags=\"{}\"; values=\"{}\" ;;\n",
+            c.name,
+            c.flags.join(" "),
+            c.values.join(" ")
+        );
+    }
+
+    format!(
+        r#"# bash completion for bruh, load with `source <(bruh completions bash)`
+_bruh() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local flags="" values=""
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{commands}" -f -- "$cur"))
+        return
+    fi
+
+    case "${{COMP_WORDS[1]}}" in
+{cases}    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$flags {global}" -- "$cur"))
+    elif [ "$COMP_CWORD" -eq 2 ] && [ -n "$values" ]; then
+        COMPREPLY=($(compgen -W "$values" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -o filenames -F _bruh bruh
+"#,
+        commands = command_names(),
+        cases = cases,
+        global = GLOBAL_FLAGS.join(" ")
+    )
+}
+
+fn zsh() -> String {
+    let mut cases = String::new();
+    for c in COMMANDS {
+        cases += &format!(
+            "        {}) flags=({}); values=({}) ;;\n",
+            c.name,
+            c.flags.join(" "),
+            c.values.join(" ")
+        );
+    }
+
+    format!(
+        r#"#compdef bruh
+# zsh completion for bruh, save as `_bruh` somewhere in $fpath or `source <(bruh completions zsh)`
+_bruh() {{
+    local -a flags values
+    if (( CURRENT == 2 )); then
+        _alternative 'commands:command:({commands})' 'files:file:_files'
+        return
+    fi
+
+    case $words[2] in
+{cases}    esac
+
+    if [[ $PREFIX == -* ]]; then
+        compadd -- $flags {global}
+    elif (( CURRENT == 3 && $#values )); then
+        compadd -- $values
+    else
+        _files
+    fi
+}}
+
+if [ "$funcstack[1]" = "_bruh" ]; then
+    _bruh "$@"
+else
+    compdef _bruh bruh
+fi
+"#,
+        commands = command_names(),
+        cases = cases,
+        global = GLOBAL_FLAGS.join(" ")
+    )
+}
+
+/// `--size` becomes `-l size`, `-o` becomes `-s o` and `-vv` the old style `-o vv`.
+fn fish_flag(flag: &str) -> String {
+    match flag.strip_prefix("--") {
+        Some(long) => format!("-l {}", long),
+        None if flag.len() == 2 => format!("-s {}", &flag[1..]),
+        None => format!("-o {}", &flag[1..]),
+    }
+}
+
+fn fish() -> String {
+    let mut script = format!(
+        "# fish completion for bruh, load with `bruh completions fish | source`\n\
+         complete -c bruh -n __fish_use_subcommand -a \"{}\"\n",
+        command_names()
+    );
+    for flag in GLOBAL_FLAGS {
+        script += &format!("complete -c bruh {}\n", fish_flag(flag));
+    }
+
+    for c in COMMANDS {
+        let condition = format!("__fish_seen_subcommand_from {}", c.name);
+        for flag in c.flags {
+            script += &format!(
+                "complete -c bruh -n \"{}\" {}\n",
+                condition,
+                fish_flag(flag)
+            );
+        }
+        if !c.values.is_empty() {
+            script += &format!(
+                "complete -c bruh -f -n \"{}; and test (count (commandline -opc)) -eq 2\" -a \"{}\"\n",
+                condition,
+                c.values.join(" ")
+            );
+        }
+    }
+
+    script
+}
+
+fn powershell() -> String {
+    let quote = |words: &[&str]| {
+        words
+            .iter()
+            .map(|w| format!("'{}'", w))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut table = String::new();
+    for c in COMMANDS {
+        table += &format!(
+            "        '{}' = @{{ Flags = @({}); Values = @({}) }}\n",
+            c.name,
+            quote(c.flags),
+            quote(c.values)
+        );
+    }
+
+    format!(
+        r#"# PowerShell completion for bruh, load with `bruh completions powershell | Out-String | Invoke-Expression`
+Register-ArgumentCompleter -Native -CommandName bruh -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $commands = @{{
+{table}    }}
+    $global = @({global})
+
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $position = $words.Count
+    if ($wordToComplete) {{ $position -= 1 }}
+
+    if ($position -le 1) {{
+        $candidates = $commands.Keys
+    }} elseif (-not $commands.ContainsKey($words[1])) {{
+        $candidates = $global
+    }} elseif ($wordToComplete -like '-*') {{
+        $candidates = $commands[$words[1]].Flags + $global
+    }} elseif ($position -eq 2 -and $commands[$words[1]].Values.Count) {{
+        $candidates = $commands[$words[1]].Values
+    }} else {{
+        return
+    }}
+
+    $candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | Sort-Object | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#,
+        table = table,
+        global = quote(GLOBAL_FLAGS)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_shell_completes_every_command_and_flag() {
+        for shell in SHELLS {
+            let script = script(shell).unwrap();
+            for c in COMMANDS {
+                assert!(script.contains(c.name), "{} misses {}", shell, c.name);
+                for flag in c.flags.iter().chain(GLOBAL_FLAGS) {
+                    // fish spells `--config` as `-l config`.
+                    let spelled = match shell {
+                        "fish" => fish_flag(flag),
+                        _ => flag.to_string(),
+                    };
+                    assert!(
+                        script.contains(&spelled),
+                        "{} misses {} of {}",
+                        shell,
+                        flag,
+                        c.name
+                    );
+                }
+                for value in c.values {
+                    assert!(
+                        script.contains(value),
+                        "{} misses {} of {}",
+                        shell,
+                        value,
+                        c.name
+                    );
+                }
+            }
+        }
+        assert_eq!(script("tcsh"), None);
+    }
+
+    #[test]
+    fn commands_are_listed_once() {
+        let mut names: Vec<_> = COMMANDS.iter().map(|c| c.name).collect();
+        names.sort_unstable();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn every_command_run_knows_is_listed() {
+        // The arms of `run`, each starting a line like `"resize" => {` or `"a" | "b" if ...`.
+        let source = include_str!("lib.rs");
+        let run = &source[source.find("\nfn run(").unwrap()..];
+        let run = &run[..run.find("\n}\n").unwrap()];
+        let arms: Vec<&str> = run
+            .lines()
+            .filter_map(|line| line.strip_prefix("        \""))
+            .flat_map(|arm| {
+                let patterns = arm.split(" =>").next().unwrap();
+                let patterns = patterns.split(" if ").next().unwrap();
+                patterns.split(" | ").map(|name| name.trim_matches('"'))
+            })
+            .collect();
+        assert!(arms.len() > 50, "found only {:?}", arms);
+
+        let names: Vec<_> = COMMANDS.iter().map(|c| c.name).collect();
+        for arm in &arms {
+            assert!(names.contains(arm), "{} isn't in COMMANDS", arm);
+        }
+        for name in names {
+            assert!(arms.contains(&name), "{} isn't a command", name);
+        }
+    }
+}
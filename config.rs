@@ -0,0 +1,319 @@
+//! User defaults from `config.toml`, e.g. on Linux `~/.config/bruh/config.toml`:
+//!
+//! ```toml
+//! compression = "deflate"       # none, rle or deflate, used for every file bruh writes
+//! output_dir = "~/Pictures"     # where results go when a command isn't given `-o`
+//! suffix = "-edited"            # added to output names instead of overwriting the input
+//! keep_name = true              # `compile` turns photo.png into photo.png.bruh
+//! max_pixels = 268435456        # larger images are refused, 16384x16384 by default
+//! jobs = 4                      # threads for commands given several files, one per CPU by default
+//!
+//! [viewer]
+//! background = "#202020"        # any CSS color, shows through transparent images
+//! theme = "light"               # dark or light
+//!
+//! [keys]
+//! copy = "C"                    # Ctrl/Cmd + letter in the viewer
+//! paste = "V"
+//! ```
+//!
+//! Every setting is optional. Command line flags (`--compression`, `--suffix`, `-o`, ...) win over
+//! the file.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    thread,
+};
+
+use css_color_parser::Color as CssColor;
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::{
+    error::BruhError,
+    format::{self, Compression},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    compression: Option<String>,
+    output_dir: Option<PathBuf>,
+    suffix: Option<String>,
+    keep_name: bool,
+    max_pixels: Option<u64>,
+    jobs: Option<usize>,
+    viewer: ViewerSection,
+    keys: KeysSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ViewerSection {
+    background: Option<String>,
+    theme: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeysSection {
+    copy: Option<String>,
+    paste: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub compression: Compression,
+    pub output_dir: Option<PathBuf>,
+    /// Added to the file stem of outputs written without `-o`.
+    pub suffix: Option<String>,
+    pub keep_name: bool,
+    /// Images with more pixels are neither decoded nor created.
+    pub max_pixels: u64,
+    /// How many files commands given several of them work on at once.
+    pub jobs: usize,
+    /// RGBA, `None` keeps the theme's panel color.
+    pub background: Option<[u8; 4]>,
+    pub theme: Theme,
+    /// Uppercase letters pressed together with Ctrl (Cmd on macOS).
+    pub copy_key: char,
+    pub paste_key: char,
+    /// Set by `--deterministic`: the same inputs and flags have to give the same files, so the
+    /// configuration file is only read when given with `--config` and encryption is refused.
+    pub deterministic: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            output_dir: None,
+            suffix: None,
+            keep_name: false,
+            max_pixels: format::DEFAULT_MAX_PIXELS,
+            jobs: thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            background: None,
+            theme: Theme::Dark,
+            copy_key: 'C',
+            paste_key: 'V',
+            deterministic: false,
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The configuration set up by `main`, or the defaults if there is none.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+pub fn set(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Configuration was already set")
+    }
+}
+
+/// `BRUH_CONFIG` if set, otherwise `bruh/config.toml` in the platform's configuration directory.
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("BRUH_CONFIG") {
+        return Some(path.into());
+    }
+    Some(dir()?.join("config.toml"))
+}
+
+/// `BRUH_PLUGINS` if set, otherwise `bruh/plugins` in the platform's configuration directory.
+pub fn plugins_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("BRUH_PLUGINS") {
+        return Some(dir.into());
+    }
+    Some(dir()?.join("plugins"))
+}
+
+/// `bruh` in the platform's configuration directory.
+pub fn dir() -> Option<PathBuf> {
+    Some(project_dirs()?.config_dir().to_path_buf())
+}
+
+/// `bruh` in the platform's cache directory, for what can be made again whenever it's gone.
+pub fn cache_dir() -> Option<PathBuf> {
+    Some(project_dirs()?.cache_dir().to_path_buf())
+}
+
+/// Where bruh keeps its files, `None` without a home directory.
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from_path(PathBuf::from("bruh"))
+}
+
+/// Reads the configuration at `path`. A missing file is only an error if `required` is set,
+/// otherwise the defaults are used.
+pub fn load(path: &Path, required: bool) -> Result<Config, BruhError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+            log::debug!("no configuration at {}", path.display());
+            return Ok(Config::default());
+        }
+        Err(e) => return Err(BruhError::io(path, e)),
+    };
+    log::debug!("loading configuration from {}", path.display());
+
+    let invalid = |message: String| BruhError::Config {
+        path: path.to_path_buf(),
+        message,
+    };
+    let file: ConfigFile = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    let defaults = Config::default();
+
+    let compression = match file.compression {
+        Some(name) => Compression::parse(&name).ok_or_else(|| {
+            invalid(format!(
+                "Unknown compression '{}'. Use one of: none, rle, deflate",
+                name
+            ))
+        })?,
+        None => defaults.compression,
+    };
+    let background = match file.viewer.background {
+        Some(color) => {
+            let color = color
+                .parse::<CssColor>()
+                .map_err(|_| invalid(format!("'{}' isn't a CSS color", color)))?;
+            Some([color.r, color.g, color.b, (color.a * 255.0).round() as u8])
+        }
+        None => defaults.background,
+    };
+    let theme = match file.viewer.theme.as_deref() {
+        Some("dark") => Theme::Dark,
+        Some("light") => Theme::Light,
+        Some(theme) => {
+            return Err(invalid(format!(
+                "Unknown theme '{}'. Use one of: dark, light",
+                theme
+            )))
+        }
+        None => defaults.theme,
+    };
+    let max_pixels = match file.max_pixels {
+        Some(0) => return Err(invalid("'max_pixels' must be at least 1".to_owned())),
+        Some(max_pixels) => max_pixels,
+        None => defaults.max_pixels,
+    };
+    let jobs = match file.jobs {
+        Some(0) => return Err(invalid("'jobs' must be at least 1".to_owned())),
+        Some(jobs) => jobs,
+        None => defaults.jobs,
+    };
+    let key = |name: Option<String>, default: char| match name {
+        Some(name) => match name.chars().collect::<Vec<_>>()[..] {
+            [letter] if letter.is_ascii_alphabetic() => Ok(letter.to_ascii_uppercase()),
+            _ => Err(invalid(format!("Key '{}' must be a single letter", name))),
+        },
+        None => Ok(default),
+    };
+
+    Ok(Config {
+        compression,
+        output_dir: file.output_dir.map(|dir| expand_home(&dir)),
+        suffix: file.suffix,
+        keep_name: file.keep_name,
+        max_pixels,
+        jobs,
+        background,
+        theme,
+        copy_key: key(file.keys.copy, defaults.copy_key)?,
+        paste_key: key(file.keys.paste, defaults.paste_key)?,
+        deterministic: false,
+    })
+}
+
+/// Replaces a leading `~` with the home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `contents` as a configuration file.
+    fn parse(test: &str, contents: &str) -> Result<Config, BruhError> {
+        let path =
+            env::temp_dir().join(format!("bruh-config-{}-{}.toml", test, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let config = load(&path, true);
+        fs::remove_file(path).unwrap();
+        config
+    }
+
+    #[test]
+    fn missing_files_are_only_an_error_when_asked_for() {
+        let path = env::temp_dir().join(format!("bruh-config-missing-{}.toml", std::process::id()));
+        let config = load(&path, false).unwrap();
+        assert_eq!(config.compression, Compression::None);
+        assert!(matches!(load(&path, true), Err(BruhError::Io { .. })));
+    }
+
+    #[test]
+    fn every_setting_is_read() {
+        let config = parse(
+            "all",
+            r##"
+            compression = "deflate"
+            output_dir = "~/out"
+            suffix = "-edited"
+            keep_name = true
+            max_pixels = 100
+            jobs = 3
+
+            [viewer]
+            background = "#202020"
+            theme = "light"
+
+            [keys]
+            copy = "k"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.compression, Compression::Deflate);
+        assert!(config.output_dir.unwrap().ends_with("out"));
+        assert_eq!(config.suffix.as_deref(), Some("-edited"));
+        assert!(config.keep_name);
+        assert_eq!((config.max_pixels, config.jobs), (100, 3));
+        assert_eq!(config.background, Some([32, 32, 32, 255]));
+        assert_eq!(config.theme, Theme::Light);
+        assert_eq!((config.copy_key, config.paste_key), ('K', 'V'));
+    }
+
+    #[test]
+    fn invalid_settings_say_what_is_wrong() {
+        let cases = [
+            ("compression = \"zstd\"", "Unknown compression 'zstd'"),
+            ("jobs = 0", "at least 1"),
+            ("max_pixels = 0", "'max_pixels' must be at least 1"),
+            ("[viewer]\ntheme = \"blue\"", "Unknown theme 'blue'"),
+            ("[viewer]\nbackground = \"nope\"", "isn't a CSS color"),
+            ("[keys]\ncopy = \"Ctrl\"", "single letter"),
+            ("colour = \"red\"", "colour"),
+            ("jobs = \"many\"", "jobs"),
+        ];
+        for (i, (contents, expected)) in cases.into_iter().enumerate() {
+            let e = parse(&format!("invalid{}", i), contents).unwrap_err();
+            assert!(matches!(e, BruhError::Config { .. }), "{}", contents);
+            assert_eq!(e.exit_code(), 2);
+            assert!(e.to_string().contains(expected), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,5142 @@
+//! The BRUH container.
+//!
+//! A file starts with a `BRUH_HEADER_SIZE` byte header:
+//!
+//! | offset | size | field                        |
+//! |--------|------|------------------------------|
+//! | 0      | 4    | magic `BRUH`                 |
+//! | 4      | 1    | version                      |
+//! | 5      | 1    | pixel format                 |
+//! | 6      | 1    | compression of `PXLS`        |
+//! | 7      | 1    | flags, `FLAG_*`              |
+//! | 8      | 4    | width, little endian         |
+//! | 12     | 4    | height, little endian        |
+//!
+//! followed by chunks until the end of the file. Every chunk is a 4 byte tag, a little endian
+//! u32 length, the data and a little endian CRC32 over tag and data. `PXLS` holds the pixels row
+//! by row without padding (compressed as given in the header), `PLTE` the RGBA entries of a
+//! palette-mode image.
+//!
+//! Like in PNG, a tag starting with an uppercase letter marks a critical chunk that is needed to
+//! reconstruct the pixels. Tags starting with a lowercase letter are ancillary (metadata and the
+//! like): readers may skip them and `strip` removes them.
+//!
+//! `meta` holds text metadata as `key NUL value NUL` pairs.
+//!
+//! Animations keep their first frame in `PXLS`, so readers that don't know about animation still
+//! show something. `ANIM` holds the little endian u32 loop count (0 loops forever) and the delay
+//! of the first frame in milliseconds. Each further frame is a `FRAM` chunk: its delay as a u32
+//! followed by pixels stored exactly like those in `PXLS`. A frame that only changes part of the
+//! previous one can be a `DFRM` chunk instead: its delay, then the little endian u32 x, y, width
+//! and height of the rectangle holding every changed pixel, then that rectangle's pixels stored
+//! like those in `PXLS`, but without padded rows. Everything outside of it stays as it was, and an
+//! empty rectangle repeats the previous frame. Frames are in the order of their chunks.
+//!
+//! Tiled files set `FLAG_TILED` and split the pixels into tiles compressed on their own, so a
+//! region can be decoded without decompressing the rest. A `TIDX` chunk before `PXLS` holds the
+//! little endian u32 tile width and height, then for every tile, row by row, the u32 offset of
+//! its data in `PXLS`. A tile's data ends where the next one starts. Tiles at the right and
+//! bottom edges are cut to the image, and every tile stores its rows like an untiled `PXLS`.
+//! Animations aren't tiled.
+//!
+//! Interlaced files set `FLAG_INTERLACED` and store the pixels in the seven passes of Adam7, like
+//! interlaced PNGs, so a reader with only the start of the file can show a coarse version of the
+//! image. `PXLS` holds each pass as a little endian u32 length followed by the pass's pixels, row
+//! by row and compressed on their own. See `ADAM7` for which pixels are in which pass.
+//!
+//! Encrypted files set `FLAG_ENCRYPTED` and start with a `CRYP` chunk: a 16 byte salt and the
+//! little endian u32 memory (in KiB), pass and lane counts Argon2id derives the key with. Every
+//! other chunk then holds a 24 byte nonce followed by its data sealed with XChaCha20-Poly1305,
+//! with the header, the index of the chunk among the sealed ones, their count (both little endian
+//! u32) and the chunk tag as associated data. Decoders without the password can only report
+//! `DecodeError::Encrypted`.
+//!
+//! A `thmb` chunk right after the header can hold a small version of the image as a BRUH file of
+//! its own, so file browsers can show it without decoding the pixels.
+//!
+//! A `phys` chunk before `PXLS` can hold the physical size as the little endian u32 horizontal
+//! and vertical pixels per inch, for printing. An `exif` chunk holds the EXIF data of the source
+//! image as it was, starting with the TIFF header.
+//!
+//! Files with mipmaps have a `mipl` chunk after `PXLS` for every level below the full image, the
+//! largest first. Each holds its level as a BRUH file of its own, half as wide and high as the
+//! level above, rounded down but at least one pixel.
+//!
+//! Signed files end with a `sign` chunk: the 32 byte Ed25519 public key and the 64 byte signature
+//! over every byte of the file before the chunk.
+//!
+//! Files that set `FLAG_BGR` store every color blue, green, red (and alpha), both in `PXLS` and in
+//! `PLTE`, for readers that hand BGRA straight to the GPU. Decoding to RGBA swaps them back.
+//!
+//! Files whose colors are linear light instead of sRGB set `FLAG_LINEAR`. The pixels are stored
+//! the same way, only their meaning changes.
+//!
+//! Lossy files set `FLAG_LOSSY` and hold in `PXLS`, compressed as the header says, pixels that
+//! only look like the original, much smaller than exact ones for photos. See `lossy` for how.
+//! They are RGB8, RGBA8 or Gray8, and neither tiled, interlaced, padded nor animated.
+//!
+//! Encoding is deterministic: the same pixels, metadata and options always give the same bytes.
+//! Chunks are written in a fixed order, nothing depends on the time or the machine, and deflate
+//! always runs at level 9. Only encryption adds randomness, a fresh salt and nonces every time.
+//!
+//! Files without the magic are legacy (v0) files: native endian width and height followed by
+//! newline separated rows of `rrggbb` hex colors.
+
+// Only `core` and `alloc`, so the codec builds for `no_std` targets, see `lib.rs`.
+extern crate alloc;
+
+use alloc::{
+    borrow::{Cow, ToOwned},
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::{fmt, iter};
+
+use log::{debug, trace};
+use miniz_oxide::{
+    inflate::{
+        stream::{inflate, InflateState},
+        TINFLStatus,
+    },
+    DataFormat, MZError, MZFlush, MZStatus,
+};
+
+pub const MAGIC: &[u8; 4] = b"BRUH";
+pub const VERSION: u8 = 1;
+pub const BRUH_HEADER_SIZE: usize = 16;
+
+pub const PIXELS: [u8; 4] = *b"PXLS";
+pub const PALETTE: [u8; 4] = *b"PLTE";
+pub const METADATA: [u8; 4] = *b"meta";
+pub const ANIMATION: [u8; 4] = *b"ANIM";
+pub const FRAME: [u8; 4] = *b"FRAM";
+pub const DELTA_FRAME: [u8; 4] = *b"DFRM";
+pub const ENCRYPTION: [u8; 4] = *b"CRYP";
+pub const SIGNATURE: [u8; 4] = *b"sign";
+pub const TILE_INDEX: [u8; 4] = *b"TIDX";
+pub const THUMBNAIL: [u8; 4] = *b"thmb";
+pub const MIPMAP: [u8; 4] = *b"mipl";
+pub const PHYSICAL_SIZE: [u8; 4] = *b"phys";
+pub const EXIF: [u8; 4] = *b"exif";
+pub const STRIDE: [u8; 4] = *b"STRD";
+pub const LAYER: [u8; 4] = *b"layr";
+pub const IMAGE: [u8; 4] = *b"imag";
+
+/// Every chunk this version knows, which `repair` tells apart from garbage by.
+pub const KNOWN_CHUNKS: [[u8; 4]; 16] = [
+    PIXELS,
+    PALETTE,
+    METADATA,
+    ANIMATION,
+    FRAME,
+    DELTA_FRAME,
+    ENCRYPTION,
+    SIGNATURE,
+    TILE_INDEX,
+    THUMBNAIL,
+    MIPMAP,
+    PHYSICAL_SIZE,
+    EXIF,
+    STRIDE,
+    LAYER,
+    IMAGE,
+];
+
+/// The most padding a row may have, enough to align rows to 256 bytes.
+pub const MAX_ROW_PADDING: usize = 255;
+
+/// Header flag for files whose chunks, all but `CRYP`, are encrypted.
+pub const FLAG_ENCRYPTED: u8 = 1;
+/// Header flag for files whose pixels are split into tiles, see `BruhFile::tile`.
+pub const FLAG_TILED: u8 = 2;
+/// Header flag for files whose pixels are stored in Adam7 passes, see `BruhFile::interlace`.
+pub const FLAG_INTERLACED: u8 = 4;
+/// Header flag for files whose colors are linear light instead of sRGB, like most textures that
+/// aren't colors. Viewers have to convert them to sRGB to show them right.
+pub const FLAG_LINEAR: u8 = 8;
+/// Header flag for files that store blue before red, see `BruhFile::to_bgra`.
+pub const FLAG_BGR: u8 = 16;
+/// Header flag for files whose pixels are stored lossy, see `BruhFile::encode_lossy`.
+pub const FLAG_LOSSY: u8 = 32;
+/// The flags this version understands, files with any other flag are refused.
+pub const KNOWN_FLAGS: u8 =
+    FLAG_ENCRYPTED | FLAG_TILED | FLAG_INTERLACED | FLAG_LINEAR | FLAG_BGR | FLAG_LOSSY;
+
+/// The Adam7 passes as first column, first row, column step and row step. Every pass fills the
+/// gaps the earlier ones left, the first one holds every 64th pixel.
+pub const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+/// Width and height of the block each pixel of an Adam7 pass covers until the next passes fill
+/// it in.
+const ADAM7_BLOCKS: [(usize, usize); 7] = [(8, 8), (4, 8), (4, 4), (2, 4), (2, 2), (1, 2), (1, 1)];
+
+/// The tile size `bruh tile` uses unless told otherwise.
+pub const DEFAULT_TILE_SIZE: u32 = 256;
+
+/// Images with more pixels than this (16384x16384) aren't decoded unless the caller allows it,
+/// so a 16 byte file can't make us allocate gigabytes.
+pub const DEFAULT_MAX_PIXELS: u64 = 1 << 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb8 = 0,
+    Rgba8 = 1,
+    /// One byte per pixel indexing into the `PLTE` chunk.
+    Palette8 = 2,
+    /// One byte per pixel, opaque.
+    Gray8 = 3,
+    /// A little endian half precision float per channel, for HDR images in linear light.
+    RgbaF16 = 4,
+    /// A little endian single precision float per channel, for HDR images in linear light.
+    RgbaF32 = 5,
+}
+
+impl PixelFormat {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Rgb8),
+            1 => Some(Self::Rgba8),
+            2 => Some(Self::Palette8),
+            3 => Some(Self::Gray8),
+            4 => Some(Self::RgbaF16),
+            5 => Some(Self::RgbaF32),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgb8 => 3,
+            Self::Rgba8 => 4,
+            Self::Palette8 | Self::Gray8 => 1,
+            Self::RgbaF16 => 8,
+            Self::RgbaF32 => 16,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgb8 => "RGB8",
+            Self::Rgba8 => "RGBA8",
+            Self::Palette8 => "Palette8",
+            Self::Gray8 => "Gray8",
+            Self::RgbaF16 => "RGBA16F",
+            Self::RgbaF32 => "RGBA32F",
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Self::RgbaF16 | Self::RgbaF32)
+    }
+
+    /// The 8 bit formats, the ones that hold any 8 bit image without wasting space.
+    pub fn all() -> [Self; 4] {
+        [Self::Gray8, Self::Palette8, Self::Rgb8, Self::Rgba8]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    /// Runs of identical pixels, see `rle_encode`.
+    Rle = 1,
+    /// zlib stream.
+    Deflate = 2,
+}
+
+impl Compression {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Rle),
+            2 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|c| c.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Rle => "rle",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    pub fn all() -> [Self; 3] {
+        [Self::None, Self::Rle, Self::Deflate]
+    }
+}
+
+/// How a layer mixes with the layers below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Over = 0,
+    Multiply = 1,
+    Screen = 2,
+}
+
+impl BlendMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Over),
+            1 => Some(Self::Multiply),
+            2 => Some(Self::Screen),
+            _ => None,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(Self::Over),
+            _ => Self::all().into_iter().find(|mode| mode.name() == name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Over => "over",
+            Self::Multiply => "multiply",
+            Self::Screen => "screen",
+        }
+    }
+
+    pub fn all() -> [Self; 3] {
+        [Self::Over, Self::Multiply, Self::Screen]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u8,
+    pub format: PixelFormat,
+    pub compression: Compression,
+    pub width: u32,
+    pub height: u32,
+    /// Set by `FLAG_ENCRYPTED`.
+    pub encrypted: bool,
+    /// Set by `FLAG_TILED`.
+    pub tiled: bool,
+    /// Set by `FLAG_INTERLACED`.
+    pub interlaced: bool,
+    /// Set by `FLAG_LINEAR`.
+    pub linear: bool,
+    /// Set by `FLAG_BGR`.
+    pub bgr: bool,
+    /// Set by `FLAG_LOSSY`.
+    pub lossy: bool,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; BRUH_HEADER_SIZE] {
+        let mut bytes = [0; BRUH_HEADER_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.format as u8;
+        bytes[6] = self.compression as u8;
+        if self.encrypted {
+            bytes[7] |= FLAG_ENCRYPTED;
+        }
+        if self.tiled {
+            bytes[7] |= FLAG_TILED;
+        }
+        if self.interlaced {
+            bytes[7] |= FLAG_INTERLACED;
+        }
+        if self.linear {
+            bytes[7] |= FLAG_LINEAR;
+        }
+        if self.bgr {
+            bytes[7] |= FLAG_BGR;
+        }
+        if self.lossy {
+            bytes[7] |= FLAG_LOSSY;
+        }
+        bytes[8..12].copy_from_slice(&self.width.to_le_bytes());
+        bytes[12..].copy_from_slice(&self.height.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn new(tag: [u8; 4], data: Vec<u8>) -> Self {
+        Self { tag, data }
+    }
+
+    pub fn is_critical(&self) -> bool {
+        self.tag[0].is_ascii_uppercase()
+    }
+
+    pub fn crc(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.tag);
+        hasher.update(&self.data);
+        hasher.finalize()
+    }
+}
+
+/// One image of an animation, in straight RGBA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub rgba: Vec<u8>,
+    /// How long the frame is shown, in milliseconds.
+    pub delay_ms: u32,
+}
+
+/// A frame of an animation decoded on its own, see `BruhFile::frame_part`.
+pub struct FramePart {
+    pub delay_ms: u32,
+    pub pixels: FramePixels,
+}
+
+pub enum FramePixels {
+    /// Every pixel in straight RGBA.
+    Full(Vec<u8>),
+    /// Only the rectangle `[x, y, width, height]` that changed since the frame before, with its
+    /// pixels in straight RGBA.
+    Delta([usize; 4], Vec<u8>),
+}
+
+/// One layer of a layered image, see `BruhFile::layers`.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    /// Where the top left corner of the layer goes, it can hang over the edges of the image.
+    pub x: i32,
+    pub y: i32,
+    pub blend: BlendMode,
+    /// 255 is opaque, 0 invisible.
+    pub opacity: u8,
+    pub visible: bool,
+    /// The pixels of the layer, a BRUH file of their own.
+    pub image: BruhFile,
+}
+
+/// Bytes in front of the name in a `layr` chunk.
+const LAYER_HEADER_SIZE: usize = 15;
+
+impl Layer {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.x.to_le_bytes().to_vec();
+        data.extend_from_slice(&self.y.to_le_bytes());
+        data.extend_from_slice(&[self.blend as u8, self.opacity, self.visible as u8]);
+        data.extend_from_slice(&(self.name.len() as u32).to_le_bytes());
+        data.extend_from_slice(self.name.as_bytes());
+        data.extend(self.image.to_bytes());
+        data
+    }
+
+    /// Reads the data of a `layr` chunk.
+    pub fn parse(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < LAYER_HEADER_SIZE {
+            return Err(invalid("layr chunk is too short"));
+        }
+        let blend = BlendMode::from_u8(data[8])
+            .ok_or_else(|| invalid("Unknown blend mode in layr chunk"))?;
+        let name_length = read_u32_le(data, 11) as usize;
+        let name = data[LAYER_HEADER_SIZE..]
+            .get(..name_length)
+            .ok_or_else(|| invalid("layr chunk is cut off in the name"))?;
+        Ok(Self {
+            name: String::from_utf8_lossy(name).into_owned(),
+            x: read_u32_le(data, 0) as i32,
+            y: read_u32_le(data, 4) as i32,
+            blend,
+            opacity: data[9],
+            visible: data[10] != 0,
+            image: BruhFile::parse(&data[LAYER_HEADER_SIZE + name_length..])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BruhFile {
+    pub header: Header,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Why a file couldn't be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The file ends in the middle of `section`.
+    TruncatedFile {
+        section: String,
+        needed: usize,
+        available: usize,
+    },
+
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+
+    /// All the bytes are there, they just don't make sense.
+    Invalid(String),
+
+    /// The pixels are encrypted and have to be decrypted with the password first.
+    Encrypted,
+
+    /// The `Source` of a `BruhDecoder` couldn't be read.
+    Read(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TruncatedFile {
+                section,
+                needed,
+                available,
+            } => write!(
+                f,
+                "file is truncated, {} needs {} bytes but only {} are left",
+                section, needed, available
+            ),
+            DecodeError::TooLarge {
+                width,
+                height,
+                max_pixels,
+            } => write!(
+                f,
+                "image is {}x{}, more than the limit of {} pixels",
+                width, height, max_pixels
+            ),
+            DecodeError::Invalid(message) => f.write_str(message),
+            DecodeError::Encrypted => f.write_str("image is encrypted, it needs a password"),
+            DecodeError::Read(message) => write!(f, "reading failed: {}", message),
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+fn invalid(message: impl Into<String>) -> DecodeError {
+    DecodeError::Invalid(message.into())
+}
+
+/// Checks that a `width`x`height` image stays within `max_pixels` and that it fits in memory in
+/// every format, up to the 16 bytes per pixel of RGBA32F, returning the size of its RGBA8
+/// expansion in bytes.
+pub fn check_dimensions(width: u32, height: u32, max_pixels: u64) -> Result<usize, DecodeError> {
+    let pixels = width as u64 * height as u64;
+    if pixels > max_pixels {
+        return Err(DecodeError::TooLarge {
+            width,
+            height,
+            max_pixels,
+        });
+    }
+
+    byte_size(
+        width as usize,
+        height as usize,
+        PixelFormat::RgbaF32.bytes_per_pixel(),
+    )?;
+    Ok(pixels as usize * 4)
+}
+
+/// The bytes `width`x`height` pixels of `pixel_size` bytes take, if they fit in memory.
+fn byte_size(width: usize, height: usize, pixel_size: usize) -> Result<usize, DecodeError> {
+    (width as u64)
+        .checked_mul(height as u64)
+        .and_then(|pixels| pixels.checked_mul(pixel_size as u64))
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(|| invalid("Image dimensions are too large"))
+}
+
+/// Checks that `bytes` holds at least `needed` bytes starting at `offset` for `section`.
+fn ensure_available(
+    bytes: &[u8],
+    offset: usize,
+    needed: usize,
+    section: impl Into<String>,
+) -> Result<(), DecodeError> {
+    let available = bytes.len().saturating_sub(offset);
+    if available < needed {
+        return Err(DecodeError::TruncatedFile {
+            section: section.into(),
+            needed,
+            available,
+        });
+    }
+    Ok(())
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+impl BruhFile {
+    /// Stores straight RGBA pixels, dropping the alpha channel if every pixel is opaque.
+    pub fn from_rgba(width: u32, height: u32, rgba: &[u8], compression: Compression) -> Self {
+        let opaque = rgba.chunks(4).all(|pixel| pixel[3] == 255);
+        let format = if opaque {
+            PixelFormat::Rgb8
+        } else {
+            PixelFormat::Rgba8
+        };
+
+        Self::encode(width, height, rgba, format, compression).unwrap()
+    }
+
+    /// Stores straight RGBA pixels lossy at `quality`, 1 to 100 like JPEG's, as Gray8 if they
+    /// are opaque shades of gray, RGB8 if they are opaque and RGBA8 otherwise. Alpha stays
+    /// exact. `compression` squeezes the lossy data further, deflate does best.
+    pub fn encode_lossy(
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        quality: u8,
+        compression: Compression,
+    ) -> Self {
+        let opaque = rgba.chunks(4).all(|pixel| pixel[3] == 255);
+        let gray = rgba.chunks(4).all(|p| p[0] == p[1] && p[1] == p[2]);
+        let format = match (opaque, gray) {
+            (true, true) => PixelFormat::Gray8,
+            (true, false) => PixelFormat::Rgb8,
+            _ => PixelFormat::Rgba8,
+        };
+        let data = lossy::encode(width as usize, height as usize, rgba, format, quality);
+        Self {
+            header: Header {
+                version: VERSION,
+                format,
+                compression,
+                width,
+                height,
+                encrypted: false,
+                tiled: false,
+                interlaced: false,
+                linear: false,
+                bgr: false,
+                lossy: true,
+            },
+            chunks: vec![Chunk::new(PIXELS, compress(&data, compression, 1))],
+        }
+    }
+
+    /// Stores straight RGBA pixels in `format`, or returns `None` if `format` can't represent
+    /// them without losing information (alpha in RGB8 or Gray8, color in Gray8, more than 256
+    /// colors in Palette8). The float formats hold any image.
+    pub fn encode(
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        format: PixelFormat,
+        compression: Compression,
+    ) -> Option<Self> {
+        let opaque = || rgba.chunks(4).all(|pixel| pixel[3] == 255);
+        let mut chunks = Vec::new();
+
+        let data: Vec<u8> = match format {
+            PixelFormat::Rgba8 => rgba.to_vec(),
+            PixelFormat::Rgb8 => {
+                if !opaque() {
+                    return None;
+                }
+                rgba.chunks(4)
+                    .flat_map(|pixel| &pixel[..3])
+                    .copied()
+                    .collect()
+            }
+            PixelFormat::Gray8 => {
+                if !rgba
+                    .chunks(4)
+                    .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 255)
+                {
+                    return None;
+                }
+                rgba.chunks(4).map(|pixel| pixel[0]).collect()
+            }
+            PixelFormat::Palette8 => {
+                let mut palette: Vec<[u8; 4]> = Vec::new();
+                let mut lookup: BTreeMap<[u8; 4], u8> = BTreeMap::new();
+                let mut indices = Vec::with_capacity(rgba.len() / 4);
+                for pixel in rgba.chunks(4) {
+                    let color = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                    let index = match lookup.get(&color) {
+                        Some(&index) => index,
+                        None if palette.len() < 256 => {
+                            palette.push(color);
+                            lookup.insert(color, (palette.len() - 1) as u8);
+                            (palette.len() - 1) as u8
+                        }
+                        None => return None,
+                    };
+                    indices.push(index);
+                }
+
+                chunks.push(Chunk::new(PALETTE, palette.concat()));
+                indices
+            }
+            PixelFormat::RgbaF16 | PixelFormat::RgbaF32 => {
+                let rgba: Vec<f32> = rgba.iter().map(|&c| c as f32 / 255.0).collect();
+                encode_floats(&rgba, format)
+            }
+        };
+        chunks.push(Chunk::new(
+            PIXELS,
+            compress(&data, compression, format.bytes_per_pixel()),
+        ));
+
+        Some(Self {
+            header: Header {
+                version: VERSION,
+                format,
+                compression,
+                width,
+                height,
+                encrypted: false,
+                tiled: false,
+                interlaced: false,
+                linear: false,
+                bgr: false,
+                lossy: false,
+            },
+            chunks,
+        })
+    }
+
+    /// Stores straight RGBA pixels with a float per channel in one of the float formats, or
+    /// returns `None` for the others. Marks the colors as linear, which they should be.
+    pub fn from_rgba_f32(
+        width: u32,
+        height: u32,
+        rgba: &[f32],
+        format: PixelFormat,
+        compression: Compression,
+    ) -> Option<Self> {
+        if !format.is_float() {
+            return None;
+        }
+        let data = encode_floats(rgba, format);
+        Some(Self {
+            header: Header {
+                version: VERSION,
+                format,
+                compression,
+                width,
+                height,
+                encrypted: false,
+                tiled: false,
+                interlaced: false,
+                linear: true,
+                bgr: false,
+                lossy: false,
+            },
+            chunks: vec![Chunk::new(
+                PIXELS,
+                compress(&data, compression, format.bytes_per_pixel()),
+            )],
+        })
+    }
+
+    /// Stores one palette index per pixel. `palette` may hold at most 256 colors.
+    pub fn from_palette(
+        width: u32,
+        height: u32,
+        palette: &[[u8; 4]],
+        indices: &[u8],
+        compression: Compression,
+    ) -> Self {
+        Self {
+            header: Header {
+                version: VERSION,
+                format: PixelFormat::Palette8,
+                compression,
+                width,
+                height,
+                encrypted: false,
+                tiled: false,
+                interlaced: false,
+                linear: false,
+                bgr: false,
+                lossy: false,
+            },
+            chunks: vec![
+                Chunk::new(PALETTE, palette.concat()),
+                Chunk::new(
+                    PIXELS,
+                    compress(
+                        indices,
+                        compression,
+                        PixelFormat::Palette8.bytes_per_pixel(),
+                    ),
+                ),
+            ],
+        }
+    }
+
+    /// Stores an animation of `frames`, which all have to be `width`x`height`. A single frame
+    /// gives a plain image. `loops` is how often the animation plays, 0 for forever. Frames that
+    /// change only part of the one before, like most of a screen recording, store just that part.
+    pub fn from_frames(
+        width: u32,
+        height: u32,
+        frames: &[Frame],
+        loops: u32,
+        compression: Compression,
+    ) -> Self {
+        let opaque = frames
+            .iter()
+            .all(|frame| frame.rgba.chunks(4).all(|pixel| pixel[3] == 255));
+        let format = if opaque {
+            PixelFormat::Rgb8
+        } else {
+            PixelFormat::Rgba8
+        };
+
+        let mut file = Self::encode(width, height, &frames[0].rgba, format, compression).unwrap();
+        if frames.len() > 1 {
+            let mut animation = loops.to_le_bytes().to_vec();
+            animation.extend_from_slice(&frames[0].delay_ms.to_le_bytes());
+            file.chunks.push(Chunk::new(ANIMATION, animation));
+        }
+        for (previous, frame) in frames.iter().zip(&frames[1..]) {
+            let chunk =
+                Self::frame_chunk(&previous.rgba, frame, width, height, format, compression);
+            file.chunks.push(chunk.unwrap());
+        }
+        file
+    }
+
+    /// `frame` following the RGBA pixels `previous` as a `DFRM` chunk, or as a `FRAM` chunk if
+    /// all of it changed. `None` if `format` can't hold its pixels.
+    fn frame_chunk(
+        previous: &[u8],
+        frame: &Frame,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        compression: Compression,
+    ) -> Option<Chunk> {
+        let mut data = frame.delay_ms.to_le_bytes().to_vec();
+        let [x, y, rect_width, rect_height] = changed_rect(previous, &frame.rgba, width);
+        if rect_width == width && rect_height == height {
+            let pixels = Self::encode(width, height, &frame.rgba, format, compression)?;
+            data.extend_from_slice(&pixels.chunk(PIXELS).unwrap().data);
+            return Some(Chunk::new(FRAME, data));
+        }
+
+        for value in [x, y, rect_width, rect_height] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        if rect_width > 0 {
+            let row_size = rect_width as usize * 4;
+            let rect: Vec<u8> = frame
+                .rgba
+                .chunks_exact(width as usize * 4)
+                .skip(y as usize)
+                .take(rect_height as usize)
+                .flat_map(|row| &row[x as usize * 4..][..row_size])
+                .copied()
+                .collect();
+            let pixels = Self::encode(rect_width, rect_height, &rect, format, compression)?;
+            data.extend_from_slice(&pixels.chunk(PIXELS).unwrap().data);
+        }
+        Some(Chunk::new(DELTA_FRAME, data))
+    }
+
+    /// Adds `frame` to the end of the animation, which has to be as big as the image. A still
+    /// image becomes the first frame, shown as long as `frame`. Frames that the pixel format can't
+    /// hold, like transparent ones in an RGB8 file, have every frame stored again in one that can.
+    pub fn push_frame(&mut self, frame: Frame, max_pixels: u64) -> Result<(), DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        if self.header.tiled || self.header.interlaced || self.chunk(STRIDE).is_some() {
+            return Err(invalid(
+                "Tiled and interlaced files and those with padded rows can't be animated",
+            ));
+        }
+        if self.header.format.is_float() || self.header.lossy {
+            return Err(invalid("Float and lossy images can't be animated"));
+        }
+        let (width, height) = (self.header.width, self.header.height);
+        if frame.rgba.len() != width as usize * height as usize * 4 {
+            return Err(invalid(format!(
+                "Frames have to be {}x{} like the image",
+                width, height
+            )));
+        }
+
+        let animated = self.is_animated();
+        let mut frames = self.frames(max_pixels)?;
+        if !animated {
+            frames[0].delay_ms = frame.delay_ms;
+        }
+        // Stored the way `expand` turns back into RGBA.
+        let stored = |rgba: &[u8]| {
+            let mut rgba = rgba.to_vec();
+            if self.header.bgr {
+                swap_red_blue(&mut rgba);
+            }
+            rgba
+        };
+        let chunk = match self.header.format {
+            // A palette of its own wouldn't match the file's.
+            PixelFormat::Palette8 => None,
+            format => Self::frame_chunk(
+                &stored(&frames.last().unwrap().rgba),
+                &Frame {
+                    rgba: stored(&frame.rgba),
+                    delay_ms: frame.delay_ms,
+                },
+                width,
+                height,
+                format,
+                self.header.compression,
+            ),
+        };
+
+        let animation = [PALETTE, PIXELS, ANIMATION, FRAME, DELTA_FRAME];
+        let Some(chunk) = chunk else {
+            frames.push(frame);
+            let rebuilt = Self::from_frames(
+                width,
+                height,
+                &frames,
+                self.loops(),
+                self.header.compression,
+            );
+            // Where the pixels were, whatever came before or after them stays there.
+            let at = self
+                .chunks
+                .iter()
+                .position(|chunk| animation.contains(&chunk.tag))
+                .unwrap_or(self.chunks.len());
+            self.chunks.retain(|chunk| !animation.contains(&chunk.tag));
+            self.chunks.splice(at..at, rebuilt.chunks);
+            self.header.format = rebuilt.header.format;
+            self.header.bgr = false;
+            return Ok(());
+        };
+
+        let mut last = self
+            .chunks
+            .iter()
+            .rposition(|chunk| animation.contains(&chunk.tag))
+            .ok_or_else(|| invalid("Missing PXLS chunk"))?;
+        if !animated {
+            let mut data = 0u32.to_le_bytes().to_vec();
+            data.extend_from_slice(&frame.delay_ms.to_le_bytes());
+            last += 1;
+            self.chunks.insert(last, Chunk::new(ANIMATION, data));
+        }
+        self.chunks.insert(last + 1, chunk);
+        Ok(())
+    }
+
+    /// Reads just the header, without looking at the chunks.
+    pub fn parse_header(bytes: &[u8]) -> Result<Header, DecodeError> {
+        if !bytes.starts_with(MAGIC) && !MAGIC.starts_with(bytes) {
+            return Err(invalid("Missing BRUH magic"));
+        }
+        ensure_available(bytes, 0, BRUH_HEADER_SIZE, "the header")?;
+
+        let version = bytes[4];
+        if version != VERSION {
+            return Err(invalid(format!("Unsupported BRUH version {}", version)));
+        }
+        let format = PixelFormat::from_u8(bytes[5])
+            .ok_or_else(|| invalid(format!("Unknown pixel format {}", bytes[5])))?;
+        let compression = Compression::from_u8(bytes[6])
+            .ok_or_else(|| invalid(format!("Unknown compression {}", bytes[6])))?;
+        if bytes[7] & !KNOWN_FLAGS != 0 {
+            return Err(invalid(format!("Unknown header flags {:#04x}", bytes[7])));
+        }
+
+        Ok(Header {
+            version,
+            format,
+            compression,
+            width: read_u32_le(bytes, 8),
+            height: read_u32_le(bytes, 12),
+            encrypted: bytes[7] & FLAG_ENCRYPTED != 0,
+            tiled: bytes[7] & FLAG_TILED != 0,
+            interlaced: bytes[7] & FLAG_INTERLACED != 0,
+            linear: bytes[7] & FLAG_LINEAR != 0,
+            bgr: bytes[7] & FLAG_BGR != 0,
+            lossy: bytes[7] & FLAG_LOSSY != 0,
+        })
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let header = Self::parse_header(bytes)?;
+        // Every image has at least its PXLS chunk, even if it is empty.
+        ensure_available(bytes, BRUH_HEADER_SIZE, 12, "the pixel data")?;
+
+        let mut chunks = Vec::new();
+        let mut offset = BRUH_HEADER_SIZE;
+        while offset < bytes.len() {
+            ensure_available(bytes, offset, 8, format!("the chunk header at {}", offset))?;
+
+            let tag: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+            let length = read_u32_le(bytes, offset + 4) as usize;
+            let start = offset + 8;
+            ensure_available(
+                bytes,
+                start,
+                length.saturating_add(4),
+                format!("chunk {}", String::from_utf8_lossy(&tag)),
+            )?;
+
+            let chunk = Chunk::new(tag, bytes[start..start + length].to_vec());
+            if chunk.crc() != read_u32_le(bytes, start + length) {
+                return Err(invalid(format!(
+                    "Checksum mismatch in chunk {}",
+                    String::from_utf8_lossy(&tag)
+                )));
+            }
+
+            trace!(
+                "chunk {} at offset {}, {} bytes",
+                String::from_utf8_lossy(&tag),
+                offset,
+                length
+            );
+            chunks.push(chunk);
+            offset = start + length + 4;
+        }
+
+        Ok(Self { header, chunks })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            BRUH_HEADER_SIZE + self.chunks.iter().map(|c| c.data.len() + 12).sum::<usize>(),
+        );
+
+        bytes.extend_from_slice(&self.header.to_bytes());
+
+        for chunk in &self.chunks {
+            bytes.extend_from_slice(&chunk.tag);
+            bytes.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&chunk.data);
+            bytes.extend_from_slice(&chunk.crc().to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Drops every ancillary chunk, returning how many were removed.
+    pub fn strip(&mut self) -> usize {
+        let before = self.chunks.len();
+        self.chunks.retain(Chunk::is_critical);
+        before - self.chunks.len()
+    }
+
+    /// The key/value pairs of the `meta` chunk, in the order they are stored. Encrypted files
+    /// have theirs sealed, so there are none until they are decrypted.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let Some(chunk) = self.chunk(METADATA).filter(|_| !self.header.encrypted) else {
+            return Vec::new();
+        };
+
+        let mut fields = chunk
+            .data
+            .split(|&b| b == 0)
+            .map(|field| String::from_utf8_lossy(field).into_owned());
+        let mut entries = Vec::new();
+        while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+            entries.push((key, value));
+        }
+        entries
+    }
+
+    pub fn metadata_value(&self, key: &str) -> Option<String> {
+        self.metadata()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Replaces the `meta` chunk with `entries`, dropping it if there are none. The chunk is kept
+    /// in front of the pixels so readers find it without going through the whole file.
+    pub fn set_metadata(&mut self, entries: &[(String, String)]) {
+        let position = self.chunks.iter().position(|chunk| chunk.tag == METADATA);
+        self.chunks.retain(|chunk| chunk.tag != METADATA);
+        if entries.is_empty() {
+            return;
+        }
+
+        let mut data = Vec::new();
+        for (key, value) in entries {
+            data.extend_from_slice(key.as_bytes());
+            data.push(0);
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+        }
+        self.chunks
+            .insert(position.unwrap_or(0), Chunk::new(METADATA, data));
+    }
+
+    /// Sets `key` to `value`, or removes it if `value` is `None`.
+    pub fn set_metadata_value(&mut self, key: &str, value: Option<&str>) {
+        let mut entries = self.metadata();
+        let existing = entries.iter().position(|(k, _)| k == key);
+
+        match (existing, value) {
+            (Some(i), Some(value)) => entries[i].1 = value.to_owned(),
+            (None, Some(value)) => entries.push((key.to_owned(), value.to_owned())),
+            (Some(i), None) => {
+                entries.remove(i);
+            }
+            (None, None) => {}
+        }
+        self.set_metadata(&entries);
+    }
+
+    /// Embeds `thumbnail` right after the header, replacing any earlier one.
+    pub fn set_thumbnail(&mut self, thumbnail: &BruhFile) {
+        self.chunks.retain(|chunk| chunk.tag != THUMBNAIL);
+        self.chunks
+            .insert(0, Chunk::new(THUMBNAIL, thumbnail.to_bytes()));
+    }
+
+    /// Width, height and straight RGBA pixels of the embedded thumbnail, if there is one.
+    pub fn thumbnail(&self, max_pixels: u64) -> Result<Option<(u32, u32, Vec<u8>)>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        self.chunk(THUMBNAIL)
+            .map(|chunk| decode_embedded(&chunk.data, max_pixels))
+            .transpose()
+    }
+
+    /// Horizontal and vertical pixels per inch, if the file says how big it is printed.
+    pub fn pixels_per_inch(&self) -> Option<(u32, u32)> {
+        let data = &self.chunk(PHYSICAL_SIZE)?.data;
+        if data.len() != 8 || self.header.encrypted {
+            return None;
+        }
+        Some((read_u32_le(data, 0), read_u32_le(data, 4)))
+    }
+
+    /// Sets the pixels per inch, or removes them with `None`.
+    pub fn set_pixels_per_inch(&mut self, density: Option<(u32, u32)>) {
+        self.chunks.retain(|chunk| chunk.tag != PHYSICAL_SIZE);
+        let Some((x, y)) = density else {
+            return;
+        };
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == PIXELS)
+            .unwrap_or(self.chunks.len());
+        let data = [x.to_le_bytes(), y.to_le_bytes()].concat();
+        self.chunks
+            .insert(position, Chunk::new(PHYSICAL_SIZE, data));
+    }
+
+    /// The EXIF data, if there is any and the file isn't encrypted.
+    pub fn exif(&self) -> Option<&[u8]> {
+        self.chunk(EXIF)
+            .filter(|_| !self.header.encrypted)
+            .map(|chunk| &chunk.data[..])
+    }
+
+    /// Replaces the EXIF data, or removes it with `None`.
+    pub fn set_exif(&mut self, exif: Option<Vec<u8>>) {
+        self.chunks.retain(|chunk| chunk.tag != EXIF);
+        let Some(exif) = exif else {
+            return;
+        };
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == PIXELS)
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(position, Chunk::new(EXIF, exif));
+    }
+
+    /// Stores `levels` as the mipmaps of the image, replacing any earlier ones. The first level
+    /// should be half the size of the image, the next half of that and so on.
+    pub fn set_mipmaps(&mut self, levels: &[BruhFile]) {
+        self.chunks.retain(|chunk| chunk.tag != MIPMAP);
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == PIXELS)
+            .map_or(self.chunks.len(), |i| i + 1);
+        let chunks = levels
+            .iter()
+            .map(|level| Chunk::new(MIPMAP, level.to_bytes()));
+        self.chunks.splice(position..position, chunks);
+    }
+
+    /// How many images a container holds, counting the file itself as image 0.
+    pub fn image_count(&self) -> usize {
+        1 + self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.tag == IMAGE)
+            .count()
+    }
+
+    /// Image `index` of a container as a file of its own, or `None` if there aren't that many.
+    /// Image 0 is the file itself without the other images.
+    pub fn image(&self, index: usize) -> Result<Option<BruhFile>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        if index == 0 {
+            let mut first = self.clone();
+            first.chunks.retain(|chunk| chunk.tag != IMAGE);
+            return Ok(Some(first));
+        }
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.tag == IMAGE)
+            .nth(index - 1)
+            .map(|chunk| BruhFile::parse(&chunk.data))
+            .transpose()
+    }
+
+    /// Adds `image` to the end of the container. Images that are containers themselves add all
+    /// of their images.
+    pub fn add_image(&mut self, image: &BruhFile) {
+        let images = (0..image.image_count()).filter_map(|index| image.image(index).ok().flatten());
+        let chunks: Vec<Chunk> = images
+            .map(|image| Chunk::new(IMAGE, image.to_bytes()))
+            .collect();
+        self.chunks.extend(chunks);
+    }
+
+    /// The layers of a layered image, bottom first. `PXLS` holds the visible ones composited,
+    /// so readers that don't know about layers still show the whole image.
+    pub fn layers(&self) -> Result<Vec<Layer>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.tag == LAYER)
+            .map(|chunk| Layer::parse(&chunk.data))
+            .collect()
+    }
+
+    /// Stores `layers`, bottom first, replacing any earlier ones. The pixels are left alone, so
+    /// they should be replaced with the composited layers too.
+    pub fn set_layers(&mut self, layers: &[Layer]) {
+        self.chunks.retain(|chunk| chunk.tag != LAYER);
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == PIXELS)
+            .map_or(self.chunks.len(), |i| i + 1);
+        let chunks = layers
+            .iter()
+            .map(|layer| Chunk::new(LAYER, layer.to_bytes()));
+        self.chunks.splice(position..position, chunks);
+    }
+
+    /// How many mipmap levels there are, counting the full image as level 0.
+    pub fn mip_levels(&self) -> usize {
+        1 + self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.tag == MIPMAP)
+            .count()
+    }
+
+    /// Width, height and straight RGBA pixels of mipmap `level`, where level 0 is the image
+    /// itself, or `None` if the file has fewer levels.
+    pub fn mip_level(
+        &self,
+        level: usize,
+        max_pixels: u64,
+    ) -> Result<Option<(u32, u32, Vec<u8>)>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        if level == 0 {
+            let rgba = self.to_rgba(max_pixels)?;
+            return Ok(Some((self.header.width, self.header.height, rgba)));
+        }
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.tag == MIPMAP)
+            .nth(level - 1)
+            .map(|chunk| decode_embedded(&chunk.data, max_pixels))
+            .transpose()
+    }
+
+    pub fn chunk(&self, tag: [u8; 4]) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| chunk.tag == tag)
+    }
+
+    /// The decompressed contents of the `PXLS` chunk, checked against the header dimensions.
+    /// Images with more than `max_pixels` pixels are refused before anything is allocated. The
+    /// pixels stay in the header's format and channel order.
+    pub fn pixel_data(&self, max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        if self.header.tiled {
+            check_dimensions(self.header.width, self.header.height, max_pixels)?;
+            return self.region_pixels(0, 0, self.header.width, self.header.height, max_pixels);
+        }
+        let pixels = &self
+            .chunk(PIXELS)
+            .ok_or_else(|| invalid("Missing PXLS chunk"))?
+            .data;
+        if self.header.interlaced {
+            return deinterlace(&self.header, pixels, max_pixels);
+        }
+        if self.header.lossy {
+            return decode_lossy(&self.header, pixels, max_pixels);
+        }
+        self.decompress_pixels(pixels, "PXLS", max_pixels)
+    }
+
+    fn decompress_pixels(
+        &self,
+        pixels: &[u8],
+        chunk: &str,
+        max_pixels: u64,
+    ) -> Result<Vec<u8>, DecodeError> {
+        let format = self.header.format;
+        check_dimensions(self.header.width, self.header.height, max_pixels)?;
+        let stride = self.stride()?;
+        let expected = byte_size(stride, self.header.height as usize, 1)?;
+        let pixels = decompress(
+            pixels,
+            self.header.compression,
+            format.bytes_per_pixel(),
+            expected,
+        )?;
+        if pixels.len() != expected {
+            return Err(invalid(format!(
+                "{} chunk holds {} bytes, expected {} for a {}x{} {} image",
+                chunk,
+                pixels.len(),
+                expected,
+                self.header.width,
+                self.header.height,
+                format.name()
+            )));
+        }
+
+        let row_size = self.header.width as usize * format.bytes_per_pixel();
+        if stride == row_size {
+            return Ok(pixels);
+        }
+        Ok(pixels
+            .chunks(stride)
+            .flat_map(|row| &row[..row_size])
+            .copied()
+            .collect())
+    }
+
+    /// Splits the pixels into `tile_width`x`tile_height` tiles that are compressed on their own.
+    pub fn tile(
+        &mut self,
+        tile_width: u32,
+        tile_height: u32,
+        max_pixels: u64,
+    ) -> Result<(), DecodeError> {
+        if self.is_animated() {
+            return Err(invalid("Animations can't be tiled"));
+        }
+        if tile_width == 0 || tile_height == 0 {
+            return Err(invalid("Tiles can't be empty"));
+        }
+        let pixels = self.pixel_data(max_pixels)?;
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+        let (tile_width, tile_height) = (tile_width as usize, tile_height as usize);
+
+        let mut index = (tile_width as u32).to_le_bytes().to_vec();
+        index.extend_from_slice(&(tile_height as u32).to_le_bytes());
+        let mut data = Vec::new();
+        for top in (0..height).step_by(tile_height) {
+            for left in (0..width).step_by(tile_width) {
+                index.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                let right = (left + tile_width).min(width);
+                let tile: Vec<u8> = (top..(top + tile_height).min(height))
+                    .flat_map(|y| {
+                        &pixels[(y * width + left) * pixel_size..(y * width + right) * pixel_size]
+                    })
+                    .copied()
+                    .collect();
+                data.extend(compress(&tile, self.header.compression, pixel_size));
+            }
+        }
+
+        self.chunks
+            .retain(|chunk| chunk.tag != TILE_INDEX && chunk.tag != STRIDE);
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == PIXELS)
+            .ok_or_else(|| invalid("Missing PXLS chunk"))?;
+        self.chunks[position].data = data;
+        self.chunks.insert(position, Chunk::new(TILE_INDEX, index));
+        self.header.tiled = true;
+        // The tiles hold the decoded pixels exactly.
+        self.header.lossy = false;
+        self.header.interlaced = false;
+        Ok(())
+    }
+
+    /// Stores the pixels in Adam7 passes, so they can be shown progressively while the file
+    /// loads. Replaces a tiled layout.
+    pub fn interlace(&mut self, max_pixels: u64) -> Result<(), DecodeError> {
+        if self.is_animated() {
+            return Err(invalid("Animations can't be interlaced"));
+        }
+        let pixels = self.pixel_data(max_pixels)?;
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+
+        let mut data = Vec::new();
+        for &(left, top, step_x, step_y) in &ADAM7 {
+            let pass: Vec<u8> = (top..height)
+                .step_by(step_y)
+                .flat_map(|y| (left..width).step_by(step_x).map(move |x| y * width + x))
+                .flat_map(|i| &pixels[i * pixel_size..(i + 1) * pixel_size])
+                .copied()
+                .collect();
+            let compressed = compress(&pass, self.header.compression, pixel_size);
+            data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            data.extend(compressed);
+        }
+
+        self.chunks
+            .retain(|chunk| chunk.tag != TILE_INDEX && chunk.tag != STRIDE);
+        self.chunks
+            .iter_mut()
+            .find(|chunk| chunk.tag == PIXELS)
+            .ok_or_else(|| invalid("Missing PXLS chunk"))?
+            .data = data;
+        self.header.tiled = false;
+        self.header.interlaced = true;
+        // The passes hold the decoded pixels exactly.
+        self.header.lossy = false;
+        Ok(())
+    }
+
+    /// Bytes from the start of one row of pixels to the next. Rows are packed unless
+    /// `align_rows` padded them.
+    pub fn stride(&self) -> Result<usize, DecodeError> {
+        row_stride(
+            &self.header,
+            self.chunk(STRIDE).map(|chunk| &chunk.data[..]),
+        )
+    }
+
+    /// Pads every row of the image and of its frames to a multiple of `alignment` bytes, so the
+    /// decompressed pixels can be handed to APIs that want aligned rows without copying them.
+    /// Aligning to 1 removes the padding again. Tiled and interlaced files have no rows to pad,
+    /// and RLE packs whole pixels, so those are refused.
+    pub fn align_rows(&mut self, alignment: usize, max_pixels: u64) -> Result<(), DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        if self.header.tiled || self.header.interlaced || self.header.lossy {
+            return Err(invalid(
+                "Only untiled, uninterlaced, lossless files can pad their rows",
+            ));
+        }
+        if !alignment.is_power_of_two() || alignment > MAX_ROW_PADDING + 1 {
+            return Err(invalid(
+                "Rows can be aligned to powers of two up to 256 bytes",
+            ));
+        }
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let row_size = self.header.width as usize * pixel_size;
+        let stride = row_size.div_ceil(alignment) * alignment;
+        if stride != row_size && self.header.compression == Compression::Rle {
+            return Err(invalid("RLE files can't pad their rows"));
+        }
+        let stride_bytes =
+            u32::try_from(stride).map_err(|_| invalid("The rows are too wide to pad"))?;
+
+        let pad = |pixels: Vec<u8>| -> Vec<u8> {
+            if stride == row_size || row_size == 0 {
+                return pixels;
+            }
+            pixels
+                .chunks(row_size)
+                .flat_map(|row| {
+                    row.iter()
+                        .copied()
+                        .chain(iter::repeat_n(0, stride - row_size))
+                })
+                .collect()
+        };
+        let mut padded = Vec::new();
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            if chunk.tag == PIXELS {
+                let pixels = self.decompress_pixels(&chunk.data, "PXLS", max_pixels)?;
+                padded.push((
+                    index,
+                    compress(&pad(pixels), self.header.compression, pixel_size),
+                ));
+            } else if chunk.tag == FRAME {
+                if chunk.data.len() < 4 {
+                    return Err(invalid("FRAM chunk is too short"));
+                }
+                let pixels = self.decompress_pixels(&chunk.data[4..], "FRAM", max_pixels)?;
+                let mut data = chunk.data[..4].to_vec();
+                data.extend(compress(&pad(pixels), self.header.compression, pixel_size));
+                padded.push((index, data));
+            }
+        }
+        for (index, data) in padded {
+            self.chunks[index].data = data;
+        }
+
+        self.chunks.retain(|chunk| chunk.tag != STRIDE);
+        if stride != row_size {
+            let position = self
+                .chunks
+                .iter()
+                .position(|chunk| chunk.tag == PIXELS)
+                .ok_or_else(|| invalid("Missing PXLS chunk"))?;
+            self.chunks.insert(
+                position,
+                Chunk::new(STRIDE, stride_bytes.to_le_bytes().to_vec()),
+            );
+        }
+        Ok(())
+    }
+
+    /// The tile width and height of tiled images.
+    pub fn tile_size(&self) -> Option<(u32, u32)> {
+        let index = &self.chunk(TILE_INDEX)?.data;
+        (self.header.tiled && index.len() >= 8)
+            .then(|| (read_u32_le(index, 0), read_u32_le(index, 4)))
+    }
+
+    /// Straight RGBA pixels of the `width`x`height` rectangle at `x`,`y`. Tiled images only
+    /// decompress the tiles covering it, and only the rectangle counts against `max_pixels`.
+    pub fn region(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    ) -> Result<Vec<u8>, DecodeError> {
+        self.expand(self.region_pixels(x, y, width, height, max_pixels)?)
+    }
+
+    fn region_pixels(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    ) -> Result<Vec<u8>, DecodeError> {
+        if self.header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+        let rect = check_region(&self.header, x, y, width, height, max_pixels)?;
+        let [x, y, width, height] = rect;
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let image_width = self.header.width as usize;
+
+        if !self.header.tiled {
+            let pixels = self.pixel_data(max_pixels)?;
+            let row = |y: usize| (y * image_width + x) * pixel_size;
+            return Ok((y..y + height)
+                .flat_map(|y| &pixels[row(y)..row(y) + width * pixel_size])
+                .copied()
+                .collect());
+        }
+
+        let index = &self
+            .chunk(TILE_INDEX)
+            .ok_or_else(|| invalid("Missing TIDX chunk"))?
+            .data;
+        let data = &self
+            .chunk(PIXELS)
+            .ok_or_else(|| invalid("Missing PXLS chunk"))?
+            .data;
+        assemble_tiles(&self.header, index, data.len(), rect, |start, end| {
+            Ok(Cow::Borrowed(&data[start..end]))
+        })
+    }
+
+    /// Expands the pixels into straight RGBA, 4 bytes per pixel. Animations give their first
+    /// frame.
+    pub fn to_rgba(&self, max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+        self.expand(self.pixel_data(max_pixels)?)
+    }
+
+    /// The image in straight RGBA with a float per channel, 0 to 1 for the 8 bit formats. Float
+    /// files keep colors brighter than 1, everything else gives what `to_rgba` does.
+    pub fn to_rgba_f32(&self, max_pixels: u64) -> Result<Vec<f32>, DecodeError> {
+        if !self.header.format.is_float() {
+            let rgba = self.to_rgba(max_pixels)?;
+            return Ok(rgba.iter().map(|&c| c as f32 / 255.0).collect());
+        }
+        let mut rgba = decode_floats(&self.pixel_data(max_pixels)?, self.header.format);
+        if self.header.bgr {
+            for pixel in rgba.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// The image in straight BGRA. RGBA8 files with `FLAG_BGR` already store that, so their
+    /// pixels come out without touching them.
+    pub fn to_bgra(&self, max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+        if self.header.bgr && self.header.format == PixelFormat::Rgba8 {
+            return self.pixel_data(max_pixels);
+        }
+        let mut bgra = self.to_rgba(max_pixels)?;
+        swap_red_blue(&mut bgra);
+        Ok(bgra)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.chunk(ANIMATION).is_some()
+    }
+
+    /// Every frame in straight RGBA. A still image is a single frame without delay.
+    pub fn frames(&self, max_pixels: u64) -> Result<Vec<Frame>, DecodeError> {
+        let parts = (0..self.frame_count())
+            .map(|index| self.frame_part(index, max_pixels))
+            .collect::<Result<_, _>>()?;
+        self.join_frames(parts)
+    }
+
+    /// Frame `index` as far as it can be decoded without the frames before it, which is all of
+    /// it unless it is a `DFRM` chunk. The parts don't depend on each other, so they can be
+    /// decoded in any order or at the same time and given to `join_frames` afterwards.
+    pub fn frame_part(&self, index: usize, max_pixels: u64) -> Result<FramePart, DecodeError> {
+        if index == 0 {
+            let delay_ms = match self.chunk(ANIMATION) {
+                Some(animation) if animation.data.len() < 8 => {
+                    return Err(invalid("ANIM chunk is too short"))
+                }
+                Some(animation) => read_u32_le(&animation.data, 4),
+                None => 0,
+            };
+            return Ok(FramePart {
+                delay_ms,
+                pixels: FramePixels::Full(self.to_rgba(max_pixels)?),
+            });
+        }
+        let chunk = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.tag == FRAME || chunk.tag == DELTA_FRAME)
+            .nth(index - 1)
+            .ok_or_else(|| invalid(format!("There is no frame {}", index)))?;
+        if chunk.data.len() < 4 {
+            return Err(invalid(format!(
+                "{} chunk is too short",
+                String::from_utf8_lossy(&chunk.tag)
+            )));
+        }
+        let pixels = if chunk.tag == FRAME {
+            let pixels = self.decompress_pixels(&chunk.data[4..], "FRAM", max_pixels)?;
+            FramePixels::Full(self.expand(pixels)?)
+        } else {
+            self.delta(&chunk.data[4..])?
+        };
+        Ok(FramePart {
+            delay_ms: read_u32_le(&chunk.data, 0),
+            pixels,
+        })
+    }
+
+    /// The frames from the `frame_part` of each, in order.
+    pub fn join_frames(&self, parts: Vec<FramePart>) -> Result<Vec<Frame>, DecodeError> {
+        let mut frames: Vec<Frame> = Vec::with_capacity(parts.len());
+        for part in parts {
+            let rgba = match (part.pixels, frames.last()) {
+                (FramePixels::Full(rgba), _) => rgba,
+                (FramePixels::Delta(rect, pixels), Some(previous)) => {
+                    let mut rgba = previous.rgba.clone();
+                    let [x, y, width, _] = rect;
+                    let image_row = self.header.width as usize * 4;
+                    // An empty rectangle has no pixels and repeats the frame before.
+                    for (row, pixels) in pixels.chunks_exact(width.max(1) * 4).enumerate() {
+                        let start = (y + row) * image_row + x * 4;
+                        rgba[start..start + width * 4].copy_from_slice(pixels);
+                    }
+                    rgba
+                }
+                (FramePixels::Delta(..), None) => {
+                    return Err(invalid("The first frame can't be a DFRM chunk"))
+                }
+            };
+            frames.push(Frame {
+                rgba,
+                delay_ms: part.delay_ms,
+            });
+        }
+        Ok(frames)
+    }
+
+    /// The rectangle of a `DFRM` chunk and its pixels in straight RGBA, `data` after its delay.
+    fn delta(&self, data: &[u8]) -> Result<FramePixels, DecodeError> {
+        if data.len() < 16 {
+            return Err(invalid("DFRM chunk is too short"));
+        }
+        let [x, y, width, height] = [0, 4, 8, 12].map(|offset| read_u32_le(data, offset) as u64);
+        if x + width > self.header.width as u64 || y + height > self.header.height as u64 {
+            return Err(invalid(format!(
+                "DFRM rectangle {},{} {}x{} reaches past the {}x{} image",
+                x, y, width, height, self.header.width, self.header.height
+            )));
+        }
+        // Within the image, so this can't overflow.
+        let rect = [x, y, width, height].map(|n| n as usize);
+        let [_, _, width, height] = rect;
+        if width == 0 || height == 0 {
+            return Ok(FramePixels::Delta(rect, Vec::new()));
+        }
+
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let expected = byte_size(width, height, pixel_size)?;
+        let pixels = decompress(&data[16..], self.header.compression, pixel_size, expected)?;
+        if pixels.len() != expected {
+            return Err(invalid(format!(
+                "DFRM chunk holds {} bytes, expected {} for a {}x{} rectangle",
+                pixels.len(),
+                expected,
+                width,
+                height
+            )));
+        }
+        Ok(FramePixels::Delta(rect, self.expand(pixels)?))
+    }
+
+    /// How many frames `frames` gives, without decoding them.
+    pub fn frame_count(&self) -> usize {
+        if !self.is_animated() {
+            return 1;
+        }
+        let later = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.tag == FRAME || chunk.tag == DELTA_FRAME)
+            .count();
+        1 + later
+    }
+
+    /// How often an animation plays, 0 for forever.
+    pub fn loops(&self) -> u32 {
+        self.chunk(ANIMATION)
+            .filter(|chunk| chunk.data.len() >= 4)
+            .map_or(0, |chunk| read_u32_le(&chunk.data, 0))
+    }
+
+    /// Turns pixels in the header's format into straight RGBA.
+    fn expand(&self, pixels: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
+        let palette = self.chunk(PALETTE).map(|chunk| &chunk.data[..]);
+        expand(&self.header, palette, pixels)
+    }
+}
+
+/// Random access to the bytes of a file, so `BruhDecoder` only has to read the parts it needs.
+pub trait Source {
+    /// The size of the file in bytes.
+    fn size(&self) -> u64;
+
+    /// Fills `buf` with the bytes at `offset`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), DecodeError>;
+}
+
+impl Source for &[u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), DecodeError> {
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        ensure_available(self, start, buf.len(), "the read")?;
+        buf.copy_from_slice(&self[start..start + buf.len()]);
+        Ok(())
+    }
+}
+
+/// How much `BruhDecoder` reads at once from compressed pixel data.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Decodes regions of a file without reading all of it. Only the header, the chunk headers, the
+/// palette and the tile index are read up front. Regions of tiled images read just the tiles
+/// covering them, uncompressed images just the rows, and compressed ones stop after the last
+/// row. The checksum of the pixel data can't be checked without reading all of it, so it isn't.
+pub struct BruhDecoder<S> {
+    source: S,
+    header: Header,
+    /// Offset and length of the `PXLS` data.
+    pixels: (u64, usize),
+    palette: Option<Vec<u8>>,
+    tile_index: Option<Vec<u8>>,
+    /// Offset and length of the `thmb` data.
+    thumbnail: Option<(u64, usize)>,
+    /// Offset and length of the data of every `mipl` chunk.
+    mipmaps: Vec<(u64, usize)>,
+    /// Bytes from one row of `PXLS` to the next.
+    stride: usize,
+    /// Offset and length of the data of every `imag` chunk.
+    images: Vec<(u64, usize)>,
+    /// Tag, offset and length of the data of every other ancillary chunk.
+    ancillary: Vec<([u8; 4], (u64, usize))>,
+}
+
+impl<S: Source> BruhDecoder<S> {
+    pub fn new(mut source: S) -> Result<Self, DecodeError> {
+        let size = source.size();
+        let mut bytes = vec![0; size.min(BRUH_HEADER_SIZE as u64) as usize];
+        source.read_at(0, &mut bytes)?;
+        let header = BruhFile::parse_header(&bytes)?;
+        if header.encrypted {
+            return Err(DecodeError::Encrypted);
+        }
+
+        let (mut pixels, mut palette, mut tile_index, mut thumbnail) = (None, None, None, None);
+        let mut stride = None;
+        let mut images = Vec::new();
+        let mut mipmaps = Vec::new();
+        let mut ancillary = Vec::new();
+        let mut offset = BRUH_HEADER_SIZE as u64;
+        while offset < size {
+            let available = usize::try_from(size - offset).unwrap_or(usize::MAX);
+            if available < 8 {
+                return Err(DecodeError::TruncatedFile {
+                    section: format!("the chunk header at {}", offset),
+                    needed: 8,
+                    available,
+                });
+            }
+            let mut chunk_header = [0; 8];
+            source.read_at(offset, &mut chunk_header)?;
+            let tag: [u8; 4] = chunk_header[..4].try_into().unwrap();
+            let length = read_u32_le(&chunk_header, 4) as usize;
+            if available - 8 < length.saturating_add(4) {
+                return Err(DecodeError::TruncatedFile {
+                    section: format!("chunk {}", String::from_utf8_lossy(&tag)),
+                    needed: length.saturating_add(4),
+                    available: available - 8,
+                });
+            }
+
+            let start = offset + 8;
+            if tag == PALETTE || tag == TILE_INDEX || tag == STRIDE {
+                let mut data = vec![0; length + 4];
+                source.read_at(start, &mut data)?;
+                let crc = read_u32_le(&data, length);
+                data.truncate(length);
+                let chunk = Chunk::new(tag, data);
+                if chunk.crc() != crc {
+                    return Err(invalid(format!(
+                        "Checksum mismatch in chunk {}",
+                        String::from_utf8_lossy(&tag)
+                    )));
+                }
+                if tag == PALETTE {
+                    palette = Some(chunk.data);
+                } else if tag == STRIDE {
+                    stride = Some(chunk.data);
+                } else {
+                    tile_index = Some(chunk.data);
+                }
+            } else if tag == PIXELS && pixels.is_none() {
+                pixels = Some((start, length));
+            } else if tag == THUMBNAIL && thumbnail.is_none() {
+                thumbnail = Some((start, length));
+            } else if tag == MIPMAP {
+                mipmaps.push((start, length));
+            } else if tag == IMAGE {
+                images.push((start, length));
+            } else if tag[0].is_ascii_lowercase() {
+                ancillary.push((tag, (start, length)));
+            }
+            offset = start + length as u64 + 4;
+        }
+
+        Ok(Self {
+            source,
+            pixels: pixels.ok_or_else(|| invalid("Missing PXLS chunk"))?,
+            stride: row_stride(&header, stride.as_deref())?,
+            header,
+            palette,
+            tile_index,
+            thumbnail,
+            mipmaps,
+            images,
+            ancillary,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The embedded thumbnail like `BruhFile::thumbnail`, reading only its chunk.
+    pub fn thumbnail(
+        &mut self,
+        max_pixels: u64,
+    ) -> Result<Option<(u32, u32, Vec<u8>)>, DecodeError> {
+        match self.thumbnail {
+            Some(location) => self
+                .read_embedded(THUMBNAIL, location, max_pixels)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        1 + self.mipmaps.len()
+    }
+
+    /// Mipmap `level` like `BruhFile::mip_level`. Levels other than 0 read only their chunk.
+    pub fn mip_level(
+        &mut self,
+        level: usize,
+        max_pixels: u64,
+    ) -> Result<Option<(u32, u32, Vec<u8>)>, DecodeError> {
+        if level == 0 {
+            let (width, height) = (self.header.width, self.header.height);
+            let rgba = self.decode_region(0, 0, width, height, max_pixels)?;
+            return Ok(Some((width, height, rgba)));
+        }
+        match self.mipmaps.get(level - 1) {
+            Some(&location) => self.read_embedded(MIPMAP, location, max_pixels).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn image_count(&self) -> usize {
+        1 + self.images.len()
+    }
+
+    /// Image `index` of a container in straight RGBA, like `BruhFile::image`. Images other than
+    /// 0 read only their chunk.
+    pub fn decode_image(
+        &mut self,
+        index: usize,
+        max_pixels: u64,
+    ) -> Result<Option<(u32, u32, Vec<u8>)>, DecodeError> {
+        if index == 0 {
+            let (width, height) = (self.header.width, self.header.height);
+            let rgba = self.decode_region(0, 0, width, height, max_pixels)?;
+            return Ok(Some((width, height, rgba)));
+        }
+        match self.images.get(index - 1) {
+            Some(&location) => self.read_embedded(IMAGE, location, max_pixels).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The ancillary chunks other than the thumbnail, the mipmaps and the images, the ones before
+    /// `PXLS` and the ones after it, reading only those chunks.
+    pub fn ancillary_chunks(&mut self) -> Result<(Vec<Chunk>, Vec<Chunk>), DecodeError> {
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        for (tag, location) in self.ancillary.clone() {
+            let chunk = self.read_chunk(tag, location)?;
+            match location.0 < self.pixels.0 {
+                true => before.push(chunk),
+                false => after.push(chunk),
+            }
+        }
+        Ok((before, after))
+    }
+
+    /// Reads and decodes the BRUH file held by the chunk with `tag` whose data is at `location`.
+    fn read_embedded(
+        &mut self,
+        tag: [u8; 4],
+        location: (u64, usize),
+        max_pixels: u64,
+    ) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+        let chunk = self.read_chunk(tag, location)?;
+        decode_embedded(&chunk.data, max_pixels)
+    }
+
+    /// Reads the chunk with `tag` whose data is at `location` and checks its checksum.
+    fn read_chunk(
+        &mut self,
+        tag: [u8; 4],
+        (offset, length): (u64, usize),
+    ) -> Result<Chunk, DecodeError> {
+        let mut data = vec![0; length + 4];
+        self.source.read_at(offset, &mut data)?;
+        let crc = read_u32_le(&data, length);
+        data.truncate(length);
+        let chunk = Chunk::new(tag, data);
+        if chunk.crc() != crc {
+            return Err(invalid(format!(
+                "Checksum mismatch in chunk {}",
+                String::from_utf8_lossy(&tag)
+            )));
+        }
+        Ok(chunk)
+    }
+
+    /// Straight RGBA pixels of the `width`x`height` rectangle at `x`,`y`, like
+    /// `BruhFile::region`. Only the rectangle counts against `max_pixels`, except for interlaced
+    /// and lossy files, which are decoded whole.
+    pub fn decode_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    ) -> Result<Vec<u8>, DecodeError> {
+        let rect = check_region(&self.header, x, y, width, height, max_pixels)?;
+        let pixels = if self.header.tiled {
+            let index = self
+                .tile_index
+                .as_deref()
+                .ok_or_else(|| invalid("Missing TIDX chunk"))?;
+            let (offset, length) = self.pixels;
+            let source = &mut self.source;
+            assemble_tiles(&self.header, index, length, rect, |start, end| {
+                let mut data = vec![0; end - start];
+                source.read_at(offset + start as u64, &mut data)?;
+                Ok(Cow::Owned(data))
+            })?
+        } else if self.header.interlaced || self.header.lossy {
+            // Every pass spreads over the whole image, so all of them are needed. Lossy pixels
+            // can't be decoded in pieces either.
+            let (offset, length) = self.pixels;
+            let mut data = vec![0; length];
+            self.source.read_at(offset, &mut data)?;
+            let pixels = if self.header.lossy {
+                decode_lossy(&self.header, &data, max_pixels)?
+            } else {
+                deinterlace(&self.header, &data, max_pixels)?
+            };
+            let [x, y, width, height] = rect;
+            let pixel_size = self.header.format.bytes_per_pixel();
+            let row = |y: usize| (y * self.header.width as usize + x) * pixel_size;
+            (y..y + height)
+                .flat_map(|y| &pixels[row(y)..row(y) + width * pixel_size])
+                .copied()
+                .collect()
+        } else {
+            self.linear_region(rect)?
+        };
+        expand(&self.header, self.palette.as_deref(), pixels)
+    }
+
+    fn linear_region(&mut self, [x, y, width, height]: [usize; 4]) -> Result<Vec<u8>, DecodeError> {
+        let pixel_size = self.header.format.bytes_per_pixel();
+        let row_size = self.stride;
+        let expected = byte_size(row_size, self.header.height as usize, 1)?;
+        let (offset, length) = self.pixels;
+        let mut out = Vec::with_capacity(byte_size(width, height, pixel_size)?);
+        if width == 0 || height == 0 {
+            return Ok(out);
+        }
+
+        if self.header.compression == Compression::None {
+            if length != expected {
+                return Err(invalid(format!(
+                    "PXLS chunk holds {} bytes, expected {}",
+                    length, expected
+                )));
+            }
+            let mut row = vec![0; width * pixel_size];
+            for py in y..y + height {
+                let start = py * row_size + x * pixel_size;
+                self.source.read_at(offset + start as u64, &mut row)?;
+                out.extend_from_slice(&row);
+            }
+            return Ok(out);
+        }
+
+        // Compressed rows have to be decompressed in order, but everything after the last row
+        // can be left alone.
+        let (left, right) = (x * pixel_size, (x + width) * pixel_size);
+        let end = (y + height) * row_size;
+        let mut position = 0;
+        let mut push = |bytes: &[u8]| {
+            let mut bytes = bytes;
+            while !bytes.is_empty() && position < end {
+                let column = position % row_size;
+                let take = (row_size - column).min(bytes.len());
+                if position / row_size >= y {
+                    let from = column.max(left);
+                    let to = (column + take).min(right);
+                    if from < to {
+                        out.extend_from_slice(&bytes[from - column..to - column]);
+                    }
+                }
+                position += take;
+                bytes = &bytes[take..];
+            }
+            position >= end
+        };
+
+        let mut reader = ChunkReader {
+            source: &mut self.source,
+            offset,
+            end: offset + length as u64,
+            buffer: Vec::new(),
+            start: 0,
+        };
+        let done = match self.header.compression {
+            Compression::Rle => {
+                let mut pixel = [0; 16];
+                let pixel = &mut pixel[..pixel_size];
+                let mut done = false;
+                while !done {
+                    let Some(control) = reader.byte()? else {
+                        break;
+                    };
+                    let control = control as usize;
+                    if control <= 127 {
+                        for _ in 0..=control {
+                            reader.fill(pixel)?;
+                            done |= push(pixel);
+                        }
+                    } else {
+                        reader.fill(pixel)?;
+                        for _ in 0..control - 126 {
+                            done |= push(pixel);
+                        }
+                    }
+                }
+                done
+            }
+            _ => {
+                let mut state = InflateState::new_boxed(DataFormat::Zlib);
+                let mut output = vec![0; READ_SIZE];
+                loop {
+                    let input = reader.buffered()?;
+                    let at_end = input.is_empty();
+                    let result = inflate(&mut state, input, &mut output, MZFlush::None);
+                    reader.consume(result.bytes_consumed);
+                    if push(&output[..result.bytes_written]) {
+                        break true;
+                    }
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => break false,
+                        Ok(_) | Err(MZError::Buf) => {}
+                        Err(_) => return Err(invalid("Invalid deflate data")),
+                    }
+                    if at_end && result.bytes_consumed == 0 && result.bytes_written == 0 {
+                        break false;
+                    }
+                }
+            }
+        };
+        if !done {
+            return Err(invalid(format!(
+                "PXLS chunk holds fewer than the {} bytes of a {}x{} {} image",
+                expected,
+                self.header.width,
+                self.header.height,
+                self.header.format.name()
+            )));
+        }
+        Ok(out)
+    }
+}
+
+/// The pixels of a file borrowed straight from its bytes, for readers that want to hand them on
+/// without copying, e.g. from a memory mapped file. Only uncompressed RGBA8 files whose rows lie
+/// one after the other in `PXLS` can be borrowed, any padding from `STRD` is skipped by `rows`.
+pub struct BruhImageRef<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from one row to the next.
+    pub stride: usize,
+    pixels: &'a [u8],
+}
+
+impl<'a> BruhImageRef<'a> {
+    /// The image in `bytes`, or `None` if its pixels have to be decoded first because they are
+    /// compressed, in another format, stored as BGRA, tiled, interlaced or lossy. Use
+    /// `BruhFile::to_rgba` for those. Unlike `BruhDecoder` the checksum of the pixels is checked.
+    pub fn parse(bytes: &'a [u8]) -> Result<Option<Self>, DecodeError> {
+        let decoder = BruhDecoder::new(bytes)?;
+        let header = &decoder.header;
+        if header.format != PixelFormat::Rgba8
+            || header.compression != Compression::None
+            || header.bgr
+            || header.tiled
+            || header.interlaced
+            || header.lossy
+        {
+            return Ok(None);
+        }
+
+        let (offset, length) = decoder.pixels;
+        let start = offset as usize;
+        let pixels = &bytes[start..start + length];
+        let crc = read_u32_le(bytes, start + length);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&PIXELS);
+        hasher.update(pixels);
+        if hasher.finalize() != crc {
+            return Err(invalid("Checksum mismatch in chunk PXLS"));
+        }
+
+        let expected = decoder.stride.checked_mul(header.height as usize);
+        if expected != Some(length) {
+            return Err(invalid(format!(
+                "PXLS holds {} bytes, expected {} rows of {}",
+                length, header.height, decoder.stride
+            )));
+        }
+        Ok(Some(Self {
+            width: header.width,
+            height: header.height,
+            stride: decoder.stride,
+            pixels,
+        }))
+    }
+
+    /// All the pixels in straight RGBA, or `None` if the rows are padded and have to be read
+    /// with `rows`.
+    pub fn as_rgba_slice(&self) -> Option<&'a [u8]> {
+        (self.stride == self.width as usize * 4).then_some(self.pixels)
+    }
+
+    /// Every row of pixels in straight RGBA, top to bottom, without its padding.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let row_size = self.width as usize * 4;
+        let (pixels, stride) = (self.pixels, self.stride);
+        (0..self.height as usize).map(move |row| &pixels[row * stride..][..row_size])
+    }
+}
+
+/// Reads a range of a `Source` in pieces of `READ_SIZE` bytes.
+struct ChunkReader<'a, S> {
+    source: &'a mut S,
+    /// Where `buffer` was read from.
+    offset: u64,
+    end: u64,
+    buffer: Vec<u8>,
+    /// How much of `buffer` was used up.
+    start: usize,
+}
+
+impl<S: Source> ChunkReader<'_, S> {
+    /// The unused part of the buffer, refilled if it is empty. Empty at the end of the range.
+    fn buffered(&mut self) -> Result<&[u8], DecodeError> {
+        if self.start == self.buffer.len() {
+            self.offset += self.buffer.len() as u64;
+            let size = (self.end - self.offset).min(READ_SIZE as u64) as usize;
+            self.buffer.resize(size, 0);
+            self.source.read_at(self.offset, &mut self.buffer)?;
+            self.start = 0;
+        }
+        Ok(&self.buffer[self.start..])
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.start += count;
+    }
+
+    fn byte(&mut self) -> Result<Option<u8>, DecodeError> {
+        let byte = self.buffered()?.first().copied();
+        self.consume(byte.is_some() as usize);
+        Ok(byte)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        for byte in buf {
+            *byte = self.byte()?.ok_or_else(|| invalid("RLE data is cut off"))?;
+        }
+        Ok(())
+    }
+}
+
+/// How many times at most `ProgressiveDecoder` gives the rows of a plain file that arrived so far,
+/// each of them expands the whole image again.
+const ROW_BATCHES: usize = 16;
+
+/// Decodes a file while it is still arriving, for viewers showing downloads. Interlaced files
+/// give a coarse image after their first pass and a sharper one after each of the next six. Plain
+/// files give the rows that arrived so far every sixteenth of their height, with the rest still
+/// transparent. Tiled and lossy files and those with padded rows give their image once all of
+/// its pixels are there.
+pub struct ProgressiveDecoder {
+    buffer: Vec<u8>,
+    max_pixels: u64,
+    header: Option<Header>,
+    /// The critical chunks before `PXLS`, like the palette.
+    chunks: Vec<Chunk>,
+    /// Where the next chunk starts while looking for `PXLS`.
+    offset: usize,
+    /// Start and length of the `PXLS` data, once its chunk header arrived.
+    pixels: Option<(usize, usize)>,
+    /// The passes decoded so far, in the header's pixel format.
+    image: Vec<u8>,
+    passes: usize,
+    /// Where the next pass starts in the `PXLS` data.
+    pass_offset: usize,
+    /// The rows of a plain file decompressed so far.
+    rows: Option<Rows>,
+    complete: bool,
+}
+
+/// Decompresses the `PXLS` data of a plain file as it arrives.
+struct Rows {
+    /// How much of the `PXLS` data was used.
+    consumed: usize,
+    inflate: Option<Box<InflateState>>,
+    /// The pixels so far, in the header's pixel format.
+    pixels: Vec<u8>,
+    /// How many rows the last image had.
+    shown: usize,
+}
+
+impl ProgressiveDecoder {
+    pub fn new(max_pixels: u64) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_pixels,
+            header: None,
+            chunks: Vec::new(),
+            offset: BRUH_HEADER_SIZE,
+            pixels: None,
+            image: Vec::new(),
+            passes: 0,
+            pass_offset: 0,
+            rows: None,
+            complete: false,
+        }
+    }
+
+    /// The header, once the first 16 bytes arrived.
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Whether the last image `push` returned is the exact one.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Adds the next `bytes` of the file, returning the image in straight RGBA whenever it got
+    /// sharper. Checksums are checked once a chunk is complete, so the coarse images of a
+    /// damaged file may show garbage before decoding fails.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Option<Vec<u8>>, DecodeError> {
+        if self.complete {
+            return Ok(None);
+        }
+        self.buffer.extend_from_slice(bytes);
+
+        let header = match &self.header {
+            Some(header) => header.clone(),
+            None => {
+                if is_legacy(&self.buffer) {
+                    return Err(invalid("Legacy files can't be decoded progressively"));
+                }
+                if self.buffer.len() < BRUH_HEADER_SIZE {
+                    return Ok(None);
+                }
+                let header = BruhFile::parse_header(&self.buffer)?;
+                if header.encrypted {
+                    return Err(DecodeError::Encrypted);
+                }
+                check_dimensions(header.width, header.height, self.max_pixels)?;
+                let pixel_size = header.format.bytes_per_pixel();
+                let size = byte_size(header.width as usize, header.height as usize, pixel_size)?;
+                self.image = vec![0; size];
+                self.header = Some(header.clone());
+                header
+            }
+        };
+
+        while self.pixels.is_none() && self.buffer.len() - self.offset >= 8 {
+            let tag: [u8; 4] = self.buffer[self.offset..self.offset + 4]
+                .try_into()
+                .unwrap();
+            let length = read_u32_le(&self.buffer, self.offset + 4) as usize;
+            let start = self.offset + 8;
+            if tag == PIXELS {
+                self.pixels = Some((start, length));
+                break;
+            }
+            if self.buffer.len() - start < length.saturating_add(4) {
+                return Ok(None);
+            }
+            let chunk = Chunk::new(tag, self.buffer[start..start + length].to_vec());
+            if chunk.crc() != read_u32_le(&self.buffer, start + length) {
+                return Err(invalid(format!(
+                    "Checksum mismatch in chunk {}",
+                    String::from_utf8_lossy(&tag)
+                )));
+            }
+            if chunk.is_critical() {
+                self.chunks.push(chunk);
+            }
+            self.offset = start + length + 4;
+        }
+        let Some((start, length)) = self.pixels else {
+            return Ok(None);
+        };
+
+        let end = start.saturating_add(length);
+        if self.buffer.len() >= end.saturating_add(4) {
+            // All the pixels are there, so decode them like those of any other file.
+            let pixels = Chunk::new(PIXELS, self.buffer[start..end].to_vec());
+            if pixels.crc() != read_u32_le(&self.buffer, end) {
+                return Err(invalid("Checksum mismatch in chunk PXLS"));
+            }
+            let mut chunks = core::mem::take(&mut self.chunks);
+            chunks.push(pixels);
+            self.complete = true;
+            self.buffer = Vec::new();
+            self.image = Vec::new();
+            self.rows = None;
+            return BruhFile { header, chunks }
+                .to_rgba(self.max_pixels)
+                .map(Some);
+        }
+        if !header.interlaced {
+            return self.push_rows(&header, start, end);
+        }
+
+        let before = self.passes;
+        let mut available = &self.buffer[start + self.pass_offset..];
+        while self.passes < ADAM7.len() {
+            let Some((pixels, used)) = read_pass(&header, available, self.passes)? else {
+                break;
+            };
+            place_pass(&header, &mut self.image, self.passes, &pixels, true);
+            self.passes += 1;
+            self.pass_offset += used;
+            available = &available[used..];
+        }
+        if self.passes == before {
+            return Ok(None);
+        }
+        let palette = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.tag == PALETTE)
+            .map(|chunk| &chunk.data[..]);
+        expand(&header, palette, self.image.clone()).map(Some)
+    }
+
+    /// Decompresses what arrived of the `PXLS` data between `start` and `end` of a plain file,
+    /// returning the rows so far once there are enough new ones.
+    fn push_rows(
+        &mut self,
+        header: &Header,
+        start: usize,
+        end: usize,
+    ) -> Result<Option<Vec<u8>>, DecodeError> {
+        if header.tiled || header.lossy || self.chunks.iter().any(|chunk| chunk.tag == STRIDE) {
+            return Ok(None);
+        }
+        let pixel_size = header.format.bytes_per_pixel();
+        let row_size = byte_size(header.width as usize, 1, pixel_size)?;
+        let expected = byte_size(row_size, header.height as usize, 1)?;
+        if expected == 0 {
+            return Ok(None);
+        }
+        let rows = self.rows.get_or_insert_with(|| Rows {
+            consumed: 0,
+            inflate: (header.compression == Compression::Deflate)
+                .then(|| InflateState::new_boxed(DataFormat::Zlib)),
+            pixels: Vec::new(),
+            shown: 0,
+        });
+        let mut available = &self.buffer[start + rows.consumed..end.min(self.buffer.len())];
+        let room = |pixels: &[u8]| expected - pixels.len().min(expected);
+        match header.compression {
+            Compression::None => {
+                let taken = available.len().min(room(&rows.pixels));
+                rows.pixels.extend_from_slice(&available[..taken]);
+                rows.consumed += available.len();
+            }
+            // Whole runs only, the rest waits for the bytes after it.
+            Compression::Rle => {
+                while let Some(&control) = available.first() {
+                    let control = control as usize;
+                    let (count, stored) = if control <= 127 {
+                        (control + 1, control + 1)
+                    } else {
+                        (control - 126, 1)
+                    };
+                    let Some(run) = available.get(1..1 + stored * pixel_size) else {
+                        break;
+                    };
+                    for _ in 0..count / stored {
+                        let taken = run.len().min(room(&rows.pixels));
+                        rows.pixels.extend_from_slice(&run[..taken]);
+                    }
+                    rows.consumed += 1 + run.len();
+                    available = &available[1 + run.len()..];
+                }
+            }
+            Compression::Deflate => {
+                let state = rows.inflate.as_mut().unwrap();
+                let mut output = vec![0; READ_SIZE];
+                loop {
+                    let result = inflate(state, available, &mut output, MZFlush::None);
+                    available = &available[result.bytes_consumed..];
+                    rows.consumed += result.bytes_consumed;
+                    let taken = result.bytes_written.min(room(&rows.pixels));
+                    rows.pixels.extend_from_slice(&output[..taken]);
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => break,
+                        Ok(_) | Err(MZError::Buf) => {}
+                        Err(_) => return Err(invalid("Invalid deflate data")),
+                    }
+                    if result.bytes_consumed == 0 && result.bytes_written == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // The last rows come with the whole image, once the checksum arrived.
+        let done = rows.pixels.len() / row_size;
+        let batch = (header.height as usize).div_ceil(ROW_BATCHES);
+        if done < rows.shown + batch || done == header.height as usize {
+            return Ok(None);
+        }
+        rows.shown = done;
+        let partial = Header {
+            height: done as u32,
+            ..header.clone()
+        };
+        let palette = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.tag == PALETTE)
+            .map(|chunk| &chunk.data[..]);
+        let mut rgba = expand(&partial, palette, rows.pixels[..done * row_size].to_vec())?;
+        rgba.resize(expected / pixel_size * 4, 0);
+        Ok(Some(rgba))
+    }
+}
+
+/// The pixels of a lossy file from its `PXLS` data, like those of a lossless one.
+fn decode_lossy(header: &Header, data: &[u8], max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+    check_dimensions(header.width, header.height, max_pixels)?;
+    if !matches!(
+        header.format,
+        PixelFormat::Rgb8 | PixelFormat::Rgba8 | PixelFormat::Gray8
+    ) {
+        return Err(invalid(format!(
+            "Lossy files can't be {}",
+            header.format.name()
+        )));
+    }
+    let (width, height) = (header.width as usize, header.height as usize);
+    let limit = lossy::max_size(width, height, header.format);
+    let data = decompress(data, header.compression, 1, limit)?;
+    lossy::decode(width, height, header.format, &data)
+}
+
+/// Decodes the BRUH file a `thmb` or `mipl` chunk holds.
+fn decode_embedded(data: &[u8], max_pixels: u64) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+    let thumbnail = BruhFile::parse(data)?;
+    let rgba = thumbnail.to_rgba(max_pixels)?;
+    Ok((thumbnail.header.width, thumbnail.header.height, rgba))
+}
+
+/// The width and height of an Adam7 pass of a `width`x`height` image.
+fn pass_size(width: usize, height: usize, pass: usize) -> (usize, usize) {
+    let (left, top, step_x, step_y) = ADAM7[pass];
+    (
+        width.saturating_sub(left).div_ceil(step_x),
+        height.saturating_sub(top).div_ceil(step_y),
+    )
+}
+
+/// Decompresses the Adam7 pass at the start of `data`, returning its pixels and the bytes it
+/// took, or `None` if `data` ends before the pass does.
+fn read_pass(
+    header: &Header,
+    data: &[u8],
+    pass: usize,
+) -> Result<Option<(Vec<u8>, usize)>, DecodeError> {
+    if data.len() < 4 {
+        return Ok(None);
+    }
+    let length = read_u32_le(data, 0) as usize;
+    if data.len() - 4 < length {
+        return Ok(None);
+    }
+
+    let pixel_size = header.format.bytes_per_pixel();
+    let (pass_width, pass_height) = pass_size(header.width as usize, header.height as usize, pass);
+    let expected = byte_size(pass_width, pass_height, pixel_size)?;
+    let pixels = decompress(
+        &data[4..4 + length],
+        header.compression,
+        pixel_size,
+        expected,
+    )?;
+    if pixels.len() != expected {
+        return Err(invalid(format!(
+            "Pass {} holds {} bytes, expected {}",
+            pass + 1,
+            pixels.len(),
+            expected
+        )));
+    }
+    Ok(Some((pixels, 4 + length)))
+}
+
+/// Puts the pixels of an Adam7 pass where they belong. With `fill`, each pixel also covers the
+/// block up to the pixels of the passes still missing, which gives the coarse preview.
+fn place_pass(header: &Header, image: &mut [u8], pass: usize, pixels: &[u8], fill: bool) {
+    let pixel_size = header.format.bytes_per_pixel();
+    let (width, height) = (header.width as usize, header.height as usize);
+    let (left, top, step_x, step_y) = ADAM7[pass];
+    // The block a pixel stands in for until the later passes arrive.
+    let (block_width, block_height) = if fill { ADAM7_BLOCKS[pass] } else { (1, 1) };
+
+    let mut pixels = pixels.chunks(pixel_size);
+    for y in (top..height).step_by(step_y) {
+        for x in (left..width).step_by(step_x) {
+            let pixel = pixels.next().unwrap();
+            for block_y in y..(y + block_height).min(height) {
+                for block_x in x..(x + block_width).min(width) {
+                    let i = (block_y * width + block_x) * pixel_size;
+                    image[i..i + pixel_size].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+}
+
+/// The row stride stored in the `STRD` chunk `data`, or packed rows without one.
+fn row_stride(header: &Header, data: Option<&[u8]>) -> Result<usize, DecodeError> {
+    let row_size = byte_size(header.width as usize, 1, header.format.bytes_per_pixel())?;
+    let Some(data) = data else {
+        return Ok(row_size);
+    };
+    if data.len() != 4 {
+        return Err(invalid("STRD chunk has to hold 4 bytes"));
+    }
+    if header.tiled || header.interlaced {
+        return Err(invalid("Tiled and interlaced files can't pad their rows"));
+    }
+    let stride = read_u32_le(data, 0) as usize;
+    if stride < row_size || stride - row_size > MAX_ROW_PADDING {
+        return Err(invalid(format!(
+            "Row stride {} doesn't fit rows of {} bytes",
+            stride, row_size
+        )));
+    }
+    Ok(stride)
+}
+
+/// The pixels of an interlaced `PXLS` chunk in the usual row by row order.
+fn deinterlace(header: &Header, data: &[u8], max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+    check_dimensions(header.width, header.height, max_pixels)?;
+    let pixel_size = header.format.bytes_per_pixel();
+    let (width, height) = (header.width as usize, header.height as usize);
+    let mut image = vec![0; byte_size(width, height, pixel_size)?];
+    let mut offset = 0;
+    for pass in 0..ADAM7.len() {
+        let (pixels, length) = read_pass(header, &data[offset..], pass)?
+            .ok_or_else(|| invalid(format!("PXLS chunk ends in pass {}", pass + 1)))?;
+        place_pass(header, &mut image, pass, &pixels, false);
+        offset += length;
+    }
+    if offset != data.len() {
+        return Err(invalid("PXLS chunk has data after the last pass"));
+    }
+    Ok(image)
+}
+
+/// Checks that the rectangle lies inside the image and isn't larger than `max_pixels`,
+/// returning it as `[x, y, width, height]`.
+fn check_region(
+    header: &Header,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    max_pixels: u64,
+) -> Result<[usize; 4], DecodeError> {
+    if x.checked_add(width)
+        .is_none_or(|right| right > header.width)
+        || y.checked_add(height)
+            .is_none_or(|bottom| bottom > header.height)
+    {
+        return Err(invalid(format!(
+            "Region {},{},{},{} doesn't fit inside the {}x{} image",
+            x, y, width, height, header.width, header.height
+        )));
+    }
+    check_dimensions(width, height, max_pixels)?;
+    Ok([x, y, width, height].map(|value| value as usize))
+}
+
+/// Puts the rectangle together from the tiles covering it. `fetch` returns the compressed data
+/// of a tile given its byte range in `PXLS`, which is `data_len` bytes long.
+fn assemble_tiles<'a>(
+    header: &Header,
+    index: &[u8],
+    data_len: usize,
+    [x, y, width, height]: [usize; 4],
+    mut fetch: impl FnMut(usize, usize) -> Result<Cow<'a, [u8]>, DecodeError>,
+) -> Result<Vec<u8>, DecodeError> {
+    if index.len() < 8 {
+        return Err(invalid("TIDX chunk is too short"));
+    }
+    let (tile_width, tile_height) = (
+        read_u32_le(index, 0) as usize,
+        read_u32_le(index, 4) as usize,
+    );
+    if tile_width == 0 || tile_height == 0 {
+        return Err(invalid("TIDX chunk has empty tiles"));
+    }
+    let (image_width, image_height) = (header.width as usize, header.height as usize);
+    let columns = image_width.div_ceil(tile_width);
+    let tiles = byte_size(columns, image_height.div_ceil(tile_height), 1)?;
+    if Some(index.len()) != byte_size(tiles, 4, 1)?.checked_add(8) {
+        return Err(invalid(format!(
+            "TIDX chunk holds {} offsets, expected {}",
+            (index.len() - 8) / 4,
+            tiles
+        )));
+    }
+
+    let pixel_size = header.format.bytes_per_pixel();
+    let mut out = vec![0; byte_size(width, height, pixel_size)?];
+    if width == 0 || height == 0 {
+        return Ok(out);
+    }
+    for row in y / tile_height..(y + height).div_ceil(tile_height) {
+        for column in x / tile_width..(x + width).div_ceil(tile_width) {
+            let tile = row * columns + column;
+            let start = read_u32_le(index, 8 + tile * 4) as usize;
+            let end = if tile + 1 < tiles {
+                read_u32_le(index, 12 + tile * 4) as usize
+            } else {
+                data_len
+            };
+            if start > end || end > data_len {
+                return Err(invalid(format!(
+                    "Tile {} lies outside the PXLS chunk",
+                    tile
+                )));
+            }
+
+            let (left, top) = (column * tile_width, row * tile_height);
+            let this_width = tile_width.min(image_width - left);
+            let this_height = tile_height.min(image_height - top);
+            let expected = byte_size(this_width, this_height, pixel_size)?;
+            let pixels = decompress(
+                &fetch(start, end)?,
+                header.compression,
+                pixel_size,
+                expected,
+            )?;
+            if pixels.len() != expected {
+                return Err(invalid(format!(
+                    "Tile {} holds {} bytes, expected {}",
+                    tile,
+                    pixels.len(),
+                    expected
+                )));
+            }
+
+            let from = left.max(x);
+            let length = ((left + this_width).min(x + width) - from) * pixel_size;
+            for py in top.max(y)..(top + this_height).min(y + height) {
+                let source = ((py - top) * this_width + from - left) * pixel_size;
+                let target = ((py - y) * width + from - x) * pixel_size;
+                out[target..target + length].copy_from_slice(&pixels[source..source + length]);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Turns pixels in `format` into straight RGBA. Palette images need the `PLTE` data.
+fn expand(
+    header: &Header,
+    palette: Option<&[u8]>,
+    pixels: Vec<u8>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut rgba = expand_format(header.format, palette, pixels)?;
+    if header.bgr {
+        swap_red_blue(&mut rgba);
+    }
+    Ok(rgba)
+}
+
+/// The smallest rectangle holding every pixel that differs between the RGBA images `a` and `b`,
+/// both `width` pixels wide, as x, y, width and height. It is empty for identical images.
+fn changed_rect(a: &[u8], b: &[u8], width: u32) -> [u32; 4] {
+    let row_size = width as usize * 4;
+    if row_size == 0 {
+        return [0; 4];
+    }
+    let (mut left, mut right, mut top, mut bottom) = (usize::MAX, 0, usize::MAX, 0);
+    for (y, (a, b)) in a
+        .chunks_exact(row_size)
+        .zip(b.chunks_exact(row_size))
+        .enumerate()
+    {
+        if a == b {
+            continue;
+        }
+        let pixels = || a.chunks_exact(4).zip(b.chunks_exact(4));
+        left = left.min(pixels().position(|(p, q)| p != q).unwrap());
+        right = right.max(pixels().rposition(|(p, q)| p != q).unwrap() + 1);
+        top = top.min(y);
+        bottom = y + 1;
+    }
+    if top == usize::MAX {
+        return [0; 4];
+    }
+    [left, top, right - left, bottom - top].map(|value| value as u32)
+}
+
+/// Swaps the first and third channel of every 4 byte pixel, turning RGBA into BGRA and back.
+pub fn swap_red_blue(pixels: &mut [u8]) {
+    let done = simd::swap_red_blue(pixels);
+    for pixel in pixels[done * 4..].chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+/// RGB8 pixels as RGBA with opaque alpha.
+fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8> {
+    let mut rgba = vec![0; rgb.len() / 3 * 4];
+    let done = simd::rgb_to_rgba(rgb, &mut rgba);
+    for (pixel, out) in rgb[done * 3..]
+        .chunks_exact(3)
+        .zip(rgba[done * 4..].chunks_exact_mut(4))
+    {
+        out.copy_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+    }
+    rgba
+}
+
+/// Gray8 pixels as RGBA with opaque alpha.
+fn gray_to_rgba(gray: &[u8]) -> Vec<u8> {
+    let mut rgba = vec![0; gray.len() * 4];
+    let done = simd::gray_to_rgba(gray, &mut rgba);
+    for (&g, out) in gray[done..]
+        .iter()
+        .zip(rgba[done * 4..].chunks_exact_mut(4))
+    {
+        out.copy_from_slice(&[g, g, g, 255]);
+    }
+    rgba
+}
+
+fn expand_format(
+    format: PixelFormat,
+    palette: Option<&[u8]>,
+    pixels: Vec<u8>,
+) -> Result<Vec<u8>, DecodeError> {
+    match format {
+        PixelFormat::Rgba8 => Ok(pixels),
+        PixelFormat::Rgb8 => Ok(rgb_to_rgba(&pixels)),
+        PixelFormat::Gray8 => Ok(gray_to_rgba(&pixels)),
+        // Anything brighter than white is clipped, `to_rgba_f32` keeps it.
+        PixelFormat::RgbaF16 | PixelFormat::RgbaF32 => Ok(decode_floats(&pixels, format)
+            .into_iter()
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+            .collect()),
+        PixelFormat::Palette8 => {
+            let palette = palette.ok_or_else(|| invalid("Missing PLTE chunk"))?;
+            let palette: Vec<&[u8]> = palette.chunks_exact(4).collect();
+
+            let mut rgba = Vec::with_capacity(pixels.len() * 4);
+            for &index in &pixels {
+                let color = palette
+                    .get(index as usize)
+                    .ok_or_else(|| invalid(format!("Palette index {} out of range", index)))?;
+                rgba.extend_from_slice(color);
+            }
+            Ok(rgba)
+        }
+    }
+}
+
+/// The little endian channels of a float format.
+fn encode_floats(rgba: &[f32], format: PixelFormat) -> Vec<u8> {
+    if format == PixelFormat::RgbaF16 {
+        rgba.iter()
+            .flat_map(|&c| f32_to_f16(c).to_le_bytes())
+            .collect()
+    } else {
+        rgba.iter().flat_map(|&c| c.to_le_bytes()).collect()
+    }
+}
+
+/// Reverses `encode_floats`.
+fn decode_floats(pixels: &[u8], format: PixelFormat) -> Vec<f32> {
+    if format == PixelFormat::RgbaF16 {
+        pixels
+            .chunks_exact(2)
+            .map(|c| f16_to_f32(u16::from_le_bytes([c[0], c[1]])))
+            .collect()
+    } else {
+        pixels
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    }
+}
+
+/// The half precision float nearest to `value`, ties going to the even one.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    let (half, mantissa, shift) = if exponent <= 0 {
+        // Too small for a normal half, the implicit 1 becomes part of the mantissa.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, mantissa, shift)
+    } else {
+        (((exponent as u32) << 10) | (mantissa >> 13), mantissa, 13)
+    };
+    let rest = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // Carrying out of the mantissa bumps the exponent, which is just what rounding up needs.
+    let rounded = half + (rest > halfway || (rest == halfway && half & 1 == 1)) as u32;
+    sign | rounded as u16
+}
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            let value = mantissa as f32 / 16_777_216.0;
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        31 => f32::from_bits(sign | 0x7f80_0000 | mantissa << 13),
+        _ => f32::from_bits(sign | (exponent + 112) << 23 | mantissa << 13),
+    }
+}
+
+/// Packs runs of identical `pixel_size` byte pixels. A control byte `n <= 127` is followed by
+/// `n + 1` literal pixels, `n >= 128` by a single pixel that repeats `n - 126` times.
+pub fn rle_encode(data: &[u8], pixel_size: usize) -> Vec<u8> {
+    let pixels: Vec<&[u8]> = data.chunks(pixel_size).collect();
+    let mut out = Vec::with_capacity(data.len());
+    let mut literals: Vec<&[u8]> = Vec::new();
+
+    let flush = |literals: &mut Vec<&[u8]>, out: &mut Vec<u8>| {
+        for run in literals.chunks(128) {
+            out.push((run.len() - 1) as u8);
+            run.iter().for_each(|pixel| out.extend_from_slice(pixel));
+        }
+        literals.clear();
+    };
+
+    let mut i = 0;
+    while i < pixels.len() {
+        let run = pixels[i..]
+            .iter()
+            .take(129)
+            .take_while(|&&pixel| pixel == pixels[i])
+            .count();
+
+        if run >= 2 {
+            flush(&mut literals, &mut out);
+            out.push((run + 126) as u8);
+            out.extend_from_slice(pixels[i]);
+        } else {
+            literals.push(pixels[i]);
+        }
+        i += run;
+    }
+    flush(&mut literals, &mut out);
+    out
+}
+
+/// Reverses `rle_encode`, refusing to produce more than `limit` bytes.
+pub fn rle_decode(data: &[u8], pixel_size: usize, limit: usize) -> Result<Vec<u8>, DecodeError> {
+    // A control byte and one pixel expand to at most 129 pixels, so no input byte turns into
+    // more than 129 output bytes. Don't trust `limit` alone, it comes from the header.
+    let mut out = Vec::with_capacity(limit.min(data.len().saturating_mul(129)));
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i] as usize;
+        i += 1;
+
+        let (count, literal_pixels) = if control <= 127 {
+            (control + 1, control + 1)
+        } else {
+            (control - 126, 1)
+        };
+        let bytes = literal_pixels * pixel_size;
+        if data.len() - i < bytes {
+            return Err(invalid("RLE data is cut off"));
+        }
+        if out.len() + count * pixel_size > limit {
+            return Err(invalid("RLE data expands past the image size"));
+        }
+
+        if control <= 127 {
+            out.extend_from_slice(&data[i..i + bytes]);
+        } else {
+            for _ in 0..count {
+                out.extend_from_slice(&data[i..i + bytes]);
+            }
+        }
+        i += bytes;
+    }
+    Ok(out)
+}
+
+pub fn compress(data: &[u8], compression: Compression, pixel_size: usize) -> Vec<u8> {
+    match compression {
+        Compression::None => data.to_vec(),
+        Compression::Rle => rle_encode(data, pixel_size),
+        Compression::Deflate => miniz_oxide::deflate::compress_to_vec_zlib(data, 9),
+    }
+}
+
+/// Decompresses `data`, refusing to produce more than `limit` bytes so a tiny file can't claim
+/// gigabytes of pixels.
+pub fn decompress(
+    data: &[u8],
+    compression: Compression,
+    pixel_size: usize,
+    limit: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Rle => rle_decode(data, pixel_size, limit),
+        Compression::Deflate => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+            data, limit,
+        )
+        .map_err(|e| match e.status {
+            TINFLStatus::HasMoreOutput => invalid("Deflate data expands past the image size"),
+            _ => invalid(format!("Invalid deflate data: {}", e)),
+        }),
+    }
+}
+
+/// Metadata that files get when they are created by upgrading an older file.
+pub fn default_metadata() -> Vec<(String, String)> {
+    vec![(
+        "software".to_owned(),
+        format!("bruh {}", env!("CARGO_PKG_VERSION")),
+    )]
+}
+
+/// Whether `bytes` lack the magic. Files too short to tell, like empty ones, count as current
+/// files so they're reported as truncated.
+pub fn is_legacy(bytes: &[u8]) -> bool {
+    !bytes.starts_with(MAGIC) && !MAGIC.starts_with(bytes)
+}
+
+fn vec_to_u32_ne(bytes: &[u8]) -> u32 {
+    let mut result = [0u8; 4];
+    result.copy_from_slice(bytes);
+    u32::from_ne_bytes(result)
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Decodes a legacy (v0) hex file into straight RGBA.
+pub fn decode_legacy(bytes: &[u8], max_pixels: u64) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+    ensure_available(bytes, 0, 8, "the legacy header")?;
+
+    let width = vec_to_u32_ne(&bytes[0..4]);
+    let height = vec_to_u32_ne(&bytes[4..8]);
+    check_dimensions(width, height, max_pixels)?;
+
+    let sanitized_content: Vec<u8> = bytes[8..].iter().copied().filter(|&b| b != b'\n').collect();
+
+    let digits = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(6))
+        .ok_or_else(|| invalid("Image dimensions are too large"))?;
+    if sanitized_content.len() < digits {
+        return Err(DecodeError::TruncatedFile {
+            section: "the hex pixel data".to_owned(),
+            needed: digits,
+            available: sanitized_content.len(),
+        });
+    }
+
+    let mut rgba = Vec::with_capacity(digits / 6 * 4);
+    for color in sanitized_content[..digits].chunks(6) {
+        let channel = |i: usize| Some(hex_digit(color[i])? << 4 | hex_digit(color[i + 1])?);
+        let (Some(r), Some(g), Some(b)) = (channel(0), channel(2), channel(4)) else {
+            return Err(invalid(format!(
+                "Invalid hex color '#{}'",
+                String::from_utf8_lossy(color)
+            )));
+        };
+        rgba.extend_from_slice(&[r, g, b, 255]);
+    }
+
+    Ok((width, height, rgba))
+}
+
+/// Decodes a BRUH file of any version into its dimensions and straight RGBA pixels, refusing
+/// images with more than `max_pixels` pixels.
+pub fn decode(bytes: &[u8], max_pixels: u64) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+    if is_legacy(bytes) {
+        debug!("no BRUH magic, decoding as a legacy hex file");
+        return decode_legacy(bytes, max_pixels);
+    }
+
+    let file = BruhFile::parse(bytes)?;
+    debug!(
+        "version {} file, {}x{} {} with {} compression, {} chunk(s)",
+        file.header.version,
+        file.header.width,
+        file.header.height,
+        file.header.format.name(),
+        file.header.compression.name(),
+        file.chunks.len()
+    );
+    Ok((
+        file.header.width,
+        file.header.height,
+        file.to_rgba(max_pixels)?,
+    ))
+}
+
+/// What `repair` fills pixels that are missing with, so they stand out.
+pub const REPAIR_MARKER: [u8; 4] = [255, 0, 255, 255];
+
+/// Salvages what it can of a damaged file, returning it and a line for each fix:
+///
+/// - the header gets back its magic and version, unknown flags are cleared, and an unknown pixel
+///   format or compression is guessed from the pixels
+/// - dimensions that can't be right are recovered if the other one and the pixels say what they
+///   are
+/// - chunks are read until the file ends or turns into something that isn't a known chunk,
+///   ancillary ones that fail their checksum are dropped, the signature too since it can't
+///   match anymore
+/// - the pixels of plain files decompress as far as they go, rows that are missing are filled
+///   with `REPAIR_MARKER` and bytes past the last row are dropped
+///
+/// Tiled, interlaced, lossy and encrypted files only get their header and chunks fixed, their
+/// pixels can't be salvaged piece by piece. Nothing to fix gives no lines. Legacy files that
+/// decode are refused rather than given a magic, since they have none to lose.
+pub fn repair(bytes: &[u8], max_pixels: u64) -> Result<(BruhFile, Vec<String>), DecodeError> {
+    if let Ok(file) = BruhFile::parse(bytes) {
+        if file.header.encrypted || file.to_rgba(max_pixels).is_ok() {
+            return Ok((file, Vec::new()));
+        }
+    }
+    let mut fixes = Vec::new();
+    if bytes.len() < BRUH_HEADER_SIZE {
+        return Err(invalid("Too short to hold a header"));
+    }
+    if is_legacy(bytes) && decode_legacy(bytes, max_pixels).is_ok() {
+        return Err(invalid("This is a legacy file, it decodes fine"));
+    }
+    let is_tag = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .is_some_and(|tag| KNOWN_CHUNKS.iter().any(|known| known == tag))
+    };
+    if !bytes.starts_with(MAGIC) {
+        if !is_tag(BRUH_HEADER_SIZE) {
+            return Err(invalid(
+                "Not a BRUH file, there is no chunk after the header",
+            ));
+        }
+        fixes.push("Restored the BRUH magic".to_owned());
+    }
+    if bytes[4] != VERSION {
+        fixes.push(format!("Set the version from {} to {}", bytes[4], VERSION));
+    }
+    if bytes[7] & !KNOWN_FLAGS != 0 {
+        fixes.push(format!(
+            "Cleared the unknown header flags {:#04x}",
+            bytes[7] & !KNOWN_FLAGS
+        ));
+    }
+    let flags = bytes[7];
+    let mut header = Header {
+        version: VERSION,
+        format: PixelFormat::from_u8(bytes[5]).unwrap_or(PixelFormat::Rgba8),
+        compression: Compression::from_u8(bytes[6]).unwrap_or(Compression::None),
+        width: read_u32_le(bytes, 8),
+        height: read_u32_le(bytes, 12),
+        encrypted: flags & FLAG_ENCRYPTED != 0,
+        tiled: flags & FLAG_TILED != 0,
+        interlaced: flags & FLAG_INTERLACED != 0,
+        linear: flags & FLAG_LINEAR != 0,
+        bgr: flags & FLAG_BGR != 0,
+        lossy: flags & FLAG_LOSSY != 0,
+    };
+
+    // Each chunk and whether it arrived whole with the right checksum.
+    let mut chunks: Vec<(Chunk, bool)> = Vec::new();
+    let mut offset = BRUH_HEADER_SIZE;
+    while offset < bytes.len() {
+        let left = bytes.len() - offset;
+        if left < 8 || !is_tag(offset) {
+            fixes.push(format!(
+                "Dropped {} unreadable bytes from offset {} on",
+                left, offset
+            ));
+            break;
+        }
+        let tag: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+        let name = String::from_utf8_lossy(&tag).into_owned();
+        let length = read_u32_le(bytes, offset + 4) as usize;
+        let start = offset + 8;
+        if bytes.len() - start < length.saturating_add(4) {
+            let end = start.saturating_add(length).min(bytes.len());
+            fixes.push(format!(
+                "The file ends {} bytes into chunk {}, which should have {}",
+                end - start,
+                name,
+                length
+            ));
+            chunks.push((Chunk::new(tag, bytes[start..end].to_vec()), false));
+            break;
+        }
+        let chunk = Chunk::new(tag, bytes[start..start + length].to_vec());
+        let intact = chunk.crc() == read_u32_le(bytes, start + length);
+        if !intact {
+            fixes.push(format!("Chunk {} failed its checksum", name));
+        }
+        chunks.push((chunk, intact));
+        offset = start + length + 4;
+    }
+
+    let pixels_at = chunks
+        .iter()
+        .position(|(chunk, _)| chunk.tag == PIXELS)
+        .ok_or_else(|| invalid("There is no PXLS chunk left to salvage"))?;
+    let plain = !(header.tiled || header.interlaced || header.lossy || header.encrypted);
+    if !plain {
+        if let Some((chunk, _)) = chunks
+            .iter()
+            .find(|(chunk, intact)| !intact && chunk.is_critical())
+        {
+            return Err(invalid(format!(
+                "Chunk {} is damaged, the pixels of tiled, interlaced, lossy and encrypted files \
+                 can't be salvaged",
+                String::from_utf8_lossy(&chunk.tag)
+            )));
+        }
+    }
+
+    let palette = chunks
+        .iter()
+        .find(|(chunk, _)| chunk.tag == PALETTE)
+        .map(|(chunk, _)| &chunk.data[..]);
+    let data = &chunks[pixels_at].0.data;
+    if header.width == 0 || header.height == 0 {
+        return Err(invalid(format!(
+            "{}x{} can't be right, there are pixels but no room for them",
+            header.width, header.height
+        )));
+    }
+    let known_format = PixelFormat::from_u8(bytes[5]).is_some();
+    if Compression::from_u8(bytes[6]).is_none() {
+        let zlib = data.len() >= 2
+            && data[0] & 0x0f == 8
+            && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31);
+        let expected = header.width as usize * header.height as usize;
+        let size = expected.saturating_mul(header.format.bytes_per_pixel());
+        let fits = |compression| {
+            known_format
+                && salvage_pixels(
+                    data,
+                    compression,
+                    header.format.bytes_per_pixel(),
+                    size.saturating_add(1),
+                )
+                .len()
+                    == size
+        };
+        header.compression = if zlib {
+            Compression::Deflate
+        } else if fits(Compression::Rle) {
+            Compression::Rle
+        } else {
+            Compression::None
+        };
+        fixes.push(format!(
+            "Guessed {} compression for the unknown compression {}",
+            header.compression.name(),
+            bytes[6]
+        ));
+    }
+    if !known_format {
+        let pixel_count = header.width as u64 * header.height as u64;
+        let candidates = [
+            PixelFormat::Palette8,
+            PixelFormat::Rgba8,
+            PixelFormat::Rgb8,
+            PixelFormat::Gray8,
+            PixelFormat::RgbaF16,
+            PixelFormat::RgbaF32,
+        ];
+        header.format = candidates
+            .into_iter()
+            .filter(|&format| format != PixelFormat::Palette8 || palette.is_some())
+            .find(|&format| {
+                let size = pixel_count * format.bytes_per_pixel() as u64;
+                let limit = size.min(usize::MAX as u64) as usize;
+                plain
+                    && check_dimensions(header.width, header.height, max_pixels).is_ok()
+                    && salvage_pixels(data, header.compression, format.bytes_per_pixel(), limit)
+                        .len() as u64
+                        == size
+            })
+            .ok_or_else(|| {
+                invalid(format!(
+                    "The pixel format {} is unknown and no other fits the pixels",
+                    bytes[5]
+                ))
+            })?;
+        fixes.push(format!(
+            "Guessed {} for the unknown pixel format {}",
+            header.format.name(),
+            bytes[5]
+        ));
+    }
+
+    if check_dimensions(header.width, header.height, max_pixels).is_err() {
+        let pixel_size = header.format.bytes_per_pixel();
+        let limit = (max_pixels as usize).saturating_mul(pixel_size);
+        let count = salvage_pixels(data, header.compression, pixel_size, limit).len() / pixel_size;
+        let fits = |known: u32| {
+            let other = count.checked_div(known as usize)?;
+            (other > 0 && other * known as usize == count && other <= u32::MAX as usize)
+                .then_some(other as u32)
+        };
+        let (width, height) = match (fits(header.width), fits(header.height)) {
+            (Some(height), _) if plain => (header.width, height),
+            (_, Some(width)) if plain => (width, header.height),
+            _ => {
+                return Err(invalid(format!(
+                    "{}x{} can't be right and the pixels don't say what it should be",
+                    header.width, header.height
+                )))
+            }
+        };
+        check_dimensions(width, height, max_pixels)?;
+        fixes.push(format!(
+            "Set the size from {}x{} to {}x{}, which the pixels fill",
+            header.width, header.height, width, height
+        ));
+        header.width = width;
+        header.height = height;
+    }
+
+    if plain {
+        let stride_chunk = chunks
+            .iter()
+            .find(|(chunk, _)| chunk.tag == STRIDE)
+            .map(|(chunk, _)| &chunk.data[..]);
+        let stride = row_stride(&header, stride_chunk).unwrap_or_else(|_| {
+            fixes.push("Dropped the row padding, its STRD chunk is wrong".to_owned());
+            header.width as usize * header.format.bytes_per_pixel()
+        });
+        let (width, height) = (header.width as usize, header.height as usize);
+        let pixel_size = header.format.bytes_per_pixel();
+        let expected = byte_size(stride, height, 1)?;
+        // One byte more than fits is enough to tell there are too many.
+        let salvaged = salvage_pixels(data, header.compression, pixel_size, expected + 1);
+        if salvaged.len() > expected {
+            fixes.push("Dropped the pixels past the last row".to_owned());
+        }
+
+        // The pixels that are there, without padding, then the first one that isn't.
+        let mut pixels = Vec::with_capacity(width * height * pixel_size);
+        for row in salvaged[..salvaged.len().min(expected)].chunks(stride) {
+            let row = &row[..row.len().min(width * pixel_size)];
+            pixels.extend_from_slice(&row[..row.len() / pixel_size * pixel_size]);
+            if row.len() < width * pixel_size {
+                break;
+            }
+        }
+        let present = pixels.len() / pixel_size;
+        let partial = Header {
+            height: present.div_ceil(width.max(1)) as u32,
+            compression: Compression::None,
+            ..header.clone()
+        };
+        // A cut off last row is filled up to the end with pixels about to be replaced anyway.
+        pixels.resize(partial.height as usize * width * pixel_size, 0);
+        let salvaged_file = BruhFile {
+            header: partial,
+            chunks: palette
+                .map(|palette| Chunk::new(PALETTE, palette.to_vec()))
+                .into_iter()
+                .chain([Chunk::new(PIXELS, pixels)])
+                .collect(),
+        };
+        let total = width * height;
+        let mut repaired = if header.format.is_float() {
+            let mut rgba = salvaged_file.to_rgba_f32(max_pixels)?;
+            rgba.truncate(present * 4);
+            let marker = REPAIR_MARKER.map(|c| c as f32 / 255.0);
+            rgba.extend(marker.iter().cycle().take((total - present) * 4));
+            BruhFile::from_rgba_f32(
+                header.width,
+                header.height,
+                &rgba,
+                header.format,
+                header.compression,
+            )
+            .unwrap()
+        } else {
+            let mut rgba = salvaged_file.to_rgba(max_pixels)?;
+            rgba.truncate(present * 4);
+            rgba.extend(REPAIR_MARKER.iter().cycle().take((total - present) * 4));
+            BruhFile::encode(
+                header.width,
+                header.height,
+                &rgba,
+                header.format,
+                header.compression,
+            )
+            .unwrap_or_else(|| {
+                BruhFile::from_rgba(header.width, header.height, &rgba, header.compression)
+            })
+        };
+        if present < total {
+            fixes.push(format!(
+                "Filled the {} missing pixels from {},{} on with magenta",
+                total - present,
+                present % width.max(1),
+                present / width.max(1)
+            ));
+        }
+        repaired.header.linear = header.linear;
+        let same_format = repaired.header.format == header.format;
+
+        let mut frames = 0;
+        let (before, after) = chunks.split_at(pixels_at);
+        for (index, (chunk, intact)) in before.iter().chain(&after[1..]).enumerate() {
+            let animation = [ANIMATION, FRAME, DELTA_FRAME].contains(&chunk.tag);
+            if !intact || (animation && !same_format) {
+                frames += (animation && *intact && chunk.tag != ANIMATION) as usize;
+                continue;
+            }
+            if !chunk.is_critical() || animation {
+                if index < before.len() {
+                    let at = repaired.chunks.len() - 1;
+                    repaired.chunks.insert(at, chunk.clone());
+                } else {
+                    repaired.chunks.push(chunk.clone());
+                }
+            }
+        }
+        if frames > 0 {
+            fixes.push(format!(
+                "Dropped {} frames of the animation, the pixels are stored as {} now",
+                frames,
+                repaired.header.format.name()
+            ));
+        }
+        drop_signature(&mut repaired, &mut fixes);
+        return Ok((repaired, fixes));
+    }
+
+    let chunks = chunks
+        .into_iter()
+        .filter(|(_, intact)| *intact)
+        .map(|(chunk, _)| chunk)
+        .collect();
+    let mut repaired = BruhFile { header, chunks };
+    drop_signature(&mut repaired, &mut fixes);
+    if !repaired.header.encrypted {
+        repaired.to_rgba(max_pixels)?;
+    }
+    Ok((repaired, fixes))
+}
+
+/// Drops the `sign` chunk of a repaired file, it signs the bytes from before.
+fn drop_signature(file: &mut BruhFile, fixes: &mut Vec<String>) {
+    if let Some(position) = file.chunks.iter().position(|chunk| chunk.tag == SIGNATURE) {
+        file.chunks.remove(position);
+        fixes.push("Dropped the signature, it can't match anymore".to_owned());
+    }
+}
+
+/// As much of the pixels in `data` as decompress, up to `limit` bytes, for `repair`. Damaged
+/// data gives what came before the damage instead of an error.
+fn salvage_pixels(
+    data: &[u8],
+    compression: Compression,
+    pixel_size: usize,
+    limit: usize,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    match compression {
+        Compression::None => out.extend_from_slice(&data[..data.len().min(limit)]),
+        Compression::Rle => {
+            let mut rest = data;
+            while let Some(&control) = rest.first() {
+                let control = control as usize;
+                let (count, stored) = if control <= 127 {
+                    (control + 1, control + 1)
+                } else {
+                    (control - 126, 1)
+                };
+                let Some(run) = rest.get(1..1 + stored * pixel_size) else {
+                    break;
+                };
+                for _ in 0..count / stored {
+                    out.extend_from_slice(run);
+                }
+                if out.len() >= limit {
+                    break;
+                }
+                rest = &rest[1 + run.len()..];
+            }
+        }
+        Compression::Deflate => {
+            let mut state = InflateState::new_boxed(DataFormat::Zlib);
+            let mut output = vec![0; READ_SIZE];
+            let mut rest = data;
+            while out.len() < limit {
+                let result = inflate(&mut state, rest, &mut output, MZFlush::None);
+                rest = &rest[result.bytes_consumed..];
+                out.extend_from_slice(&output[..result.bytes_written]);
+                let stuck = result.bytes_consumed == 0 && result.bytes_written == 0;
+                if stuck || !matches!(result.status, Ok(MZStatus::Ok) | Err(MZError::Buf)) {
+                    break;
+                }
+            }
+        }
+    }
+    out.truncate(limit);
+    out
+}
+
+/// Lossy pixels like JPEG's, for `FLAG_LOSSY`: the colors become YCbCr, Cb and Cr keep one value
+/// per 2x2 pixels (4:2:0), and each plane is cut into 8x8 blocks whose DCT coefficients are
+/// divided by the quantization tables of the JPEG standard, scaled for the quality. The
+/// coefficients are stored in zigzag order as LEB128 varints of their zigzag encoded value, which
+/// the file's compression squeezes further since most of them are 0. Alpha is kept exactly.
+///
+/// The data is the quality (1 to 100), the blocks of Y row by row, those of Cb and Cr unless the
+/// image is Gray8, then one alpha byte per pixel if it is RGBA8. Blocks reaching past the edge of
+/// a plane repeat its last column and row.
+mod lossy {
+    use super::{format, invalid, vec, DecodeError, PixelFormat, Vec};
+
+    /// Which coefficient, row by row, comes at each position of the zigzag order.
+    const ZIGZAG: [usize; 64] = [
+        0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27,
+        20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+        58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+    ];
+
+    /// The luminance quantization table of the JPEG standard (Annex K), row by row.
+    const LUMA: [u16; 64] = [
+        16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+        56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81,
+        104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+    ];
+
+    /// The chrominance quantization table of the JPEG standard, row by row.
+    const CHROMA: [u16; 64] = [
+        17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99,
+        99, 47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    ];
+
+    /// `COS[u * 8 + x]` is `c(u) / 2 * cos((2x + 1) * u * pi / 16)` with `c(0) = 1 / sqrt(2)`
+    /// and `c(u) = 1` otherwise, the basis of the 8 point DCT. Written out, `core` has no `cos`.
+    #[rustfmt::skip]
+    const COS: [f64; 64] = [
+        0.353553391, 0.353553391, 0.353553391, 0.353553391, 0.353553391, 0.353553391, 0.353553391, 0.353553391,
+        0.490392640, 0.415734806, 0.277785117, 0.097545161, -0.097545161, -0.277785117, -0.415734806, -0.490392640,
+        0.461939766, 0.191341716, -0.191341716, -0.461939766, -0.461939766, -0.191341716, 0.191341716, 0.461939766,
+        0.415734806, -0.097545161, -0.490392640, -0.277785117, 0.277785117, 0.490392640, 0.097545161, -0.415734806,
+        0.353553391, -0.353553391, -0.353553391, 0.353553391, 0.353553391, -0.353553391, -0.353553391, 0.353553391,
+        0.277785117, -0.490392640, 0.097545161, 0.415734806, -0.415734806, -0.097545161, 0.490392640, -0.277785117,
+        0.191341716, -0.461939766, 0.461939766, -0.191341716, -0.191341716, 0.461939766, -0.461939766, 0.191341716,
+        0.097545161, -0.277785117, 0.415734806, -0.490392640, 0.490392640, -0.415734806, 0.277785117, -0.097545161,
+    ];
+
+    /// The most bytes a coefficient takes, enough for every value a valid block can have.
+    const MAX_VARINT: usize = 3;
+
+    /// `base` scaled for `quality` the way libjpeg does it.
+    fn table(base: &[u16; 64], quality: u8) -> [f64; 64] {
+        let quality = quality.clamp(1, 100) as u32;
+        let scale = if quality < 50 {
+            5000 / quality
+        } else {
+            200 - 2 * quality
+        };
+        base.map(|step| ((step as u32 * scale + 50) / 100).clamp(1, 255) as f64)
+    }
+
+    /// Rounds half away from zero, `core` has no `round` either.
+    fn round(value: f64) -> i32 {
+        if value < 0.0 {
+            (value - 0.5) as i32
+        } else {
+            (value + 0.5) as i32
+        }
+    }
+
+    /// How many coefficients the planes of a `width`x`height` image in `format` have.
+    fn coefficients(width: usize, height: usize, format: PixelFormat) -> usize {
+        let blocks = |width: usize, height: usize| width.div_ceil(8) * height.div_ceil(8) * 64;
+        let chroma = if format == PixelFormat::Gray8 {
+            0
+        } else {
+            2 * blocks(width.div_ceil(2), height.div_ceil(2))
+        };
+        blocks(width, height) + chroma
+    }
+
+    /// The most bytes the lossy data of a `width`x`height` image in `format` can take, what it
+    /// may decompress to.
+    pub fn max_size(width: usize, height: usize, format: PixelFormat) -> usize {
+        let alpha = if format == PixelFormat::Rgba8 {
+            width * height
+        } else {
+            0
+        };
+        1 + coefficients(width, height, format) * MAX_VARINT + alpha
+    }
+
+    /// Encodes straight RGBA `rgba` for a file in `format`, RGB8, RGBA8 or Gray8.
+    pub fn encode(
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        format: PixelFormat,
+        quality: u8,
+    ) -> Vec<u8> {
+        let quality = quality.clamp(1, 100);
+        let mut out = vec![quality];
+        let pixel = |i: usize| {
+            let p = &rgba[i * 4..i * 4 + 3];
+            (p[0] as f64, p[1] as f64, p[2] as f64)
+        };
+        let luma: Vec<f64> = (0..width * height)
+            .map(|i| {
+                let (r, g, b) = pixel(i);
+                0.299 * r + 0.587 * g + 0.114 * b
+            })
+            .collect();
+        encode_plane(&mut out, &luma, width, height, &table(&LUMA, quality));
+
+        if format != PixelFormat::Gray8 {
+            let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+            let mut cb = Vec::with_capacity(chroma_width * chroma_height);
+            let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+            for y in 0..chroma_height {
+                for x in 0..chroma_width {
+                    let (mut sum_cb, mut sum_cr, mut count) = (0.0, 0.0, 0.0);
+                    for py in y * 2..(y * 2 + 2).min(height) {
+                        for px in x * 2..(x * 2 + 2).min(width) {
+                            let (r, g, b) = pixel(py * width + px);
+                            sum_cb += -0.168736 * r - 0.331264 * g + 0.5 * b + 128.0;
+                            sum_cr += 0.5 * r - 0.418688 * g - 0.081312 * b + 128.0;
+                            count += 1.0;
+                        }
+                    }
+                    cb.push(sum_cb / count);
+                    cr.push(sum_cr / count);
+                }
+            }
+            let chroma = table(&CHROMA, quality);
+            encode_plane(&mut out, &cb, chroma_width, chroma_height, &chroma);
+            encode_plane(&mut out, &cr, chroma_width, chroma_height, &chroma);
+        }
+        if format == PixelFormat::Rgba8 {
+            out.extend(rgba.chunks_exact(4).map(|pixel| pixel[3]));
+        }
+        out
+    }
+
+    fn encode_plane(
+        out: &mut Vec<u8>,
+        plane: &[f64],
+        width: usize,
+        height: usize,
+        table: &[f64; 64],
+    ) {
+        for block_y in 0..height.div_ceil(8) {
+            for block_x in 0..width.div_ceil(8) {
+                let mut block = [0.0; 64];
+                for y in 0..8 {
+                    let row = (block_y * 8 + y).min(height - 1) * width;
+                    for x in 0..8 {
+                        block[y * 8 + x] = plane[row + (block_x * 8 + x).min(width - 1)] - 128.0;
+                    }
+                }
+                let coefficients = transform(&block, false);
+                for &index in &ZIGZAG {
+                    let value = round(coefficients[index] / table[index]);
+                    // Zigzag encoded, so small negative values stay small too.
+                    let mut value = ((value << 1) ^ (value >> 31)) as u32;
+                    while value >= 0x80 {
+                        out.push(value as u8 | 0x80);
+                        value >>= 7;
+                    }
+                    out.push(value as u8);
+                }
+            }
+        }
+    }
+
+    /// The DCT of an 8x8 block, row by row, or with `inverse` the block of its coefficients.
+    fn transform(block: &[f64; 64], inverse: bool) -> [f64; 64] {
+        // `basis(u, x)` is the weight of frequency `u` at position `x`.
+        let basis = |u: usize, x: usize| COS[u * 8 + x];
+        let weight = |a: usize, b: usize| if inverse { basis(b, a) } else { basis(a, b) };
+        let mut rows = [0.0; 64];
+        for y in 0..8 {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..8).map(|x| weight(u, x) * block[y * 8 + x]).sum();
+            }
+        }
+        let mut out = [0.0; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                out[v * 8 + u] = (0..8).map(|y| weight(v, y) * rows[y * 8 + u]).sum();
+            }
+        }
+        out
+    }
+
+    /// Decodes `data` back into the pixels of a `width`x`height` image in `format`, like those
+    /// of a lossless `PXLS`.
+    pub fn decode(
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        data: &[u8],
+    ) -> Result<Vec<u8>, DecodeError> {
+        let (&quality, mut data) = data
+            .split_first()
+            .ok_or_else(|| invalid("The lossy pixels are empty"))?;
+        if !(1..=100).contains(&quality) {
+            return Err(invalid(format!("Unknown lossy quality {}", quality)));
+        }
+        let luma = decode_plane(&mut data, width, height, &table(&LUMA, quality))?;
+        let chroma = if format == PixelFormat::Gray8 {
+            None
+        } else {
+            let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+            let table = table(&CHROMA, quality);
+            let cb = decode_plane(&mut data, chroma_width, chroma_height, &table)?;
+            let cr = decode_plane(&mut data, chroma_width, chroma_height, &table)?;
+            Some((chroma_width, cb, cr))
+        };
+        let alpha_size = if format == PixelFormat::Rgba8 {
+            width * height
+        } else {
+            0
+        };
+        if data.len() != alpha_size {
+            return Err(invalid(format!(
+                "The lossy pixels end with {} bytes, expected {} of alpha",
+                data.len(),
+                alpha_size
+            )));
+        }
+
+        let channel = |value: f64| round(value).clamp(0, 255) as u8;
+        let Some((chroma_width, cb, cr)) = chroma else {
+            return Ok(luma.iter().map(|&y| channel(y)).collect());
+        };
+        let mut out = Vec::with_capacity(width * height * format.bytes_per_pixel());
+        for y in 0..height {
+            for x in 0..width {
+                let luma = luma[y * width + x];
+                let chroma = (y / 2) * chroma_width + x / 2;
+                let (cb, cr) = (cb[chroma] - 128.0, cr[chroma] - 128.0);
+                out.push(channel(luma + 1.402 * cr));
+                out.push(channel(luma - 0.344136 * cb - 0.714136 * cr));
+                out.push(channel(luma + 1.772 * cb));
+                if format == PixelFormat::Rgba8 {
+                    out.push(data[y * width + x]);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Reads the blocks of a `width`x`height` plane from the start of `data`, leaving the rest.
+    fn decode_plane(
+        data: &mut &[u8],
+        width: usize,
+        height: usize,
+        table: &[f64; 64],
+    ) -> Result<Vec<f64>, DecodeError> {
+        let mut plane = vec![0.0; width * height];
+        for block_y in 0..height.div_ceil(8) {
+            for block_x in 0..width.div_ceil(8) {
+                let mut coefficients = [0.0; 64];
+                for &index in &ZIGZAG {
+                    let mut value = 0u32;
+                    let mut length = 0;
+                    loop {
+                        let (&byte, rest) = data
+                            .split_first()
+                            .ok_or_else(|| invalid("The lossy pixels are cut off"))?;
+                        *data = rest;
+                        value |= ((byte & 0x7f) as u32) << (7 * length);
+                        length += 1;
+                        if byte & 0x80 == 0 {
+                            break;
+                        }
+                        if length == MAX_VARINT {
+                            return Err(invalid("A lossy coefficient is too large"));
+                        }
+                    }
+                    let value = (value >> 1) as i32 ^ -((value & 1) as i32);
+                    coefficients[index] = value as f64 * table[index];
+                }
+
+                let block = transform(&coefficients, true);
+                for y in 0..8.min(height - block_y * 8) {
+                    let row = (block_y * 8 + y) * width + block_x * 8;
+                    for x in 0..8.min(width - block_x * 8) {
+                        plane[row + x] = block[y * 8 + x] + 128.0;
+                    }
+                }
+            }
+        }
+        Ok(plane)
+    }
+}
+
+/// The pixel conversions images spend most of their decoding time in, 16 bytes at a time. SSE2
+/// and NEON are part of every x86_64 and aarch64 CPU, so neither needs detecting at runtime.
+/// Each function converts as many whole pixels as it can and returns how many, the callers
+/// finish the rest one pixel at a time, which is all that happens on other architectures.
+mod simd {
+    #[cfg(target_arch = "x86_64")]
+    pub fn swap_red_blue(pixels: &mut [u8]) -> usize {
+        use core::arch::x86_64::*;
+
+        let mut done = 0;
+        // SAFETY: SSE2 is always there on x86_64, and every load and store is of 16 bytes that
+        // are inside `pixels`.
+        unsafe {
+            let green_alpha = _mm_set1_epi32(0xff00_ff00_u32 as i32);
+            let low = _mm_set1_epi32(0xff);
+            while done * 4 + 16 <= pixels.len() {
+                let at = pixels.as_mut_ptr().add(done * 4).cast::<__m128i>();
+                let v = _mm_loadu_si128(at);
+                let red = _mm_slli_epi32(_mm_and_si128(v, low), 16);
+                let blue = _mm_and_si128(_mm_srli_epi32(v, 16), low);
+                let swapped = _mm_or_si128(_mm_and_si128(v, green_alpha), _mm_or_si128(red, blue));
+                _mm_storeu_si128(at, swapped);
+                done += 4;
+            }
+        }
+        done
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn rgb_to_rgba(rgb: &[u8], rgba: &mut [u8]) -> usize {
+        use core::arch::x86_64::*;
+
+        let mut done = 0;
+        // SAFETY: SSE2 is always there on x86_64. Every load reads 16 bytes of `rgb` (using the
+        // first 12) and every store writes 16 bytes of `rgba`, which holds 4 per pixel.
+        unsafe {
+            let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+            let lane = |index| {
+                let mut mask = [0; 4];
+                mask[index] = 0x00ff_ffff;
+                _mm_setr_epi32(mask[0], mask[1], mask[2], mask[3])
+            };
+            let masks = [lane(0), lane(1), lane(2), lane(3)];
+            while done * 3 + 16 <= rgb.len() {
+                let v = _mm_loadu_si128(rgb.as_ptr().add(done * 3).cast());
+                // Move pixel n from byte 3n to byte 4n, then keep its three bytes there.
+                let p0 = _mm_and_si128(v, masks[0]);
+                let p1 = _mm_and_si128(_mm_slli_si128(v, 1), masks[1]);
+                let p2 = _mm_and_si128(_mm_slli_si128(v, 2), masks[2]);
+                let p3 = _mm_and_si128(_mm_slli_si128(v, 3), masks[3]);
+                let pixels = _mm_or_si128(_mm_or_si128(p0, p1), _mm_or_si128(p2, p3));
+                let at = rgba.as_mut_ptr().add(done * 4).cast();
+                _mm_storeu_si128(at, _mm_or_si128(pixels, alpha));
+                done += 4;
+            }
+        }
+        done
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn gray_to_rgba(gray: &[u8], rgba: &mut [u8]) -> usize {
+        use core::arch::x86_64::*;
+
+        let mut done = 0;
+        // SAFETY: SSE2 is always there on x86_64. Every load reads 16 bytes of `gray` and the
+        // stores write the 64 bytes of `rgba` for those pixels.
+        unsafe {
+            let alpha = _mm_set1_epi8(-1);
+            while done + 16 <= gray.len() {
+                let g = _mm_loadu_si128(gray.as_ptr().add(done).cast());
+                let at = rgba.as_mut_ptr().add(done * 4).cast::<__m128i>();
+                for (half, (gg, ga)) in [
+                    (_mm_unpacklo_epi8(g, g), _mm_unpacklo_epi8(g, alpha)),
+                    (_mm_unpackhi_epi8(g, g), _mm_unpackhi_epi8(g, alpha)),
+                ]
+                .into_iter()
+                .enumerate()
+                {
+                    _mm_storeu_si128(at.add(half * 2), _mm_unpacklo_epi16(gg, ga));
+                    _mm_storeu_si128(at.add(half * 2 + 1), _mm_unpackhi_epi16(gg, ga));
+                }
+                done += 16;
+            }
+        }
+        done
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn swap_red_blue(pixels: &mut [u8]) -> usize {
+        use core::arch::aarch64::*;
+
+        let mut done = 0;
+        // SAFETY: NEON is always there on aarch64, and every load and store is of the 64 bytes
+        // of 16 pixels inside `pixels`.
+        unsafe {
+            while done * 4 + 64 <= pixels.len() {
+                let at = pixels.as_mut_ptr().add(done * 4);
+                let v = vld4q_u8(at);
+                vst4q_u8(at, uint8x16x4_t(v.2, v.1, v.0, v.3));
+                done += 16;
+            }
+        }
+        done
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn rgb_to_rgba(rgb: &[u8], rgba: &mut [u8]) -> usize {
+        use core::arch::aarch64::*;
+
+        let mut done = 0;
+        // SAFETY: NEON is always there on aarch64. Every load reads the 48 bytes of 16 pixels
+        // of `rgb`, every store writes their 64 bytes of `rgba`.
+        unsafe {
+            let alpha = vdupq_n_u8(255);
+            while done * 3 + 48 <= rgb.len() {
+                let v = vld3q_u8(rgb.as_ptr().add(done * 3));
+                vst4q_u8(
+                    rgba.as_mut_ptr().add(done * 4),
+                    uint8x16x4_t(v.0, v.1, v.2, alpha),
+                );
+                done += 16;
+            }
+        }
+        done
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn gray_to_rgba(gray: &[u8], rgba: &mut [u8]) -> usize {
+        use core::arch::aarch64::*;
+
+        let mut done = 0;
+        // SAFETY: NEON is always there on aarch64. Every load reads 16 bytes of `gray`, every
+        // store writes their 64 bytes of `rgba`.
+        unsafe {
+            let alpha = vdupq_n_u8(255);
+            while done + 16 <= gray.len() {
+                let g = vld1q_u8(gray.as_ptr().add(done));
+                vst4q_u8(
+                    rgba.as_mut_ptr().add(done * 4),
+                    uint8x16x4_t(g, g, g, alpha),
+                );
+                done += 16;
+            }
+        }
+        done
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn swap_red_blue(_: &mut [u8]) -> usize {
+        0
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn rgb_to_rgba(_: &[u8], _: &mut [u8]) -> usize {
+        0
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn gray_to_rgba(_: &[u8], _: &mut [u8]) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Property tests driven by a seeded RNG, so failures are reproducible. Decoding must never
+    //! panic, whatever the input: a panic here is a bug even if the input is nonsense.
+
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const CASES: usize = 300;
+
+    /// A small random image. Pixels often repeat their left neighbor so RLE has runs to find,
+    /// and the colors are gray, opaque or translucent so every pixel format gets exercised.
+    fn random_image(rng: &mut StdRng) -> (u32, u32, Vec<u8>) {
+        let width = rng.gen_range(0..=40);
+        let height = rng.gen_range(0..=40);
+        let kind = rng.gen_range(0..3);
+        let colors: Vec<[u8; 4]> = (0..rng.gen_range(1..=300))
+            .map(|_| {
+                let [r, g, b, a]: [u8; 4] = rng.gen();
+                match kind {
+                    0 => [r, r, r, 255],
+                    1 => [r, g, b, 255],
+                    _ => [r, g, b, a],
+                }
+            })
+            .collect();
+
+        let mut rgba: Vec<u8> = Vec::with_capacity(width as usize * height as usize * 4);
+        for _ in 0..width * height {
+            let repeat = rgba.len() >= 4 && rng.gen_bool(0.7);
+            let pixel = if repeat {
+                rgba[rgba.len() - 4..].try_into().unwrap()
+            } else {
+                colors[rng.gen_range(0..colors.len())]
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+        (width, height, rgba)
+    }
+
+    fn random_encoding(rng: &mut StdRng) -> BruhFile {
+        let (width, height, rgba) = random_image(rng);
+        let compression = Compression::all()[rng.gen_range(0..3)];
+        PixelFormat::all()
+            .into_iter()
+            .filter_map(|format| BruhFile::encode(width, height, &rgba, format, compression))
+            .nth(rng.gen_range(0..2))
+            .unwrap_or_else(|| BruhFile::from_rgba(width, height, &rgba, compression))
+    }
+
+    #[test]
+    fn encode_then_decode_is_lossless() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+
+            for format in PixelFormat::all() {
+                for compression in Compression::all() {
+                    let Some(file) = BruhFile::encode(width, height, &rgba, format, compression)
+                    else {
+                        assert_ne!(format, PixelFormat::Rgba8, "RGBA8 holds any image");
+                        continue;
+                    };
+
+                    let decoded = decode(&file.to_bytes(), DEFAULT_MAX_PIXELS).unwrap();
+                    assert_eq!(
+                        decoded,
+                        (width, height, rgba.clone()),
+                        "{}x{} {} with {} compression",
+                        width,
+                        height,
+                        format.name(),
+                        compression.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encoding_is_deterministic() {
+        let mut rng = StdRng::seed_from_u64(20);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let format = PixelFormat::all()[rng.gen_range(0..4)];
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let layout = rng.gen_range(0..3);
+            let encode = || {
+                let mut file = BruhFile::encode(width, height, &rgba, format, compression)?;
+                file.set_metadata(&[("Title".to_owned(), "same".to_owned())]);
+                match layout {
+                    1 => file.tile(7, 5, DEFAULT_MAX_PIXELS).unwrap(),
+                    2 => file.interlace(DEFAULT_MAX_PIXELS).unwrap(),
+                    _ => {}
+                }
+                Some(file.to_bytes())
+            };
+            assert_eq!(encode(), encode());
+        }
+    }
+
+    #[test]
+    fn float_formats_round_trip() {
+        // Every half survives the trip through a float, and known values round the right way.
+        for bits in 0..=u16::MAX {
+            if bits & 0x7c00 != 0x7c00 || bits & 0x3ff == 0 {
+                assert_eq!(f32_to_f16(f16_to_f32(bits)), bits);
+            }
+        }
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1.0 + 1.0 / 2048.0), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 / 2048.0), 0x3c02);
+        assert_eq!(f32_to_f16(1e-8), 0);
+
+        let mut rng = StdRng::seed_from_u64(17);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let floats: Vec<f32> = rgba.iter().map(|_| rng.gen_range(0.0..16.0)).collect();
+            for format in [PixelFormat::RgbaF16, PixelFormat::RgbaF32] {
+                let compression = Compression::all()[rng.gen_range(0..3)];
+                let file =
+                    BruhFile::from_rgba_f32(width, height, &floats, format, compression).unwrap();
+                let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+                assert!(parsed.header.linear);
+                let expected: Vec<f32> = match format {
+                    PixelFormat::RgbaF16 => {
+                        floats.iter().map(|&c| f16_to_f32(f32_to_f16(c))).collect()
+                    }
+                    _ => floats.clone(),
+                };
+                assert_eq!(parsed.to_rgba_f32(DEFAULT_MAX_PIXELS).unwrap(), expected);
+
+                // 8 bit pixels come back exactly.
+                let file = BruhFile::encode(width, height, &rgba, format, compression).unwrap();
+                assert_eq!(file.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+            }
+        }
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..CASES {
+            let pixel_size = rng.gen_range(1..=4);
+            let (_, _, rgba) = random_image(&mut rng);
+            let data: Vec<u8> = rgba
+                .chunks(4)
+                .flat_map(|pixel| pixel[..pixel_size].to_vec())
+                .collect();
+
+            let encoded = rle_encode(&data, pixel_size);
+            assert_eq!(rle_decode(&encoded, pixel_size, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn every_prefix_fails_to_decode() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let mut file = random_encoding(&mut rng);
+            file.set_metadata(&default_metadata());
+            let bytes = file.to_bytes();
+
+            for end in 0..bytes.len() {
+                let result = decode(&bytes[..end], DEFAULT_MAX_PIXELS);
+                assert!(result.is_err(), "{} of {} bytes decoded", end, bytes.len());
+                if end < BRUH_HEADER_SIZE {
+                    assert!(matches!(result, Err(DecodeError::TruncatedFile { .. })));
+                }
+            }
+            assert!(decode(&bytes, DEFAULT_MAX_PIXELS).is_ok());
+        }
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20 * CASES {
+            let mut bytes: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+            if rng.gen_bool(0.5) {
+                // Get past the magic and version checks.
+                bytes.splice(0..bytes.len().min(5), [b'B', b'R', b'U', b'H', VERSION]);
+            }
+            let _ = decode(&bytes, DEFAULT_MAX_PIXELS);
+        }
+    }
+
+    #[test]
+    fn corrupted_files_never_panic() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..20 * CASES {
+            let mut file = random_encoding(&mut rng);
+
+            // Mangle the contents but keep the checksums valid, so decoding gets as far as
+            // decompressing and expanding the pixels.
+            match rng.gen_range(0..5) {
+                0 => file.header.width = rng.gen_range(0..64),
+                1 => file.header.height = rng.gen_range(0..64),
+                2 => file.header.format = PixelFormat::all()[rng.gen_range(0..4)],
+                3 => file.header.compression = Compression::all()[rng.gen_range(0..3)],
+                _ => {}
+            }
+            for chunk in &mut file.chunks {
+                if chunk.data.is_empty() || rng.gen_bool(0.5) {
+                    continue;
+                }
+                match rng.gen_range(0..3) {
+                    0 => {
+                        let i = rng.gen_range(0..chunk.data.len());
+                        chunk.data[i] ^= 1 << rng.gen_range(0..8);
+                    }
+                    1 => chunk.data.truncate(rng.gen_range(0..chunk.data.len())),
+                    _ => chunk
+                        .data
+                        .extend((0..rng.gen_range(1..16)).map(|_| rng.gen::<u8>())),
+                }
+            }
+
+            let bytes = file.to_bytes();
+            if let Ok((width, height, rgba)) = decode(&bytes, DEFAULT_MAX_PIXELS) {
+                assert_eq!(rgba.len(), width as usize * height as usize * 4);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_headers_are_refused() {
+        let (width, height, rgba) = (2, 2, vec![255; 16]);
+        let mut file = BruhFile::from_rgba(width, height, &rgba, Compression::Rle);
+        file.header.width = u32::MAX;
+        file.header.height = u32::MAX;
+
+        assert!(matches!(
+            decode(&file.to_bytes(), DEFAULT_MAX_PIXELS),
+            Err(DecodeError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn animations_keep_every_frame() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..CASES {
+            let (width, height, first) = random_image(&mut rng);
+            let frames: Vec<Frame> = (0..rng.gen_range(1..5))
+                .map(|i| Frame {
+                    rgba: if i == 0 {
+                        first.clone()
+                    } else {
+                        (0..first.len()).map(|_| rng.gen()).collect()
+                    },
+                    delay_ms: rng.gen_range(0..1000),
+                })
+                .collect();
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let loops = rng.gen_range(0..3);
+
+            let file = BruhFile::from_frames(width, height, &frames, loops, compression);
+            let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+            assert_eq!(parsed.is_animated(), frames.len() > 1);
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), first);
+            if frames.len() > 1 {
+                assert_eq!(parsed.frames(DEFAULT_MAX_PIXELS).unwrap(), frames);
+                assert_eq!(parsed.loops(), loops);
+            }
+        }
+    }
+
+    #[test]
+    fn animations_store_only_what_changed() {
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..CASES {
+            let (width, height, first) = random_image(&mut rng);
+            let mut frames = vec![Frame {
+                rgba: first,
+                delay_ms: 100,
+            }];
+            for _ in 0..rng.gen_range(1..5) {
+                let mut rgba = frames.last().unwrap().rgba.clone();
+                // Sometimes nothing, usually a few pixels.
+                for _ in 0..rng.gen_range(0..4).min(rgba.len()) {
+                    let pixel = rng.gen_range(0..rgba.len() / 4) * 4;
+                    rgba[pixel..pixel + 4].copy_from_slice(&rng.gen::<[u8; 4]>());
+                }
+                frames.push(Frame { rgba, delay_ms: 50 });
+            }
+            let compression = Compression::all()[rng.gen_range(0..3)];
+
+            let file = BruhFile::from_frames(width, height, &frames, 0, compression);
+            let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+            assert_eq!(parsed.frames(DEFAULT_MAX_PIXELS).unwrap(), frames);
+        }
+
+        let still = vec![7; 64 * 64 * 4];
+        let mut moved = still.clone();
+        moved[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let frames: Vec<Frame> = [&still, &still, &moved]
+            .map(|rgba| Frame {
+                rgba: rgba.clone(),
+                delay_ms: 10,
+            })
+            .into();
+        let file = BruhFile::from_frames(64, 64, &frames, 0, Compression::None);
+        let deltas: Vec<usize> = file
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.tag == DELTA_FRAME)
+            .map(|chunk| chunk.data.len())
+            .collect();
+        // The delay and the rectangle, then a single RGBA pixel.
+        assert_eq!(deltas, [20, 24]);
+        assert_eq!(file.frames(DEFAULT_MAX_PIXELS).unwrap(), frames);
+    }
+
+    #[test]
+    fn frame_parts_decode_in_any_order() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..CASES {
+            let (width, height, first) = random_image(&mut rng);
+            let mut frames = vec![Frame {
+                rgba: first,
+                delay_ms: 100,
+            }];
+            for _ in 0..rng.gen_range(1..6) {
+                // A few pixels changed gives a DFRM chunk, all of them a FRAM chunk.
+                let rgba = if rng.gen() && !frames[0].rgba.is_empty() {
+                    let mut rgba = frames.last().unwrap().rgba.clone();
+                    let pixel = rng.gen_range(0..rgba.len() / 4) * 4;
+                    rgba[pixel..pixel + 4].copy_from_slice(&rng.gen::<[u8; 4]>());
+                    rgba
+                } else {
+                    (0..frames[0].rgba.len()).map(|_| rng.gen()).collect()
+                };
+                frames.push(Frame { rgba, delay_ms: 50 });
+            }
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let file = BruhFile::from_frames(width, height, &frames, 0, compression);
+
+            let mut parts: Vec<_> = (0..file.frame_count()).collect();
+            parts.sort_by_key(|_| rng.gen::<u32>());
+            let mut parts: Vec<_> = parts
+                .into_iter()
+                .map(|index| (index, file.frame_part(index, DEFAULT_MAX_PIXELS).unwrap()))
+                .collect();
+            parts.sort_by_key(|&(index, _)| index);
+            let parts = parts.into_iter().map(|(_, part)| part).collect();
+            assert_eq!(file.join_frames(parts).unwrap(), frames);
+        }
+    }
+
+    #[test]
+    fn repair_salvages_cut_off_files() {
+        let mut rng = StdRng::seed_from_u64(12);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            if rgba.len() < 16 {
+                continue;
+            }
+            let compression = [Compression::None, Compression::Deflate][rng.gen_range(0..2)];
+            let mut bytes = BruhFile::from_rgba(width, height, &rgba, compression).to_bytes();
+            bytes.truncate(rng.gen_range(BRUH_HEADER_SIZE + 12..bytes.len()));
+            bytes[4] = VERSION + 1;
+
+            let (file, fixes) = repair(&bytes, DEFAULT_MAX_PIXELS).unwrap();
+            assert!(!fixes.is_empty());
+            let repaired = file.to_rgba(DEFAULT_MAX_PIXELS).unwrap();
+            assert_eq!(repaired.len(), rgba.len());
+            // What survived is the start of the image, everything after it is marked.
+            let kept = repaired
+                .chunks_exact(4)
+                .zip(rgba.chunks_exact(4))
+                .take_while(|(repaired, original)| repaired == original)
+                .count();
+            assert!(repaired[kept * 4..]
+                .chunks_exact(4)
+                .all(|pixel| pixel == REPAIR_MARKER));
+        }
+    }
+
+    #[test]
+    fn repair_stops_at_unknown_chunks_and_leaves_legacy_files_alone() {
+        let rgba = [9; 2 * 2 * 4];
+        let mut file = BruhFile::from_rgba(2, 2, &rgba, Compression::None);
+        file.set_metadata(&[("author".to_owned(), "me".to_owned())]);
+        let mut bytes = file.to_bytes();
+        let intact = bytes.len();
+        bytes.extend_from_slice(b"JUNK\x04\0\0\0junk\0\0\0\0");
+        bytes[4] = VERSION + 1;
+        let (repaired, fixes) = repair(&bytes, DEFAULT_MAX_PIXELS).unwrap();
+        assert_eq!(repaired.to_bytes().len(), intact);
+        assert!(fixes.iter().any(|fix| fix.starts_with(&format!(
+            "Dropped 16 unreadable bytes from offset {}",
+            intact
+        ))));
+
+        let mut legacy = Vec::new();
+        legacy.extend_from_slice(&2u32.to_ne_bytes());
+        legacy.extend_from_slice(&1u32.to_ne_bytes());
+        legacy.extend_from_slice(b"deadbeefcafe\n");
+        assert!(matches!(
+            repair(&legacy, DEFAULT_MAX_PIXELS),
+            Err(DecodeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn sizes_that_dont_fit_in_memory_are_refused() {
+        assert!(matches!(
+            check_dimensions(u32::MAX, u32::MAX, u64::MAX),
+            Err(DecodeError::Invalid(_))
+        ));
+        #[cfg(target_pointer_width = "64")]
+        {
+            // RGBA32F takes 16 bytes a pixel, this would be 2^64 of them.
+            assert!(check_dimensions(1 << 30, 1 << 30, u64::MAX).is_err());
+            assert_eq!(
+                check_dimensions(1 << 30, 1 << 29, u64::MAX).unwrap(),
+                1 << 61
+            );
+        }
+
+        let rgba = [9; 4];
+        for (format, tiled) in [(PixelFormat::RgbaF32, false), (PixelFormat::Rgba8, true)] {
+            let mut file = BruhFile::from_rgba(1, 1, &rgba, Compression::None);
+            if tiled {
+                file.tile(1, 1, DEFAULT_MAX_PIXELS).unwrap();
+            }
+            let mut bytes = file.to_bytes();
+            bytes[5] = format as u8;
+            bytes[8..16].copy_from_slice(&[0xff; 8]);
+            let file = BruhFile::parse(&bytes).unwrap();
+            assert!(file.to_rgba_f32(u64::MAX).is_err());
+            assert!(file.region(0, 0, 1, 1, u64::MAX).is_err());
+        }
+    }
+
+    #[test]
+    fn repair_refuses_empty_sizes() {
+        let rgba = [9; 4 * 4 * 4];
+        for dimension in [8, 12] {
+            let mut bytes = BruhFile::from_rgba(4, 4, &rgba, Compression::None).to_bytes();
+            bytes[dimension..dimension + 4].copy_from_slice(&0u32.to_le_bytes());
+            assert!(matches!(
+                repair(&bytes, DEFAULT_MAX_PIXELS),
+                Err(DecodeError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn appended_frames_follow_the_others() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..CASES {
+            let (width, height, first) = random_image(&mut rng);
+            let mut file = if rng.gen() {
+                BruhFile::from_rgba(
+                    width,
+                    height,
+                    &first,
+                    Compression::all()[rng.gen_range(0..3)],
+                )
+            } else {
+                BruhFile::encode(
+                    width,
+                    height,
+                    &first,
+                    PixelFormat::Palette8,
+                    Compression::Rle,
+                )
+                .unwrap_or_else(|| BruhFile::from_rgba(width, height, &first, Compression::Rle))
+            };
+            // Reads the first frame swapped, the ones appended have to come back as they were.
+            file.header.bgr = rng.gen();
+            let mut frames = file.frames(DEFAULT_MAX_PIXELS).unwrap();
+
+            for delay_ms in 1..rng.gen_range(2..5) {
+                let mut rgba = frames.last().unwrap().rgba.clone();
+                for _ in 0..rng.gen_range(0..4).min(rgba.len()) {
+                    let pixel = rng.gen_range(0..rgba.len() / 4) * 4;
+                    rgba[pixel..pixel + 4].copy_from_slice(&rng.gen::<[u8; 4]>());
+                }
+                let frame = Frame { rgba, delay_ms };
+                file.push_frame(frame.clone(), DEFAULT_MAX_PIXELS).unwrap();
+                if delay_ms == 1 {
+                    frames[0].delay_ms = 1;
+                }
+                frames.push(frame);
+
+                let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+                assert_eq!(parsed.frames(DEFAULT_MAX_PIXELS).unwrap(), frames);
+                assert_eq!(parsed.frame_count(), frames.len());
+            }
+        }
+    }
+
+    #[test]
+    fn lossy_files_look_like_the_original() {
+        let mut rng = StdRng::seed_from_u64(10);
+        for _ in 0..CASES {
+            let (width, height) = (rng.gen_range(1..=40), rng.gen_range(1..=40));
+            let kind = rng.gen_range(0..3);
+            // Smooth, like photos, which is what lossy compression is for.
+            let rgba: Vec<u8> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .flat_map(|(x, y)| {
+                    let (red, green) = ((x * 4) as u8, (y * 4) as u8);
+                    match kind {
+                        0 => [red, red, red, 255],
+                        1 => [red, green, 128, 255],
+                        _ => [red, green, 128, (x * y) as u8],
+                    }
+                })
+                .collect();
+            let quality = rng.gen_range(80..=100);
+            let compression = Compression::all()[rng.gen_range(0..3)];
+
+            let bytes =
+                BruhFile::encode_lossy(width, height, &rgba, quality, compression).to_bytes();
+            let parsed = BruhFile::parse(&bytes).unwrap();
+            assert!(parsed.header.lossy);
+            let expected = [PixelFormat::Gray8, PixelFormat::Rgb8, PixelFormat::Rgba8][kind];
+            assert_eq!(parsed.header.format, expected);
+            let decoded = parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap();
+            assert_eq!(decoded.len(), rgba.len());
+            let error: u64 = decoded
+                .chunks(4)
+                .zip(rgba.chunks(4))
+                .map(|(a, b)| {
+                    assert_eq!(a[3], b[3], "alpha has to stay exact");
+                    (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum::<u64>()
+                })
+                .sum();
+            let average = error as f64 / (width * height * 3) as f64;
+            assert!(
+                average < 3.0,
+                "{}x{} at {}: {}",
+                width,
+                height,
+                quality,
+                average
+            );
+
+            let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+            let region = decoder.decode_region(0, 0, width, height, DEFAULT_MAX_PIXELS);
+            assert_eq!(region.unwrap(), decoded);
+        }
+    }
+
+    #[test]
+    fn cut_off_lossy_pixels_are_refused() {
+        let rgba = vec![200; 16 * 16 * 4];
+        let mut file = BruhFile::encode_lossy(16, 16, &rgba, 50, Compression::None);
+        file.chunks[0].data.pop();
+        assert!(file.to_rgba(DEFAULT_MAX_PIXELS).is_err());
+        file.chunks[0].data.clear();
+        assert!(file.to_rgba(DEFAULT_MAX_PIXELS).is_err());
+    }
+
+    #[test]
+    fn encrypted_files_need_the_password() {
+        let mut file = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None);
+        file.header.encrypted = true;
+        let mut bytes = file.to_bytes();
+        let parsed = BruhFile::parse(&bytes).unwrap();
+        assert!(parsed.header.encrypted);
+        assert!(matches!(
+            parsed.pixel_data(DEFAULT_MAX_PIXELS),
+            Err(DecodeError::Encrypted)
+        ));
+
+        bytes[7] = 0x80;
+        assert!(matches!(
+            BruhFile::parse_header(&bytes),
+            Err(DecodeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn sealed_chunks_of_encrypted_files_are_not_read() {
+        let mut file = BruhFile::from_rgba(2, 2, &[9; 16], Compression::None);
+        file.set_metadata_value("author", Some("me"));
+        file.set_exif(Some(b"Exif".to_vec()));
+        file.set_pixels_per_inch(Some((72, 72)));
+        file.set_thumbnail(&BruhFile::from_rgba(1, 1, &[9; 4], Compression::None));
+        file.header.encrypted = true;
+
+        assert!(file.metadata().is_empty());
+        assert_eq!(file.exif(), None);
+        assert!(matches!(file.layers(), Err(DecodeError::Encrypted)));
+        assert_eq!(file.pixels_per_inch(), None);
+        assert!(matches!(
+            file.thumbnail(DEFAULT_MAX_PIXELS),
+            Err(DecodeError::Encrypted)
+        ));
+        assert!(matches!(
+            file.mip_level(1, DEFAULT_MAX_PIXELS),
+            Err(DecodeError::Encrypted)
+        ));
+        assert!(matches!(file.image(0), Err(DecodeError::Encrypted)));
+    }
+
+    #[test]
+    fn tiled_regions_match_the_whole_image() {
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let mut file = BruhFile::from_rgba(width, height, &rgba, compression);
+            let untiled = file.clone();
+            file.tile(
+                rng.gen_range(1..20),
+                rng.gen_range(1..20),
+                DEFAULT_MAX_PIXELS,
+            )
+            .unwrap();
+            let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+            assert!(parsed.header.tiled);
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+
+            let (x, y) = (rng.gen_range(0..=width), rng.gen_range(0..=height));
+            let (w, h) = (rng.gen_range(0..=width - x), rng.gen_range(0..=height - y));
+            assert_eq!(
+                parsed.region(x, y, w, h, DEFAULT_MAX_PIXELS).unwrap(),
+                untiled.region(x, y, w, h, DEFAULT_MAX_PIXELS).unwrap()
+            );
+            assert!(parsed
+                .region(x, y, width - x + 1, h, DEFAULT_MAX_PIXELS)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn decoder_regions_match_the_parsed_file() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let format = PixelFormat::all()[rng.gen_range(0..4)];
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let Some(mut file) = BruhFile::encode(width, height, &rgba, format, compression) else {
+                continue;
+            };
+            if rng.gen() {
+                file.tile(
+                    rng.gen_range(1..20),
+                    rng.gen_range(1..20),
+                    DEFAULT_MAX_PIXELS,
+                )
+                .unwrap();
+            }
+            let bytes = file.to_bytes();
+
+            let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+            let (x, y) = (rng.gen_range(0..=width), rng.gen_range(0..=height));
+            let (w, h) = (rng.gen_range(0..=width - x), rng.gen_range(0..=height - y));
+            assert_eq!(
+                decoder
+                    .decode_region(x, y, w, h, DEFAULT_MAX_PIXELS)
+                    .unwrap(),
+                file.region(x, y, w, h, DEFAULT_MAX_PIXELS).unwrap()
+            );
+            assert_eq!(
+                decoder
+                    .decode_region(0, 0, width, height, DEFAULT_MAX_PIXELS)
+                    .unwrap(),
+                rgba
+            );
+
+            // Cut off files fail instead of panicking.
+            let end = rng.gen_range(0..bytes.len());
+            if let Ok(mut decoder) = BruhDecoder::new(&bytes[..end]) {
+                let _ = decoder.decode_region(0, 0, width, height, DEFAULT_MAX_PIXELS);
+            }
+        }
+    }
+
+    #[test]
+    fn embedded_thumbnails_round_trip() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let (width, height, rgba) = random_image(&mut rng);
+        let mut file = BruhFile::from_rgba(width, height, &rgba, Compression::Rle);
+        assert_eq!(file.thumbnail(DEFAULT_MAX_PIXELS).unwrap(), None);
+
+        let thumbnail = BruhFile::from_rgba(2, 1, &[1, 2, 3, 4, 5, 6, 7, 8], Compression::Deflate);
+        file.set_thumbnail(&thumbnail);
+        file.set_thumbnail(&thumbnail);
+        let bytes = file.to_bytes();
+        let expected = Some((2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let parsed = BruhFile::parse(&bytes).unwrap();
+        assert_eq!(parsed.chunks[0].tag, THUMBNAIL);
+        assert_eq!(
+            parsed.chunks.iter().filter(|c| c.tag == THUMBNAIL).count(),
+            1
+        );
+        assert_eq!(parsed.thumbnail(DEFAULT_MAX_PIXELS).unwrap(), expected);
+        assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+
+        let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(decoder.thumbnail(DEFAULT_MAX_PIXELS).unwrap(), expected);
+    }
+
+    #[test]
+    fn regions_of_huge_interlaced_and_lossy_files_are_refused() {
+        let rgba = [7; 4 * 4 * 4];
+        let mut interlaced = BruhFile::from_rgba(4, 4, &rgba, Compression::Deflate);
+        interlaced.interlace(DEFAULT_MAX_PIXELS).unwrap();
+        let lossy = BruhFile::encode_lossy(4, 4, &rgba, 80, Compression::Deflate);
+        for mut file in [interlaced, lossy] {
+            // A few bytes claiming tens of gigapixels.
+            file.header.width = 200_000;
+            file.header.height = 200_000;
+            let bytes = file.to_bytes();
+            let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+            assert!(matches!(
+                decoder.decode_region(0, 0, 1, 1, DEFAULT_MAX_PIXELS),
+                Err(DecodeError::TooLarge { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn linear_flag_round_trips() {
+        let mut file = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None);
+        file.header.linear = true;
+        let bytes = file.to_bytes();
+        assert_eq!(bytes[7], FLAG_LINEAR);
+        assert!(BruhFile::parse(&bytes).unwrap().header.linear);
+    }
+
+    #[test]
+    fn bgr_files_decode_to_rgba() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let mut bgra = rgba.clone();
+            swap_red_blue(&mut bgra);
+            let format = PixelFormat::all()[rng.gen_range(0..4)];
+            let Some(mut file) = BruhFile::encode(width, height, &bgra, format, Compression::Rle)
+            else {
+                continue;
+            };
+            file.header.bgr = true;
+
+            let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+            assert!(parsed.header.bgr);
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+            assert_eq!(parsed.to_bgra(DEFAULT_MAX_PIXELS).unwrap(), bgra);
+        }
+    }
+
+    #[test]
+    fn simd_conversions_match_one_pixel_at_a_time() {
+        let mut rng = StdRng::seed_from_u64(18);
+        // Every length up to a few SIMD steps, so each leaves a different remainder.
+        for pixels in 0..80 {
+            let bytes: Vec<u8> = (0..pixels * 4).map(|_| rng.gen()).collect();
+
+            let mut swapped = bytes.clone();
+            swap_red_blue(&mut swapped);
+            let expected: Vec<u8> = bytes
+                .chunks(4)
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect();
+            assert_eq!(swapped, expected);
+
+            let rgb = &bytes[..pixels * 3];
+            let expected: Vec<u8> = rgb
+                .chunks(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect();
+            assert_eq!(rgb_to_rgba(rgb), expected);
+
+            let gray = &bytes[..pixels];
+            let expected: Vec<u8> = gray.iter().flat_map(|&g| [g, g, g, 255]).collect();
+            assert_eq!(gray_to_rgba(gray), expected);
+        }
+    }
+
+    #[test]
+    fn pixels_per_inch_round_trip() {
+        let mut file = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None);
+        assert_eq!(file.pixels_per_inch(), None);
+        file.set_pixels_per_inch(Some((300, 150)));
+        file.set_pixels_per_inch(Some((300, 600)));
+
+        let mut parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+        assert_eq!(parsed.pixels_per_inch(), Some((300, 600)));
+        assert_eq!(parsed.chunks.len(), 2);
+        assert_eq!(parsed.chunks[1].tag, PIXELS);
+        parsed.set_pixels_per_inch(None);
+        assert_eq!(parsed.pixels_per_inch(), None);
+    }
+
+    #[test]
+    fn exif_round_trips() {
+        let mut file = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None);
+        file.set_exif(Some(b"II*\0old".to_vec()));
+        file.set_exif(Some(b"II*\0new".to_vec()));
+
+        let mut parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+        assert_eq!(parsed.exif(), Some(&b"II*\0new"[..]));
+        assert_eq!(parsed.chunks.len(), 2);
+        parsed.set_exif(None);
+        assert_eq!(parsed.exif(), None);
+    }
+
+    #[test]
+    fn mipmap_levels_round_trip() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let (width, height, rgba) = random_image(&mut rng);
+        let mut file = BruhFile::from_rgba(width, height, &rgba, Compression::Deflate);
+        assert_eq!(file.mip_levels(), 1);
+
+        let levels = [
+            BruhFile::from_rgba(2, 1, &[1, 2, 3, 4, 5, 6, 7, 8], Compression::None),
+            BruhFile::from_rgba(1, 1, &[9, 9, 9, 9], Compression::Rle),
+        ];
+        file.set_mipmaps(&levels[..1]);
+        file.set_mipmaps(&levels);
+        let bytes = file.to_bytes();
+
+        let parsed = BruhFile::parse(&bytes).unwrap();
+        let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(parsed.mip_levels(), 3);
+        assert_eq!(decoder.mip_levels(), 3);
+        let expected = [
+            Some((width, height, rgba)),
+            Some((2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8])),
+            Some((1, 1, vec![9, 9, 9, 9])),
+            None,
+        ];
+        for (level, expected) in expected.into_iter().enumerate() {
+            assert_eq!(
+                parsed.mip_level(level, DEFAULT_MAX_PIXELS).unwrap(),
+                expected
+            );
+            assert_eq!(
+                decoder.mip_level(level, DEFAULT_MAX_PIXELS).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn container_images_round_trip() {
+        let mut rng = StdRng::seed_from_u64(16);
+        let (width, height, rgba) = random_image(&mut rng);
+        let mut file = BruhFile::from_rgba(width, height, &rgba, Compression::Deflate);
+        assert_eq!(file.image_count(), 1);
+
+        let mut second = BruhFile::from_rgba(2, 1, &[1, 2, 3, 4, 5, 6, 7, 8], Compression::None);
+        second.add_image(&BruhFile::from_rgba(1, 1, &[9, 9, 9, 9], Compression::Rle));
+        file.add_image(&second);
+        let bytes = file.to_bytes();
+
+        let parsed = BruhFile::parse(&bytes).unwrap();
+        let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+        assert_eq!(parsed.image_count(), 3);
+        assert_eq!(decoder.image_count(), 3);
+        let expected = [
+            Some((width, height, rgba)),
+            Some((2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8])),
+            Some((1, 1, vec![9, 9, 9, 9])),
+            None,
+        ];
+        for (index, expected) in expected.into_iter().enumerate() {
+            let image = parsed.image(index).unwrap().map(|image| {
+                assert_eq!(image.image_count(), 1);
+                let (width, height) = (image.header.width, image.header.height);
+                (width, height, image.to_rgba(DEFAULT_MAX_PIXELS).unwrap())
+            });
+            assert_eq!(image, expected);
+            assert_eq!(
+                decoder.decode_image(index, DEFAULT_MAX_PIXELS).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn interlaced_files_sharpen_to_the_exact_image() {
+        let mut rng = StdRng::seed_from_u64(10);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let format = PixelFormat::all()[rng.gen_range(0..4)];
+            let compression = Compression::all()[rng.gen_range(0..3)];
+            let Some(mut file) = BruhFile::encode(width, height, &rgba, format, compression) else {
+                continue;
+            };
+            let interlaced = rng.gen();
+            if interlaced {
+                file.interlace(DEFAULT_MAX_PIXELS).unwrap();
+            }
+            let bytes = file.to_bytes();
+            let parsed = BruhFile::parse(&bytes).unwrap();
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+            let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+            assert_eq!(
+                decoder
+                    .decode_region(0, 0, width, height, DEFAULT_MAX_PIXELS)
+                    .unwrap(),
+                rgba
+            );
+
+            let mut progressive = ProgressiveDecoder::new(DEFAULT_MAX_PIXELS);
+            let mut images = Vec::new();
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (piece, next) = rest.split_at(rng.gen_range(1..=rest.len().min(200)));
+                images.extend(progressive.push(piece).unwrap());
+                rest = next;
+            }
+            assert!(progressive.is_complete());
+            assert_eq!(images.last(), Some(&rgba));
+            assert!(images.len() <= if interlaced { 8 } else { ROW_BATCHES });
+            if interlaced {
+                continue;
+            }
+            // Plain files fill in from the top, each image has more of the rows than the last.
+            let row_size = (width * 4) as usize;
+            let mut before = 0;
+            for image in &images[..images.len() - 1] {
+                let rows = (0..height as usize)
+                    .take_while(|&y| {
+                        image[y * row_size..][..row_size] == rgba[y * row_size..][..row_size]
+                    })
+                    .count();
+                assert!(image[rows * row_size..].iter().all(|&c| c == 0));
+                assert!(rows > before);
+                before = rows;
+            }
+        }
+    }
+
+    #[test]
+    fn padded_rows_decode_to_the_same_image() {
+        let mut rng = StdRng::seed_from_u64(14);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let format = PixelFormat::all()[rng.gen_range(0..4)];
+            let compression = [Compression::None, Compression::Deflate][rng.gen_range(0..2)];
+            let Some(mut file) = BruhFile::encode(width, height, &rgba, format, compression) else {
+                continue;
+            };
+            let alignment = [4, 8][rng.gen_range(0..2)];
+            file.align_rows(alignment, DEFAULT_MAX_PIXELS).unwrap();
+            let stride = file.stride().unwrap();
+            assert_eq!(stride % alignment, 0);
+            assert!(stride >= width as usize * format.bytes_per_pixel());
+
+            let bytes = file.to_bytes();
+            let parsed = BruhFile::parse(&bytes).unwrap();
+            assert_eq!(parsed.stride().unwrap(), stride);
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+
+            let mut decoder = BruhDecoder::new(&bytes[..]).unwrap();
+            let (x, y) = (rng.gen_range(0..=width), rng.gen_range(0..=height));
+            let (w, h) = (rng.gen_range(0..=width - x), rng.gen_range(0..=height - y));
+            assert_eq!(
+                decoder
+                    .decode_region(x, y, w, h, DEFAULT_MAX_PIXELS)
+                    .unwrap(),
+                parsed.region(x, y, w, h, DEFAULT_MAX_PIXELS).unwrap()
+            );
+
+            // Tiling drops the padding again.
+            file.tile(7, 5, DEFAULT_MAX_PIXELS).unwrap();
+            assert!(file.chunk(STRIDE).is_none());
+            assert_eq!(file.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+        }
+    }
+
+    #[test]
+    fn borrowed_pixels_match_the_decoded_ones() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let mut file =
+                BruhFile::encode(width, height, &rgba, PixelFormat::Rgba8, Compression::None)
+                    .unwrap();
+            let padded = rng.gen();
+            if padded {
+                file.align_rows(16, DEFAULT_MAX_PIXELS).unwrap();
+            }
+            let bytes = file.to_bytes();
+            let image = BruhImageRef::parse(&bytes).unwrap().unwrap();
+            assert_eq!((image.width, image.height), (width, height));
+            assert_eq!(image.rows().flatten().copied().collect::<Vec<u8>>(), rgba);
+            match image.as_rgba_slice() {
+                Some(pixels) => assert_eq!(pixels, &rgba[..]),
+                None => assert!(padded && width % 4 != 0),
+            }
+
+            let compressed = BruhFile::encode(
+                width,
+                height,
+                &rgba,
+                PixelFormat::Rgba8,
+                Compression::Deflate,
+            )
+            .unwrap();
+            assert!(BruhImageRef::parse(&compressed.to_bytes())
+                .unwrap()
+                .is_none());
+            // The pixels are the last chunk, flip their last byte.
+            let mut broken = bytes.clone();
+            if !rgba.is_empty() {
+                let end = broken.len() - 5;
+                broken[end] ^= 1;
+                assert!(BruhImageRef::parse(&broken).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn layers_round_trip() {
+        let mut rng = StdRng::seed_from_u64(15);
+        for _ in 0..CASES {
+            let (width, height, rgba) = random_image(&mut rng);
+            let mut file = BruhFile::from_rgba(width, height, &rgba, Compression::Deflate);
+            let layers: Vec<Layer> = (0..rng.gen_range(1..4))
+                .map(|i| {
+                    let (width, height, rgba) = random_image(&mut rng);
+                    Layer {
+                        name: format!("layer {}", i),
+                        x: rng.gen_range(-20..20),
+                        y: rng.gen_range(-20..20),
+                        blend: BlendMode::all()[rng.gen_range(0..3)],
+                        opacity: rng.gen(),
+                        visible: rng.gen(),
+                        image: BruhFile::from_rgba(width, height, &rgba, Compression::Rle),
+                    }
+                })
+                .collect();
+            file.set_layers(&layers);
+
+            let parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+            assert_eq!(parsed.to_rgba(DEFAULT_MAX_PIXELS).unwrap(), rgba);
+            let parsed_layers = parsed.layers().unwrap();
+            assert_eq!(parsed_layers.len(), layers.len());
+            for (parsed, layer) in parsed_layers.iter().zip(&layers) {
+                assert_eq!(
+                    (&parsed.name, parsed.x, parsed.y, parsed.blend),
+                    (&layer.name, layer.x, layer.y, layer.blend)
+                );
+                assert_eq!(
+                    (parsed.opacity, parsed.visible),
+                    (layer.opacity, layer.visible)
+                );
+                assert_eq!(parsed.image.to_bytes(), layer.image.to_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_files_decode_and_never_panic() {
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..CASES {
+            let width: u32 = rng.gen_range(0..8);
+            let height: u32 = rng.gen_range(0..8);
+            let colors: Vec<[u8; 3]> = (0..width * height).map(|_| rng.gen()).collect();
+
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&width.to_ne_bytes());
+            bytes.extend_from_slice(&height.to_ne_bytes());
+            for row in colors.chunks(width.max(1) as usize) {
+                for [r, g, b] in row {
+                    bytes.extend_from_slice(format!("{:02x}{:02x}{:02x}", r, g, b).as_bytes());
+                }
+                bytes.push(b'\n');
+            }
+
+            let rgba: Vec<u8> = colors
+                .iter()
+                .flat_map(|&[r, g, b]| [r, g, b, 255])
+                .collect();
+            assert_eq!(
+                decode_legacy(&bytes, DEFAULT_MAX_PIXELS).unwrap(),
+                (width, height, rgba)
+            );
+
+            // Now break it: swap in characters that aren't hex digits and cut it short.
+            for _ in 0..rng.gen_range(1..4) {
+                let i = rng.gen_range(0..bytes.len());
+                bytes[i] = *b"0fFx \n\xff".get(rng.gen_range(0..7)).unwrap();
+            }
+            bytes.truncate(rng.gen_range(0..=bytes.len()));
+            let _ = decode_legacy(&bytes, DEFAULT_MAX_PIXELS);
+        }
+    }
+
+    #[test]
+    fn metadata_keeps_its_order_and_strips_with_the_rest() {
+        let mut file = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None);
+        file.set_metadata_value("title", Some("old"));
+        file.set_metadata_value("author", Some("me"));
+        file.set_metadata_value("title", Some("nuit étoilée"));
+        file.set_metadata_value("missing", None);
+        file.set_exif(Some(b"II*\0".to_vec()));
+
+        let mut parsed = BruhFile::parse(&file.to_bytes()).unwrap();
+        let entries = parsed.metadata();
+        assert_eq!(
+            entries,
+            [
+                ("title".to_string(), "nuit étoilée".to_string()),
+                ("author".to_string(), "me".to_string())
+            ]
+        );
+        // The metadata stays in front of the pixels when it changes.
+        assert_eq!(parsed.chunks[0].tag, METADATA);
+        parsed.set_metadata_value("author", None);
+        assert_eq!(parsed.metadata_value("author"), None);
+        assert_eq!(
+            parsed.metadata_value("title").as_deref(),
+            Some("nuit étoilée")
+        );
+
+        let pixels = decode(&parsed.to_bytes(), DEFAULT_MAX_PIXELS).unwrap();
+        assert_eq!(parsed.strip(), 2);
+        assert_eq!(parsed.strip(), 0);
+        assert!(parsed.metadata().is_empty());
+        assert_eq!(parsed.exif(), None);
+        assert_eq!(
+            decode(&parsed.to_bytes(), DEFAULT_MAX_PIXELS).unwrap(),
+            pixels
+        );
+    }
+}
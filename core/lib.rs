@@ -0,0 +1,12 @@
+//! The BRUH codec without the command line around it: headers, chunks, compression and pixel
+//! conversion. With `default-features = false` it is `no_std` and only needs an allocator, so
+//! .bruh files can be read on embedded devices.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod format;
+
+#[cfg(feature = "std")]
+pub mod storage;
+
+pub use format::*;
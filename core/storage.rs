@@ -0,0 +1,227 @@
+//! Where image bytes are read from and written to. The command line works on files, the web
+//! viewer on whatever was dropped onto the page, and both decode them with the same codec.
+
+use std::{io, path::Path};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+#[cfg(feature = "net")]
+use std::time::Duration;
+
+#[cfg(target_arch = "wasm32")]
+use std::{collections::HashMap, path::PathBuf};
+
+pub trait Storage {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Replaces whatever was at `path` with `bytes`.
+    fn write(&mut self, path: &Path, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// The local file system.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Disk;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Storage for Disk {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&mut self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        write_atomic(path, bytes)
+    }
+}
+
+/// Lets `BruhDecoder` read just the parts of a file it needs instead of all of it.
+#[cfg(not(target_arch = "wasm32"))]
+impl crate::format::Source for fs::File {
+    fn size(&self) -> u64 {
+        self.metadata().map_or(0, |metadata| metadata.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), crate::format::DecodeError> {
+        self.seek(SeekFrom::Start(offset))
+            .and_then(|_| self.read_exact(buf))
+            .map_err(|e| crate::format::DecodeError::Read(e.to_string()))
+    }
+}
+
+/// Whether `path` is an `http://` or `https://` URL rather than a file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_url(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// The name a downloaded file would get locally: the last part of the URL without the query.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn url_file_name(url: &Path) -> PathBuf {
+    let url = url.to_string_lossy();
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    PathBuf::from(url.rsplit('/').next().unwrap_or_default())
+}
+
+/// Files on web servers, downloaded into memory. Only reading is supported.
+#[cfg(feature = "net")]
+pub struct Http;
+
+#[cfg(feature = "net")]
+impl Storage for Http {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(30))
+            .user_agent(concat!("bruh/", env!("CARGO_PKG_VERSION")))
+            .build();
+        let response = agent
+            .get(&path.to_string_lossy())
+            .call()
+            .map_err(io::Error::other)?;
+
+        let mut bytes = Vec::new();
+        response.into_reader().read_to_end(&mut bytes)?;
+        log::debug!("downloaded {}, {} bytes", path.display(), bytes.len());
+        Ok(bytes)
+    }
+
+    fn write(&mut self, path: &Path, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("can't upload to {}", path.display()),
+        ))
+    }
+}
+
+/// A hidden name next to `path` for files that get renamed into place once they're complete.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn temp_path_for(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Writes `bytes` to a temporary file in the same directory and renames it over `path`, so
+/// `path` ends up either untouched or completely written, never cut off halfway.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), io::Error> {
+    let temp = temp_path_for(path);
+    let write = || -> Result<(), io::Error> {
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+
+        out.write_all(bytes)?;
+        out.sync_all()?;
+        if let Ok(existing) = fs::metadata(path) {
+            fs::set_permissions(&temp, existing.permissions())?;
+        }
+
+        fs::rename(&temp, path)
+    };
+
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Files kept in memory, like the ones dropped into the browser, which has no file system to
+/// speak of.
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+pub struct Memory {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Storage for Memory {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was never opened", path.display()),
+            )
+        })
+    }
+
+    fn write(&mut self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        self.files.insert(path.to_path_buf(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bruh-storage-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writes_replace_files_and_leave_no_temporary_ones() {
+        let dir = dir("replace");
+        let path = dir.join("a.bruh");
+        Disk.write(&path, b"first").unwrap();
+        Disk.write(&path, b"second").unwrap();
+        assert_eq!(Disk.read(&path).unwrap(), b"second");
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.bruh"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_old_file() {
+        let dir = dir("failed");
+        let path = dir.join("a.bruh");
+        fs::write(&path, b"old").unwrap();
+        // A leftover temporary file of this process makes the write fail before it renames.
+        fs::write(temp_path_for(&path), b"").unwrap();
+        assert!(Disk.write(&path, b"new").is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert!(Disk.write(&dir.join("missing/a.bruh"), b"new").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaced_files_keep_their_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = dir("permissions");
+        let path = dir.join("a.bruh");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        Disk.write(&path, b"new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn urls_are_named_after_their_last_part() {
+        assert!(is_url(Path::new("https://example.com/a.bruh")));
+        assert!(!is_url(Path::new("example.com/a.bruh")));
+        assert_eq!(
+            url_file_name(Path::new("http://example.com/img/a.bruh?size=2#top")),
+            Path::new("a.bruh")
+        );
+    }
+}
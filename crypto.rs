@@ -0,0 +1,248 @@
+//! The primitives behind encrypted and signed files: XChaCha20-Poly1305 for the pixels and
+//! Argon2id to turn passwords into keys, both from RustCrypto, and Ed25519 from `ed25519-dalek`
+//! for signatures. SHA-512 is for the checksums of `compile --manifest`, and MD5 is only here
+//! because the freedesktop thumbnail cache names files by it, it protects nothing.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use md5::Md5;
+use sha2::{Digest, Sha512};
+
+/// Authentication tag size of XChaCha20-Poly1305.
+pub const TAG_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 24;
+pub const KEY_SIZE: usize = 32;
+
+/// Ed25519 secret keys are the 32 byte seed the key pair is derived from.
+pub const SECRET_KEY_SIZE: usize = 32;
+pub const PUBLIC_KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// The `out_len` byte Argon2id hash of `password`, or `None` if the parameters are out of what
+/// Argon2 allows.
+pub fn argon2id(
+    password: &[u8],
+    salt: &[u8],
+    memory_kib: u32,
+    passes: u32,
+    lanes: u32,
+    out_len: usize,
+) -> Option<Vec<u8>> {
+    let params = Params::new(memory_kib, passes, lanes, Some(out_len)).ok()?;
+    let mut out = vec![0; out_len];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, salt, &mut out)
+        .ok()?;
+    Some(out)
+}
+
+/// Encrypts `plaintext` and authenticates it together with `aad`, returning the ciphertext
+/// followed by the tag.
+pub fn seal(
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let payload = Payload {
+        msg: plaintext,
+        aad,
+    };
+    XChaCha20Poly1305::new(key.into())
+        .encrypt(XNonce::from_slice(nonce), payload)
+        .expect("XChaCha20-Poly1305 takes messages of up to 256 GiB")
+}
+
+/// Checks and decrypts what `seal` made, or returns `None` if the key is wrong or anything was
+/// changed.
+pub fn open(
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    let payload = Payload { msg: sealed, aad };
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .ok()
+}
+
+/// SHA-512 of the concatenated `parts`.
+pub fn sha512(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// MD5 of `message`.
+pub fn md5(message: &[u8]) -> [u8; 16] {
+    Md5::digest(message).into()
+}
+
+pub fn public_key(secret: &[u8; SECRET_KEY_SIZE]) -> [u8; PUBLIC_KEY_SIZE] {
+    SigningKey::from_bytes(secret).verifying_key().to_bytes()
+}
+
+/// The Ed25519 signature of `message`.
+pub fn sign(secret: &[u8; SECRET_KEY_SIZE], message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+    SigningKey::from_bytes(secret).sign(message).to_bytes()
+}
+
+/// Whether `signature` is a valid Ed25519 signature of `message` by `public`. Weak keys and
+/// signatures that only some implementations accept are refused.
+pub fn verify(
+    public: &[u8; PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_SIZE],
+) -> bool {
+    VerifyingKey::from_bytes(public).is_ok_and(|public| {
+        public
+            .verify_strict(message, &Signature::from_bytes(signature))
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    //! Known answers from RFC 8032, RFC 1321, draft-irtf-cfrg-xchacha and OpenSSL, so files
+    //! written before keep opening.
+
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn xchacha20_poly1305_round_trips_and_detects_changes() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let nonce: [u8; 24] = core::array::from_fn(|i| 0x40 + i as u8);
+        assert_eq!(
+            seal(&key, &nonce, b"", b""),
+            hex("61d8489bd58cfbe3ec6d5913671c3779")
+        );
+
+        let plaintext = vec![7; 300];
+        let sealed = seal(&key, &nonce, b"aad", &plaintext);
+        assert_eq!(&sealed[..16], &hex("d33e0277d7e77e1188f380b9a89b6295")[..]);
+        assert_eq!(&sealed[300..], &hex("4b068a8abb98c3c12633accfb5f4f9f3")[..]);
+        assert_eq!(open(&key, &nonce, b"aad", &sealed), Some(plaintext));
+
+        let mut tampered = sealed.clone();
+        tampered[5] ^= 1;
+        assert_eq!(open(&key, &nonce, b"aad", &tampered), None);
+        assert_eq!(open(&key, &nonce, b"other", &sealed), None);
+    }
+
+    #[test]
+    fn argon2id_matches_openssl() {
+        assert_eq!(
+            argon2id(b"password", b"somesalt12345678", 64, 3, 1, 32),
+            Some(hex(
+                "bfbd88e639fa991331f6e10953221dbd873896e754050e45d3871dfbb08b75f3"
+            ))
+        );
+        assert_eq!(
+            argon2id(b"password", b"somesalt12345678", 256, 2, 4, 32),
+            Some(hex(
+                "288d953d9da638ecec019ea558ceb278bdacbf3001cef0db14e2cecc9a57a9a4"
+            ))
+        );
+        // Less memory than the lanes need.
+        assert_eq!(
+            argon2id(b"password", b"somesalt12345678", 8, 2, 4, 32),
+            None
+        );
+    }
+
+    #[test]
+    fn sha512_matches_fips_180() {
+        assert_eq!(
+            sha512(&[b"abc"]).to_vec(),
+            hex(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+        );
+    }
+
+    #[test]
+    fn md5_matches_rfc_1321() {
+        let cases: [(&[u8], &str); 4] = [
+            (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        for (message, digest) in cases {
+            assert_eq!(md5(message).to_vec(), hex(digest));
+        }
+    }
+
+    #[test]
+    fn ed25519_matches_rfc_8032() {
+        let secret: [u8; 32] =
+            hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .try_into()
+                .unwrap();
+        let public = public_key(&secret);
+        assert_eq!(
+            public.to_vec(),
+            hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+        );
+        let signature = sign(&secret, b"");
+        assert_eq!(
+            signature.to_vec(),
+            hex(
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                 5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+            )
+        );
+        assert!(verify(&public, b"", &signature));
+    }
+
+    #[test]
+    fn ed25519_rejects_changed_messages_and_signatures() {
+        let secret: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let message: Vec<u8> = (0..300).map(|i| (i % 251) as u8).collect();
+        let public = public_key(&secret);
+        let signature = sign(&secret, &message);
+        assert_eq!(
+            signature.to_vec(),
+            hex(
+                "bee060e289b2e20e361555db5f92385da7711359721295c9bf0c5804442bbf98\
+                 a07212fbffd800d76eb996d28c0282993e431dba9048e2ea20a9ea6cdd235a02"
+            )
+        );
+        assert!(verify(&public, &message, &signature));
+
+        assert!(!verify(&public, &message[1..], &signature));
+        let mut changed = signature;
+        changed[40] ^= 1;
+        assert!(!verify(&public, &message, &changed));
+        assert!(!verify(&public_key(&[1; 32]), &message, &signature));
+    }
+
+    #[test]
+    fn ed25519_rejects_weak_keys() {
+        // The neutral point as the key and as R with s = 0 satisfies the verification equation
+        // for every message, only strict verification refuses it.
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let mut signature = [0; SIGNATURE_SIZE];
+        signature[0] = 1;
+        assert!(!verify(&identity, b"anything", &signature));
+    }
+}
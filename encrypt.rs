@@ -0,0 +1,266 @@
+//! Password protection for `bruh compile --encrypt`. The key is derived from the password with
+//! Argon2id and every chunk is sealed on its own with XChaCha20-Poly1305, so a wrong password and
+//! a damaged file are both caught by the tag instead of producing garbage pixels. See the format
+//! docs for the layout of the `CRYP` chunk.
+//!
+//! Metadata, EXIF data, thumbnails and mipmaps are sealed just like the pixels. What stays
+//! readable without the password is the header (the size, pixel format, compression and flags of
+//! the image), the tag and length of every chunk, the `CRYP` chunk and a `sign` chunk added after
+//! encrypting. Each chunk is authenticated together with the header, its position and the number
+//! of chunks, so chunks can't be moved, swapped between files or dropped either.
+
+use std::{
+    env,
+    io::{self, BufRead, Write},
+};
+
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    crypto::{self, KEY_SIZE, NONCE_SIZE, TAG_SIZE},
+    format::{BruhFile, Chunk, ENCRYPTION, SIGNATURE},
+};
+
+const SALT_SIZE: usize = 16;
+const PARAMS_SIZE: usize = SALT_SIZE + 12;
+
+// The parameters OWASP recommends for Argon2id: 19 MiB, two passes, one lane.
+const MEMORY_KIB: u32 = 19 * 1024;
+const PASSES: u32 = 2;
+const LANES: u32 = 1;
+
+// Files from elsewhere can ask for anything, these keep them from taking minutes or gigabytes.
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_PASSES: u32 = 16;
+
+/// Set to use a password without being asked for one, for scripts.
+pub const PASSWORD_VAR: &str = "BRUH_PASSWORD";
+
+/// A copy of `file` with every chunk encrypted with `password`. A signature is dropped, it
+/// wouldn't match the encrypted file.
+pub fn encrypt(file: &BruhFile, password: &str) -> BruhFile {
+    let mut salt = [0; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let mut params = salt.to_vec();
+    for value in [MEMORY_KIB, PASSES, LANES] {
+        params.extend_from_slice(&value.to_le_bytes());
+    }
+    let key = derive_key(password, &salt, MEMORY_KIB, PASSES, LANES)
+        .expect("the default parameters are valid");
+
+    let mut header = file.header.clone();
+    header.encrypted = true;
+    let header_bytes = header.to_bytes();
+    let sealed: Vec<_> = file
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.tag != SIGNATURE)
+        .collect();
+    let mut chunks = vec![Chunk::new(ENCRYPTION, params)];
+    for (index, chunk) in sealed.iter().enumerate() {
+        let mut nonce = [0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let aad = associated_data(&header_bytes, index, sealed.len(), chunk.tag);
+        let mut data = nonce.to_vec();
+        data.extend(crypto::seal(&key, &nonce, &aad, &chunk.data));
+        chunks.push(Chunk::new(chunk.tag, data));
+    }
+
+    BruhFile { header, chunks }
+}
+
+/// A copy of the encrypted `file` with the chunks decrypted again. A signature is dropped, it
+/// was made over the encrypted file.
+pub fn decrypt(file: &BruhFile, password: &str) -> Result<BruhFile, &'static str> {
+    let params = &file
+        .chunk(ENCRYPTION)
+        .ok_or("the CRYP chunk is missing")?
+        .data;
+    if params.len() != PARAMS_SIZE {
+        return Err("the CRYP chunk is damaged");
+    }
+    let param = |index: usize| {
+        let start = SALT_SIZE + index * 4;
+        u32::from_le_bytes(params[start..start + 4].try_into().unwrap())
+    };
+    let (memory_kib, passes, lanes) = (param(0), param(1), param(2));
+    if !(1..=MAX_PASSES).contains(&passes)
+        || !(1..=0xff_ffff).contains(&lanes)
+        || memory_kib < 8 * lanes
+        || memory_kib > MAX_MEMORY_KIB
+    {
+        return Err("the key derivation parameters are out of range");
+    }
+    let key = derive_key(password, &params[..SALT_SIZE], memory_kib, passes, lanes)
+        .ok_or("the key derivation parameters are out of range")?;
+
+    let mut header = file.header.clone();
+    let header_bytes = header.to_bytes();
+    let sealed: Vec<_> = file
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.tag != ENCRYPTION && chunk.tag != SIGNATURE)
+        .collect();
+    let mut chunks = Vec::with_capacity(sealed.len());
+    for (index, chunk) in sealed.iter().enumerate() {
+        if chunk.data.len() < NONCE_SIZE + TAG_SIZE {
+            return Err("an encrypted chunk is cut off");
+        }
+        let (nonce, data) = chunk.data.split_at(NONCE_SIZE);
+        let aad = associated_data(&header_bytes, index, sealed.len(), chunk.tag);
+        let data = crypto::open(&key, nonce.try_into().unwrap(), &aad, data)
+            .ok_or("wrong password, or the file is damaged")?;
+        chunks.push(Chunk::new(chunk.tag, data));
+    }
+
+    header.encrypted = false;
+    Ok(BruhFile { header, chunks })
+}
+
+/// Asks for a password on the terminal without echoing it, or takes it from `BRUH_PASSWORD`.
+pub fn read_password(prompt: &str) -> io::Result<String> {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return Ok(password);
+    }
+
+    let mut stderr = io::stderr();
+    write!(stderr, "{}", prompt)?;
+    stderr.flush()?;
+    let line = read_hidden_line()?;
+    writeln!(stderr)?;
+
+    let password = line.trim_end_matches(['\r', '\n']).to_owned();
+    if password.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no password entered",
+        ));
+    }
+    Ok(password)
+}
+
+fn derive_key(
+    password: &str,
+    salt: &[u8],
+    memory_kib: u32,
+    passes: u32,
+    lanes: u32,
+) -> Option<[u8; KEY_SIZE]> {
+    let key = crypto::argon2id(
+        password.as_bytes(),
+        salt,
+        memory_kib,
+        passes,
+        lanes,
+        KEY_SIZE,
+    )?;
+    key.try_into().ok()
+}
+
+/// What a chunk is authenticated with besides its data: the header, which of the `count` sealed
+/// chunks it is and its tag.
+fn associated_data(header: &[u8], index: usize, count: usize, tag: [u8; 4]) -> Vec<u8> {
+    [
+        header,
+        &(index as u32).to_le_bytes(),
+        &(count as u32).to_le_bytes(),
+        &tag,
+    ]
+    .concat()
+}
+
+/// Reads a line from the terminal with echo turned off, falling back to plain stdin when there
+/// is no terminal.
+#[cfg(unix)]
+fn read_hidden_line() -> io::Result<String> {
+    use std::{fs::OpenOptions, io::BufReader, os::unix::io::AsRawFd};
+
+    let Ok(tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        return read_line(io::stdin().lock());
+    };
+    let fd = tty.as_raw_fd();
+
+    // SAFETY: tcgetattr and tcsetattr only read and write the termios struct they are given.
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return read_line(BufReader::new(tty));
+    }
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) };
+
+    let line = read_line(BufReader::new(&tty));
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    line
+}
+
+#[cfg(not(unix))]
+fn read_hidden_line() -> io::Result<String> {
+    read_line(io::stdin().lock())
+}
+
+fn read_line(mut reader: impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Compression, EXIF, METADATA, PIXELS, THUMBNAIL};
+
+    fn file() -> BruhFile {
+        let mut file = BruhFile::from_rgba(2, 1, &[1, 2, 3, 255, 4, 5, 6, 255], Compression::None);
+        file.set_metadata_value("author", Some("me"));
+        file.set_exif(Some(b"Exif\0\0secret".to_vec()));
+        file.set_thumbnail(&BruhFile::from_rgba(
+            1,
+            1,
+            &[1, 2, 3, 255],
+            Compression::None,
+        ));
+        file
+    }
+
+    #[test]
+    fn every_chunk_is_sealed_and_opened_again() {
+        let file = file();
+        let encrypted = encrypt(&file, "hunter2");
+        assert!(encrypted.header.encrypted);
+        for tag in [PIXELS, METADATA, EXIF, THUMBNAIL] {
+            let plain = &file.chunk(tag).unwrap().data;
+            let sealed = &encrypted.chunk(tag).unwrap().data;
+            assert_eq!(sealed.len(), NONCE_SIZE + plain.len() + TAG_SIZE);
+            assert!(!sealed
+                .windows(plain.len())
+                .any(|window| window == &plain[..]));
+        }
+
+        let decrypted = decrypt(&encrypted, "hunter2").unwrap();
+        assert_eq!(decrypted.to_bytes(), file.to_bytes());
+        assert!(decrypt(&encrypted, "hunter3").is_err());
+    }
+
+    #[test]
+    fn moved_dropped_and_changed_chunks_are_caught() {
+        let encrypted = encrypt(&file(), "hunter2");
+
+        let mut swapped = encrypted.clone();
+        swapped.chunks.swap(1, 2);
+        assert!(decrypt(&swapped, "hunter2").is_err());
+
+        let mut dropped = encrypted.clone();
+        dropped.chunks.pop();
+        assert!(decrypt(&dropped, "hunter2").is_err());
+
+        let mut resized = encrypted.clone();
+        resized.header.width = 1;
+        resized.header.height = 2;
+        assert!(decrypt(&resized, "hunter2").is_err());
+
+        let mut changed = encrypted;
+        changed.chunks[1].data[NONCE_SIZE] ^= 1;
+        assert!(decrypt(&changed, "hunter2").is_err());
+    }
+}
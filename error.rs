@@ -0,0 +1,149 @@
+//! Errors reported by the command line. Each kind maps to its own exit code so scripts can tell
+//! a typo in the arguments apart from a missing file or a corrupt image.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::format::DecodeError;
+
+#[derive(Debug, Error)]
+pub enum BruhError {
+    /// The arguments don't make sense, nothing was touched.
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("invalid configuration in {}: {message}", path.display())]
+    Config { path: PathBuf, message: String },
+
+    #[error("couldn't access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("couldn't decode {}", path.display())]
+    Decode {
+        path: PathBuf,
+        #[source]
+        source: DecodeError,
+    },
+
+    #[error("couldn't read image {}", path.display())]
+    Image {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+
+    #[error("clipboard unavailable")]
+    Clipboard(#[from] arboard::Error),
+
+    /// The command ran, but its answer is "no", e.g. `compare` on images that differ.
+    #[error("{0}")]
+    Failed(String),
+
+    /// Some inputs of a command working on many files failed, each was already reported.
+    #[error("{failed} of {total} file(s) failed")]
+    Batch {
+        failed: usize,
+        total: usize,
+        /// Exit code of the first failure.
+        code: i32,
+    },
+}
+
+impl BruhError {
+    pub fn usage(message: impl Into<String>) -> Self {
+        BruhError::Usage(message.into())
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        BruhError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn decode(path: &Path, source: DecodeError) -> Self {
+        BruhError::Decode {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// 1 for failed checks, 2 for bad arguments or configuration, 3 for I/O and 4 for undecodable files.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BruhError::Failed(_) => 1,
+            BruhError::Usage(_) | BruhError::Config { .. } => 2,
+            BruhError::Io { .. } | BruhError::Clipboard(_) => 3,
+            BruhError::Decode { .. } | BruhError::Image { .. } => 4,
+            BruhError::Batch { code, .. } => *code,
+        }
+    }
+
+    /// The error along with everything that caused it, one cause per line.
+    pub fn message(&self) -> String {
+        let mut message = self.to_string();
+
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            message.push_str(&format!("\n  caused by: {}", cause));
+            source = cause.source();
+        }
+        message
+    }
+
+    /// Logs the error along with everything that caused it.
+    pub fn report(&self) {
+        log::error!("{}", self.message());
+    }
+}
+
+/// Turns the failures of a command that kept going over `total` inputs, given by their exit
+/// codes, into its result.
+pub fn batch_result(total: usize, failures: &[i32]) -> Result<(), BruhError> {
+    match failures.first() {
+        None => Ok(()),
+        Some(&code) => Err(BruhError::Batch {
+            failed: failures.len(),
+            total,
+            code,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_kind_of_error_has_its_exit_code() {
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(BruhError::Failed("differ".into()).exit_code(), 1);
+        assert_eq!(BruhError::usage("typo").exit_code(), 2);
+        assert_eq!(BruhError::io(Path::new("a.bruh"), missing).exit_code(), 3);
+        let corrupt = BruhError::decode(Path::new("a.bruh"), DecodeError::Invalid("bad".into()));
+        assert_eq!(corrupt.exit_code(), 4);
+    }
+
+    #[test]
+    fn messages_include_their_causes() {
+        let missing = io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let message = BruhError::io(Path::new("a.bruh"), missing).message();
+        assert_eq!(message, "couldn't access a.bruh\n  caused by: no such file");
+    }
+
+    #[test]
+    fn batches_fail_with_the_code_of_the_first_failure() {
+        assert!(batch_result(3, &[]).is_ok());
+        let e = batch_result(3, &[4, 3]).unwrap_err();
+        assert_eq!(e.exit_code(), 4);
+        assert_eq!(e.to_string(), "2 of 3 file(s) failed");
+    }
+}
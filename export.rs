@@ -0,0 +1,737 @@
+//! Other formats for `bruh decode`, so animations can leave BRUH with their frames and timing
+//! intact. GIF and WebP are small enough to write here, APNG comes from the `png` crate.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use image::RgbaImage;
+use png::{BitDepth, ColorType, Encoder};
+
+use crate::{format::Frame, ops};
+
+/// Writes `frames` as a file in some format, played `loops` times, 0 for forever.
+pub type Export = fn(u32, u32, &[Frame], u32) -> Result<Vec<u8>, String>;
+
+/// The PNG signature and the `IHDR` chunk, which every encoder writes first.
+const PNG_HEADER_SIZE: usize = 8 + 25;
+
+/// A gamma of 1.0 in the 100000ths `gAMA` uses.
+const LINEAR_GAMMA: u32 = 100_000;
+
+/// Pixels with less alpha are transparent in GIF, which has nothing in between.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// The first frame as a plain PNG.
+pub fn png(width: u32, height: u32, frames: &[Frame], _loops: u32) -> Result<Vec<u8>, String> {
+    apng(width, height, &frames[..1], 0)
+}
+
+/// An animated PNG, which keeps full color and alpha. A single frame gives a plain PNG.
+pub fn apng(width: u32, height: u32, frames: &[Frame], loops: u32) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = Encoder::new(&mut out, width, height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    if frames.len() > 1 {
+        encoder
+            .set_animated(frames.len() as u32, loops)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for frame in frames {
+        if frames.len() > 1 {
+            // Delays are fractions of a second with 16 bit parts.
+            let (numerator, denominator) = match frame.delay_ms {
+                ms if ms <= u16::MAX as u32 => (ms as u16, 1000),
+                ms => ((ms / 100).min(u16::MAX as u32) as u16, 10),
+            };
+            writer
+                .set_frame_delay(numerator, denominator)
+                .map_err(|e| e.to_string())?;
+        }
+        writer
+            .write_image_data(&frame.rgba)
+            .map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// Adds a `pHYs` chunk to `png` so it prints at `pixels_per_inch`. PNG counts pixels per meter.
+pub fn set_png_pixels_per_inch(png: &mut Vec<u8>, (x, y): (u32, u32)) {
+    let per_meter = |ppi: u32| (ppi as f64 / 0.0254).round().min(u32::MAX as f64) as u32;
+    let mut data = per_meter(x).to_be_bytes().to_vec();
+    data.extend_from_slice(&per_meter(y).to_be_bytes());
+    data.push(1);
+    insert_png_chunk(png, b"pHYs", &data);
+}
+
+/// Adds a `gAMA` chunk of 1.0 to `png`, which tells viewers its colors are linear.
+pub fn set_png_linear(png: &mut Vec<u8>) {
+    insert_png_chunk(png, b"gAMA", &LINEAR_GAMMA.to_be_bytes());
+}
+
+/// Adds a `tEXt` chunk to `png` holding `text` under `keyword`, which has to be Latin-1.
+pub fn set_png_text(png: &mut Vec<u8>, keyword: &str, text: &str) {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    data.extend_from_slice(text.as_bytes());
+    insert_png_chunk(png, b"tEXt", &data);
+}
+
+/// Whether `png` says its colors are linear with a gamma of 1.0.
+pub fn png_is_linear(png: &[u8]) -> bool {
+    png::Decoder::new(png).read_info().is_ok_and(|reader| {
+        reader
+            .info()
+            .source_gamma
+            .map(png::ScaledFloat::into_scaled)
+            == Some(LINEAR_GAMMA)
+    })
+}
+
+/// The pixels per inch the `pHYs` chunk of `png` asks for, if it has one in meters.
+pub fn png_pixels_per_inch(png: &[u8]) -> Option<(u32, u32)> {
+    let reader = png::Decoder::new(png).read_info().ok()?;
+    let dims = reader.info().pixel_dims?;
+    if dims.unit != png::Unit::Meter {
+        return None;
+    }
+    let per_inch = |ppm: u32| (ppm as f64 * 0.0254).round() as u32;
+    Some((per_inch(dims.xppu), per_inch(dims.yppu)))
+}
+
+/// Adds an `eXIf` chunk with `exif` to `png`.
+pub fn set_png_exif(png: &mut Vec<u8>, exif: &[u8]) {
+    insert_png_chunk(png, b"eXIf", exif);
+}
+
+/// The data of the `eXIf` chunk of `png`, if it has one.
+pub fn png_exif(png: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = 8;
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let tag = &png[offset + 4..offset + 8];
+        let data = png.get(offset + 8..(offset + 8).checked_add(length)?)?;
+        match tag {
+            b"eXIf" => return Some(data.to_vec()),
+            b"IEND" => return None,
+            _ => offset += 12 + length,
+        }
+    }
+    None
+}
+
+/// The EXIF orientation, 1 to 8, if `exif` has one.
+pub fn exif_orientation(exif: &[u8]) -> Option<u16> {
+    let (offset, little_endian) = orientation_offset(exif)?;
+    let bytes = exif[offset..offset + 2].try_into().unwrap();
+    Some(if little_endian {
+        u16::from_le_bytes(bytes)
+    } else {
+        u16::from_be_bytes(bytes)
+    })
+}
+
+/// Sets the EXIF orientation to 1, for images that were turned to match it.
+pub fn reset_exif_orientation(exif: &mut [u8]) {
+    if let Some((offset, little_endian)) = orientation_offset(exif) {
+        let value = if little_endian {
+            1u16.to_le_bytes()
+        } else {
+            1u16.to_be_bytes()
+        };
+        exif[offset..offset + 2].copy_from_slice(&value);
+    }
+}
+
+/// Where the value of the orientation tag in the first IFD of `exif` is, and whether the TIFF
+/// data is little endian.
+fn orientation_offset(exif: &[u8]) -> Option<(usize, bool)> {
+    let little_endian = match exif.get(..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = exif.get(offset..offset + 2)?.try_into().unwrap();
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = exif.get(offset..offset + 4)?.try_into().unwrap();
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    const ORIENTATION: u16 = 0x0112;
+    const SHORT: u16 = 3;
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION))
+        .filter(|&entry| u16_at(entry + 2) == Some(SHORT))
+        .map(|entry| (entry + 8, little_endian))
+        .filter(|&(offset, _)| offset + 2 <= exif.len())
+}
+
+/// Inserts a chunk right after the signature and `IHDR`, before the pixels as `pHYs` and `gAMA`
+/// have to be and `eXIf` may be.
+fn insert_png_chunk(png: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(tag);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+    png.splice(PNG_HEADER_SIZE..PNG_HEADER_SIZE, chunk);
+}
+
+/// An animated GIF. Every frame gets its own palette of up to 256 colors, dithered if the frame
+/// has more, and alpha is reduced to on or off.
+pub fn gif(width: u32, height: u32, frames: &[Frame], loops: u32) -> Result<Vec<u8>, String> {
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("GIF images can be at most {0}x{0}", u16::MAX));
+    }
+
+    let mut out = b"GIF89a".to_vec();
+    out.extend_from_slice(&(width as u16).to_le_bytes());
+    out.extend_from_slice(&(height as u16).to_le_bytes());
+    // No global color table, every frame brings its own.
+    out.extend_from_slice(&[0, 0, 0]);
+
+    // GIF counts the repeats after the first time.
+    if frames.len() > 1 && loops != 1 {
+        let repeats = loops.saturating_sub(1).min(u16::MAX as u32) as u16;
+        out.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01");
+        out.extend_from_slice(&repeats.to_le_bytes());
+        out.push(0);
+    }
+
+    for frame in frames {
+        let (palette, indices, transparent) = gif_palette(width, height, &frame.rgba);
+
+        // Graphic control: clear to the background afterwards, so transparent pixels of the next
+        // frame don't show this one, then the delay in hundredths of a second.
+        let delay = frame.delay_ms.div_ceil(10).min(u16::MAX as u32) as u16;
+        out.extend_from_slice(&[0x21, 0xf9, 4, (2 << 2) | u8::from(transparent.is_some())]);
+        out.extend_from_slice(&delay.to_le_bytes());
+        out.extend_from_slice(&[transparent.unwrap_or(0), 0]);
+
+        // The image covers the whole screen, with a local color table of 256 entries.
+        out.push(0x2c);
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&(width as u16).to_le_bytes());
+        out.extend_from_slice(&(height as u16).to_le_bytes());
+        out.push(0x80 | 7);
+        for i in 0..256 {
+            out.extend_from_slice(&palette.get(i).copied().unwrap_or_default());
+        }
+
+        out.push(8);
+        for block in lzw_encode(&indices).chunks(255) {
+            out.push(block.len() as u8);
+            out.extend_from_slice(block);
+        }
+        out.push(0);
+    }
+
+    out.push(0x3b);
+    Ok(out)
+}
+
+/// A palette of at most 256 colors for `rgba`, the index of every pixel and the index standing
+/// for transparency, if anything is transparent.
+fn gif_palette(width: u32, height: u32, rgba: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>, Option<u8>) {
+    let visible = |pixel: &[u8]| pixel[3] >= ALPHA_THRESHOLD;
+    let has_transparency = rgba.chunks(4).any(|pixel| !visible(pixel));
+    let colors = if has_transparency { 255 } else { 256 };
+
+    // Frames with few colors, like pixel art and screenshots, keep them exactly.
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    for pixel in rgba.chunks(4).filter(|pixel| visible(pixel)) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        if let Entry::Vacant(entry) = lookup.entry(color) {
+            if palette.len() == colors {
+                palette.clear();
+                break;
+            }
+            entry.insert(palette.len() as u8);
+            palette.push(color);
+        }
+    }
+
+    let mut indices: Vec<u8> = if palette.is_empty() && !lookup.is_empty() {
+        let img = RgbaImage::from_raw(width, height, rgba.to_vec()).unwrap();
+        let (quantized, indices) = ops::quantize(&img, colors, true);
+        palette = quantized.iter().map(|&[r, g, b, _]| [r, g, b]).collect();
+        indices
+    } else {
+        rgba.chunks(4)
+            .map(|pixel| {
+                lookup
+                    .get(&[pixel[0], pixel[1], pixel[2]])
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect()
+    };
+
+    let transparent = has_transparency.then_some(255);
+    if let Some(index) = transparent {
+        for (i, pixel) in rgba.chunks(4).enumerate() {
+            if !visible(pixel) {
+                indices[i] = index;
+            }
+        }
+    }
+    (palette, indices, transparent)
+}
+
+/// Accumulates codes of varying width, least significant bit first, the way both GIF and WebP
+/// pack them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// GIF's LZW compression of 8 bit palette indices.
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u32 = 256;
+    const END: u32 = 257;
+    const MAX_CODES: u32 = 4096;
+
+    let mut out = BitWriter::default();
+    // The code for a string followed by a byte, 0 where there is none yet.
+    let mut table = vec![0u16; MAX_CODES as usize * 256];
+    let mut size = 9;
+    let mut next = END + 1;
+    out.write(CLEAR, size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        out.write(END, size);
+        return out.finish();
+    };
+    let mut current = first as u32;
+    for &index in rest {
+        let slot = current as usize * 256 + index as usize;
+        if table[slot] != 0 {
+            current = table[slot] as u32;
+            continue;
+        }
+
+        out.write(current, size);
+        // The decoder adds its entries one code later, so the width grows once the table is
+        // full before this entry.
+        if next >= 1 << size && size < 12 {
+            size += 1;
+        }
+        if next >= MAX_CODES - 1 {
+            out.write(CLEAR, size);
+            table.fill(0);
+            size = 9;
+            next = END + 1;
+        } else {
+            table[slot] = next as u16;
+            next += 1;
+        }
+        current = index as u32;
+    }
+
+    out.write(current, size);
+    if next >= 1 << size && size < 12 {
+        size += 1;
+    }
+    out.write(END, size);
+    out.finish()
+}
+
+/// A lossless WebP, animated if there is more than one frame.
+pub fn webp(width: u32, height: u32, frames: &[Frame], loops: u32) -> Result<Vec<u8>, String> {
+    const MAX_SIZE: u32 = 1 << 14;
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!(
+            "WebP images have to be between 1x1 and {0}x{0}",
+            MAX_SIZE
+        ));
+    }
+
+    let mut body = Vec::new();
+    if frames.len() == 1 {
+        push_riff_chunk(&mut body, b"VP8L", &vp8l(width, height, &frames[0].rgba));
+    } else {
+        let alpha = frames
+            .iter()
+            .any(|frame| frame.rgba.chunks(4).any(|pixel| pixel[3] != 255));
+        let mut header = vec![0x02 | if alpha { 0x10 } else { 0 }, 0, 0, 0];
+        header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        push_riff_chunk(&mut body, b"VP8X", &header);
+
+        // A transparent background, then the loop count.
+        let mut animation = vec![0; 4];
+        animation.extend_from_slice(&(loops.min(u16::MAX as u32) as u16).to_le_bytes());
+        push_riff_chunk(&mut body, b"ANIM", &animation);
+
+        for frame in frames {
+            // At the origin, the whole canvas, replacing the previous frame without blending.
+            let mut data = vec![0; 6];
+            data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            data.extend_from_slice(&frame.delay_ms.min(0xff_ffff).to_le_bytes()[..3]);
+            data.push(0x02);
+            push_riff_chunk(&mut data, b"VP8L", &vp8l(width, height, &frame.rgba));
+            push_riff_chunk(&mut body, b"ANMF", &data);
+        }
+    }
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn push_riff_chunk(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// A VP8L bitstream: every pixel stored as a literal, with a Huffman code per channel. No
+/// transforms or back references, which keeps it simple at the cost of some size.
+fn vp8l(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    out.write(0x2f, 8);
+    out.write(width - 1, 14);
+    out.write(height - 1, 14);
+    out.write(u32::from(rgba.chunks(4).any(|pixel| pixel[3] != 255)), 1);
+    out.write(0, 3);
+    // No transforms, no color cache, a single group of codes for the whole image.
+    out.write(0, 1);
+    out.write(0, 1);
+    out.write(0, 1);
+
+    // Green, red, blue and alpha take literals in that order. The green alphabet also holds the
+    // 24 length codes and the distance code gets a single symbol, neither is ever used here.
+    let channels = [1, 0, 2, 3];
+    let mut codes = Vec::new();
+    for (i, &channel) in channels.iter().enumerate() {
+        let mut counts = vec![0u32; if i == 0 { 256 + 24 } else { 256 }];
+        for pixel in rgba.chunks(4) {
+            counts[pixel[channel] as usize] += 1;
+        }
+        codes.push(write_prefix_code(&mut out, &counts));
+    }
+    write_prefix_code(&mut out, &[0; 40]);
+
+    for pixel in rgba.chunks(4) {
+        for (code, &channel) in codes.iter().zip(&channels) {
+            let (bits, length) = code[pixel[channel] as usize];
+            out.write(bits, length);
+        }
+    }
+    out.finish()
+}
+
+/// Writes a prefix code for symbols appearing `counts` times and returns the code of every
+/// symbol, already bit reversed for writing, with its length.
+fn write_prefix_code(out: &mut BitWriter, counts: &[u32]) -> Vec<(u32, u32)> {
+    let used: Vec<usize> = (0..counts.len()).filter(|&s| counts[s] > 0).collect();
+
+    // One or two symbols below 256 fit the short form. A single symbol takes no bits at all.
+    if used.len() <= 2 && used.iter().all(|&s| s < 256) {
+        let symbols = if used.is_empty() { vec![0] } else { used };
+        out.write(1, 1);
+        out.write(symbols.len() as u32 - 1, 1);
+        out.write(1, 1);
+        for &symbol in &symbols {
+            out.write(symbol as u32, 8);
+        }
+        let mut lengths = vec![0; counts.len()];
+        if symbols.len() == 2 {
+            symbols.iter().for_each(|&s| lengths[s] = 1);
+        }
+        return canonical_codes(&lengths);
+    }
+
+    let lengths = code_lengths(counts, 15);
+
+    // The lengths themselves are written with another prefix code, whose lengths come first in a
+    // fixed order.
+    const ORDER: [usize; 19] = [
+        17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    ];
+    let mut length_counts = vec![0u32; 19];
+    lengths.iter().for_each(|&l| length_counts[l as usize] += 1);
+    if length_counts.iter().filter(|&&c| c > 0).count() < 2 {
+        // A code needs two symbols to be complete, so add one that is never used.
+        let unused = length_counts.iter().position(|&c| c == 0).unwrap();
+        length_counts[unused] = 1;
+    }
+    let length_lengths = code_lengths(&length_counts, 7);
+    let length_codes = canonical_codes(&length_lengths);
+
+    out.write(0, 1);
+    out.write(19 - 4, 4);
+    for &symbol in &ORDER {
+        out.write(length_lengths[symbol], 3);
+    }
+    // Lengths follow for the whole alphabet.
+    out.write(0, 1);
+    for &length in &lengths {
+        let (bits, size) = length_codes[length as usize];
+        out.write(bits, size);
+    }
+
+    canonical_codes(&lengths)
+}
+
+/// Huffman code lengths for symbols appearing `counts` times, at most `limit` bits long. At least
+/// two symbols have to appear.
+fn code_lengths(counts: &[u32], limit: u32) -> Vec<u32> {
+    let mut counts = counts.to_vec();
+    loop {
+        let lengths = huffman_lengths(&counts);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        // Evening out the counts flattens the tree, halving them until it fits.
+        for count in counts.iter_mut().filter(|c| **c > 0) {
+            *count = (*count / 2).max(1);
+        }
+    }
+}
+
+fn huffman_lengths(counts: &[u32]) -> Vec<u32> {
+    // Nodes are (weight, symbols below it), merged two lightest at a time.
+    let mut nodes: Vec<(u64, Vec<usize>)> = (0..counts.len())
+        .filter(|&s| counts[s] > 0)
+        .map(|s| (counts[s] as u64, vec![s]))
+        .collect();
+    let mut lengths = vec![0; counts.len()];
+    while nodes.len() > 1 {
+        nodes.sort_by_key(|node| std::cmp::Reverse(node.0));
+        let (lightest, symbols) = nodes.pop().unwrap();
+        let (next, more) = nodes.pop().unwrap();
+        for &symbol in symbols.iter().chain(&more) {
+            lengths[symbol] += 1;
+        }
+        nodes.push((lightest + next, [symbols, more].concat()));
+    }
+    lengths
+}
+
+/// The canonical code for every symbol given the lengths, bit reversed so the first bit of the
+/// code is written first.
+fn canonical_codes(lengths: &[u32]) -> Vec<(u32, u32)> {
+    let mut next = [0u32; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        code = (code
+            + lengths
+                .iter()
+                .filter(|&&l| l == length - 1 && l > 0)
+                .count() as u32)
+            << 1;
+        next[length as usize] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return (0, 0);
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            (code.reverse_bits() >> (32 - length), length)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{
+        codecs::{gif::GifDecoder, webp::WebPDecoder},
+        AnimationDecoder, ImageFormat,
+    };
+
+    /// Noise with every color and some transparency, no pattern any codec could make use of.
+    fn noise(width: u32, height: u32, seed: u32) -> Vec<u8> {
+        (0..width * height * 4)
+            .map(|i| {
+                let hash = (i ^ seed).wrapping_mul(2_654_435_761);
+                match i % 4 {
+                    3 if hash.is_multiple_of(5) => 0,
+                    3 => 255,
+                    _ => (hash >> 24) as u8,
+                }
+            })
+            .collect()
+    }
+
+    /// Exactly 256 opaque colors, enough pixels to fill GIF's code table a few times over.
+    fn many_colors(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let index = (i * 7 + i / width * 13) % 256;
+                [index as u8, 255 - index as u8, (index * 3) as u8, 255]
+            })
+            .collect()
+    }
+
+    fn frames(delay_ms: u32, frames: impl IntoIterator<Item = Vec<u8>>) -> Vec<Frame> {
+        frames
+            .into_iter()
+            .map(|rgba| Frame { rgba, delay_ms })
+            .collect()
+    }
+
+    fn decoded<'a>(decoder: impl AnimationDecoder<'a>) -> Vec<(RgbaImage, (u32, u32))> {
+        decoder
+            .into_frames()
+            .map(|frame| {
+                let frame = frame.unwrap();
+                let delay = frame.delay().numer_denom_ms();
+                (frame.into_buffer(), delay)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn apngs_keep_every_frame_delay_and_loop() {
+        let (width, height) = (9, 5);
+        let animation = frames(70_000, [noise(width, height, 1), noise(width, height, 2)]);
+        let out = apng(width, height, &animation, 3).unwrap();
+
+        let mut reader = png::Decoder::new(out.as_slice()).read_info().unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (2, 3));
+        for frame in &animation {
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buffer).unwrap();
+            assert_eq!(buffer, frame.rgba);
+            // Too long for milliseconds in 16 bits, so it's counted in tenths.
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (700, 10));
+        }
+
+        let still = png(width, height, &animation, 0).unwrap();
+        let reader = png::Decoder::new(still.as_slice()).read_info().unwrap();
+        assert!(reader.info().animation_control.is_none());
+    }
+
+    #[test]
+    fn gifs_decode_to_what_was_exported() {
+        let (width, height) = (128, 96);
+        let mut few = noise(width, height, 3);
+        // Few colors, which are kept exactly, and transparency where the noise has it.
+        for pixel in few.chunks_mut(4) {
+            pixel[..3].iter_mut().for_each(|c| *c &= 0xc0);
+        }
+        let animation = frames(45, [few.clone(), many_colors(width, height)]);
+        let out = gif(width, height, &animation, 0).unwrap();
+        assert!(out.windows(11).any(|w| w == b"NETSCAPE2.0"));
+
+        let decoded = decoded(GifDecoder::new(out.as_slice()).unwrap());
+        assert_eq!(decoded.len(), 2);
+        for ((img, delay), frame) in decoded.iter().zip(&animation) {
+            assert_eq!(img.dimensions(), (width, height));
+            // GIF counts hundredths.
+            assert_eq!(*delay, (50, 1));
+            for (got, wanted) in img.as_raw().chunks(4).zip(frame.rgba.chunks(4)) {
+                assert_eq!(got[3] == 0, wanted[3] < ALPHA_THRESHOLD);
+                if wanted[3] >= ALPHA_THRESHOLD {
+                    assert_eq!(got[..3], wanted[..3]);
+                }
+            }
+        }
+
+        // Playing once needs no loop extension.
+        let once = gif(width, height, &animation, 1).unwrap();
+        assert!(!once.windows(11).any(|w| w == b"NETSCAPE2.0"));
+        assert!(gif(70_000, 1, &animation, 0).is_err());
+    }
+
+    #[test]
+    fn gifs_of_too_many_colors_come_close() {
+        let (width, height) = (64, 64);
+        let mut rgba = noise(width, height, 4);
+        rgba.chunks_mut(4).for_each(|pixel| pixel[3] = 255);
+        let out = gif(width, height, &frames(0, [rgba.clone()]), 0).unwrap();
+
+        let decoded = decoded(GifDecoder::new(out.as_slice()).unwrap());
+        let error: u64 = decoded[0]
+            .0
+            .as_raw()
+            .iter()
+            .zip(&rgba)
+            .map(|(&a, &b)| a.abs_diff(b) as u64)
+            .sum();
+        assert!(error / (rgba.len() as u64) < 32);
+    }
+
+    #[test]
+    fn webps_are_lossless() {
+        let (width, height) = (37, 21);
+        // Noise needs full prefix codes, a single color none at all, two colors the short form.
+        let stills = [
+            noise(width, height, 5),
+            [10, 20, 30, 255].repeat((width * height) as usize),
+            many_colors(width, height)
+                .chunks(4)
+                .flat_map(|pixel| if pixel[0] % 2 == 0 { [0; 4] } else { [255; 4] })
+                .collect(),
+        ];
+        for rgba in &stills {
+            let out = webp(width, height, &frames(0, [rgba.clone()]), 0).unwrap();
+            let decoded = image::load_from_memory_with_format(&out, ImageFormat::WebP).unwrap();
+            assert_eq!(decoded.to_rgba8().as_raw(), rgba);
+        }
+
+        let animation = frames(120, stills);
+        let out = webp(width, height, &animation, 0).unwrap();
+        let decoded = decoded(WebPDecoder::new(out.as_slice()).unwrap());
+        assert_eq!(decoded.len(), animation.len());
+        for ((img, delay), frame) in decoded.iter().zip(&animation) {
+            assert_eq!(img.as_raw(), &frame.rgba);
+            assert_eq!(*delay, (120, 1));
+        }
+
+        assert!(webp(0, 1, &animation, 0).is_err());
+        assert!(webp(1 << 15, 1, &animation, 0).is_err());
+    }
+}
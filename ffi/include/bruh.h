@@ -0,0 +1,200 @@
+#ifndef BRUH_H
+#define BRUH_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ * Pixel limit for `bruh_decode` that matches the command line's default.
+ */
+#define BRUH_DEFAULT_MAX_PIXELS (1 << 28)
+
+typedef enum BruhStatus {
+  BRUH_STATUS_OK = 0,
+  /*
+   * A required pointer was null.
+   */
+  BRUH_STATUS_NULL_ARGUMENT = 1,
+  /*
+   * The data ends before the file does.
+   */
+  BRUH_STATUS_TRUNCATED = 2,
+  /*
+   * The image has more pixels than the given limit.
+   */
+  BRUH_STATUS_TOO_LARGE = 3,
+  /*
+   * The data isn't a BRUH file, or a corrupt one.
+   */
+  BRUH_STATUS_INVALID = 4,
+  /*
+   * An argument is out of range, like an unknown compression.
+   */
+  BRUH_STATUS_BAD_ARGUMENT = 5,
+  /*
+   * A bug in the library, please report it.
+   */
+  BRUH_STATUS_INTERNAL = 6,
+  /*
+   * The file is password protected, which this library can't decrypt.
+   */
+  BRUH_STATUS_ENCRYPTED = 7,
+} BruhStatus;
+
+typedef struct BruhHeader {
+  /*
+   * 0 for legacy hex files.
+   */
+  uint8_t version;
+  /*
+   * 0 RGB8, 1 RGBA8, 2 Palette8, 3 Gray8, 4 RGBA16F, 5 RGBA32F.
+   */
+  uint8_t format;
+  /*
+   * 0 none, 1 RLE, 2 deflate.
+   */
+  uint8_t compression;
+  uint32_t width;
+  uint32_t height;
+} BruhHeader;
+
+/*
+ * Bytes owned by the caller once a function returned them.
+ */
+typedef struct BruhBuffer {
+  uint8_t *data;
+  size_t len;
+} BruhBuffer;
+
+/*
+ * Reads the dimensions and layout of the file in `data` without decoding any pixels.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes and `header` to a writable `BruhHeader`.
+ */
+BruhStatus bruh_read_header(const uint8_t *data, size_t len, struct BruhHeader *header);
+
+/*
+ * Decodes the file in `data` into straight RGBA, 4 bytes per pixel row by row. Images with more
+ * than `max_pixels` pixels are refused with `BRUH_STATUS_TOO_LARGE`.
+ *
+ * On success `width` and `height` are set and `rgba` holds the pixels, to be released with
+ * `bruh_buffer_free`. On failure none of them are touched.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+ */
+BruhStatus bruh_decode(const uint8_t *data,
+                       size_t len,
+                       uint64_t max_pixels,
+                       uint32_t *width,
+                       uint32_t *height,
+                       struct BruhBuffer *rgba);
+
+/*
+ * Like `bruh_decode`, but decodes the thumbnail embedded in the file if it has one, for file
+ * managers and other thumbnailers. Files without one decode whole, so the result may still need
+ * scaling down.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+ */
+BruhStatus bruh_decode_thumbnail(const uint8_t *data,
+                                 size_t len,
+                                 uint64_t max_pixels,
+                                 uint32_t *width,
+                                 uint32_t *height,
+                                 struct BruhBuffer *rgba);
+
+/*
+ * Like `bruh_decode`, but decodes mipmap `level`, where level 0 is the image itself and every
+ * level after it half as big. Files have as many levels as this succeeds for, the first level
+ * they don't have gives `BRUH_STATUS_BAD_ARGUMENT`.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+ */
+BruhStatus bruh_decode_level(const uint8_t *data,
+                             size_t len,
+                             uint32_t level,
+                             uint64_t max_pixels,
+                             uint32_t *width,
+                             uint32_t *height,
+                             struct BruhBuffer *rgba);
+
+/*
+ * Like `bruh_decode`, but decodes image `index` of a file holding several, where image 0 is the
+ * one `bruh_decode` gives. Indices the file doesn't have give `BRUH_STATUS_BAD_ARGUMENT`.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+ */
+BruhStatus bruh_decode_image(const uint8_t *data,
+                             size_t len,
+                             uint32_t index,
+                             uint64_t max_pixels,
+                             uint32_t *width,
+                             uint32_t *height,
+                             struct BruhBuffer *rgba);
+
+/*
+ * Like `bruh_decode`, but gives straight BGRA for upload paths that want it. Files stored
+ * with the `bgra` channel order come out without swapping any channels.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height` and `bgra` must be writable.
+ */
+BruhStatus bruh_decode_bgra(const uint8_t *data,
+                            size_t len,
+                            uint64_t max_pixels,
+                            uint32_t *width,
+                            uint32_t *height,
+                            struct BruhBuffer *bgra);
+
+/*
+ * Points `rgba` at the straight RGBA pixels inside `data` itself, without decoding or copying
+ * them, with `stride` bytes from one row to the next. Only uncompressed RGBA8 files that aren't
+ * tiled, interlaced or stored as BGRA can be borrowed, anything else gives
+ * `BRUH_STATUS_BAD_ARGUMENT` and has to go through `bruh_decode`. The pixels are part of
+ * `data`, so they stay valid as long as it does and aren't freed on their own.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, `width`, `height`, `stride` and `rgba` must be
+ * writable.
+ */
+BruhStatus bruh_borrow_rgba(const uint8_t *data,
+                            size_t len,
+                            uint32_t *width,
+                            uint32_t *height,
+                            size_t *stride,
+                            const uint8_t **rgba);
+
+/*
+ * Encodes `width * height` straight RGBA pixels at `rgba` into a BRUH file, compressed with
+ * `compression` (0 none, 1 RLE, 2 deflate). The file is written to `out`, to be released with
+ * `bruh_buffer_free`.
+ *
+ * # Safety
+ * `rgba` must point to `width * height * 4` readable bytes and `out` must be writable.
+ */
+BruhStatus bruh_encode(const uint8_t *rgba,
+                       uint32_t width,
+                       uint32_t height,
+                       uint8_t compression,
+                       struct BruhBuffer *out);
+
+/*
+ * Releases a buffer returned by one of the decode functions or `bruh_encode` and clears it.
+ * Freeing a cleared buffer again does nothing.
+ *
+ * # Safety
+ * `buffer` must be null or point to a buffer filled in by this library and not changed since.
+ */
+void bruh_buffer_free(struct BruhBuffer *buffer);
+
+#endif /* BRUH_H */
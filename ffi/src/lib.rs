@@ -0,0 +1,704 @@
+//! C bindings to the BRUH codec, built as `libbruh` (shared and static). The declarations are in
+//! `include/bruh.h`.
+//!
+//! Buffers handed out by the library belong to the caller, who gives them back with
+//! `bruh_buffer_free`. Nothing here keeps state between calls, so every function is safe to call
+//! from any thread.
+
+use std::{panic, ptr, slice};
+
+#[cfg(all(windows, feature = "shell-ext"))]
+mod shell_ext;
+
+use bruh_core::{BruhFile, BruhImageRef, Compression, DecodeError};
+
+/// Pixel limit for `bruh_decode` that matches the command line's default.
+pub const BRUH_DEFAULT_MAX_PIXELS: u64 = 1 << 28;
+
+const _: () = assert!(BRUH_DEFAULT_MAX_PIXELS == bruh_core::DEFAULT_MAX_PIXELS);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BruhStatus {
+    Ok = 0,
+    /// A required pointer was null.
+    NullArgument = 1,
+    /// The data ends before the file does.
+    Truncated = 2,
+    /// The image has more pixels than the given limit.
+    TooLarge = 3,
+    /// The data isn't a BRUH file, or a corrupt one.
+    Invalid = 4,
+    /// An argument is out of range, like an unknown compression.
+    BadArgument = 5,
+    /// A bug in the library, please report it.
+    Internal = 6,
+    /// The file is password protected, which this library can't decrypt.
+    Encrypted = 7,
+}
+
+impl From<DecodeError> for BruhStatus {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::TruncatedFile { .. } => BruhStatus::Truncated,
+            DecodeError::TooLarge { .. } => BruhStatus::TooLarge,
+            DecodeError::Invalid(_) => BruhStatus::Invalid,
+            DecodeError::Encrypted => BruhStatus::Encrypted,
+            // Only the decoder's file sources fail to read, this library works on memory.
+            DecodeError::Read(_) => BruhStatus::Internal,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BruhHeader {
+    /// 0 for legacy hex files.
+    pub version: u8,
+    /// 0 RGB8, 1 RGBA8, 2 Palette8, 3 Gray8, 4 RGBA16F, 5 RGBA32F.
+    pub format: u8,
+    /// 0 none, 1 RLE, 2 deflate.
+    pub compression: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Bytes owned by the caller once a function returned them.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BruhBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl BruhBuffer {
+    fn new(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+}
+
+/// `len` bytes at `data`, where a null `data` is only fine for an empty slice.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Runs `f`, keeping panics from unwinding into C.
+fn guarded(f: impl FnOnce() -> BruhStatus + panic::UnwindSafe) -> BruhStatus {
+    panic::catch_unwind(f).unwrap_or(BruhStatus::Internal)
+}
+
+/// Reads the dimensions and layout of the file in `data` without decoding any pixels.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `header` to a writable `BruhHeader`.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_read_header(
+    data: *const u8,
+    len: usize,
+    header: *mut BruhHeader,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if header.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let parsed = if bruh_core::is_legacy(bytes) {
+            if bytes.len() < 8 {
+                return BruhStatus::Truncated;
+            }
+            BruhHeader {
+                version: 0,
+                format: bruh_core::PixelFormat::Rgb8 as u8,
+                compression: Compression::None as u8,
+                width: u32::from_ne_bytes(bytes[0..4].try_into().unwrap()),
+                height: u32::from_ne_bytes(bytes[4..8].try_into().unwrap()),
+            }
+        } else {
+            match BruhFile::parse_header(bytes) {
+                Ok(parsed) => BruhHeader {
+                    version: parsed.version,
+                    format: parsed.format as u8,
+                    compression: parsed.compression as u8,
+                    width: parsed.width,
+                    height: parsed.height,
+                },
+                Err(e) => return e.into(),
+            }
+        };
+
+        header.write(parsed);
+        BruhStatus::Ok
+    })
+}
+
+/// Decodes the file in `data` into straight RGBA, 4 bytes per pixel row by row. Images with more
+/// than `max_pixels` pixels are refused with `BRUH_STATUS_TOO_LARGE`.
+///
+/// On success `width` and `height` are set and `rgba` holds the pixels, to be released with
+/// `bruh_buffer_free`. On failure none of them are touched.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_decode(
+    data: *const u8,
+    len: usize,
+    max_pixels: u64,
+    width: *mut u32,
+    height: *mut u32,
+    rgba: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || rgba.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| match bruh_core::decode(bytes, max_pixels) {
+        Ok((w, h, pixels)) => {
+            width.write(w);
+            height.write(h);
+            rgba.write(BruhBuffer::new(pixels));
+            BruhStatus::Ok
+        }
+        Err(e) => e.into(),
+    })
+}
+
+/// Like `bruh_decode`, but decodes the thumbnail embedded in the file if it has one, for file
+/// managers and other thumbnailers. Files without one decode whole, so the result may still need
+/// scaling down.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_decode_thumbnail(
+    data: *const u8,
+    len: usize,
+    max_pixels: u64,
+    width: *mut u32,
+    height: *mut u32,
+    rgba: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || rgba.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let thumbnail = match bruh_core::BruhDecoder::new(bytes) {
+            Ok(mut decoder) => decoder.thumbnail(max_pixels),
+            Err(_) => Ok(None),
+        };
+        let decoded = match thumbnail {
+            Ok(Some(thumbnail)) => Ok(thumbnail),
+            Ok(None) => bruh_core::decode(bytes, max_pixels),
+            Err(e) => Err(e),
+        };
+        match decoded {
+            Ok((w, h, pixels)) => {
+                width.write(w);
+                height.write(h);
+                rgba.write(BruhBuffer::new(pixels));
+                BruhStatus::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Like `bruh_decode`, but decodes mipmap `level`, where level 0 is the image itself and every
+/// level after it half as big. Files have as many levels as this succeeds for, the first level
+/// they don't have gives `BRUH_STATUS_BAD_ARGUMENT`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_decode_level(
+    data: *const u8,
+    len: usize,
+    level: u32,
+    max_pixels: u64,
+    width: *mut u32,
+    height: *mut u32,
+    rgba: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || rgba.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let decoded = if level == 0 {
+            bruh_core::decode(bytes, max_pixels).map(Some)
+        } else {
+            BruhFile::parse(bytes).and_then(|file| file.mip_level(level as usize, max_pixels))
+        };
+        match decoded {
+            Ok(Some((w, h, pixels))) => {
+                width.write(w);
+                height.write(h);
+                rgba.write(BruhBuffer::new(pixels));
+                BruhStatus::Ok
+            }
+            Ok(None) => BruhStatus::BadArgument,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Like `bruh_decode`, but decodes image `index` of a file holding several, where image 0 is the
+/// one `bruh_decode` gives. Indices the file doesn't have give `BRUH_STATUS_BAD_ARGUMENT`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height` and `rgba` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_decode_image(
+    data: *const u8,
+    len: usize,
+    index: u32,
+    max_pixels: u64,
+    width: *mut u32,
+    height: *mut u32,
+    rgba: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || rgba.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let decoded = if index == 0 {
+            bruh_core::decode(bytes, max_pixels).map(Some)
+        } else {
+            BruhFile::parse(bytes)
+                .and_then(|file| file.image(index as usize))
+                .and_then(|image| {
+                    image
+                        .map(|image| {
+                            let pixels = image.to_rgba(max_pixels)?;
+                            Ok((image.header.width, image.header.height, pixels))
+                        })
+                        .transpose()
+                })
+        };
+        match decoded {
+            Ok(Some((w, h, pixels))) => {
+                width.write(w);
+                height.write(h);
+                rgba.write(BruhBuffer::new(pixels));
+                BruhStatus::Ok
+            }
+            Ok(None) => BruhStatus::BadArgument,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Like `bruh_decode`, but gives straight BGRA for upload paths that want it. Files stored
+/// with the `bgra` channel order come out without swapping any channels.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height` and `bgra` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_decode_bgra(
+    data: *const u8,
+    len: usize,
+    max_pixels: u64,
+    width: *mut u32,
+    height: *mut u32,
+    bgra: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || bgra.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let decoded = if bruh_core::is_legacy(bytes) {
+            bruh_core::decode(bytes, max_pixels).map(|(w, h, mut pixels)| {
+                bruh_core::swap_red_blue(&mut pixels);
+                (w, h, pixels)
+            })
+        } else {
+            BruhFile::parse(bytes).and_then(|file| {
+                let pixels = file.to_bgra(max_pixels)?;
+                Ok((file.header.width, file.header.height, pixels))
+            })
+        };
+        match decoded {
+            Ok((w, h, pixels)) => {
+                width.write(w);
+                height.write(h);
+                bgra.write(BruhBuffer::new(pixels));
+                BruhStatus::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Points `rgba` at the straight RGBA pixels inside `data` itself, without decoding or copying
+/// them, with `stride` bytes from one row to the next. Only uncompressed RGBA8 files that aren't
+/// tiled, interlaced or stored as BGRA can be borrowed, anything else gives
+/// `BRUH_STATUS_BAD_ARGUMENT` and has to go through `bruh_decode`. The pixels are part of
+/// `data`, so they stay valid as long as it does and aren't freed on their own.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, `width`, `height`, `stride` and `rgba` must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_borrow_rgba(
+    data: *const u8,
+    len: usize,
+    width: *mut u32,
+    height: *mut u32,
+    stride: *mut usize,
+    rgba: *mut *const u8,
+) -> BruhStatus {
+    let Some(bytes) = input(data, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if width.is_null() || height.is_null() || stride.is_null() || rgba.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        if bruh_core::is_legacy(bytes) {
+            return BruhStatus::BadArgument;
+        }
+        match BruhImageRef::parse(bytes) {
+            Ok(Some(image)) => {
+                width.write(image.width);
+                height.write(image.height);
+                stride.write(image.stride);
+                rgba.write(image.rows().next().map_or(data, <[u8]>::as_ptr));
+                BruhStatus::Ok
+            }
+            Ok(None) => BruhStatus::BadArgument,
+            Err(e) => e.into(),
+        }
+    })
+}
+
+/// Encodes `width * height` straight RGBA pixels at `rgba` into a BRUH file, compressed with
+/// `compression` (0 none, 1 RLE, 2 deflate). The file is written to `out`, to be released with
+/// `bruh_buffer_free`.
+///
+/// # Safety
+/// `rgba` must point to `width * height * 4` readable bytes and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_encode(
+    rgba: *const u8,
+    width: u32,
+    height: u32,
+    compression: u8,
+    out: *mut BruhBuffer,
+) -> BruhStatus {
+    let Some(compression) = Compression::from_u8(compression) else {
+        return BruhStatus::BadArgument;
+    };
+    let Ok(len) = bruh_core::check_dimensions(width, height, u64::MAX) else {
+        return BruhStatus::TooLarge;
+    };
+    let Some(pixels) = input(rgba, len) else {
+        return BruhStatus::NullArgument;
+    };
+    if out.is_null() {
+        return BruhStatus::NullArgument;
+    }
+
+    guarded(|| {
+        let file = BruhFile::from_rgba(width, height, pixels, compression);
+        out.write(BruhBuffer::new(file.to_bytes()));
+        BruhStatus::Ok
+    })
+}
+
+/// Releases a buffer returned by one of the decode functions or `bruh_encode` and clears it.
+/// Freeing a cleared buffer again does nothing.
+///
+/// # Safety
+/// `buffer` must be null or point to a buffer filled in by this library and not changed since.
+#[no_mangle]
+pub unsafe extern "C" fn bruh_buffer_free(buffer: *mut BruhBuffer) {
+    let Some(buffer) = buffer.as_mut() else {
+        return;
+    };
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+    buffer.data = ptr::null_mut();
+    buffer.len = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: [u8; 16] = [
+        255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 255, 10, 20, 30, 0,
+    ];
+
+    fn empty() -> BruhBuffer {
+        BruhBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    /// Takes the bytes out of a buffer the library filled in, freeing it.
+    unsafe fn take(mut buffer: BruhBuffer) -> Vec<u8> {
+        let bytes = input(buffer.data, buffer.len).unwrap().to_vec();
+        bruh_buffer_free(&mut buffer);
+        assert!(buffer.data.is_null() && buffer.len == 0);
+        bruh_buffer_free(&mut buffer);
+        bytes
+    }
+
+    fn encode(compression: Compression) -> Vec<u8> {
+        let mut out = empty();
+        let status = unsafe { bruh_encode(PIXELS.as_ptr(), 2, 2, compression as u8, &mut out) };
+        assert_eq!(status, BruhStatus::Ok);
+        unsafe { take(out) }
+    }
+
+    /// Runs one of the decode functions that fill in a size and a buffer.
+    fn decode(
+        data: &[u8],
+        f: impl FnOnce(*const u8, usize, *mut u32, *mut u32, *mut BruhBuffer) -> BruhStatus,
+    ) -> Result<(u32, u32, Vec<u8>), BruhStatus> {
+        let (mut width, mut height, mut pixels) = (0, 0, empty());
+        match f(
+            data.as_ptr(),
+            data.len(),
+            &mut width,
+            &mut height,
+            &mut pixels,
+        ) {
+            BruhStatus::Ok => Ok((width, height, unsafe { take(pixels) })),
+            status => {
+                assert!(pixels.data.is_null());
+                Err(status)
+            }
+        }
+    }
+
+    #[test]
+    fn files_round_trip_through_every_decoder() {
+        for compression in [Compression::None, Compression::Rle, Compression::Deflate] {
+            let file = encode(compression);
+
+            let mut header = BruhHeader {
+                version: 9,
+                format: 9,
+                compression: 9,
+                width: 0,
+                height: 0,
+            };
+            let status = unsafe { bruh_read_header(file.as_ptr(), file.len(), &mut header) };
+            assert_eq!(status, BruhStatus::Ok);
+            assert_eq!(
+                (header.version, header.format, header.compression),
+                (1, 1, compression as u8)
+            );
+            assert_eq!((header.width, header.height), (2, 2));
+
+            let whole = Ok((2, 2, PIXELS.to_vec()));
+            let max = BRUH_DEFAULT_MAX_PIXELS;
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode(d, l, max, w, h, p)
+                }),
+                whole
+            );
+            // Without a thumbnail, a mipmap or more images there's only the image itself.
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_thumbnail(d, l, max, w, h, p)
+                }),
+                whole
+            );
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_level(d, l, 0, max, w, h, p)
+                }),
+                whole
+            );
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_level(d, l, 1, max, w, h, p)
+                }),
+                Err(BruhStatus::BadArgument)
+            );
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_image(d, l, 0, max, w, h, p)
+                }),
+                whole
+            );
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_image(d, l, 1, max, w, h, p)
+                }),
+                Err(BruhStatus::BadArgument)
+            );
+
+            let mut bgra = PIXELS;
+            bgra.chunks_mut(4).for_each(|pixel| pixel.swap(0, 2));
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode_bgra(d, l, max, w, h, p)
+                }),
+                Ok((2, 2, bgra.to_vec()))
+            );
+            assert_eq!(
+                decode(&file, |d, l, w, h, p| unsafe {
+                    bruh_decode(d, l, 3, w, h, p)
+                }),
+                Err(BruhStatus::TooLarge)
+            );
+        }
+    }
+
+    #[test]
+    fn thumbnails_mipmaps_and_images_are_found() {
+        let mut file = BruhFile::from_rgba(2, 2, &PIXELS, Compression::Rle);
+        let small = BruhFile::from_rgba(1, 1, &[1, 2, 3, 255], Compression::None);
+        file.set_thumbnail(&small);
+        file.set_mipmaps(slice::from_ref(&small));
+        file.add_image(&small);
+        let file = file.to_bytes();
+
+        let max = BRUH_DEFAULT_MAX_PIXELS;
+        let small = Ok((1, 1, vec![1, 2, 3, 255]));
+        assert_eq!(
+            decode(&file, |d, l, w, h, p| unsafe {
+                bruh_decode_thumbnail(d, l, max, w, h, p)
+            }),
+            small
+        );
+        assert_eq!(
+            decode(&file, |d, l, w, h, p| unsafe {
+                bruh_decode_level(d, l, 1, max, w, h, p)
+            }),
+            small
+        );
+        assert_eq!(
+            decode(&file, |d, l, w, h, p| unsafe {
+                bruh_decode_level(d, l, 2, max, w, h, p)
+            }),
+            Err(BruhStatus::BadArgument)
+        );
+    }
+
+    #[test]
+    fn uncompressed_rgba_is_borrowed_in_place() {
+        let borrow = |file: &[u8]| {
+            let (mut width, mut height, mut stride, mut rgba) = (0, 0, 0, ptr::null());
+            let status = unsafe {
+                bruh_borrow_rgba(
+                    file.as_ptr(),
+                    file.len(),
+                    &mut width,
+                    &mut height,
+                    &mut stride,
+                    &mut rgba,
+                )
+            };
+            (status, width, height, stride, rgba)
+        };
+
+        let file = encode(Compression::None);
+        let (status, width, height, stride, rgba) = borrow(&file);
+        assert_eq!((status, width, height), (BruhStatus::Ok, 2, 2));
+        assert!(file.as_ptr_range().contains(&rgba));
+        for row in 0..2 {
+            let pixels = unsafe { slice::from_raw_parts(rgba.add(row * stride), 8) };
+            assert_eq!(pixels, &PIXELS[row * 8..][..8]);
+        }
+
+        assert_eq!(borrow(&encode(Compression::Rle)).0, BruhStatus::BadArgument);
+        let legacy = [1u32.to_ne_bytes(), 1u32.to_ne_bytes()].concat();
+        assert_eq!(borrow(&legacy).0, BruhStatus::BadArgument);
+    }
+
+    #[test]
+    fn bad_input_gets_a_status_instead_of_a_crash() {
+        let file = encode(Compression::Deflate);
+        let max = BRUH_DEFAULT_MAX_PIXELS;
+        let status = |data: &[u8]| {
+            decode(data, |d, l, w, h, p| unsafe {
+                bruh_decode(d, l, max, w, h, p)
+            })
+            .unwrap_err()
+        };
+        assert_eq!(status(&file[..file.len() - 1]), BruhStatus::Truncated);
+        let mut corrupt = file.clone();
+        corrupt[6] = 9;
+        assert_eq!(status(&corrupt), BruhStatus::Invalid);
+
+        let (mut width, mut height, mut pixels) = (0, 0, empty());
+        unsafe {
+            assert_eq!(
+                bruh_decode(ptr::null(), 1, max, &mut width, &mut height, &mut pixels),
+                BruhStatus::NullArgument
+            );
+            assert_eq!(
+                bruh_decode(
+                    file.as_ptr(),
+                    file.len(),
+                    max,
+                    ptr::null_mut(),
+                    &mut height,
+                    &mut pixels
+                ),
+                BruhStatus::NullArgument
+            );
+            assert_eq!(
+                bruh_read_header(file.as_ptr(), file.len(), ptr::null_mut()),
+                BruhStatus::NullArgument
+            );
+            assert_eq!(
+                bruh_encode(PIXELS.as_ptr(), 2, 2, 3, &mut pixels),
+                BruhStatus::BadArgument
+            );
+            assert_eq!(
+                bruh_encode(ptr::null(), 2, 2, 0, &mut pixels),
+                BruhStatus::NullArgument
+            );
+            assert_eq!(
+                bruh_encode(PIXELS.as_ptr(), u32::MAX, u32::MAX, 0, &mut pixels),
+                BruhStatus::TooLarge
+            );
+            // An empty image needs no pixels at all.
+            assert_eq!(
+                bruh_encode(ptr::null(), 0, 0, 0, &mut pixels),
+                BruhStatus::Ok
+            );
+            take(pixels);
+            bruh_buffer_free(ptr::null_mut());
+        }
+    }
+}
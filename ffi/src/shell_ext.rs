@@ -0,0 +1,695 @@
+//! A Windows Explorer thumbnail provider, so .bruh files show their pictures instead of an icon.
+//! It is built into the DLL with the `shell-ext` feature and registered for the current user
+//! with `regsvr32 bruh.dll`, `regsvr32 /u bruh.dll` removes it again.
+//!
+//! Explorer hands the provider the file as a stream. It decodes the embedded thumbnail if that
+//! is big enough, otherwise the smallest mipmap level that is, otherwise the whole image, and
+//! scales it down to the size Explorer asked for. Encrypted files get no thumbnail.
+//!
+//! The few COM interfaces this needs are declared here by hand, laid out like in the Windows
+//! SDK, instead of pulling in bindings for all of Windows.
+
+use std::{
+    cell::Cell,
+    ffi::c_void,
+    mem, panic, ptr,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::BRUH_DEFAULT_MAX_PIXELS;
+use bruh_core::{BruhDecoder, DecodeError, Source};
+
+type HResult = i32;
+
+const S_OK: HResult = 0;
+const S_FALSE: HResult = 1;
+const E_UNEXPECTED: HResult = 0x8000_FFFF_u32 as i32;
+const E_NOINTERFACE: HResult = 0x8000_4002_u32 as i32;
+const E_POINTER: HResult = 0x8000_4003_u32 as i32;
+const E_FAIL: HResult = 0x8000_4005_u32 as i32;
+const E_OUTOFMEMORY: HResult = 0x8007_000E_u32 as i32;
+const CLASS_E_NOAGGREGATION: HResult = 0x8004_0110_u32 as i32;
+const CLASS_E_CLASSNOTAVAILABLE: HResult = 0x8004_0111_u32 as i32;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(u32, u16, u16, [u8; 8]);
+
+const IID_IUNKNOWN: Guid = Guid(0, 0, 0, [0xc0, 0, 0, 0, 0, 0, 0, 0x46]);
+const IID_ICLASSFACTORY: Guid = Guid(1, 0, 0, [0xc0, 0, 0, 0, 0, 0, 0, 0x46]);
+const IID_IINITIALIZEWITHSTREAM: Guid = Guid(
+    0xb824b49d,
+    0x22ac,
+    0x4161,
+    [0xac, 0x8a, 0x99, 0x16, 0xe8, 0xfa, 0x3f, 0x7f],
+);
+const IID_ITHUMBNAILPROVIDER: Guid = Guid(
+    0xe357fccd,
+    0xa995,
+    0x4576,
+    [0xb0, 0x1f, 0x23, 0x46, 0x30, 0x15, 0x4e, 0x96],
+);
+/// The class of the provider, which the registry points Explorer to.
+const CLSID_PROVIDER: Guid = Guid(
+    0x2307e7c9,
+    0x99bf,
+    0x466f,
+    [0x95, 0x12, 0xe0, 0x41, 0xd6, 0xe5, 0xee, 0xf1],
+);
+const CLSID_PROVIDER_TEXT: &str = "{2307e7c9-99bf-466f-9512-e041d6e5eef1}";
+const IID_ITHUMBNAILPROVIDER_TEXT: &str = "{e357fccd-a995-4576-b01f-234630154e96}";
+
+/// Thumbnails with alpha, as premultiplied BGRA.
+const WTSAT_ARGB: u32 = 2;
+
+#[repr(C)]
+struct UnknownVtbl {
+    query_interface:
+        unsafe extern "system" fn(*mut c_void, *const Guid, *mut *mut c_void) -> HResult,
+    add_ref: unsafe extern "system" fn(*mut c_void) -> u32,
+    release: unsafe extern "system" fn(*mut c_void) -> u32,
+}
+
+#[repr(C)]
+struct ClassFactoryVtbl {
+    unknown: UnknownVtbl,
+    create_instance: unsafe extern "system" fn(
+        *mut c_void,
+        *mut c_void,
+        *const Guid,
+        *mut *mut c_void,
+    ) -> HResult,
+    lock_server: unsafe extern "system" fn(*mut c_void, i32) -> HResult,
+}
+
+#[repr(C)]
+struct InitializeWithStreamVtbl {
+    unknown: UnknownVtbl,
+    initialize: unsafe extern "system" fn(*mut c_void, *mut Stream, u32) -> HResult,
+}
+
+#[repr(C)]
+struct ThumbnailProviderVtbl {
+    unknown: UnknownVtbl,
+    get_thumbnail:
+        unsafe extern "system" fn(*mut c_void, u32, *mut *mut c_void, *mut u32) -> HResult,
+}
+
+/// An `IStream`, of which only reading and seeking are used, so the vtable ends there.
+#[repr(C)]
+struct Stream {
+    vtbl: *const StreamVtbl,
+}
+
+#[repr(C)]
+struct StreamVtbl {
+    unknown: UnknownVtbl,
+    read: unsafe extern "system" fn(*mut Stream, *mut c_void, u32, *mut u32) -> HResult,
+    write: *const c_void,
+    seek: unsafe extern "system" fn(*mut Stream, i64, u32, *mut u64) -> HResult,
+}
+
+const STREAM_SEEK_SET: u32 = 0;
+const STREAM_SEEK_END: u32 = 2;
+
+#[repr(C)]
+struct BitmapInfoHeader {
+    size: u32,
+    width: i32,
+    height: i32,
+    planes: u16,
+    bit_count: u16,
+    compression: u32,
+    size_image: u32,
+    x_pixels_per_meter: i32,
+    y_pixels_per_meter: i32,
+    colors_used: u32,
+    colors_important: u32,
+}
+
+const DIB_RGB_COLORS: u32 = 0;
+const HKEY_CURRENT_USER: *mut c_void = 0x8000_0001_u32 as i32 as isize as *mut c_void;
+const REG_SZ: u32 = 1;
+const GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS: u32 = 4;
+const GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT: u32 = 2;
+const SHCNE_ASSOCCHANGED: i32 = 0x0800_0000;
+
+#[link(name = "gdi32")]
+extern "system" {
+    fn CreateDIBSection(
+        dc: *mut c_void,
+        info: *const BitmapInfoHeader,
+        usage: u32,
+        bits: *mut *mut c_void,
+        section: *mut c_void,
+        offset: u32,
+    ) -> *mut c_void;
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegSetKeyValueW(
+        key: *mut c_void,
+        sub_key: *const u16,
+        value_name: *const u16,
+        value_type: u32,
+        data: *const c_void,
+        len: u32,
+    ) -> i32;
+    fn RegDeleteTreeW(key: *mut c_void, sub_key: *const u16) -> i32;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetModuleHandleExW(flags: u32, name: *const u16, module: *mut *mut c_void) -> i32;
+    fn GetModuleFileNameW(module: *mut c_void, name: *mut u16, size: u32) -> u32;
+}
+
+#[link(name = "shell32")]
+extern "system" {
+    fn SHChangeNotify(event: i32, flags: u32, item1: *const c_void, item2: *const c_void);
+}
+
+/// Providers alive and `LockServer` calls not undone, the DLL can't be unloaded before both are
+/// zero.
+static OBJECTS: AtomicUsize = AtomicUsize::new(0);
+static LOCKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `f`, keeping panics from unwinding into Explorer.
+fn guarded(f: impl FnOnce() -> HResult) -> HResult {
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).unwrap_or(E_UNEXPECTED)
+}
+
+// The class factory, of which there is only the one static instance.
+
+#[repr(C)]
+struct ClassFactory {
+    vtbl: *const ClassFactoryVtbl,
+}
+
+// SAFETY: the factory has no state, only the pointer to its vtable, which never changes.
+unsafe impl Sync for ClassFactory {}
+
+static FACTORY: ClassFactory = ClassFactory {
+    vtbl: &ClassFactoryVtbl {
+        unknown: UnknownVtbl {
+            query_interface: factory_query_interface,
+            add_ref: factory_add_ref,
+            release: factory_release,
+        },
+        create_instance: factory_create_instance,
+        lock_server: factory_lock_server,
+    },
+};
+
+unsafe extern "system" fn factory_query_interface(
+    this: *mut c_void,
+    iid: *const Guid,
+    out: *mut *mut c_void,
+) -> HResult {
+    if iid.is_null() || out.is_null() {
+        return E_POINTER;
+    }
+    if *iid == IID_IUNKNOWN || *iid == IID_ICLASSFACTORY {
+        out.write(this);
+        S_OK
+    } else {
+        out.write(ptr::null_mut());
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn factory_add_ref(_: *mut c_void) -> u32 {
+    2
+}
+
+unsafe extern "system" fn factory_release(_: *mut c_void) -> u32 {
+    1
+}
+
+unsafe extern "system" fn factory_create_instance(
+    _: *mut c_void,
+    outer: *mut c_void,
+    iid: *const Guid,
+    out: *mut *mut c_void,
+) -> HResult {
+    if out.is_null() {
+        return E_POINTER;
+    }
+    out.write(ptr::null_mut());
+    if !outer.is_null() {
+        return CLASS_E_NOAGGREGATION;
+    }
+
+    let provider = Box::into_raw(Box::new(Provider {
+        initialize: &INITIALIZE_VTBL,
+        thumbnail: &THUMBNAIL_VTBL,
+        refs: AtomicU32::new(1),
+        stream: Cell::new(ptr::null_mut()),
+    }));
+    OBJECTS.fetch_add(1, Ordering::SeqCst);
+    // The reference from `new` is dropped again, so a failing query frees the provider.
+    let result = provider_query_interface::<0>(provider.cast(), iid, out);
+    provider_release::<0>(provider.cast());
+    result
+}
+
+unsafe extern "system" fn factory_lock_server(_: *mut c_void, lock: i32) -> HResult {
+    if lock != 0 {
+        LOCKS.fetch_add(1, Ordering::SeqCst);
+    } else {
+        LOCKS.fetch_sub(1, Ordering::SeqCst);
+    }
+    S_OK
+}
+
+// The provider, one per file Explorer wants a thumbnail of. It implements two interfaces, each
+// a pointer to its vtable, so the methods of the second one find the object `OFFSET` bytes
+// before the pointer they get.
+
+#[repr(C)]
+struct Provider {
+    initialize: *const InitializeWithStreamVtbl,
+    thumbnail: *const ThumbnailProviderVtbl,
+    refs: AtomicU32,
+    /// The stream from `Initialize`, which the provider holds a reference to.
+    stream: Cell<*mut Stream>,
+}
+
+const THUMBNAIL_OFFSET: usize = mem::offset_of!(Provider, thumbnail);
+
+static INITIALIZE_VTBL: InitializeWithStreamVtbl = InitializeWithStreamVtbl {
+    unknown: UnknownVtbl {
+        query_interface: provider_query_interface::<0>,
+        add_ref: provider_add_ref::<0>,
+        release: provider_release::<0>,
+    },
+    initialize: provider_initialize,
+};
+
+static THUMBNAIL_VTBL: ThumbnailProviderVtbl = ThumbnailProviderVtbl {
+    unknown: UnknownVtbl {
+        query_interface: provider_query_interface::<THUMBNAIL_OFFSET>,
+        add_ref: provider_add_ref::<THUMBNAIL_OFFSET>,
+        release: provider_release::<THUMBNAIL_OFFSET>,
+    },
+    get_thumbnail: provider_get_thumbnail,
+};
+
+unsafe fn provider<'a, const OFFSET: usize>(this: *mut c_void) -> &'a Provider {
+    &*this.cast::<u8>().sub(OFFSET).cast::<Provider>()
+}
+
+unsafe extern "system" fn provider_query_interface<const OFFSET: usize>(
+    this: *mut c_void,
+    iid: *const Guid,
+    out: *mut *mut c_void,
+) -> HResult {
+    if iid.is_null() || out.is_null() {
+        return E_POINTER;
+    }
+    let provider = provider::<OFFSET>(this);
+    let interface: *const c_void = if *iid == IID_IUNKNOWN || *iid == IID_IINITIALIZEWITHSTREAM {
+        ptr::addr_of!(provider.initialize).cast()
+    } else if *iid == IID_ITHUMBNAILPROVIDER {
+        ptr::addr_of!(provider.thumbnail).cast()
+    } else {
+        out.write(ptr::null_mut());
+        return E_NOINTERFACE;
+    };
+    provider.refs.fetch_add(1, Ordering::SeqCst);
+    out.write(interface.cast_mut());
+    S_OK
+}
+
+unsafe extern "system" fn provider_add_ref<const OFFSET: usize>(this: *mut c_void) -> u32 {
+    provider::<OFFSET>(this).refs.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+unsafe extern "system" fn provider_release<const OFFSET: usize>(this: *mut c_void) -> u32 {
+    let provider = this.cast::<u8>().sub(OFFSET).cast::<Provider>();
+    let refs = (*provider).refs.fetch_sub(1, Ordering::SeqCst) - 1;
+    if refs == 0 {
+        let provider = Box::from_raw(provider);
+        let stream = provider.stream.get();
+        if !stream.is_null() {
+            ((*(*stream).vtbl).unknown.release)(stream.cast());
+        }
+        OBJECTS.fetch_sub(1, Ordering::SeqCst);
+    }
+    refs
+}
+
+unsafe extern "system" fn provider_initialize(
+    this: *mut c_void,
+    stream: *mut Stream,
+    _mode: u32,
+) -> HResult {
+    if stream.is_null() {
+        return E_POINTER;
+    }
+    let provider = provider::<0>(this);
+    if !provider.stream.get().is_null() {
+        return E_UNEXPECTED;
+    }
+    ((*(*stream).vtbl).unknown.add_ref)(stream.cast());
+    provider.stream.set(stream);
+    S_OK
+}
+
+unsafe extern "system" fn provider_get_thumbnail(
+    this: *mut c_void,
+    size: u32,
+    bitmap: *mut *mut c_void,
+    alpha: *mut u32,
+) -> HResult {
+    if bitmap.is_null() || alpha.is_null() {
+        return E_POINTER;
+    }
+    let stream = provider::<THUMBNAIL_OFFSET>(this).stream.get();
+    if stream.is_null() {
+        return E_UNEXPECTED;
+    }
+
+    guarded(|| {
+        let Ok((width, height, rgba)) = decode_thumbnail(StreamSource::new(stream), size) else {
+            return E_FAIL;
+        };
+        let (width, height, bgra) = premultiplied_bgra(width, height, &rgba, size.max(1));
+        let Some(created) = create_bitmap(width, height, &bgra) else {
+            return E_OUTOFMEMORY;
+        };
+        bitmap.write(created);
+        alpha.write(WTSAT_ARGB);
+        S_OK
+    })
+}
+
+/// Explorer's stream, read through `BruhDecoder` so only the parts of the file it needs are.
+#[derive(Clone, Copy)]
+struct StreamSource {
+    stream: *mut Stream,
+}
+
+impl StreamSource {
+    fn new(stream: *mut Stream) -> Self {
+        Self { stream }
+    }
+
+    fn seek(&self, offset: i64, origin: u32) -> Result<u64, DecodeError> {
+        let mut position = 0;
+        // SAFETY: the provider holds a reference to the stream while it is used.
+        let result =
+            unsafe { ((*(*self.stream).vtbl).seek)(self.stream, offset, origin, &mut position) };
+        if result < 0 {
+            return Err(DecodeError::Read(format!(
+                "seeking failed with {:#010x}",
+                result
+            )));
+        }
+        Ok(position)
+    }
+
+    fn read_all(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let size = usize::try_from(self.size())
+            .map_err(|_| DecodeError::Read("the file is too large".to_owned()))?;
+        let mut bytes = vec![0; size];
+        self.read_at(0, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Source for StreamSource {
+    fn size(&self) -> u64 {
+        self.seek(0, STREAM_SEEK_END).unwrap_or(0)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), DecodeError> {
+        let offset = i64::try_from(offset)
+            .map_err(|_| DecodeError::Read(format!("offset {} is out of range", offset)))?;
+        self.seek(offset, STREAM_SEEK_SET)?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let wanted = u32::try_from(buf.len() - filled).unwrap_or(u32::MAX);
+            let mut read = 0;
+            // SAFETY: as for `seek`, and the rest of `buf` has room for `wanted` bytes.
+            let result = unsafe {
+                ((*(*self.stream).vtbl).read)(
+                    self.stream,
+                    buf[filled..].as_mut_ptr().cast(),
+                    wanted,
+                    &mut read,
+                )
+            };
+            if result < 0 {
+                return Err(DecodeError::Read(format!(
+                    "reading failed with {:#010x}",
+                    result
+                )));
+            }
+            if read == 0 {
+                return Err(DecodeError::Read("the file ended early".to_owned()));
+            }
+            filled += read as usize;
+        }
+        Ok(())
+    }
+}
+
+/// The smallest picture in the file that is at least `size` pixels on its longer side, or the
+/// image itself if none is.
+fn decode_thumbnail(
+    mut source: StreamSource,
+    size: u32,
+) -> Result<(u32, u32, Vec<u8>), DecodeError> {
+    let max_pixels = BRUH_DEFAULT_MAX_PIXELS;
+    let mut decoder = match BruhDecoder::new(source) {
+        Ok(decoder) => decoder,
+        Err(DecodeError::Encrypted) => return Err(DecodeError::Encrypted),
+        // Legacy files have no chunks to pick from.
+        Err(_) => return bruh_core::decode(&source.read_all()?, max_pixels),
+    };
+
+    if let Some(thumbnail) = decoder.thumbnail(max_pixels)? {
+        if thumbnail.0.max(thumbnail.1) >= size {
+            return Ok(thumbnail);
+        }
+    }
+    let (width, height) = (decoder.header().width, decoder.header().height);
+    for level in (1..decoder.mip_levels()).rev() {
+        if width.max(height) >> level >= size {
+            if let Some(level) = decoder.mip_level(level, max_pixels)? {
+                return Ok(level);
+            }
+        }
+    }
+    let rgba = decoder.decode_region(0, 0, width, height, max_pixels)?;
+    Ok((width, height, rgba))
+}
+
+/// `rgba` scaled down to fit `size`x`size`, every pixel the average of those it covers, as the
+/// premultiplied BGRA Explorer wants. Smaller images keep their size.
+fn premultiplied_bgra(width: u32, height: u32, rgba: &[u8], size: u32) -> (u32, u32, Vec<u8>) {
+    let scale = (size as f64 / width.max(height) as f64).min(1.0);
+    let out_width = ((width as f64 * scale).round() as u32).clamp(1, width);
+    let out_height = ((height as f64 * scale).round() as u32).clamp(1, height);
+
+    let mut bgra = Vec::with_capacity(out_width as usize * out_height as usize * 4);
+    for y in 0..out_height {
+        let rows = covered(y, out_height, height);
+        for x in 0..out_width {
+            let columns = covered(x, out_width, width);
+            let mut sums = [0u64; 4];
+            for row in rows.clone() {
+                for column in columns.clone() {
+                    let i = (row as usize * width as usize + column as usize) * 4;
+                    let alpha = rgba[i + 3] as u64;
+                    sums[0] += rgba[i + 2] as u64 * alpha;
+                    sums[1] += rgba[i + 1] as u64 * alpha;
+                    sums[2] += rgba[i] as u64 * alpha;
+                    sums[3] += alpha;
+                }
+            }
+            let count = rows.len() as u64 * columns.len() as u64;
+            bgra.extend_from_slice(&[
+                (sums[0] / (255 * count)) as u8,
+                (sums[1] / (255 * count)) as u8,
+                (sums[2] / (255 * count)) as u8,
+                (sums[3] / count) as u8,
+            ]);
+        }
+    }
+    (out_width, out_height, bgra)
+}
+
+/// The pixels of a line of `len` that pixel `i` of `out` pixels covers.
+fn covered(i: u32, out: u32, len: u32) -> std::ops::Range<u32> {
+    let start = (i as u64 * len as u64 / out as u64) as u32;
+    let end = ((i as u64 + 1) * len as u64).div_ceil(out as u64) as u32;
+    start..end.max(start + 1)
+}
+
+/// A top-down 32 bit DIB section holding `bgra`, which Explorer takes ownership of.
+fn create_bitmap(width: u32, height: u32, bgra: &[u8]) -> Option<*mut c_void> {
+    let info = BitmapInfoHeader {
+        size: mem::size_of::<BitmapInfoHeader>() as u32,
+        width: width as i32,
+        // Negative for rows from top to bottom.
+        height: -(height as i32),
+        planes: 1,
+        bit_count: 32,
+        compression: 0,
+        size_image: 0,
+        x_pixels_per_meter: 0,
+        y_pixels_per_meter: 0,
+        colors_used: 0,
+        colors_important: 0,
+    };
+    let mut bits = ptr::null_mut();
+    // SAFETY: `info` describes a bitmap of exactly `bgra.len()` bytes, which `bits` points to.
+    unsafe {
+        let bitmap = CreateDIBSection(
+            ptr::null_mut(),
+            &info,
+            DIB_RGB_COLORS,
+            &mut bits,
+            ptr::null_mut(),
+            0,
+        );
+        if bitmap.is_null() || bits.is_null() {
+            return None;
+        }
+        ptr::copy_nonoverlapping(bgra.as_ptr(), bits.cast(), bgra.len());
+        Some(bitmap)
+    }
+}
+
+// What `regsvr32` and COM call.
+
+/// Hands out the class factory of the provider.
+///
+/// # Safety
+/// Called by COM with valid pointers.
+#[no_mangle]
+pub unsafe extern "system" fn DllGetClassObject(
+    clsid: *const Guid,
+    iid: *const Guid,
+    out: *mut *mut c_void,
+) -> HResult {
+    if clsid.is_null() || out.is_null() {
+        return E_POINTER;
+    }
+    if *clsid != CLSID_PROVIDER {
+        out.write(ptr::null_mut());
+        return CLASS_E_CLASSNOTAVAILABLE;
+    }
+    factory_query_interface(ptr::addr_of!(FACTORY).cast_mut().cast(), iid, out)
+}
+
+#[no_mangle]
+pub extern "system" fn DllCanUnloadNow() -> HResult {
+    if OBJECTS.load(Ordering::SeqCst) == 0 && LOCKS.load(Ordering::SeqCst) == 0 {
+        S_OK
+    } else {
+        S_FALSE
+    }
+}
+
+/// Registers the provider for .bruh files under `HKEY_CURRENT_USER`, which needs no
+/// administrator rights.
+#[no_mangle]
+pub extern "system" fn DllRegisterServer() -> HResult {
+    guarded(|| {
+        let Some(dll) = dll_path() else {
+            return E_FAIL;
+        };
+        let class = format!(r"Software\Classes\CLSID\{}", CLSID_PROVIDER_TEXT);
+        let server = format!(r"{}\InprocServer32", class);
+        let handler = format!(
+            r"Software\Classes\.bruh\ShellEx\{}",
+            IID_ITHUMBNAILPROVIDER_TEXT
+        );
+        let values = [
+            (class.as_str(), "", "BRUH Thumbnail Provider"),
+            (server.as_str(), "", dll.as_str()),
+            (server.as_str(), "ThreadingModel", "Apartment"),
+            (handler.as_str(), "", CLSID_PROVIDER_TEXT),
+        ];
+        for (key, name, value) in values {
+            let status = unsafe {
+                let value = wide(value);
+                RegSetKeyValueW(
+                    HKEY_CURRENT_USER,
+                    wide(key).as_ptr(),
+                    wide(name).as_ptr(),
+                    REG_SZ,
+                    value.as_ptr().cast(),
+                    (value.len() * 2) as u32,
+                )
+            };
+            if status != 0 {
+                return from_win32(status);
+            }
+        }
+        associations_changed();
+        S_OK
+    })
+}
+
+/// Removes what `DllRegisterServer` wrote. Keys that are already gone are fine.
+#[no_mangle]
+pub extern "system" fn DllUnregisterServer() -> HResult {
+    guarded(|| {
+        let keys = [
+            format!(
+                r"Software\Classes\.bruh\ShellEx\{}",
+                IID_ITHUMBNAILPROVIDER_TEXT
+            ),
+            format!(r"Software\Classes\CLSID\{}", CLSID_PROVIDER_TEXT),
+        ];
+        for key in &keys {
+            // 2 is ERROR_FILE_NOT_FOUND.
+            let status = unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, wide(key).as_ptr()) };
+            if status != 0 && status != 2 {
+                return from_win32(status);
+            }
+        }
+        associations_changed();
+        S_OK
+    })
+}
+
+/// Tells Explorer to look at the registered handlers again.
+fn associations_changed() {
+    // SAFETY: the event takes no items.
+    unsafe { SHChangeNotify(SHCNE_ASSOCCHANGED, 0, ptr::null(), ptr::null()) };
+}
+
+/// Where the DLL holding this code is, for `InprocServer32`.
+fn dll_path() -> Option<String> {
+    let mut module = ptr::null_mut();
+    let mut name = vec![0u16; 32 * 1024];
+    // SAFETY: with `FROM_ADDRESS` the name is an address inside the module, any of its functions.
+    unsafe {
+        let found = GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            DllRegisterServer as *const u16,
+            &mut module,
+        );
+        if found == 0 {
+            return None;
+        }
+        let len = GetModuleFileNameW(module, name.as_mut_ptr(), name.len() as u32) as usize;
+        (len != 0 && len < name.len()).then(|| String::from_utf16_lossy(&name[..len]))
+    }
+}
+
+/// `text` as a null-terminated UTF-16 string.
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// The `HRESULT` of a Win32 error code.
+fn from_win32(code: i32) -> HResult {
+    (code as u32 & 0xffff | 0x8007_0000) as i32
+}
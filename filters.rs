@@ -0,0 +1,536 @@
+//! Named operations for `bruh apply`, which runs a chain of them over an image, e.g.
+//! `bruh apply in.bruh blur:sigma=2 grayscale`. Anything implementing `BruhFilter` can be added
+//! to a `Registry`; the built-in ones wrap the functions in `ops`.
+//!
+//! Filters can also be other programs, found in the `plugins` directory next to the
+//! configuration file or in `BRUH_PLUGINS`. Each executable there is a filter named after it,
+//! run as `program WIDTH HEIGHT [name=value]...` with the straight RGBA pixels on stdin, and has
+//! to write the same number of pixels back to stdout.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use image::RgbaImage;
+
+use crate::ops;
+
+/// A parameter a filter takes, as `name=value` after its name.
+pub struct Param {
+    pub name: &'static str,
+    pub default: f32,
+    pub about: &'static str,
+}
+
+/// The values a filter was given, in the order they were given.
+#[derive(Debug, Default)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    /// The value of `name`, which `Registry::parse` made sure is there and a number for the
+    /// parameters the filter declares.
+    pub fn number(&self, name: &str) -> f32 {
+        self.get(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub trait BruhFilter: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// One line for `bruh apply --list`.
+    fn about(&self) -> &str;
+
+    /// What the filter can be given, or `None` if it takes anything, like plugins do.
+    fn params(&self) -> Option<&[Param]>;
+
+    fn apply(&self, img: &mut RgbaImage, params: &Params) -> Result<(), String>;
+}
+
+/// A built-in filter, a function from `ops` and what it takes.
+#[derive(Clone, Copy)]
+struct Builtin {
+    name: &'static str,
+    about: &'static str,
+    params: &'static [Param],
+    apply: fn(&mut RgbaImage, &Params) -> Result<(), String>,
+}
+
+impl BruhFilter for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn about(&self) -> &str {
+        self.about
+    }
+
+    fn params(&self) -> Option<&[Param]> {
+        Some(self.params)
+    }
+
+    fn apply(&self, img: &mut RgbaImage, params: &Params) -> Result<(), String> {
+        (self.apply)(img, params)
+    }
+}
+
+fn effect(img: &mut RgbaImage, effect: ops::Effect) -> Result<(), String> {
+    *img = ops::apply_effect(img, effect);
+    Ok(())
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "grayscale",
+        about: "Turns the colors into shades of gray",
+        params: &[],
+        apply: |img, _| effect(img, ops::Effect::Grayscale),
+    },
+    Builtin {
+        name: "invert",
+        about: "Inverts the colors",
+        params: &[],
+        apply: |img, _| effect(img, ops::Effect::Invert),
+    },
+    Builtin {
+        name: "sepia",
+        about: "Tints the colors brown like an old photo",
+        params: &[],
+        apply: |img, _| effect(img, ops::Effect::Sepia),
+    },
+    Builtin {
+        name: "adjust",
+        about: "Changes the brightness, contrast and gamma",
+        params: &[
+            Param {
+                name: "brightness",
+                default: 0.0,
+                about: "offset in 0-255 units",
+            },
+            Param {
+                name: "contrast",
+                default: 1.0,
+                about: "factor away from the midpoint",
+            },
+            Param {
+                name: "gamma",
+                default: 1.0,
+                about: "above 1 brightens the midtones",
+            },
+        ],
+        apply: |img, params| {
+            let gamma = params.number("gamma");
+            if gamma <= 0.0 {
+                return Err("gamma must be greater than 0".to_owned());
+            }
+            let adjustments = ops::Adjustments {
+                brightness: params.number("brightness"),
+                contrast: params.number("contrast"),
+                gamma,
+                linear: false,
+            };
+            *img = ops::adjust(img, adjustments);
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "auto-levels",
+        about: "Stretches every channel to the whole range",
+        params: &[],
+        apply: |img, _| {
+            *img = ops::auto_levels(img);
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "equalize",
+        about: "Evens out how often every value of a channel occurs",
+        params: &[],
+        apply: |img, _| {
+            *img = ops::equalize(img);
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "blur",
+        about: "Gaussian blur",
+        params: &[Param {
+            name: "sigma",
+            default: 1.0,
+            about: "standard deviation in pixels",
+        }],
+        apply: |img, params| {
+            *img = ops::blur(img, params.number("sigma"));
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "sharpen",
+        about: "Unsharp mask",
+        params: &[Param {
+            name: "amount",
+            default: 1.0,
+            about: "how much of the detail to add back",
+        }],
+        apply: |img, params| {
+            *img = ops::sharpen(img, params.number("amount"));
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "rotate",
+        about: "Rotates clockwise",
+        params: &[Param {
+            name: "degrees",
+            default: 90.0,
+            about: "90, 180 or 270",
+        }],
+        apply: |img, params| {
+            let degrees = params.number("degrees");
+            *img = ops::rotate(img, degrees as u32)
+                .filter(|_| degrees.fract() == 0.0)
+                .ok_or("degrees must be 90, 180 or 270")?;
+            Ok(())
+        },
+    },
+    Builtin {
+        name: "flip",
+        about: "Mirrors left to right",
+        params: &[],
+        apply: |img, _| {
+            *img = ops::flip(img, true, false);
+            Ok(())
+        },
+    },
+];
+
+/// A program in the plugins directory.
+struct Plugin {
+    name: String,
+    path: PathBuf,
+}
+
+impl BruhFilter for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn about(&self) -> &str {
+        "Plugin"
+    }
+
+    fn params(&self) -> Option<&[Param]> {
+        None
+    }
+
+    fn apply(&self, img: &mut RgbaImage, params: &Params) -> Result<(), String> {
+        let mut child = Command::new(&self.path)
+            .arg(img.width().to_string())
+            .arg(img.height().to_string())
+            .args(
+                params
+                    .values
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value)),
+            )
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("can't run {}: {}", self.path.display(), e))?;
+
+        // Written from another thread, a plugin may start answering before it read everything.
+        let mut stdin = child.stdin.take().unwrap();
+        let output = thread::scope(|scope| {
+            scope.spawn(|| {
+                let _ = stdin.write_all(img.as_raw());
+                drop(stdin);
+            });
+            let mut rgba = Vec::with_capacity(img.as_raw().len());
+            child
+                .stdout
+                .take()
+                .unwrap()
+                .read_to_end(&mut rgba)
+                .map(|_| rgba)
+        });
+        // What the plugin prints to stderr goes straight to ours.
+        let status = child.wait().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(status.to_string());
+        }
+
+        let rgba = output.map_err(|e| e.to_string())?;
+        if rgba.len() != img.as_raw().len() {
+            return Err(format!(
+                "wrote {} bytes instead of the {} of a {}x{} image",
+                rgba.len(),
+                img.as_raw().len(),
+                img.width(),
+                img.height()
+            ));
+        }
+        *img = RgbaImage::from_raw(img.width(), img.height(), rgba).unwrap();
+        Ok(())
+    }
+}
+
+/// A filter from the registry with the values it was given.
+pub struct Step<'a> {
+    pub filter: &'a dyn BruhFilter,
+    pub params: Params,
+}
+
+impl Step<'_> {
+    pub fn apply(&self, img: &mut RgbaImage) -> Result<(), String> {
+        self.filter.apply(img, &self.params)
+    }
+}
+
+/// The filters `bruh apply` knows, by name.
+pub struct Registry {
+    filters: Vec<Box<dyn BruhFilter>>,
+}
+
+impl Registry {
+    /// Every built-in filter.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            filters: Vec::new(),
+        };
+        for builtin in BUILTINS {
+            registry.register(Box::new(*builtin));
+        }
+        registry
+    }
+
+    /// Adds `filter`, replacing one with the same name.
+    pub fn register(&mut self, filter: Box<dyn BruhFilter>) {
+        self.filters.retain(|known| known.name() != filter.name());
+        self.filters.push(filter);
+    }
+
+    /// Registers every executable in `dir` as a plugin, warning about any that replace a
+    /// built-in. A missing directory has none.
+    pub fn load_plugins(&mut self, dir: &Path) -> io::Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+        for path in paths {
+            if !is_executable(&path) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(OsStr::to_str) else {
+                continue;
+            };
+            log::debug!("filter plugin {} at {}", name, path.display());
+            if BUILTINS.iter().any(|builtin| builtin.name == name) {
+                log::warn!("{} replaces the built-in {} filter", path.display(), name);
+            }
+            self.register(Box::new(Plugin {
+                name: name.to_owned(),
+                path: path.clone(),
+            }));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn BruhFilter> {
+        self.filters
+            .iter()
+            .find(|filter| filter.name() == name)
+            .map(|filter| filter.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn BruhFilter> {
+        self.filters.iter().map(|filter| filter.as_ref())
+    }
+
+    /// Reads `name[:param=value,...]`, checking the filter exists and takes those parameters.
+    /// Parameters left out get their defaults.
+    pub fn parse(&self, step: &str) -> Result<Step<'_>, String> {
+        let (name, given) = step.split_once(':').unwrap_or((step, ""));
+        let filter = self
+            .get(name)
+            .ok_or_else(|| format!("Unknown filter '{}', see `bruh apply --list`", name))?;
+
+        let mut values = Vec::new();
+        for pair in given.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' should be name=value", pair))?;
+            values.push((key.to_owned(), value.to_owned()));
+        }
+        if let Some(params) = filter.params() {
+            for (key, value) in &values {
+                if !params.iter().any(|param| param.name == key) {
+                    return Err(format!("{} doesn't take '{}'", name, key));
+                }
+                if value.parse::<f32>().is_err() {
+                    return Err(format!("{} of {} must be a number", key, name));
+                }
+            }
+            for param in params {
+                if !values.iter().any(|(key, _)| key == param.name) {
+                    values.push((param.name.to_owned(), param.default.to_string()));
+                }
+            }
+        }
+
+        Ok(Step {
+            filter,
+            params: Params { values },
+        })
+    }
+}
+
+/// Splits a chain of steps written as one argument, like
+/// `resize=50%,adjust:gamma=0.9,contrast=1.2,grayscale`, into `resize=50%`,
+/// `adjust:gamma=0.9,contrast=1.2` and `grayscale`. A part whose name `is_step` knows starts the
+/// next step, any other is one more `param=value` of the step before.
+pub fn split_chain(chain: &str, is_step: impl Fn(&str) -> bool) -> Result<Vec<String>, String> {
+    let mut steps: Vec<String> = Vec::new();
+    for part in chain
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let name = part.split([':', '=']).next().unwrap_or(part);
+        match steps.last_mut() {
+            Some(step) if !is_step(name) && part.contains('=') => {
+                // The first parameter goes after a colon, the others after commas.
+                step.push(if step.contains(':') { ',' } else { ':' });
+                step.push_str(part);
+            }
+            _ if is_step(name) => steps.push(part.to_owned()),
+            _ => return Err(format!("Unknown step '{}', see `bruh apply --list`", name)),
+        }
+    }
+    Ok(steps)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn chains_split_where_a_known_step_starts() {
+        let is_step = |name: &str| ["resize", "adjust", "grayscale"].contains(&name);
+        assert_eq!(
+            split_chain(
+                "resize=50%,adjust:gamma=0.9,contrast=1.2, grayscale,",
+                is_step
+            )
+            .unwrap(),
+            ["resize=50%", "adjust:gamma=0.9,contrast=1.2", "grayscale"]
+        );
+        // The first parameter of a step written without one gets the colon.
+        assert_eq!(
+            split_chain("adjust,gamma=2,contrast=1", is_step).unwrap(),
+            ["adjust:gamma=2,contrast=1"]
+        );
+        assert!(split_chain("gamma=2", is_step).is_err());
+        assert!(split_chain("grayscale,sparkle", is_step).is_err());
+    }
+
+    #[test]
+    fn steps_are_checked_and_get_their_defaults() {
+        let registry = Registry::new();
+
+        let step = registry.parse("adjust:gamma=0.5").unwrap();
+        assert_eq!(step.filter.name(), "adjust");
+        assert_eq!(step.params.number("gamma"), 0.5);
+        assert_eq!(step.params.number("contrast"), 1.0);
+        assert_eq!(registry.parse("blur").unwrap().params.number("sigma"), 1.0);
+
+        assert!(registry.parse("sparkle").is_err());
+        assert!(registry.parse("blur:radius=2").is_err());
+        assert!(registry.parse("blur:sigma=lots").is_err());
+        assert!(registry.parse("blur:sigma").is_err());
+        // Rotating isn't limited by parse, but by the filter itself.
+        let mut img = RgbaImage::new(2, 1);
+        let step = registry.parse("rotate:degrees=45").unwrap();
+        assert!(step.apply(&mut img).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugins_run_fail_and_replace_builtins() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("bruh-filters-plugins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = |name: &str, body: &str, mode: u32| {
+            let path = dir.join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        script("same", "cat", 0o755);
+        script("fails", "cat >/dev/null; exit 3", 0o755);
+        script("short", "cat >/dev/null; printf abcd", 0o755);
+        script("blur", "cat", 0o755);
+        script("skipped", "cat", 0o644);
+
+        let mut registry = Registry::new();
+        registry.load_plugins(&dir).unwrap();
+        registry.load_plugins(&dir.join("missing")).unwrap();
+        assert!(registry.get("skipped").is_none());
+        // A plugin named like a built-in replaces it, with a warning.
+        assert_eq!(registry.get("blur").unwrap().about(), "Plugin");
+        assert_eq!(registry.iter().filter(|f| f.name() == "blur").count(), 1);
+
+        let original = RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+        let mut img = original.clone();
+        registry
+            .parse("same:anything=goes")
+            .unwrap()
+            .apply(&mut img)
+            .unwrap();
+        assert_eq!(img, original);
+
+        for failing in ["fails", "short"] {
+            let mut img = original.clone();
+            assert!(registry.parse(failing).unwrap().apply(&mut img).is_err());
+            assert_eq!(img, original);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+//! Feeds arbitrary bytes to the decoder. Failing to decode is fine, panicking is a bug.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((width, height, rgba)) = bruh_core::decode(data, 1 << 20) {
+        assert_eq!(rgba.len(), width as usize * height as usize * 4);
+    }
+    if let Ok(Some(image)) = bruh_core::BruhImageRef::parse(data) {
+        let row_size = image.width as usize * 4;
+        assert!(image.rows().all(|row| row.len() == row_size));
+    }
+});
@@ -0,0 +1,30 @@
+//! Encodes arbitrary pixels in every format and compression and checks that decoding gives
+//! them back unchanged.
+
+#![no_main]
+
+use bruh_core::{BruhFile, Compression, PixelFormat};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the width, the rest are RGBA pixels.
+    let Some((&width, pixels)) = data.split_first() else {
+        return;
+    };
+    let width = width.max(1) as usize;
+    let rgba = &pixels[..pixels.len() / 4 / width * width * 4];
+    let height = rgba.len() / 4 / width;
+
+    for pixel_format in PixelFormat::all() {
+        for compression in Compression::all() {
+            let Some(file) =
+                BruhFile::encode(width as u32, height as u32, rgba, pixel_format, compression)
+            else {
+                continue;
+            };
+
+            let decoded = bruh_core::decode(&file.to_bytes(), u64::MAX).unwrap();
+            assert_eq!(decoded, (width as u32, height as u32, rgba.to_vec()));
+        }
+    }
+});
@@ -0,0 +1,264 @@
+//! `bruh identify`: one line per file in the style of ImageMagick's `identify`, so scripts
+//! written for `magick identify` work on BRUH files too. Only the start of a file is read, all of
+//! this is in its header.
+
+use std::{io, path::Path};
+
+use image::{ColorType, ImageDecoder, ImageFormat};
+
+use crate::format::{self, BruhFile};
+
+/// How much of a file `identify` needs at most. PNGs can have a few chunks before their pixels.
+pub const PREFIX_SIZE: usize = 64 * 1024;
+
+/// What `--format` prints without one: `image.bruh BRUH 1920x1080 RGBA8 deflate 1.2MiB`. The
+/// escapes follow ImageMagick as far as they can:
+///
+/// - `%i` the path as given, `%f` its file name, `%d` its directory and `%e` its extension
+/// - `%m` the format, `BRUH`, `PNG` or `HDR`
+/// - `%w` and `%h` the width and height
+/// - `%r` the pixel format, e.g. `RGBA8`, and `%z` the bits per channel
+/// - `%C` the compression
+/// - `%b` the size of the file, e.g. `1.2MiB`
+/// - `%%` a `%`, and `\n` and `\t` a newline and a tab.
+pub const DEFAULT_FORMAT: &str = "%i %m %wx%h %r %C %b\\n";
+
+pub struct Identity {
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: &'static str,
+    pub depth: u32,
+    pub compression: &'static str,
+}
+
+/// Reads what a file is from `prefix`, its first `PREFIX_SIZE` bytes or all of it if it is
+/// shorter.
+pub fn identify(prefix: &[u8]) -> Result<Identity, String> {
+    if !prefix.starts_with(format::MAGIC) {
+        if let Ok(image_format) = image::guess_format(prefix) {
+            return identify_foreign(prefix, image_format);
+        }
+    }
+
+    if format::is_legacy(prefix) {
+        // Legacy pixels are hex text, anything else isn't an image at all.
+        let hex = |byte: &u8| byte.is_ascii_hexdigit() || *byte == b'\n';
+        if prefix.len() < 8 || !prefix[8..].iter().all(hex) {
+            return Err("not an image".to_owned());
+        }
+        return Ok(Identity {
+            format: "BRUH",
+            width: u32::from_ne_bytes(prefix[0..4].try_into().unwrap()),
+            height: u32::from_ne_bytes(prefix[4..8].try_into().unwrap()),
+            pixel_format: format::PixelFormat::Rgb8.name(),
+            depth: 8,
+            compression: "legacy",
+        });
+    }
+    let header = BruhFile::parse_header(prefix).map_err(|e| e.to_string())?;
+    Ok(Identity {
+        format: "BRUH",
+        width: header.width,
+        height: header.height,
+        pixel_format: header.format.name(),
+        depth: match header.format {
+            format::PixelFormat::RgbaF16 => 16,
+            format::PixelFormat::RgbaF32 => 32,
+            _ => 8,
+        },
+        compression: if header.lossy {
+            "lossy"
+        } else {
+            header.compression.name()
+        },
+    })
+}
+
+/// The PNG and HDR files bruh reads wherever it reads BRUH.
+fn identify_foreign(prefix: &[u8], image_format: ImageFormat) -> Result<Identity, String> {
+    let decoded = match image_format {
+        ImageFormat::Png => image::codecs::png::PngDecoder::new(io::Cursor::new(prefix))
+            .map(|decoder| ("PNG", decoder.dimensions(), decoder.color_type(), "deflate")),
+        ImageFormat::Hdr => image::codecs::hdr::HdrAdapter::new(io::Cursor::new(prefix))
+            // The adapter hands out 8-bit pixels, but the file holds floats.
+            .map(|decoder| ("HDR", decoder.dimensions(), ColorType::Rgb32F, "rle")),
+        _ => {
+            return Err(format!(
+                "a {} image, which bruh can't read",
+                image_format.extensions_str()[0].to_uppercase()
+            ))
+        }
+    };
+    let (format, (width, height), color, compression) = decoded.map_err(|e| e.to_string())?;
+    let pixel_format = match color {
+        ColorType::L8 => "Gray8",
+        ColorType::La8 => "GrayA8",
+        ColorType::Rgb8 => "RGB8",
+        ColorType::Rgba8 => "RGBA8",
+        ColorType::L16 => "Gray16",
+        ColorType::La16 => "GrayA16",
+        ColorType::Rgb16 => "RGB16",
+        ColorType::Rgba16 => "RGBA16",
+        ColorType::Rgb32F => "RGB32F",
+        ColorType::Rgba32F => "RGBA32F",
+        _ => "unknown",
+    };
+    Ok(Identity {
+        format,
+        width,
+        height,
+        pixel_format,
+        depth: (color.bits_per_pixel() / color.channel_count() as u16) as u32,
+        compression,
+    })
+}
+
+/// `template` with the escapes of `DEFAULT_FORMAT` filled in for the file at `path`, which is
+/// `size` bytes. Unknown escapes are kept as they are.
+pub fn render(template: &str, path: &Path, size: u64, identity: &Identity) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' && c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let Some(next) = chars.next() else {
+            out.push(c);
+            break;
+        };
+        match (c, next) {
+            ('%', 'i') => out += &path.display().to_string(),
+            ('%', 'f') => out += &path.file_name().unwrap_or_default().to_string_lossy(),
+            ('%', 'd') => out += &path.parent().unwrap_or(Path::new("")).display().to_string(),
+            ('%', 'e') => out += &path.extension().unwrap_or_default().to_string_lossy(),
+            ('%', 'm') => out += identity.format,
+            ('%', 'w') => out += &identity.width.to_string(),
+            ('%', 'h') => out += &identity.height.to_string(),
+            ('%', 'r') => out += identity.pixel_format,
+            ('%', 'z') => out += &identity.depth.to_string(),
+            ('%', 'C') => out += identity.compression,
+            ('%', 'b') => out += &human_size(size),
+            ('%', '%') => out.push('%'),
+            ('\\', 'n') => out.push('\n'),
+            ('\\', 't') => out.push('\t'),
+            ('\\', '\\') => out.push('\\'),
+            _ => {
+                out.push(c);
+                out.push(next);
+            }
+        }
+    }
+    out
+}
+
+/// `bytes` in binary units like ImageMagick prints them: `512B`, `34.5KiB`, `1.2MiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use format::{Compression, PixelFormat};
+    use image::{codecs::png::PngEncoder, ImageEncoder};
+
+    fn summary(identity: &Identity) -> String {
+        render("%m %wx%h %r %z %C", Path::new(""), 0, identity)
+    }
+
+    #[test]
+    fn files_are_told_apart_from_their_start() {
+        let rgba = [200, 100, 50, 128].repeat(300 * 200);
+        let file = BruhFile::from_rgba(300, 200, &rgba, Compression::Deflate).to_bytes();
+        assert_eq!(
+            summary(&identify(&file).unwrap()),
+            "BRUH 300x200 RGBA8 8 deflate"
+        );
+        let noise: Vec<u8> = (0..300 * 200 * 4u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let file = BruhFile::from_rgba(300, 200, &noise, Compression::None).to_bytes();
+        assert!(file.len() > PREFIX_SIZE);
+        assert_eq!(
+            summary(&identify(&file[..PREFIX_SIZE]).unwrap()),
+            "BRUH 300x200 RGBA8 8 none"
+        );
+        let lossy = BruhFile::encode_lossy(300, 200, &rgba, 80, Compression::Rle).to_bytes();
+        assert_eq!(
+            summary(&identify(&lossy).unwrap()).rsplit(' ').next(),
+            Some("lossy")
+        );
+        let floats = vec![0.5; 4 * 4 * 4];
+        let hdr = BruhFile::from_rgba_f32(4, 4, &floats, PixelFormat::RgbaF16, Compression::Rle)
+            .unwrap()
+            .to_bytes();
+        assert_eq!(summary(&identify(&hdr).unwrap()), "BRUH 4x4 RGBA16F 16 rle");
+
+        let mut legacy = [3u32.to_ne_bytes(), 1u32.to_ne_bytes()].concat();
+        legacy.extend_from_slice(b"ff0000\n00ff00\n0000ff\n");
+        assert_eq!(
+            summary(&identify(&legacy).unwrap()),
+            "BRUH 3x1 RGB8 8 legacy"
+        );
+
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(&[1, 2, 3, 4, 5, 6], 1, 3, ColorType::L16)
+            .unwrap();
+        assert_eq!(
+            summary(&identify(&png).unwrap()),
+            "PNG 1x3 Gray16 16 deflate"
+        );
+
+        assert!(identify(b"GIF89a\x01\x00\x01\x00").is_err());
+        assert!(identify(b"just some text, not hex").is_err());
+    }
+
+    #[test]
+    fn templates_fill_in_every_escape() {
+        let identity = Identity {
+            format: "BRUH",
+            width: 4,
+            height: 3,
+            pixel_format: "RGB8",
+            depth: 8,
+            compression: "rle",
+        };
+        let path = Path::new("art/cat.bruh");
+        assert_eq!(
+            render(DEFAULT_FORMAT, path, 2048, &identity),
+            "art/cat.bruh BRUH 4x3 RGB8 rle 2.0KiB\n"
+        );
+        assert_eq!(
+            render("%f|%d|%e|%z|100%%\\t\\\\", path, 0, &identity),
+            "cat.bruh|art|bruh|8|100%\t\\"
+        );
+        // Unknown escapes and a lone one at the end stay as they are.
+        assert_eq!(render("%q \\x %", path, 0, &identity), "%q \\x %");
+    }
+
+    #[test]
+    fn sizes_are_printed_in_binary_units() {
+        assert_eq!(human_size(0), "0B");
+        assert_eq!(human_size(1023), "1023B");
+        assert_eq!(human_size(1024), "1.0KiB");
+        assert_eq!(human_size(1536), "1.5KiB");
+        assert_eq!(human_size(1_258_291), "1.2MiB");
+        assert_eq!(human_size(5 << 40), "5.0TiB");
+        // Past the last unit the number grows instead.
+        assert_eq!(human_size(u64::MAX), "16384.0PiB");
+    }
+}
@@ -0,0 +1,344 @@
+//! Annotated dumps of the raw file layout, for debugging BRUH readers and writers.
+//!
+//! This walks the bytes by itself instead of going through `BruhFile::parse`, so broken files
+//! can be inspected up to the point where they stop making sense.
+
+use crate::format::{self, BruhFile, Chunk, Compression, Layer, PixelFormat, BRUH_HEADER_SIZE};
+
+struct Dump {
+    /// How many data bytes of each section to show, `None` to only print the annotations.
+    hex: Option<usize>,
+    out: String,
+}
+
+impl Dump {
+    fn line(&mut self, line: &str) {
+        self.out += line;
+        self.out.push('\n');
+    }
+
+    fn field(&mut self, offset: usize, bytes: &[u8], note: &str) {
+        if self.hex.is_none() {
+            self.line(&format!("{:08x}  {}", offset, note));
+            return;
+        }
+
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        self.line(&format!(
+            "{:08x}  {:<47}  {:<16}  {}",
+            offset,
+            hex.join(" "),
+            ascii,
+            note
+        ));
+    }
+
+    fn data(&mut self, offset: usize, bytes: &[u8], note: &str) {
+        let Some(limit) = self.hex else {
+            self.line(&format!("{:08x}  {}", offset, note));
+            return;
+        };
+
+        let shown = &bytes[..bytes.len().min(limit)];
+        if shown.is_empty() {
+            self.line(&format!("{:08x}  {}", offset, note));
+        }
+        for (i, line) in shown.chunks(16).enumerate() {
+            self.field(offset + i * 16, line, if i == 0 { note } else { "" });
+        }
+        if shown.len() < bytes.len() {
+            self.line(&format!(
+                "{:08x}  ... {} more bytes",
+                offset + shown.len(),
+                bytes.len() - shown.len()
+            ));
+        }
+    }
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// The layout of `bytes`, one line per field. With `hex` set, the raw bytes are shown next to
+/// the annotations, limited to that many bytes per chunk payload.
+pub fn inspection(bytes: &[u8], hex: Option<usize>) -> String {
+    let mut dump = Dump {
+        hex,
+        out: String::new(),
+    };
+    if format::is_legacy(bytes) {
+        write_legacy(&mut dump, bytes);
+    } else {
+        write_layout(&mut dump, bytes);
+    }
+    dump.out
+}
+
+fn write_layout(dump: &mut Dump, bytes: &[u8]) {
+    if bytes.len() < BRUH_HEADER_SIZE {
+        dump.line(&format!(
+            "File is {} bytes long, shorter than the {} byte header",
+            bytes.len(),
+            BRUH_HEADER_SIZE
+        ));
+        return;
+    }
+
+    let format = match PixelFormat::from_u8(bytes[5]) {
+        Some(format) => format!("pixel format {} ({})", bytes[5], format.name()),
+        None => format!("pixel format {} (unknown)", bytes[5]),
+    };
+    dump.field(0, &bytes[0..4], "magic");
+    dump.field(4, &bytes[4..5], &format!("version {}", bytes[4]));
+    dump.field(5, &bytes[5..6], &format);
+    let compression = match Compression::from_u8(bytes[6]) {
+        Some(compression) => format!("compression {} ({})", bytes[6], compression.name()),
+        None => format!("compression {} (unknown)", bytes[6]),
+    };
+    dump.field(6, &bytes[6..7], &compression);
+    let names: Vec<&str> = [
+        (format::FLAG_ENCRYPTED, "encrypted"),
+        (format::FLAG_TILED, "tiled"),
+        (format::FLAG_INTERLACED, "interlaced"),
+        (format::FLAG_LINEAR, "linear"),
+        (format::FLAG_BGR, "bgr"),
+        (format::FLAG_LOSSY, "lossy"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| bytes[7] & flag != 0)
+    .map(|(_, name)| name)
+    .collect();
+    let flags = if bytes[7] & !format::KNOWN_FLAGS != 0 {
+        format!("flags {:#04x} (unknown)", bytes[7])
+    } else if names.is_empty() {
+        "flags 0".to_owned()
+    } else {
+        format!("flags {} ({})", bytes[7], names.join(", "))
+    };
+    dump.field(7, &bytes[7..8], &flags);
+    dump.field(8, &bytes[8..12], &format!("width {}", u32_le(&bytes[8..])));
+    dump.field(
+        12,
+        &bytes[12..16],
+        &format!("height {}", u32_le(&bytes[12..])),
+    );
+
+    let mut offset = BRUH_HEADER_SIZE;
+    while offset < bytes.len() {
+        dump.line("");
+        if bytes.len() - offset < 8 {
+            dump.data(
+                offset,
+                &bytes[offset..],
+                "trailing bytes, too short for a chunk header",
+            );
+            return;
+        }
+
+        let tag: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+        let length = u32_le(&bytes[offset + 4..]) as usize;
+        let start = offset + 8;
+        let chunk = Chunk::new(tag, Vec::new());
+        let kind = if chunk.is_critical() {
+            "critical"
+        } else {
+            "ancillary"
+        };
+
+        dump.field(
+            offset,
+            &bytes[offset..offset + 4],
+            &format!("chunk {} ({})", String::from_utf8_lossy(&tag), kind),
+        );
+        dump.field(
+            offset + 4,
+            &bytes[offset + 4..start],
+            &format!("length {}", length),
+        );
+
+        if bytes.len() - start < length.saturating_add(4) {
+            dump.data(
+                start,
+                &bytes[start..],
+                &format!(
+                    "data, cut off after {} of {} bytes",
+                    bytes.len() - start,
+                    length
+                ),
+            );
+            return;
+        }
+
+        let data = &bytes[start..start + length];
+        let embedded = match tag {
+            format::THUMBNAIL => Some("thumbnail"),
+            format::MIPMAP => Some("mipmap level"),
+            format::IMAGE => Some("image"),
+            _ => None,
+        };
+        let note = match embedded.zip(BruhFile::parse_header(data).ok()) {
+            Some((name, header)) => format!(
+                "{} {}x{}, {} bytes",
+                name, header.width, header.height, length
+            ),
+            None if tag == format::PHYSICAL_SIZE && length == 8 => {
+                format!("{}x{} pixels per inch", u32_le(data), u32_le(&data[4..]))
+            }
+            None if tag == format::LAYER => match Layer::parse(data) {
+                Ok(layer) => format!(
+                    "layer '{}' {}x{} at {},{}, {}, {} bytes",
+                    layer.name,
+                    layer.image.header.width,
+                    layer.image.header.height,
+                    layer.x,
+                    layer.y,
+                    layer.blend.name(),
+                    length
+                ),
+                Err(_) => format!("damaged layer, {} bytes", length),
+            },
+            None if tag == format::STRIDE && length == 4 => {
+                format!("row stride {} bytes", u32_le(data))
+            }
+            None => format!("data, {} bytes", length),
+        };
+        dump.data(start, data, &note);
+
+        let crc = u32_le(&bytes[start + length..]);
+        let expected = Chunk::new(tag, data.to_vec()).crc();
+        let note = if crc == expected {
+            format!("crc {:08x} ok", crc)
+        } else {
+            format!("crc {:08x} MISMATCH, expected {:08x}", crc, expected)
+        };
+        dump.field(
+            start + length,
+            &bytes[start + length..start + length + 4],
+            &note,
+        );
+
+        offset = start + length + 4;
+    }
+}
+
+fn write_legacy(dump: &mut Dump, bytes: &[u8]) {
+    dump.line("Legacy (v0) file, no magic");
+    if bytes.len() < 8 {
+        dump.line(&format!(
+            "File is {} bytes long, shorter than the 8 byte header",
+            bytes.len()
+        ));
+        return;
+    }
+
+    let width = u32::from_ne_bytes(bytes[0..4].try_into().unwrap());
+    let height = u32::from_ne_bytes(bytes[4..8].try_into().unwrap());
+    dump.field(0, &bytes[0..4], &format!("width {} (native endian)", width));
+    dump.field(
+        4,
+        &bytes[4..8],
+        &format!("height {} (native endian)", height),
+    );
+    dump.data(
+        8,
+        &bytes[8..],
+        &format!("hex rows, {} bytes", bytes.len() - 8),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vec<u8> {
+        let mut file =
+            BruhFile::from_rgba(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255], Compression::Rle);
+        file.set_pixels_per_inch(Some((300, 150)));
+        file.set_thumbnail(&BruhFile::from_rgba(
+            1,
+            1,
+            &[9, 9, 9, 255],
+            Compression::None,
+        ));
+        file.to_bytes()
+    }
+
+    #[test]
+    fn every_field_and_chunk_is_annotated() {
+        let out = inspection(&example(), None);
+        for line in [
+            "00000000  magic",
+            "00000004  version 1",
+            "00000005  pixel format 0 (RGB8)",
+            "00000006  compression 1 (rle)",
+            "00000007  flags 0",
+            "00000008  width 2",
+            "0000000c  height 1",
+            "00000010  chunk thmb (ancillary)",
+            "00000014  length ",
+        ] {
+            assert!(
+                out.lines().any(|l| l.starts_with(line)),
+                "no {:?} in\n{}",
+                line,
+                out
+            );
+        }
+        assert!(out.contains("300x150 pixels per inch"));
+        assert!(out.contains("thumbnail 1x1, "));
+        assert!(out.contains("  chunk PXLS (critical)\n"));
+        assert!(!out.contains("MISMATCH"));
+        assert_eq!(
+            out.matches(" ok\n").count(),
+            out.matches("  chunk ").count()
+        );
+    }
+
+    #[test]
+    fn hex_shows_the_bytes_up_to_the_limit() {
+        let out = inspection(&example(), Some(2));
+        assert!(out.starts_with(&format!(
+            "00000000  {:<47}  {:<16}  magic\n",
+            "42 52 55 48", "BRUH"
+        )));
+        assert!(out.contains(" more bytes\n"));
+    }
+
+    #[test]
+    fn damage_is_pointed_out_where_it_starts() {
+        let mut bytes = example();
+        // The last byte of the first chunk, just before its crc.
+        let length = u32_le(&bytes[BRUH_HEADER_SIZE + 4..]) as usize;
+        bytes[BRUH_HEADER_SIZE + 8 + length - 1] ^= 0xff;
+        assert_eq!(inspection(&bytes, None).matches("MISMATCH").count(), 1);
+
+        let out = inspection(&bytes[..BRUH_HEADER_SIZE + 10], None);
+        assert!(out.ends_with(&format!("data, cut off after 2 of {} bytes\n", length)));
+        let out = inspection(&bytes[..BRUH_HEADER_SIZE + 3], None);
+        assert!(out.ends_with("trailing bytes, too short for a chunk header\n"));
+        assert!(inspection(&bytes[..10], None).starts_with("File is 10 bytes long"));
+
+        let mut unknown = example();
+        unknown[5] = 99;
+        unknown[7] = 0x80;
+        let out = inspection(&unknown, None);
+        assert!(out.contains("pixel format 99 (unknown)"));
+        assert!(out.contains("flags 0x80 (unknown)"));
+    }
+
+    #[test]
+    fn legacy_files_show_their_native_header() {
+        let mut bytes = [2u32.to_ne_bytes(), 1u32.to_ne_bytes()].concat();
+        bytes.extend_from_slice(b"ff0000\n0000ff\n");
+        let out = inspection(&bytes, None);
+        assert!(out.starts_with("Legacy (v0) file, no magic\n"));
+        assert!(out.contains("width 2 (native endian)"));
+        assert!(out.contains("hex rows, 14 bytes"));
+        assert!(inspection(&bytes[..5], None).contains("shorter than the 8 byte header"));
+    }
+}
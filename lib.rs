@@ -0,0 +1,4382 @@
+//! The commands of `bruh`. The viewer is `bruh-viewer`, a program of its own that `bruh` runs for
+//! anything that isn't a command, so the commands build without egui and skia. It uses the file
+//! handling and image operations of this crate, which are public for it.
+
+extern crate css_color_parser;
+
+mod analysis;
+pub mod archive;
+mod association;
+mod bench;
+mod completions;
+pub mod config;
+mod crypto;
+mod encrypt;
+pub mod error;
+mod export;
+mod filters;
+mod identify;
+mod inspect;
+#[cfg(all(feature = "fuse", any(target_os = "linux", target_os = "macos")))]
+mod mount;
+pub mod ops;
+mod pipeline;
+mod raw;
+mod screenshot;
+mod script;
+mod serve;
+mod signature;
+mod stego;
+mod terminal;
+mod thumbnailer;
+#[cfg(feature = "ffmpeg")]
+mod video;
+mod watch;
+
+// The codec was a module of the commands before it was a crate of its own.
+pub use bruh_core as format;
+pub use bruh_core::storage;
+
+use arboard::{Clipboard, ImageData};
+use image::{
+    codecs::{
+        hdr::{HdrDecoder, HdrMetadata},
+        png::PngEncoder,
+    },
+    imageops::FilterType,
+    ImageEncoder, Pixel, RgbaImage,
+};
+use log::{debug, info, warn};
+use std::{
+    borrow::Cow,
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    panic,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use css_color_parser::Color as CssColor;
+use error::BruhError;
+use format::{BruhDecoder, BruhFile, Compression, DecodeError, Layer};
+use serde::Serialize;
+use storage::{Disk, Storage};
+
+// Lets `bench` report how much memory encoding and decoding take.
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
+
+fn write_bruh_file(path: &Path, file: &BruhFile) -> Result<(), BruhError> {
+    let started = Instant::now();
+    let bytes = file.to_bytes();
+
+    Disk.write(path, &bytes)
+        .map_err(|e| BruhError::io(path, e))?;
+    debug!(
+        "wrote {}: {}x{} {} with {} compression, {} bytes in {:.2?}",
+        path.display(),
+        file.header.width,
+        file.header.height,
+        file.header.format.name(),
+        file.header.compression.name(),
+        bytes.len(),
+        started.elapsed()
+    );
+
+    Ok(())
+}
+
+pub fn write_bruh(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), BruhError> {
+    write_bruh_file(
+        path,
+        &BruhFile::from_rgba(width, height, rgba, config::get().compression),
+    )
+}
+
+/// What `compile` adds to a plain conversion.
+#[derive(Default)]
+struct CompileOptions {
+    /// Encrypts the file with this password.
+    password: Option<String>,
+    /// Embeds a thumbnail fitting into a square this big.
+    thumbnail: Option<u32>,
+    /// Stores every mipmap level down to 1x1.
+    mipmaps: bool,
+    /// Marks the colors as linear even if the PNG doesn't say so.
+    linear: bool,
+    /// Keeps the pixels as they are instead of turning them the way the EXIF orientation says.
+    no_auto_orient: bool,
+    /// Stores blue before red.
+    bgr: bool,
+    /// Stores a float per channel in this format. HDR sources get 32 bit floats anyway.
+    float: Option<format::PixelFormat>,
+    /// Stores the pixels lossy at this quality, 1 to 100.
+    quality: Option<u8>,
+    /// Works out the SHA-512 of every input and output, for `--manifest`.
+    checksums: bool,
+}
+
+/// What converting one image cost or saved.
+struct Conversion {
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    /// Bytes of the PNG or HDR file.
+    input_size: usize,
+    /// Bytes of the BRUH file.
+    output_size: u64,
+    format: format::PixelFormat,
+    compression: Compression,
+    /// The SHA-512 of the original and of the BRUH file, if they were asked for.
+    checksums: Option<[[u8; 64]; 2]>,
+}
+
+impl Conversion {
+    /// The size of the BRUH file in percent of the original.
+    fn ratio(&self) -> f64 {
+        self.output_size as f64 / self.input_size.max(1) as f64 * 100.0
+    }
+}
+
+/// A converted image, ready to be written.
+struct Encoded {
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    input_size: usize,
+    bytes: Vec<u8>,
+    format: format::PixelFormat,
+    compression: Compression,
+    checksums: Option<[[u8; 64]; 2]>,
+}
+
+impl Encoded {
+    fn write(self) -> Result<Conversion, BruhError> {
+        let started = Instant::now();
+        Disk.write(&self.output, &self.bytes)
+            .map_err(|e| BruhError::io(&self.output, e))?;
+        debug!(
+            "wrote {}: {} bytes in {:.2?}",
+            self.output.display(),
+            self.bytes.len(),
+            started.elapsed()
+        );
+        Ok(Conversion {
+            output: self.output,
+            width: self.width,
+            height: self.height,
+            input_size: self.input_size,
+            output_size: self.bytes.len() as u64,
+            format: self.format,
+            compression: self.compression,
+            checksums: self.checksums,
+        })
+    }
+}
+
+/// Converts the PNG at `path`, returning where the BRUH ended up and how big it is.
+fn png_to_bruh(path: PathBuf, options: &CompileOptions) -> Result<Conversion, BruhError> {
+    let bytes = read_file(&path)?;
+    encode_png(&path, &bytes, options)?.write()
+}
+
+/// The extensions of the files `compile` reads, PNG and Radiance HDR.
+const COMPILE_EXTENSIONS: &[&str] = &["png", "hdr"];
+
+/// Converts `bytes`, the PNG or HDR file at `path`, to BRUH.
+fn encode_png(path: &Path, bytes: &[u8], options: &CompileOptions) -> Result<Encoded, BruhError> {
+    let started = Instant::now();
+    let image_error = |source| BruhError::Image {
+        path: path.to_path_buf(),
+        source,
+    };
+    // The generic HDR decoder gives 8 bit pixels, the float ones need the codec itself.
+    let hdr = bytes.starts_with(b"#?");
+    let img = if hdr {
+        let decoder = HdrDecoder::new(bytes).map_err(image_error)?;
+        let HdrMetadata { width, height, .. } = decoder.metadata();
+        let pixels = decoder.read_image_hdr().map_err(image_error)?;
+        let rgba = pixels.iter().flat_map(|pixel| pixel.to_rgba().0).collect();
+        image::DynamicImage::ImageRgba32F(
+            image::Rgba32FImage::from_raw(width, height, rgba).unwrap(),
+        )
+    } else {
+        image::load_from_memory(bytes).map_err(image_error)?
+    };
+    debug!(
+        "read {}: {}x{} {:?} in {:.2?}",
+        path.display(),
+        img.width(),
+        img.height(),
+        img.color(),
+        started.elapsed()
+    );
+
+    let output = output_path(None, &compiled_path(path))?;
+
+    let mut rgba = img.to_rgba8();
+    let mut exif = export::png_exif(bytes);
+    let mut pixels_per_inch = export::png_pixels_per_inch(bytes);
+    let mut orientation = 1;
+    if let Some(exif) = exif.as_mut().filter(|_| !options.no_auto_orient) {
+        orientation = export::exif_orientation(exif).unwrap_or(1);
+        if orientation != 1 {
+            debug!(
+                "turning {} to EXIF orientation {}",
+                path.display(),
+                orientation
+            );
+            rgba = ops::orient(&rgba, orientation);
+            // Orientations 5 to 8 turn the image on its side.
+            if orientation >= 5 {
+                pixels_per_inch = pixels_per_inch.map(|(x, y)| (y, x));
+            }
+            export::reset_exif_orientation(exif);
+        }
+    }
+
+    if hdr && options.quality.is_some() {
+        return Err(BruhError::Failed(format!(
+            "{} is an HDR image, lossy pixels would lose everything brighter than white",
+            path.display()
+        )));
+    }
+    let linear = options.linear || export::png_is_linear(bytes);
+    let float = options
+        .float
+        .or(hdr.then_some(format::PixelFormat::RgbaF32));
+    let mut file = match float {
+        Some(float) => {
+            let mut floats = ops::orient(&img.to_rgba32f(), orientation);
+            // Floats are linear light, HDR sources already are.
+            if !hdr && !linear {
+                for pixel in floats.pixels_mut() {
+                    for channel in &mut pixel.0[..3] {
+                        *channel = ops::srgb_to_linear(*channel);
+                    }
+                }
+            }
+            // The thumbnail and mipmaps are 8 bit sRGB.
+            rgba = RgbaImage::from_raw(
+                floats.width(),
+                floats.height(),
+                ops::tone_map(&floats, ops::ToneMap::Clamp, 0.0),
+            )
+            .unwrap();
+            if options.bgr {
+                floats.pixels_mut().for_each(|pixel| pixel.0.swap(0, 2));
+            }
+            BruhFile::from_rgba_f32(
+                floats.width(),
+                floats.height(),
+                &floats,
+                float,
+                config::get().compression,
+            )
+            .unwrap()
+        }
+        None => {
+            let mut pixels = Cow::Borrowed(rgba.as_raw().as_slice());
+            if options.bgr {
+                format::swap_red_blue(pixels.to_mut());
+            }
+            let mut file = match options.quality {
+                // Deflate, most of the coefficients are 0, raw they'd make lossy files pointless.
+                Some(quality) => BruhFile::encode_lossy(
+                    rgba.width(),
+                    rgba.height(),
+                    &pixels,
+                    quality,
+                    Compression::Deflate,
+                ),
+                None => BruhFile::from_rgba(
+                    rgba.width(),
+                    rgba.height(),
+                    &pixels,
+                    config::get().compression,
+                ),
+            };
+            file.header.linear = linear;
+            file
+        }
+    };
+    file.header.bgr = options.bgr;
+    file.set_pixels_per_inch(pixels_per_inch);
+    file.set_exif(exif);
+    if let Some(size) = options.thumbnail {
+        let small = ops::thumbnail(&rgba, size);
+        file.set_thumbnail(&BruhFile::from_rgba(
+            small.width(),
+            small.height(),
+            &small,
+            Compression::Deflate,
+        ));
+    }
+    if options.mipmaps {
+        let levels: Vec<BruhFile> = ops::mipmaps(&rgba)
+            .iter()
+            .map(|level| {
+                BruhFile::from_rgba(
+                    level.width(),
+                    level.height(),
+                    level,
+                    config::get().compression,
+                )
+            })
+            .collect();
+        file.set_mipmaps(&levels);
+    }
+    let bytes_out = match &options.password {
+        Some(password) => encrypt::encrypt(&file, password).to_bytes(),
+        None => file.to_bytes(),
+    };
+    let checksums = options
+        .checksums
+        .then(|| [crypto::sha512(&[bytes]), crypto::sha512(&[&bytes_out])]);
+    Ok(Encoded {
+        output,
+        width: file.header.width,
+        height: file.header.height,
+        input_size: bytes.len(),
+        bytes: bytes_out,
+        format: file.header.format,
+        compression: file.header.compression,
+        checksums,
+    })
+}
+
+/// A decoder that reads only what it needs from the local file at `path`. Anything it can't
+/// handle, like URLs, archive members, legacy and encrypted files or broken ones, has to be read
+/// whole, which also reports what is wrong with it.
+fn open_decoder(path: &Path) -> Result<Option<BruhDecoder<fs::File>>, BruhError> {
+    if storage::is_url(path) || archive::split(path).is_some() {
+        return Ok(None);
+    }
+    let file = fs::File::open(path).map_err(|e| BruhError::io(path, e))?;
+    match BruhDecoder::new(file) {
+        Ok(decoder) => Ok(Some(decoder)),
+        Err(DecodeError::Read(message)) => Err(BruhError::io(path, io::Error::other(message))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The thumbnail embedded in the local file at `path`, if it is at least `size` pixels on its
+/// longer side so scaling it down looks as good as scaling down the whole image.
+fn embedded_thumbnail(path: &Path, size: u32) -> Result<Option<RgbaImage>, BruhError> {
+    let Some(mut decoder) = open_decoder(path)? else {
+        return Ok(None);
+    };
+    let thumbnail = decoder
+        .thumbnail(config::get().max_pixels)
+        .map_err(|e| BruhError::decode(path, e))?;
+    Ok(thumbnail
+        .filter(|(width, height, _)| *width.max(height) >= size)
+        .and_then(|(width, height, rgba)| RgbaImage::from_raw(width, height, rgba)))
+}
+
+/// The thumbnail `bruh thumbnailer` shows file managers, at most `size` pixels on its longer
+/// side, from bruh's thumbnail cache once it was made. Encrypted images get none, it would show
+/// what they hide.
+fn file_manager_thumbnail(path: &Path, size: u32) -> Result<RgbaImage, BruhError> {
+    let mut header = [0; format::BRUH_HEADER_SIZE];
+    let read =
+        fs::File::open(path).and_then(|mut file| io::Read::read_exact(&mut file, &mut header));
+    if read.is_ok() && BruhFile::parse_header(&header).is_ok_and(|header| header.encrypted) {
+        return Err(BruhError::Failed(format!(
+            "{} is encrypted, it gets no thumbnail",
+            path.display()
+        )));
+    }
+
+    thumbnailer::cached(path, size, || {
+        let img = match embedded_thumbnail(path, size)? {
+            Some(thumbnail) => thumbnail,
+            None => read_bruh_image(path)?,
+        };
+        Ok(ops::thumbnail(&img, size))
+    })
+}
+
+/// Parses the BRUH file in `contents`, asking for the password if it is encrypted.
+fn parse_bruh(path: &Path, contents: &[u8]) -> Result<BruhFile, BruhError> {
+    let file = BruhFile::parse(contents).map_err(|e| BruhError::decode(path, e))?;
+    if !file.header.encrypted {
+        return Ok(file);
+    }
+
+    let password = read_password(&format!("Password for {}: ", path.display()))?;
+    encrypt::decrypt(&file, &password)
+        .map_err(|e| BruhError::Failed(format!("Can't decrypt {}: {}", path.display(), e)))
+}
+
+/// Every frame of the animation `file`, decoded on up to `jobs` threads at once. Only pasting
+/// the rectangles of delta frames over the frame before has to happen in order, and that is the
+/// quick part.
+fn decode_frames(path: &Path, file: &BruhFile) -> Result<Vec<format::Frame>, BruhError> {
+    let max_pixels = config::get().max_pixels;
+    let count = file.frame_count();
+    let jobs = config::get().jobs.clamp(1, count);
+    let next = AtomicUsize::new(0);
+    let mut parts: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut parts = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= count {
+                            break parts;
+                        }
+                        parts.push((index, file.frame_part(index, max_pixels)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    parts.sort_by_key(|&(index, _)| index);
+    let parts = parts
+        .into_iter()
+        .map(|(_, part)| part)
+        .collect::<Result<_, _>>()
+        .map_err(|e| BruhError::decode(path, e))?;
+    file.join_frames(parts)
+        .map_err(|e| BruhError::decode(path, e))
+}
+
+fn read_password(prompt: &str) -> Result<String, BruhError> {
+    // Files read on several threads at once take turns asking.
+    static PROMPT: Mutex<()> = Mutex::new(());
+    let _prompt = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+    encrypt::read_password(prompt)
+        .map_err(|e| BruhError::Failed(format!("Can't read the password: {}", e)))
+}
+
+/// Asks for a password to encrypt with, twice so a typo doesn't lock the image away.
+fn new_password() -> Result<String, BruhError> {
+    let password = read_password("Password: ")?;
+    // A password from the environment can't be mistyped.
+    if env::var_os(encrypt::PASSWORD_VAR).is_none()
+        && read_password("Repeat the password: ")? != password
+    {
+        return Err(BruhError::usage("The passwords don't match"));
+    }
+    Ok(password)
+}
+
+fn read_bruh(path: &Path) -> Result<(u32, u32, Vec<u8>), BruhError> {
+    read_bruh_colors(path).map(|(width, height, rgba, _)| (width, height, rgba))
+}
+
+/// Like `read_bruh`, but also says whether the colors are linear.
+pub fn read_bruh_colors(path: &Path) -> Result<(u32, u32, Vec<u8>, bool), BruhError> {
+    let started = Instant::now();
+    let contents = read_file(path)?;
+    let colors = decode_colors(path, &contents)?;
+    debug!(
+        "read {}: {}x{}, {} bytes in {:.2?}",
+        path.display(),
+        colors.0,
+        colors.1,
+        contents.len(),
+        started.elapsed()
+    );
+    Ok(colors)
+}
+
+/// Decodes `contents`, read from `path`, like `read_bruh_colors`.
+fn decode_colors(path: &Path, contents: &[u8]) -> Result<(u32, u32, Vec<u8>, bool), BruhError> {
+    if let Some(image_format) = foreign_format(contents) {
+        debug!("reading {} as {:?}", path.display(), image_format);
+        return decode_foreign(path, contents, image_format);
+    }
+    let header = BruhFile::parse_header(contents).ok();
+    let encrypted = header.as_ref().is_some_and(|header| header.encrypted);
+    let (width, height, rgba) = if encrypted {
+        let file = parse_bruh(path, contents)?;
+        let rgba = file
+            .to_rgba(config::get().max_pixels)
+            .map_err(|e| BruhError::decode(path, e))?;
+        (file.header.width, file.header.height, rgba)
+    } else {
+        format::decode(contents, config::get().max_pixels)
+            .map_err(|e| BruhError::decode(path, e))?
+    };
+    Ok((
+        width,
+        height,
+        rgba,
+        header.is_some_and(|header| header.linear),
+    ))
+}
+
+/// The format of `contents` if its magic bytes say it is an image the `image` crate can read
+/// rather than BRUH, so every command and the viewer take those too. Anything else is read as
+/// BRUH, including legacy files, which have no magic bytes.
+fn foreign_format(contents: &[u8]) -> Option<image::ImageFormat> {
+    if contents.starts_with(format::MAGIC) {
+        return None;
+    }
+    image::guess_format(contents)
+        .ok()
+        .filter(|image_format| image_format.can_read())
+}
+
+/// Decodes an image that isn't BRUH to straight RGBA like `read_bruh_colors`.
+fn decode_foreign(
+    path: &Path,
+    contents: &[u8],
+    image_format: image::ImageFormat,
+) -> Result<(u32, u32, Vec<u8>, bool), BruhError> {
+    let image_error = |source| BruhError::Image {
+        path: path.to_path_buf(),
+        source,
+    };
+    let reader = || image::io::Reader::with_format(io::Cursor::new(contents), image_format);
+    // Checked before decoding, so a huge image is refused without allocating it.
+    let (width, height) = reader().into_dimensions().map_err(image_error)?;
+    format::check_dimensions(width, height, config::get().max_pixels)
+        .map_err(|e| BruhError::decode(path, e))?;
+    let img = reader().decode().map_err(image_error)?;
+    let linear = match image_format {
+        // Radiance files hold linear light.
+        image::ImageFormat::Hdr => true,
+        image::ImageFormat::Png => export::png_is_linear(contents),
+        _ => false,
+    };
+    Ok((width, height, img.to_rgba8().into_raw(), linear))
+}
+
+/// Reads the file at `path`, or downloads it if `path` is a URL, or reads it out of its archive
+/// if `path` is `archive.zip!member`.
+pub fn read_file(path: &Path) -> Result<Vec<u8>, BruhError> {
+    if let Some((archive, member)) = archive::split(path) {
+        let mut bytes = Vec::new();
+        archive::open(&archive, &member)
+            .and_then(|mut reader| io::Read::read_to_end(&mut reader, &mut bytes))
+            .map_err(|e| BruhError::io(path, e))?;
+        return Ok(bytes);
+    }
+    if storage::is_url(path) {
+        #[cfg(feature = "net")]
+        return storage::Http.read(path).map_err(|e| BruhError::io(path, e));
+        #[cfg(not(feature = "net"))]
+        return Err(BruhError::usage(format!(
+            "Can't open {}, bruh was built without the `net` feature",
+            path.display()
+        )));
+    }
+
+    Disk.read(path).map_err(|e| BruhError::io(path, e))
+}
+
+fn read_bruh_image(path: &Path) -> Result<RgbaImage, BruhError> {
+    let (width, height, rgba) = read_bruh(path)?;
+    into_image(path, width, height, rgba)
+}
+
+fn into_image(path: &Path, width: u32, height: u32, rgba: Vec<u8>) -> Result<RgbaImage, BruhError> {
+    RgbaImage::from_raw(width, height, rgba).ok_or_else(|| {
+        BruhError::decode(
+            path,
+            DecodeError::Invalid("pixel data doesn't match the dimensions".to_owned()),
+        )
+    })
+}
+
+/// What an edit keeps of the file it changes besides the pixels: whether the colors are linear
+/// and stored blue first, and the ancillary chunks, like the metadata, the EXIF data and the
+/// physical size, where they were. Thumbnails, mipmaps, layers and the other images of a
+/// container would still show the old pixels and signatures wouldn't match, so they are dropped.
+#[derive(Default)]
+struct Original {
+    linear: bool,
+    bgr: bool,
+    /// The chunks before `PXLS` and the ones after it.
+    chunks: (Vec<format::Chunk>, Vec<format::Chunk>),
+}
+
+impl Original {
+    fn new(header: &format::Header, before: Vec<format::Chunk>, after: Vec<format::Chunk>) -> Self {
+        let keep = |chunks: Vec<format::Chunk>| {
+            chunks
+                .into_iter()
+                .filter(|chunk| {
+                    !chunk.is_critical()
+                        && ![
+                            format::THUMBNAIL,
+                            format::MIPMAP,
+                            format::SIGNATURE,
+                            format::LAYER,
+                            format::IMAGE,
+                        ]
+                        .contains(&chunk.tag)
+                })
+                .collect()
+        };
+        Self {
+            linear: header.linear,
+            bgr: header.bgr,
+            chunks: (keep(before), keep(after)),
+        }
+    }
+
+    fn of(file: &BruhFile) -> Self {
+        let pixels = file
+            .chunks
+            .iter()
+            .position(|chunk| chunk.tag == format::PIXELS)
+            .unwrap_or(0);
+        let (before, after) = file.chunks.split_at(pixels);
+        Self::new(&file.header, before.to_vec(), after.to_vec())
+    }
+
+    /// Gives `file`, made of the edited pixels, the colors and the chunks of the original. The
+    /// pixels are expected in straight RGBA; storing them blue first is left to `write_edit`.
+    fn restore(&self, mut file: BruhFile) -> BruhFile {
+        file.header.linear = self.linear;
+        let (before, after) = self.chunks.clone();
+        file.chunks = before.into_iter().chain(file.chunks).chain(after).collect();
+        file
+    }
+}
+
+/// Refuses to edit a float file, whose colors would be clipped to 8 bits.
+fn refuse_floats(path: &Path, header: &format::Header) -> Result<(), BruhError> {
+    if header.format.is_float() {
+        return Err(BruhError::Failed(format!(
+            "{} holds float colors, which edits would clip. Convert it with `bruh convert-depth` first",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the image at `path` to edit it, with what `write_edit` keeps of it.
+fn read_edit(path: &Path) -> Result<(RgbaImage, Original), BruhError> {
+    let contents = read_file(path)?;
+    if foreign_format(&contents).is_some() || format::is_legacy(&contents) {
+        let (width, height, rgba, linear) = decode_colors(path, &contents)?;
+        let original = Original {
+            linear,
+            ..Original::default()
+        };
+        return Ok((into_image(path, width, height, rgba)?, original));
+    }
+    let file = parse_bruh(path, &contents)?;
+    refuse_floats(path, &file.header)?;
+    let rgba = file
+        .to_rgba(config::get().max_pixels)
+        .map_err(|e| BruhError::decode(path, e))?;
+    let (width, height) = (file.header.width, file.header.height);
+    Ok((into_image(path, width, height, rgba)?, Original::of(&file)))
+}
+
+/// Writes `img`, the edited pixels of `original`, to `out` as BRUH.
+fn write_edit(out: &Path, original: &Original, img: &RgbaImage) -> Result<(), BruhError> {
+    let mut rgba = img.as_raw().clone();
+    if original.bgr {
+        format::swap_red_blue(&mut rgba);
+    }
+    let file = BruhFile::from_rgba(img.width(), img.height(), &rgba, config::get().compression);
+    let mut file = original.restore(file);
+    file.header.bgr = original.bgr;
+    write_bruh_file(out, &file)
+}
+
+/// The layers of `file` with their pixels.
+pub fn decode_layers(path: &Path, file: &BruhFile) -> Result<Vec<(Layer, RgbaImage)>, BruhError> {
+    let layers = file.layers().map_err(|e| BruhError::decode(path, e))?;
+    layers
+        .into_iter()
+        .map(|layer| {
+            let (width, height) = (layer.image.header.width, layer.image.header.height);
+            let rgba = layer
+                .image
+                .to_rgba(config::get().max_pixels)
+                .map_err(|e| BruhError::decode(path, e))?;
+            let img = RgbaImage::from_raw(width, height, rgba).ok_or_else(|| {
+                BruhError::decode(
+                    path,
+                    DecodeError::Invalid("layer pixels don't match the dimensions".to_owned()),
+                )
+            })?;
+            Ok((layer, img))
+        })
+        .collect()
+}
+
+/// Writes `file` with `layers` to `out`, its pixels replaced by the visible layers composited.
+/// Thumbnails, mipmaps and signatures would still show the old pixels, so they are dropped.
+fn write_layered(
+    out: &Path,
+    file: &BruhFile,
+    layers: &[(Layer, RgbaImage)],
+) -> Result<(), BruhError> {
+    let (width, height) = (file.header.width, file.header.height);
+    let composite = ops::composite_layers(width, height, layers);
+    let layered = BruhFile::from_rgba(width, height, &composite, config::get().compression);
+    let mut layered = Original::of(file).restore(layered);
+    let layers: Vec<Layer> = layers.iter().map(|(layer, _)| layer.clone()).collect();
+    layered.set_layers(&layers);
+    write_bruh_file(out, &layered)
+}
+
+fn write_bruh_image(path: &Path, img: &RgbaImage) -> Result<(), BruhError> {
+    write_bruh(path, img.width(), img.height(), img)
+}
+
+pub fn write_png(path: &Path, img: &RgbaImage) -> Result<(), BruhError> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            image::ColorType::Rgba8,
+        )
+        .map_err(|source| BruhError::Image {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Disk.write(path, &png).map_err(|e| BruhError::io(path, e))
+}
+
+pub fn paste_from_clipboard() -> Result<(u32, u32, Vec<u8>), arboard::Error> {
+    let mut clipboard = Clipboard::new()?;
+    let image = clipboard.get_image()?;
+
+    Ok((
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    ))
+}
+
+pub fn copy_to_clipboard(width: u32, height: u32, rgba: &[u8]) -> Result<(), arboard::Error> {
+    let mut clipboard = Clipboard::new()?;
+    clipboard.set_image(ImageData {
+        width: width as usize,
+        height: height as usize,
+        bytes: Cow::Borrowed(rgba),
+    })
+}
+
+/// Collects every `.bruh` file in `dir` and its subdirectories, sorted by path.
+fn find_bruh_files(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "bruh") {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// `arg` as text, for arguments that aren't paths and so have to be valid UTF-8.
+fn text(arg: &OsStr) -> Result<&str, BruhError> {
+    arg.to_str()
+        .ok_or_else(|| BruhError::usage(format!("'{}' isn't valid UTF-8", arg.to_string_lossy())))
+}
+
+/// Removes `flag` and the value following it from `args`, returning the value as a path.
+fn take_path(args: &mut Vec<OsString>, flag: &str) -> Result<Option<PathBuf>, BruhError> {
+    let Some(i) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(BruhError::usage(format!(
+            "Flag '{}' requires a value",
+            flag
+        )));
+    }
+
+    args.remove(i);
+    Ok(Some(args.remove(i).into()))
+}
+
+/// Like `take_path`, for flags whose value is text.
+fn take_flag(args: &mut Vec<OsString>, flag: &str) -> Result<Option<String>, BruhError> {
+    take_path(args, flag)?
+        .map(|value| text(value.as_os_str()).map(str::to_owned))
+        .transpose()
+}
+
+/// Like `take_flag`, but also parses the value as a number.
+fn take_number<T: FromStr>(args: &mut Vec<OsString>, flag: &str) -> Result<Option<T>, BruhError> {
+    take_flag(args, flag)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| BruhError::usage(format!("'{}' must be a number", flag)))
+        })
+        .transpose()
+}
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_switch(args: &mut Vec<OsString>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// `path` with `suffix` inserted before the extension: `a.b.bruh` and `-x` become `a.b-x.bruh`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// The BRUH file `compile` makes of `input`: `photo.png` becomes `photo.bruh`, or
+/// `photo.png.bruh` with `--keep-name`.
+fn compiled_path(input: &Path) -> PathBuf {
+    if config::get().keep_name {
+        let mut name = input.file_name().unwrap_or_default().to_os_string();
+        name.push(".bruh");
+        input.with_file_name(name)
+    } else {
+        input.with_extension("bruh")
+    }
+}
+
+/// Where a command writes the BRUH file it makes of `input`, like `output_file`. Inputs that
+/// aren't .bruh files are never overwritten with BRUH: `photo.png` gives `photo.bruh`, named the
+/// way `compile` names it.
+fn output_path(out: Option<PathBuf>, input: &Path) -> Result<PathBuf, BruhError> {
+    if out.is_some() {
+        return output_file(out, input);
+    }
+    let local = local_name(input);
+    let is_bruh = local
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("bruh"));
+    match is_bruh {
+        true => output_file(None, &local),
+        false => output_file(None, &compiled_path(&local)),
+    }
+}
+
+/// Where a command writes its result: `out` if given, otherwise `name` with the configured
+/// `--suffix`, moved into the configured output directory. Without either that's `name` itself,
+/// or its file name in the current directory if it was downloaded, or next to the archive if it
+/// was read out of one.
+fn output_file(out: Option<PathBuf>, name: &Path) -> Result<PathBuf, BruhError> {
+    if let Some(out) = out {
+        return Ok(out);
+    }
+
+    let name = local_name(name);
+    let config = config::get();
+    let name = match &config.suffix {
+        Some(suffix) => with_suffix(&name, suffix),
+        None => name,
+    };
+    match (&config.output_dir, name.file_name()) {
+        (Some(dir), Some(file_name)) => {
+            fs::create_dir_all(dir).map_err(|e| BruhError::io(dir, e))?;
+            Ok(dir.join(file_name))
+        }
+        _ => Ok(name),
+    }
+}
+
+/// `path` on this machine: the file name of a download in the current directory, a member of an
+/// archive next to the archive, and anything else as it is.
+fn local_name(path: &Path) -> PathBuf {
+    if storage::is_url(path) {
+        storage::url_file_name(path)
+    } else if let Some((archive, member)) = archive::split(path) {
+        archive.with_file_name(member.rsplit('/').next().unwrap_or_default())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Refuses to create images that would be too large to decode again.
+fn check_output_size(width: u32, height: u32) -> Result<(), BruhError> {
+    format::check_dimensions(width, height, config::get().max_pixels)
+        .map(|_| ())
+        .map_err(|e| BruhError::usage(format!("{}, raise it with --max-pixels", e)))
+}
+
+/// Runs `f` on every path, reporting failures as they happen instead of stopping at the first.
+fn for_each_path(
+    paths: &[OsString],
+    f: impl Fn(&Path) -> Result<(), BruhError> + Sync,
+) -> Result<(), BruhError> {
+    map_paths(paths, f).1
+}
+
+/// Runs `f` on every path on up to `jobs` threads at once, returning what it gave for the paths
+/// that worked, in the order of `paths`, and the result of the whole batch.
+fn map_paths<T: Send>(
+    paths: &[OsString],
+    f: impl Fn(&Path) -> Result<T, BruhError> + Sync,
+) -> (Vec<T>, Result<(), BruhError>) {
+    let jobs = config::get().jobs.clamp(1, paths.len().max(1));
+    let next = AtomicUsize::new(0);
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            break results;
+                        };
+                        // Not every error can be sent to another thread, so only its exit code
+                        // comes back.
+                        let result = f(Path::new(path)).map_err(|e| {
+                            e.report();
+                            e.exit_code()
+                        });
+                        results.push((index, result));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    });
+    batch_outcome(paths.len(), results)
+}
+
+/// Puts what a batch of `total` paths gave back in the order of the paths, along with the
+/// result of the whole batch.
+fn batch_outcome<T>(
+    total: usize,
+    mut outcomes: Vec<pipeline::Outcome<T>>,
+) -> (Vec<T>, Result<(), BruhError>) {
+    outcomes.sort_by_key(|(index, _)| *index);
+    let mut values = Vec::new();
+    let mut failures = Vec::new();
+    for (_, result) in outcomes {
+        match result {
+            Ok(value) => values.push(value),
+            Err(code) => failures.push(code),
+        }
+    }
+    (values, error::batch_result(total, &failures))
+}
+
+/// What `compile --manifest` writes for build systems: every image converted, in the order they
+/// were given, and every one that failed with why.
+#[derive(Serialize)]
+struct Manifest {
+    converted: Vec<ManifestEntry>,
+    failed: Vec<ManifestFailure>,
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    input: String,
+    output: String,
+    width: u32,
+    height: u32,
+    input_size: u64,
+    output_size: u64,
+    format: &'static str,
+    compression: &'static str,
+    /// Hex, like `sha512sum` prints it.
+    input_sha512: String,
+    output_sha512: String,
+}
+
+#[derive(Serialize)]
+struct ManifestFailure {
+    input: String,
+    error: String,
+}
+
+fn write_manifest(
+    path: &Path,
+    conversions: &[(PathBuf, Conversion)],
+    failures: &[(PathBuf, String)],
+) -> Result<(), BruhError> {
+    let name = |path: &Path| path.display().to_string();
+    let manifest = Manifest {
+        converted: conversions
+            .iter()
+            .map(|(input, conversion)| {
+                let [input_sha512, output_sha512] = conversion
+                    .checksums
+                    .expect("the manifest asks for checksums")
+                    .map(|sum| signature::to_hex(&sum));
+                ManifestEntry {
+                    input: name(input),
+                    output: name(&conversion.output),
+                    width: conversion.width,
+                    height: conversion.height,
+                    input_size: conversion.input_size as u64,
+                    output_size: conversion.output_size,
+                    format: conversion.format.name(),
+                    compression: conversion.compression.name(),
+                    input_sha512,
+                    output_sha512,
+                }
+            })
+            .collect(),
+        failed: failures
+            .iter()
+            .map(|(input, error)| ManifestFailure {
+                input: name(input),
+                error: error.clone(),
+            })
+            .collect(),
+    };
+    let mut bytes = serde_json::to_vec_pretty(&manifest).expect("the manifest always serializes");
+    bytes.push(b'\n');
+    Disk.write(path, &bytes)
+        .map_err(|e| BruhError::io(path, e))?;
+    info!(
+        "Wrote the manifest of {} images to {}",
+        conversions.len() + failures.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// The sizes of every image `compile` converted in a batch, and their total.
+fn print_conversions(conversions: &[(PathBuf, Conversion)]) {
+    let name = |path: &Path| path.display().to_string();
+    let width = conversions
+        .iter()
+        .map(|(path, _)| name(path).len())
+        .max()
+        .unwrap_or(0)
+        .max("total".len());
+    info!(
+        "{:<width$}  {:>12}  {:>12}  {:>6}  {:<8}  compression",
+        "file", "original", "bruh", "ratio", "format"
+    );
+    for (path, conversion) in conversions {
+        info!(
+            "{:<width$}  {:>12}  {:>12}  {:>5.1}%  {:<8}  {}",
+            name(path),
+            conversion.input_size,
+            conversion.output_size,
+            conversion.ratio(),
+            conversion.format.name(),
+            conversion.compression.name()
+        );
+    }
+    let input: usize = conversions.iter().map(|(_, c)| c.input_size).sum();
+    let output: u64 = conversions.iter().map(|(_, c)| c.output_size).sum();
+    info!(
+        "{:<width$}  {:>12}  {:>12}  {:>5.1}%",
+        "total",
+        input,
+        output,
+        output as f64 / input.max(1) as f64 * 100.0
+    );
+}
+
+/// The filters `apply` and `pipe` know: the built-in ones and the plugins.
+fn filter_registry() -> Result<filters::Registry, BruhError> {
+    let mut registry = filters::Registry::new();
+    if let Some(dir) = config::plugins_dir() {
+        registry
+            .load_plugins(&dir)
+            .map_err(|e| BruhError::io(&dir, e))?;
+    }
+    Ok(registry)
+}
+
+/// Runs one filter of a chain over `img`.
+fn apply_step(img: &mut RgbaImage, step: &filters::Step) -> Result<(), BruhError> {
+    let started = Instant::now();
+    step.apply(img)
+        .map_err(|e| BruhError::Failed(format!("{} failed: {}", step.filter.name(), e)))?;
+    debug!(
+        "applied {} in {:.2?}",
+        step.filter.name(),
+        started.elapsed()
+    );
+    Ok(())
+}
+
+/// One operation of `pipe`.
+enum PipeStep<'a> {
+    Resize(Scale),
+    Filter(filters::Step<'a>),
+}
+
+/// How `pipe` resizes: by a percentage of the size, or to an exact one.
+enum Scale {
+    Percent(f64),
+    To(u32, u32),
+}
+
+impl Scale {
+    /// The size an image of `width`x`height` is resized to, at least 1x1.
+    fn of(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            Scale::Percent(percent) => {
+                let scaled = |side: u32| ((side as f64 * percent / 100.0).round() as u32).max(1);
+                (scaled(width), scaled(height))
+            }
+            Scale::To(width, height) => (width, height),
+        }
+    }
+}
+
+/// Parses a size like `1920x1080` or a percentage like `50%`.
+fn parse_scale(scale: &str) -> Option<Scale> {
+    match scale.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.trim().parse().ok()?;
+            (percent > 0.0 && percent.is_finite()).then_some(Scale::Percent(percent))
+        }
+        None => parse_size(scale)
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .map(|(width, height)| Scale::To(width, height)),
+    }
+}
+
+/// The JSON written next to an atlas by `pack`.
+#[derive(Serialize)]
+struct AtlasMap {
+    /// The file name of the atlas.
+    image: String,
+    width: u32,
+    height: u32,
+    sprites: Vec<Sprite>,
+}
+
+/// One of the colors printed by `palette --format json`, with the share of the image it covers.
+#[derive(Serialize)]
+struct PaletteColor {
+    color: String,
+    share: f64,
+}
+
+/// Where one of the packed images ended up, named after its file without the extension.
+#[derive(Serialize)]
+struct Sprite {
+    name: String,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Parses a `WIDTHxHEIGHT` size like `1920x1080`.
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Parses a CSS color like `#ffffff`, `#fff8` or `white` into RGBA.
+fn parse_color(color: &str) -> Option<image::Rgba<u8>> {
+    // The parser only knows hex colors without alpha.
+    if let Some(hex) = color.trim().strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()?;
+        let channels: Vec<u8> = match digits.len() {
+            4 => digits.iter().map(|d| d * 17).collect(),
+            8 => digits
+                .chunks(2)
+                .map(|pair| pair[0] * 16 + pair[1])
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let [r, g, b, a] = channels[..] {
+            return Some(image::Rgba([r, g, b, a]));
+        }
+    }
+    let color = color.parse::<CssColor>().ok()?;
+    let alpha = (color.a * 255.0).round() as u8;
+    Some(image::Rgba([color.r, color.g, color.b, alpha]))
+}
+
+/// Parses a `X,Y` position like `100,50`. Negative coordinates are allowed.
+fn parse_point(point: &str) -> Option<(i64, i64)> {
+    let (x, y) = point.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// Parses a range of frames like `2-5`, `3-` up to the last one or a single frame like `4`,
+/// counting from 1.
+fn parse_range(range: &str) -> Option<(usize, Option<usize>)> {
+    let (first, last) = match range.split_once('-') {
+        Some((first, "")) => (first.trim().parse().ok()?, None),
+        Some((first, last)) => (first.trim().parse().ok()?, Some(last.trim().parse().ok()?)),
+        None => {
+            let frame = range.trim().parse().ok()?;
+            (frame, Some(frame))
+        }
+    };
+    (first >= 1 && last.is_none_or(|last| last >= first)).then_some((first, last))
+}
+
+/// Parses a `X,Y,WIDTH,HEIGHT` rectangle like `10,10,640,480`.
+fn parse_rect(rect: &str) -> Option<(u32, u32, u32, u32)> {
+    let parts = rect
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+
+    match parts[..] {
+        [x, y, width, height] => Some((x, y, width, height)),
+        _ => None,
+    }
+}
+
+/// Runs the command given on the command line, exiting with the code of its error if it fails.
+pub fn main() {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    init_logging(&mut args);
+
+    let started = Instant::now();
+    let result = load_config(&mut args).and_then(|()| run(&args));
+    debug!("finished in {:.2?}", started.elapsed());
+
+    if let Err(e) = result {
+        e.report();
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Sets up logging from the `-q`/`--quiet`, `-v` and `-vv` flags, removing them from `args`.
+/// Status messages are logged at info level, so `--quiet` leaves only errors and the actual
+/// output of commands like `hash`. `RUST_LOG` overrides the flags, e.g. `RUST_LOG=bruh=trace`.
+pub fn init_logging(args: &mut Vec<OsString>) {
+    let mut quiet = false;
+    let mut verbosity = 0;
+    args.retain(|arg| match arg.to_str() {
+        Some("-q" | "--quiet") => {
+            quiet = true;
+            false
+        }
+        Some("-v") => {
+            verbosity += 1;
+            false
+        }
+        Some("-vv") => {
+            verbosity += 2;
+            false
+        }
+        _ => true,
+    });
+
+    let level = match (quiet, verbosity) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .filter_module("bruh", level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Loads the configuration file, applying the `--config`, `--deterministic`, `--compression`,
+/// `--suffix`, `--keep-name`, `--max-pixels` and `-j`/`--jobs` flags (and removing them from
+/// `args`).
+pub fn load_config(args: &mut Vec<OsString>) -> Result<(), BruhError> {
+    let deterministic = take_switch(args, "--deterministic");
+    let mut config = match take_path(args, "--config")? {
+        Some(path) => config::load(&path, true)?,
+        // Whatever happens to be in the user's configuration would change the output.
+        None if deterministic => config::Config::default(),
+        None => match config::default_path() {
+            Some(path) => config::load(&path, false)?,
+            None => config::Config::default(),
+        },
+    };
+    config.deterministic = deterministic;
+
+    if let Some(name) = take_flag(args, "--compression")? {
+        config.compression = Compression::parse(&name).ok_or_else(|| {
+            BruhError::usage("Unknown compression. Use one of: none, rle, deflate")
+        })?;
+    }
+
+    if let Some(suffix) = take_flag(args, "--suffix")? {
+        config.suffix = Some(suffix);
+    }
+    config.keep_name |= take_switch(args, "--keep-name");
+    if let Some(max_pixels) = take_number(args, "--max-pixels")? {
+        if max_pixels == 0 {
+            return Err(BruhError::usage("'--max-pixels' must be at least 1"));
+        }
+        config.max_pixels = max_pixels;
+    }
+    let jobs = match take_number(args, "-j")? {
+        Some(jobs) => Some(jobs),
+        None => take_number(args, "--jobs")?,
+    };
+    if let Some(jobs) = jobs {
+        if jobs == 0 {
+            return Err(BruhError::usage("'--jobs' must be at least 1"));
+        }
+        config.jobs = jobs;
+    }
+
+    config::set(config);
+    Ok(())
+}
+
+fn run(args: &[OsString]) -> Result<(), BruhError> {
+    let Some(command) = args.get(1) else {
+        return Err(BruhError::usage(
+            "No command or path provided. Example: `cargo run ~/image.bruh`",
+        ));
+    };
+    let file_path: PathBuf = command.into();
+
+    // Anything that isn't a command, including paths that aren't valid UTF-8, opens the viewer.
+    match command.to_str().unwrap_or_default() {
+        "compile" => {
+            let mut rest = args[2..].to_vec();
+            let encrypted = take_switch(&mut rest, "--encrypt");
+            let thumbnail = take_number(&mut rest, "--embed-thumbnail")?;
+            let mipmaps = take_switch(&mut rest, "--mipmaps");
+            let linear = take_switch(&mut rest, "--linear");
+            let no_auto_orient = take_switch(&mut rest, "--no-auto-orient");
+            let channel_order = take_flag(&mut rest, "--channel-order")?;
+            let float = take_flag(&mut rest, "--float")?;
+            let lossy = take_switch(&mut rest, "--lossy");
+            let quality: Option<u8> = take_number(&mut rest, "--quality")?;
+            let asynchronous = take_switch(&mut rest, "--async");
+            let manifest = take_path(&mut rest, "--manifest")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run compile ~/image.png`"));
+            }
+            let bgr = match channel_order.as_deref() {
+                None | Some("rgba") => false,
+                Some("bgra") => true,
+                Some(_) => {
+                    return Err(BruhError::usage(
+                        "Unknown channel order. Use one of: rgba, bgra",
+                    ))
+                }
+            };
+            let float = match float.as_deref() {
+                None => None,
+                Some("f16") => Some(format::PixelFormat::RgbaF16),
+                Some("f32") => Some(format::PixelFormat::RgbaF32),
+                Some(_) => {
+                    return Err(BruhError::usage(
+                        "Unknown float format. Use one of: f16, f32",
+                    ))
+                }
+            };
+            if thumbnail == Some(0) {
+                return Err(BruhError::usage("'--embed-thumbnail' must be at least 1"));
+            }
+            if quality.is_some() && !lossy {
+                return Err(BruhError::usage("'--quality' only applies with '--lossy'"));
+            }
+            if !(1..=100).contains(&quality.unwrap_or(80)) {
+                return Err(BruhError::usage("'--quality' must be between 1 and 100"));
+            }
+            if lossy && float.is_some() {
+                return Err(BruhError::usage(
+                    "'--lossy' can't be used with '--float', lossy pixels have 8 bits per channel",
+                ));
+            }
+            if encrypted && config::get().deterministic {
+                return Err(BruhError::usage(
+                    "'--encrypt' can't be used with '--deterministic', every encryption uses a new random salt",
+                ));
+            }
+
+            let password = if encrypted {
+                Some(new_password()?)
+            } else {
+                None
+            };
+            let options = CompileOptions {
+                password,
+                thumbnail,
+                mipmaps,
+                linear,
+                no_auto_orient,
+                bgr,
+                float,
+                quality: lossy.then_some(quality.unwrap_or(80)),
+                checksums: manifest.is_some(),
+            };
+
+            let converted = |path: &Path, conversion: Conversion| {
+                info!(
+                    "Converted {} to {}: {} -> {} bytes ({:.1}% of the original) as {} with {} compression",
+                    path.display(),
+                    conversion.output.display(),
+                    conversion.input_size,
+                    conversion.output_size,
+                    conversion.ratio(),
+                    conversion.format.name(),
+                    conversion.compression.name()
+                );
+                (path.to_path_buf(), conversion)
+            };
+            // What went wrong, for the manifest. The errors themselves are reported on the way.
+            let failures = Mutex::new(Vec::new());
+            let noted = |path: &Path, e: BruhError| {
+                failures
+                    .lock()
+                    .unwrap()
+                    .push((path.to_path_buf(), e.message()));
+                e
+            };
+            let (conversions, result) = if asynchronous {
+                let outcomes = pipeline::run(
+                    &rest,
+                    config::get().jobs,
+                    |path| read_file(path).map_err(|e| noted(path, e)),
+                    |path, bytes| encode_png(path, &bytes, &options).map_err(|e| noted(path, e)),
+                    |path, encoded| {
+                        let conversion = encoded.write().map_err(|e| noted(path, e))?;
+                        Ok(converted(path, conversion))
+                    },
+                );
+                batch_outcome(rest.len(), outcomes)
+            } else {
+                map_paths(&rest, |path| {
+                    let conversion =
+                        png_to_bruh(path.to_path_buf(), &options).map_err(|e| noted(path, e))?;
+                    Ok(converted(path, conversion))
+                })
+            };
+            if conversions.len() > 1 {
+                print_conversions(&conversions);
+            }
+            if let Some(manifest) = manifest {
+                let mut failures = failures.into_inner().unwrap();
+                // In the order the files were given, like the conversions.
+                failures.sort_by_key(|(path, _)| rest.iter().position(|arg| arg == path));
+                write_manifest(&manifest, &conversions, &failures)?;
+            }
+
+            result
+        }
+        "copy" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run copy ~/image.bruh`"));
+            }
+
+            let (width, height, rgba) = read_bruh(Path::new(&args[2]))?;
+
+            copy_to_clipboard(width, height, &rgba)?;
+            info!("Copied image to clipboard");
+
+            Ok(())
+        }
+        "from-clipboard" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run from-clipboard ~/image.bruh`"));
+            }
+
+            let path: PathBuf = (&args[2]).into();
+
+            let (width, height, rgba) = paste_from_clipboard()?;
+            write_bruh(&path, width, height, &rgba)?;
+            info!("Successfully saved clipboard image to BRUH");
+
+            Ok(())
+        }
+        "thumbnail" => {
+            let mut rest = args[2..].to_vec();
+            let size = take_number(&mut rest, "--size")?.unwrap_or(256);
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('in', 'out') not provided. Example: `cargo run thumbnail --size 256 ~/image.bruh ~/thumb.bruh`"));
+            }
+
+            let path = Path::new(&rest[0]);
+            let img = match embedded_thumbnail(path, size)? {
+                Some(img) => img,
+                None => read_bruh_image(path)?,
+            };
+
+            write_bruh_image(Path::new(&rest[1]), &ops::thumbnail(&img, size))?;
+            info!("Successfully wrote thumbnail");
+
+            Ok(())
+        }
+        "resize" => {
+            let mut rest = args[2..].to_vec();
+            let size = take_flag(&mut rest, "--to")?;
+            let filter = take_flag(&mut rest, "--filter")?.unwrap_or_else(|| "lanczos".into());
+            let linear = !take_switch(&mut rest, "--no-linear");
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(size)) = (rest.first(), size) else {
+                return Err(BruhError::usage("Arguments ('path', '--to') not provided. Example: `cargo run resize ~/image.bruh --to 1920x1080`"));
+            };
+
+            let (width, height) = parse_size(&size)
+                .ok_or_else(|| BruhError::usage("'--to' must look like 1920x1080"))?;
+            let filter = ops::parse_filter(&filter).ok_or_else(|| {
+                BruhError::usage(
+                    "Unknown filter. Use one of: nearest, triangle, catmull-rom, gaussian, lanczos",
+                )
+            })?;
+            let path = PathBuf::from(path);
+            let out = output_path(out, &path)?;
+
+            check_output_size(width, height)?;
+
+            let (img, original) = read_edit(&path)?;
+
+            write_edit(
+                &out,
+                &original,
+                &ops::resize(&img, width, height, filter, linear),
+            )?;
+            info!("Successfully resized BRUH");
+
+            Ok(())
+        }
+        "crop" => {
+            let mut rest = args[2..].to_vec();
+            let rect = take_flag(&mut rest, "--rect")?;
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(rect)) = (rest.first(), rect) else {
+                return Err(BruhError::usage("Arguments ('path', '--rect') not provided. Example: `cargo run crop ~/image.bruh --rect 10,10,640,480 -o ~/cropped.bruh`"));
+            };
+
+            let (x, y, width, height) = parse_rect(&rect)
+                .ok_or_else(|| BruhError::usage("'--rect' must look like x,y,width,height"))?;
+            let path = PathBuf::from(path);
+            let out = output_path(out, &path)?;
+
+            let fits = |image_width: u32, image_height: u32| {
+                let fits = width > 0
+                    && height > 0
+                    && x.checked_add(width)
+                        .is_some_and(|right| right <= image_width)
+                    && y.checked_add(height)
+                        .is_some_and(|bottom| bottom <= image_height);
+                if fits {
+                    return Ok(());
+                }
+                Err(BruhError::usage(format!(
+                    "Crop rectangle {},{},{},{} doesn't fit inside the {}x{} image",
+                    x, y, width, height, image_width, image_height
+                )))
+            };
+
+            let (rgba, original) = match open_decoder(&path)? {
+                // Only the rows or tiles under the rectangle are read.
+                Some(mut decoder) => {
+                    refuse_floats(&path, decoder.header())?;
+                    fits(decoder.header().width, decoder.header().height)?;
+                    let (before, after) = decoder
+                        .ancillary_chunks()
+                        .map_err(|e| BruhError::decode(&path, e))?;
+                    let original = Original::new(decoder.header(), before, after);
+                    let rgba = decoder
+                        .decode_region(x, y, width, height, config::get().max_pixels)
+                        .map_err(|e| BruhError::decode(&path, e))?;
+                    (rgba, original)
+                }
+                None => {
+                    let (img, original) = read_edit(&path)?;
+                    fits(img.width(), img.height())?;
+                    let cropped = image::imageops::crop_imm(&img, x, y, width, height);
+                    (cropped.to_image().into_raw(), original)
+                }
+            };
+            let cropped = RgbaImage::from_raw(width, height, rgba).ok_or_else(|| {
+                BruhError::decode(
+                    &path,
+                    DecodeError::Invalid("cropped pixels don't match the rectangle".to_owned()),
+                )
+            })?;
+            write_edit(&out, &original, &cropped)?;
+            info!("Successfully cropped BRUH");
+
+            Ok(())
+        }
+        "rotate" => {
+            let mut rest = args[2..].to_vec();
+            let degrees = take_number::<u32>(&mut rest, "--degrees")?;
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(degrees)) = (rest.first(), degrees) else {
+                return Err(BruhError::usage("Arguments ('path', '--degrees') not provided. Example: `cargo run rotate ~/image.bruh --degrees 90`"));
+            };
+
+            let path = PathBuf::from(path);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+
+            let rotated = ops::rotate(&img, degrees)
+                .ok_or_else(|| BruhError::usage("'--degrees' must be 90, 180 or 270"))?;
+            write_edit(&out, &original, &rotated)?;
+            info!("Successfully rotated BRUH");
+
+            Ok(())
+        }
+        "flip" => {
+            let mut rest = args[2..].to_vec();
+            let horizontal = take_switch(&mut rest, "--horizontal");
+            let vertical = take_switch(&mut rest, "--vertical");
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() || !(horizontal || vertical) {
+                return Err(BruhError::usage("Arguments ('path', '--horizontal' or '--vertical') not provided. Example: `cargo run flip --horizontal ~/image.bruh`"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+
+            write_edit(&out, &original, &ops::flip(&img, horizontal, vertical))?;
+            info!("Successfully flipped BRUH");
+
+            Ok(())
+        }
+        "fx" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('op', 'path') not provided. Example: `cargo run fx grayscale ~/image.bruh -o ~/gray.bruh`"));
+            }
+
+            let name = text(&rest[0])?;
+            let effect = ops::Effect::parse(name).ok_or_else(|| {
+                BruhError::usage("Unknown effect. Use one of: grayscale, invert, sepia")
+            })?;
+            let path = PathBuf::from(&rest[1]);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+
+            write_edit(&out, &original, &ops::apply_effect(&img, effect))?;
+            info!("Successfully applied {} to BRUH", name);
+
+            Ok(())
+        }
+        "adjust" => {
+            let mut rest = args[2..].to_vec();
+            let defaults = ops::Adjustments::default();
+            let adjustments = ops::Adjustments {
+                brightness: take_number(&mut rest, "--brightness")?.unwrap_or(defaults.brightness),
+                contrast: take_number(&mut rest, "--contrast")?.unwrap_or(defaults.contrast),
+                gamma: take_number(&mut rest, "--gamma")?.unwrap_or(defaults.gamma),
+                linear: take_switch(&mut rest, "--linear"),
+            };
+            let auto_levels = take_switch(&mut rest, "--auto-levels");
+            let equalize = take_switch(&mut rest, "--equalize");
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run adjust ~/image.bruh --brightness +10 --contrast 1.2 --gamma 0.9`"));
+            }
+            if adjustments.gamma <= 0.0 {
+                return Err(BruhError::usage("'--gamma' must be greater than 0"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (mut img, original) = read_edit(&path)?;
+            if auto_levels {
+                img = ops::auto_levels(&img);
+            }
+            if equalize {
+                img = ops::equalize(&img);
+            }
+
+            write_edit(&out, &original, &ops::adjust(&img, adjustments))?;
+            info!("Successfully adjusted BRUH");
+
+            Ok(())
+        }
+        "filter" => {
+            let mut rest = args[2..].to_vec();
+            let blur = take_number::<f32>(&mut rest, "--blur")?;
+            let sharpen = take_number::<f32>(&mut rest, "--sharpen")?;
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() || (blur.is_none() && sharpen.is_none()) {
+                return Err(BruhError::usage("Arguments ('path', '--blur' or '--sharpen') not provided. Example: `cargo run filter ~/image.bruh --blur 2.5`"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (mut img, original) = read_edit(&path)?;
+            if let Some(sigma) = blur {
+                img = ops::blur(&img, sigma);
+            }
+            if let Some(amount) = sharpen {
+                img = ops::sharpen(&img, amount);
+            }
+
+            write_edit(&out, &original, &img)?;
+            info!("Successfully filtered BRUH");
+
+            Ok(())
+        }
+        "apply" => {
+            let mut rest = args[2..].to_vec();
+            let list = take_switch(&mut rest, "--list");
+            let out = take_path(&mut rest, "-o")?;
+            let registry = filter_registry()?;
+            if list {
+                for filter in registry.iter() {
+                    println!("{:<12}  {}", filter.name(), filter.about());
+                    for param in filter.params().unwrap_or_default() {
+                        let default = format!("{}={}", param.name, param.default);
+                        println!("  {:<16}  {}", default, param.about);
+                    }
+                }
+                return Ok(());
+            }
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('path', 'filter') not provided. Example: `cargo run apply ~/image.bruh blur:sigma=2 grayscale`"));
+            }
+
+            let steps = rest[1..]
+                .iter()
+                .map(|step| registry.parse(text(step)?).map_err(BruhError::usage))
+                .collect::<Result<Vec<_>, _>>()?;
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (mut img, original) = read_edit(&path)?;
+            for step in &steps {
+                apply_step(&mut img, step)?;
+            }
+
+            write_edit(&out, &original, &img)?;
+            info!("Successfully applied {} filter(s) to BRUH", steps.len());
+
+            Ok(())
+        }
+        "pipe" => {
+            let mut rest = args[2..].to_vec();
+            let chain = take_flag(&mut rest, "--ops")?;
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(chain)) = (rest.first(), chain) else {
+                return Err(BruhError::usage("Arguments ('path', '--ops') not provided. Example: `cargo run pipe ~/image.png --ops \"resize=50%,grayscale,adjust:gamma=0.9,convert=bruh\"`"));
+            };
+
+            let registry = filter_registry()?;
+            let is_step =
+                |name: &str| ["resize", "convert"].contains(&name) || registry.get(name).is_some();
+            let mut steps = Vec::new();
+            let mut png = false;
+            for step in filters::split_chain(&chain, is_step).map_err(BruhError::usage)? {
+                match step.split_once('=') {
+                    Some(("resize", size)) => {
+                        let size = parse_scale(size).ok_or_else(|| {
+                            BruhError::usage("'resize' must look like 50% or 1920x1080")
+                        })?;
+                        steps.push(PipeStep::Resize(size));
+                    }
+                    Some(("convert", "bruh")) => png = false,
+                    Some(("convert", "png")) => png = true,
+                    Some(("convert", _)) => {
+                        return Err(BruhError::usage(
+                            "Unknown format to convert to. Use one of: bruh, png",
+                        ))
+                    }
+                    _ => steps.push(PipeStep::Filter(
+                        registry.parse(&step).map_err(BruhError::usage)?,
+                    )),
+                }
+            }
+            let path = PathBuf::from(path);
+            let out = match out {
+                Some(out) => out,
+                None if png => output_file(None, &path.with_extension("png"))?,
+                None => output_path(None, &compiled_path(&path))?,
+            };
+
+            let (mut img, original) = read_edit(&path)?;
+            for step in &steps {
+                match step {
+                    PipeStep::Resize(scale) => {
+                        let (width, height) = scale.of(img.width(), img.height());
+                        check_output_size(width, height)?;
+                        img = ops::resize(&img, width, height, FilterType::Lanczos3, true);
+                    }
+                    PipeStep::Filter(step) => apply_step(&mut img, step)?,
+                }
+            }
+
+            if png {
+                write_png(&out, &img)?;
+            } else {
+                write_edit(&out, &original, &img)?;
+            }
+            info!(
+                "Ran {} operation(s) on {}, wrote {}",
+                steps.len(),
+                path.display(),
+                out.display()
+            );
+
+            Ok(())
+        }
+        "script" => {
+            let rest = &args[2..];
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('script', 'paths') not provided. Example: `cargo run script ~/transform.rhai ~/a.png ~/b.png`"));
+            }
+
+            let path = Path::new(&rest[0]);
+            let source = String::from_utf8(read_file(path)?)
+                .map_err(|_| BruhError::usage(format!("{} isn't text", path.display())))?;
+            let script = script::Script::new(&source, filter_registry()?)
+                .map_err(|e| BruhError::usage(format!("{}: {}", path.display(), e)))?;
+            for_each_path(&rest[1..], |file| script.run(file))?;
+            info!("Ran {} on {} file(s)", path.display(), rest.len() - 1);
+
+            Ok(())
+        }
+        "composite" => {
+            let mut rest = args[2..].to_vec();
+            let at = take_flag(&mut rest, "--at")?.unwrap_or_else(|| "0,0".into());
+            let blend = take_flag(&mut rest, "--blend")?.unwrap_or_else(|| "over".into());
+            let linear = !take_switch(&mut rest, "--no-linear");
+            let out = take_path(&mut rest, "-o")?;
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('base', 'overlay') not provided. Example: `cargo run composite ~/base.bruh ~/overlay.bruh --at 100,50 -o ~/out.bruh`"));
+            }
+
+            let (x, y) =
+                parse_point(&at).ok_or_else(|| BruhError::usage("'--at' must look like x,y"))?;
+            let mode = format::BlendMode::parse(&blend).ok_or_else(|| {
+                BruhError::usage("Unknown blend mode. Use one of: over, multiply, screen")
+            })?;
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (base, original) = read_edit(&path)?;
+            let overlay = read_bruh_image(Path::new(&rest[1]))?;
+
+            write_edit(
+                &out,
+                &original,
+                &ops::composite(&base, &overlay, x, y, mode, linear),
+            )?;
+            info!("Successfully composited BRUH");
+
+            Ok(())
+        }
+        "watermark" => {
+            let mut rest = args[2..].to_vec();
+            let mark = take_path(&mut rest, "--image")?;
+            let position = take_flag(&mut rest, "--position")?;
+            let tile = take_switch(&mut rest, "--tile");
+            let opacity: f32 = take_number(&mut rest, "--opacity")?.unwrap_or(1.0);
+            let margin = take_number(&mut rest, "--margin")?.unwrap_or(16);
+            let linear = !take_switch(&mut rest, "--no-linear");
+            let out = take_path(&mut rest, "-o")?;
+            let Some(mark) = mark.filter(|_| !rest.is_empty()) else {
+                return Err(BruhError::usage("Arguments ('paths', '--image') not provided. Example: `cargo run watermark ~/image.bruh --image ~/logo.png --position bottom-right --opacity 0.5`"));
+            };
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err(BruhError::usage("'--opacity' must be between 0 and 1"));
+            }
+            if out.is_some() && rest.len() > 1 {
+                return Err(BruhError::usage("'-o' only works with a single image"));
+            }
+            let placement = match (position, tile) {
+                (Some(_), true) => {
+                    return Err(BruhError::usage(
+                        "'--position' and '--tile' can't be combined",
+                    ))
+                }
+                (None, true) => ops::Placement::Tiled,
+                (None, false) => ops::Placement::BottomRight,
+                (Some(position), false) => ops::Placement::parse(&position).ok_or_else(|| {
+                    BruhError::usage("Unknown position. Use one of: top-left, top, top-right, left, center, right, bottom-left, bottom, bottom-right")
+                })?,
+            };
+
+            let mark = read_bruh_image(&mark)?;
+            for_each_path(&rest, |path| {
+                let out = output_path(out.clone(), path)?;
+                let (img, original) = read_edit(path)?;
+                let marked = ops::watermark(&img, &mark, placement, margin, opacity, linear);
+                write_edit(&out, &original, &marked)?;
+                info!("Watermarked {}", out.display());
+
+                Ok(())
+            })
+        }
+        "annotate" => {
+            let mut rest = args[2..].to_vec();
+            let text = take_flag(&mut rest, "--text")?;
+            let at = take_flag(&mut rest, "--at")?.unwrap_or_else(|| "0,0".into());
+            let size: f32 = take_number(&mut rest, "--size")?.unwrap_or(16.0);
+            let color = match take_flag(&mut rest, "--color")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--color' must be a CSS color"))?,
+                None => image::Rgba([255, 255, 255, 255]),
+            };
+            let font = take_path(&mut rest, "--font")?;
+            let linear = !take_switch(&mut rest, "--no-linear");
+            let out = take_path(&mut rest, "-o")?;
+            let Some(text) = text.filter(|_| !rest.is_empty()) else {
+                return Err(BruhError::usage("Arguments ('path', '--text') not provided. Example: `cargo run annotate ~/image.bruh --text \"v1.2 build 345\" --at 10,10 --size 18 --color '#fff'`"));
+            };
+            let (x, y) =
+                parse_point(&at).ok_or_else(|| BruhError::usage("'--at' must look like x,y"))?;
+            if !(size > 0.0 && size <= 1000.0) {
+                return Err(BruhError::usage("'--size' must be between 0 and 1000"));
+            }
+
+            let font_data = match &font {
+                Some(font) => Cow::Owned(read_file(font)?),
+                None => Cow::Borrowed(ops::DEFAULT_FONT),
+            };
+            let font = ab_glyph::FontRef::try_from_slice(&font_data).map_err(|_| {
+                BruhError::usage(format!(
+                    "{} isn't a TrueType or OpenType font",
+                    font.as_deref().unwrap_or(Path::new("")).display()
+                ))
+            })?;
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+            let label = ops::text(&font, &text, size, color);
+            write_edit(
+                &out,
+                &original,
+                &ops::composite(&img, &label, x, y, format::BlendMode::Over, linear),
+            )?;
+            info!("Successfully annotated BRUH");
+
+            Ok(())
+        }
+        "montage" => {
+            let mut rest = args[2..].to_vec();
+            let columns = take_number::<u32>(&mut rest, "--columns")?;
+            let gap = take_number(&mut rest, "--gap")?.unwrap_or(0);
+            let background = match take_flag(&mut rest, "--background")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--background' must be a CSS color"))?,
+                None => image::Rgba([255, 255, 255, 255]),
+            };
+            let out = take_path(&mut rest, "-o")?;
+            let Some(out) = out.filter(|_| !rest.is_empty()) else {
+                return Err(BruhError::usage("Arguments ('paths', '-o') not provided. Example: `cargo run montage ~/a.bruh ~/b.bruh ~/c.bruh --columns 3 --gap 4 -o ~/montage.bruh`"));
+            };
+
+            let images = rest
+                .iter()
+                .map(|path| read_bruh_image(Path::new(path)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let columns = columns.unwrap_or_else(|| (images.len() as f32).sqrt().ceil() as u32);
+
+            let too_large = || BruhError::usage("The montage would be too large");
+            let (width, height) = ops::montage_size(&images, columns, gap).ok_or_else(too_large)?;
+            check_output_size(width, height)?;
+            let montage = ops::montage(&images, columns, gap, background).ok_or_else(too_large)?;
+
+            write_bruh_image(&out, &montage)?;
+            info!("Successfully wrote montage");
+
+            Ok(())
+        }
+        "slice" => {
+            let mut rest = args[2..].to_vec();
+            let tile = take_flag(&mut rest, "--tile")?;
+            let margin = take_number(&mut rest, "--margin")?.unwrap_or(0);
+            let spacing = take_number(&mut rest, "--spacing")?.unwrap_or(0);
+            let to = take_flag(&mut rest, "--to")?.unwrap_or_else(|| "bruh".into());
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(tile)) = (rest.first(), tile) else {
+                return Err(BruhError::usage("Arguments ('path', '--tile') not provided. Example: `cargo run slice ~/sheet.bruh --tile 32x32 -o ~/tiles`"));
+            };
+
+            let (tile_width, tile_height) = parse_size(&tile)
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .ok_or_else(|| BruhError::usage("'--tile' must look like 32x32"))?;
+            let write: fn(&Path, &RgbaImage) -> Result<(), BruhError> = match to.as_str() {
+                "bruh" => write_bruh_image,
+                "png" => write_png,
+                _ => {
+                    return Err(BruhError::usage(
+                        "Unknown target format. Use one of: bruh, png",
+                    ))
+                }
+            };
+
+            let path = PathBuf::from(path);
+            let img = read_bruh_image(&path)?;
+            let tiles = ops::slice(&img, tile_width, tile_height, margin, spacing);
+            if tiles.is_empty() {
+                return Err(BruhError::usage(format!(
+                    "No {}x{} tiles fit in the {}x{} image",
+                    tile_width,
+                    tile_height,
+                    img.width(),
+                    img.height()
+                )));
+            }
+
+            let dir = match out {
+                Some(out) => out,
+                None => output_file(None, &path.with_extension(""))?,
+            };
+            fs::create_dir_all(&dir).map_err(|e| BruhError::io(&dir, e))?;
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let digits = (tiles.len() - 1).to_string().len();
+            for (i, tile) in tiles.iter().enumerate() {
+                let name = format!("{}-{:0digits$}.{}", stem, i, to, digits = digits);
+                write(&dir.join(name), tile)?;
+            }
+            info!("Cut {} tiles into {}", tiles.len(), dir.display());
+
+            Ok(())
+        }
+        "pack" => {
+            let mut rest = args[2..].to_vec();
+            let max_width = take_number(&mut rest, "--max-width")?.unwrap_or(2048);
+            let map = take_path(&mut rest, "--map")?;
+            let out = take_path(&mut rest, "-o")?;
+            let Some(out) = out.filter(|_| !rest.is_empty()) else {
+                return Err(BruhError::usage("Arguments ('paths', '-o') not provided. Example: `cargo run pack ~/tiles/*.bruh -o ~/atlas.bruh --max-width 2048`"));
+            };
+
+            let images = rest
+                .iter()
+                .map(|path| read_bruh_image(Path::new(path)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let sizes: Vec<(u32, u32)> = images.iter().map(|img| img.dimensions()).collect();
+            let packing = ops::pack(&sizes, max_width).ok_or_else(|| {
+                BruhError::usage(format!("An image is wider than --max-width {}", max_width))
+            })?;
+            check_output_size(packing.width, packing.height)?;
+            write_bruh_image(&out, &ops::atlas(&images, &packing))?;
+
+            let sprites = rest
+                .iter()
+                .zip(&sizes)
+                .zip(&packing.positions)
+                .map(|((path, &(width, height)), &(x, y))| Sprite {
+                    name: Path::new(path)
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    x,
+                    y,
+                    width,
+                    height,
+                })
+                .collect();
+            let json = AtlasMap {
+                image: out
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                width: packing.width,
+                height: packing.height,
+                sprites,
+            };
+            let map = map.unwrap_or_else(|| out.with_extension("json"));
+            let mut bytes = serde_json::to_vec_pretty(&json).expect("the map always serializes");
+            bytes.push(b'\n');
+            Disk.write(&map, &bytes)
+                .map_err(|e| BruhError::io(&map, e))?;
+            info!(
+                "Packed {} images into {}x{} at {}, map at {}",
+                images.len(),
+                packing.width,
+                packing.height,
+                out.display(),
+                map.display()
+            );
+
+            Ok(())
+        }
+        "quantize" => {
+            let mut rest = args[2..].to_vec();
+            let colors = take_number(&mut rest, "--colors")?.unwrap_or(256);
+            let dither = take_flag(&mut rest, "--dither")?.unwrap_or_else(|| "none".into());
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run quantize ~/image.bruh --colors 64 --dither floyd-steinberg`"));
+            }
+            if !(2..=256).contains(&colors) {
+                return Err(BruhError::usage("'--colors' must be between 2 and 256"));
+            }
+
+            let dither = match dither.as_str() {
+                "none" => false,
+                "floyd-steinberg" => true,
+                _ => {
+                    return Err(BruhError::usage(
+                        "Unknown dither mode. Use one of: none, floyd-steinberg",
+                    ))
+                }
+            };
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+            let (palette, indices) = ops::quantize(&img, colors, dither);
+            let file = BruhFile::from_palette(
+                img.width(),
+                img.height(),
+                &palette,
+                &indices,
+                config::get().compression,
+            );
+
+            // The palette holds its colors red first, so the file is never stored blue first.
+            write_bruh_file(&out, &original.restore(file))?;
+            info!("Successfully quantized BRUH to {} colors", palette.len());
+
+            Ok(())
+        }
+        "convert-depth" => {
+            let mut rest = args[2..].to_vec();
+            let target = take_flag(&mut rest, "--to")?;
+            let dither =
+                take_flag(&mut rest, "--dither")?.unwrap_or_else(|| "floyd-steinberg".into());
+            let background = match take_flag(&mut rest, "--background")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--background' must be a CSS color"))?,
+                None => image::Rgba([255, 255, 255, 255]),
+            };
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(path), Some(target)) = (rest.first(), target) else {
+                return Err(BruhError::usage("Arguments ('path', '--to') not provided. Example: `cargo run convert-depth ~/render.bruh --to rgb8`"));
+            };
+            let target =
+                match target.as_str() {
+                    "gray8" => format::PixelFormat::Gray8,
+                    "rgb8" => format::PixelFormat::Rgb8,
+                    "rgba8" => format::PixelFormat::Rgba8,
+                    "rgba16f" => format::PixelFormat::RgbaF16,
+                    "rgba32f" => format::PixelFormat::RgbaF32,
+                    "rgba16" => {
+                        return Err(BruhError::usage(
+                            "BRUH has no 16 bit integer pixels, use rgba16f for half floats",
+                        ))
+                    }
+                    _ => return Err(BruhError::usage(
+                        "Unknown pixel format. Use one of: gray8, rgb8, rgba8, rgba16f, rgba32f",
+                    )),
+                };
+            let dither = match dither.as_str() {
+                "none" => false,
+                "floyd-steinberg" => true,
+                _ => {
+                    return Err(BruhError::usage(
+                        "Unknown dither mode. Use one of: none, floyd-steinberg",
+                    ))
+                }
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let file = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                BruhFile::from_rgba(width, height, &rgba, config::get().compression)
+            } else {
+                parse_bruh(&path, &contents)?
+            };
+            if file.is_animated() {
+                return Err(BruhError::Failed(format!(
+                    "{} is animated, only still images can change their pixel format",
+                    path.display()
+                )));
+            }
+
+            let (width, height) = (file.header.width, file.header.height);
+            let rgba = file
+                .to_rgba_f32(config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            // Float files are linear light, and so is what they become. 8 bit files keep what
+            // they were, floats becoming 8 bits end up in sRGB, which has the bits where eyes
+            // need them.
+            let from_linear = file.header.linear || file.header.format.is_float();
+            let to_linear =
+                target.is_float() || (file.header.linear && !file.header.format.is_float());
+            let compression = file.header.compression;
+            let mut converted = if target.is_float() {
+                let rgba = ops::convert_channels(&rgba, 4, background, from_linear, true);
+                BruhFile::from_rgba_f32(width, height, &rgba, target, compression).unwrap()
+            } else {
+                let channels = target.bytes_per_pixel();
+                let values =
+                    ops::convert_channels(&rgba, channels, background, from_linear, to_linear);
+                let bytes = ops::dither_to_u8(&values, width as usize, channels, dither);
+                let rgba: Vec<u8> = bytes
+                    .chunks_exact(channels)
+                    .flat_map(|pixel| match *pixel {
+                        [gray] => [gray, gray, gray, 255],
+                        [r, g, b] => [r, g, b, 255],
+                        _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+                    })
+                    .collect();
+                let mut converted =
+                    BruhFile::encode(width, height, &rgba, target, compression).unwrap();
+                converted.header.linear = to_linear;
+                converted
+            };
+            // Metadata carries over. The signature doesn't match the new bytes, and mipmaps and
+            // layers would still be in the old format.
+            let dropped = [format::SIGNATURE, format::MIPMAP, format::LAYER];
+            converted.chunks.extend(
+                file.chunks
+                    .into_iter()
+                    .filter(|chunk| !chunk.is_critical() && !dropped.contains(&chunk.tag)),
+            );
+
+            let out = output_path(out, &path)?;
+            write_bruh_file(&out, &converted)?;
+            info!(
+                "Converted {} from {} to {}",
+                out.display(),
+                file.header.format.name(),
+                target.name()
+            );
+
+            Ok(())
+        }
+        "palette" => {
+            let mut rest = args[2..].to_vec();
+            let colors = take_number(&mut rest, "--colors")?.unwrap_or(8);
+            let output = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "hex".into());
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run palette ~/image.bruh --colors 8 --format json`"));
+            }
+            if !(1..=256).contains(&colors) {
+                return Err(BruhError::usage("'--colors' must be between 1 and 256"));
+            }
+            if !["hex", "json", "swatch"].contains(&output.as_str()) {
+                return Err(BruhError::usage(
+                    "Unknown palette format. Use one of: hex, json, swatch",
+                ));
+            }
+            if out.is_some() && output != "swatch" {
+                return Err(BruhError::usage("'-o' only applies with '--format swatch'"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let img = read_bruh_image(&path)?;
+            let palette = ops::dominant_colors(&img, colors as usize);
+            if palette.is_empty() {
+                return Err(BruhError::Failed(format!(
+                    "{} is fully transparent, it has no colors",
+                    path.display()
+                )));
+            }
+            let total: usize = palette.iter().map(|&(_, pixels)| pixels).sum();
+            let hex = |[r, g, b]: [u8; 3]| format!("#{:02x}{:02x}{:02x}", r, g, b);
+
+            match output.as_str() {
+                "hex" => {
+                    for &(color, pixels) in &palette {
+                        println!(
+                            "{}  {:5.1}%",
+                            hex(color),
+                            pixels as f64 * 100.0 / total as f64
+                        );
+                    }
+                }
+                "json" => {
+                    let json: Vec<PaletteColor> = palette
+                        .iter()
+                        .map(|&(color, pixels)| PaletteColor {
+                            color: hex(color),
+                            share: pixels as f64 / total as f64,
+                        })
+                        .collect();
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&json).expect("the palette always serializes")
+                    );
+                }
+                _ => {
+                    let out = match out {
+                        Some(out) => out,
+                        None => {
+                            let local = local_name(&path);
+                            let stem = local.file_stem().unwrap_or_default().to_string_lossy();
+                            let name = local.with_file_name(format!("{}-palette.bruh", stem));
+                            output_file(None, &name)?
+                        }
+                    };
+                    let colors: Vec<[u8; 3]> = palette.iter().map(|&(color, _)| color).collect();
+                    let swatch = ops::swatch(&colors, 64);
+                    if out.extension().is_some_and(|ext| ext == "png") {
+                        write_png(&out, &swatch)?;
+                    } else {
+                        write_bruh_image(&out, &swatch)?;
+                    }
+                    info!(
+                        "Wrote a swatch of {} colors to {}",
+                        colors.len(),
+                        out.display()
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        "flatten" => {
+            let mut rest = args[2..].to_vec();
+            let background = match take_flag(&mut rest, "--background")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--background' must be a CSS color"))?,
+                None => image::Rgba([255, 255, 255, 255]),
+            };
+            let linear = !take_switch(&mut rest, "--no-linear");
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run flatten ~/image.bruh --background '#ffffff'`"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let (img, original) = read_edit(&path)?;
+
+            write_edit(&out, &original, &ops::flatten(&img, background, linear))?;
+            info!("Successfully flattened BRUH");
+
+            Ok(())
+        }
+        "stats" => {
+            let mut rest = args[2..].to_vec();
+            let output = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "text".into());
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run stats ~/image.bruh --format json`"));
+            };
+            if !["text", "json"].contains(&output.as_str()) {
+                return Err(BruhError::usage(
+                    "Unknown stats format. Use one of: text, json",
+                ));
+            }
+
+            // Float files are measured as they are, anything else in bytes.
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let (rgba, float) = if let Some(image_format) = foreign_format(&contents) {
+                let (_, _, rgba, _) = decode_foreign(&path, &contents, image_format)?;
+                (rgba.iter().map(|&c| c as f32 / 255.0).collect(), false)
+            } else if format::is_legacy(&contents) {
+                let (_, _, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                (rgba.iter().map(|&c| c as f32 / 255.0).collect(), false)
+            } else {
+                let file = parse_bruh(&path, &contents)?;
+                let rgba = file
+                    .to_rgba_f32(config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                (rgba, file.header.format.is_float())
+            };
+            let stats = analysis::stats(&rgba, float);
+
+            if output == "json" {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&stats).expect("the stats always serialize")
+                );
+                return Ok(());
+            }
+            println!(
+                "{:<8} {:>10} {:>10} {:>10} {:>10} {:>8}",
+                "channel", "min", "max", "mean", "stddev", "entropy"
+            );
+            let channels = [
+                ("red", stats.red),
+                ("green", stats.green),
+                ("blue", stats.blue),
+                ("alpha", stats.alpha),
+            ];
+            for (name, channel) in channels {
+                let precision = if float { 4 } else { 2 };
+                println!(
+                    "{:<8} {:>10.*} {:>10.*} {:>10.*} {:>10.*} {:>8.2}",
+                    name,
+                    precision,
+                    channel.min,
+                    precision,
+                    channel.max,
+                    precision,
+                    channel.mean,
+                    precision,
+                    channel.stddev,
+                    channel.entropy
+                );
+            }
+            println!("Transparent: {:.1}%", stats.transparent * 100.0);
+            println!("Opaque: {:.1}%", stats.opaque * 100.0);
+            println!("Unique colors: {}", stats.unique_colors);
+            println!("Entropy: {:.2} bits per pixel", stats.entropy);
+            if let Some(format) = stats.smallest_format {
+                println!("Fits losslessly in: {}", format);
+            }
+
+            Ok(())
+        }
+        "compare" => {
+            let mut rest = args[2..].to_vec();
+            let min_psnr = take_number::<f64>(&mut rest, "--min-psnr")?;
+            let min_ssim = take_number::<f64>(&mut rest, "--min-ssim")?;
+            let diff_out = take_path(&mut rest, "--diff-out")?;
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('a', 'b') not provided. Example: `cargo run compare ~/a.bruh ~/b.bruh --min-psnr 40`"));
+            }
+
+            let a = read_bruh_image(Path::new(&rest[0]))?;
+            let b = read_bruh_image(Path::new(&rest[1]))?;
+            if a.dimensions() != b.dimensions() {
+                return Err(BruhError::Failed(format!(
+                    "Dimensions differ: {}x{} vs {}x{}",
+                    a.width(),
+                    a.height(),
+                    b.width(),
+                    b.height()
+                )));
+            }
+
+            let differing = analysis::differing_pixels(&a, &b);
+            let psnr = analysis::psnr(&a, &b);
+            let ssim = analysis::ssim(&a, &b);
+            println!(
+                "Identical: {} ({} of {} pixels differ)",
+                if differing == 0 { "yes" } else { "no" },
+                differing,
+                a.width() as u64 * a.height() as u64
+            );
+            println!("PSNR: {:.2} dB", psnr);
+            println!("SSIM: {:.4}", ssim);
+
+            if let Some(diff_out) = diff_out {
+                write_bruh_image(&diff_out, &analysis::diff_image(&a, &b))?;
+                info!("Wrote diff image to {}", diff_out.display());
+            }
+
+            // Without thresholds only identical images pass.
+            let passed = if min_psnr.is_none() && min_ssim.is_none() {
+                differing == 0
+            } else {
+                min_psnr.is_none_or(|min| psnr >= min) && min_ssim.is_none_or(|min| ssim >= min)
+            };
+            if !passed {
+                return Err(BruhError::Failed("Images don't match".to_owned()));
+            }
+
+            Ok(())
+        }
+        "bench" => {
+            let mut rest = args[2..].to_vec();
+            let iterations = take_number::<usize>(&mut rest, "--iterations")?.unwrap_or(10);
+            let Some(path) = rest.first().map(PathBuf::from) else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run bench ~/image.png --iterations 20`"));
+            };
+            if iterations == 0 {
+                return Err(BruhError::usage("--iterations has to be at least 1"));
+            }
+
+            let img = if path.extension() == Some(OsStr::new("bruh")) {
+                read_bruh_image(&path)?
+            } else {
+                image::load_from_memory(&read_file(&path)?)
+                    .map_err(|source| BruhError::Image {
+                        path: path.clone(),
+                        source,
+                    })?
+                    .to_rgba8()
+            };
+            let (width, height) = img.dimensions();
+            let measurements =
+                bench::run(width, height, &img, iterations, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+
+            let rgba_size = img.len();
+            let megabytes = |bytes: usize| bytes as f64 / 1_000_000.0;
+            println!(
+                "{}: {}x{}, {:.1} MB of RGBA, median of {} runs",
+                path.display(),
+                width,
+                height,
+                megabytes(rgba_size),
+                iterations
+            );
+            println!(
+                "{:<12} {:>12} {:>6} {:>10} {:>8} {:>10} {:>8} {:>11} {:>11}",
+                "compression",
+                "bytes",
+                "ratio",
+                "encode",
+                "MB/s",
+                "decode",
+                "MB/s",
+                "encode mem",
+                "decode mem"
+            );
+            for m in &measurements {
+                println!(
+                    "{:<12} {:>12} {:>5.1}% {:>10} {:>8.1} {:>10} {:>8.1} {:>8.1} MB {:>8.1} MB",
+                    m.compression.name(),
+                    m.size,
+                    m.size as f64 / rgba_size.max(1) as f64 * 100.0,
+                    format!("{:.2?}", m.encode),
+                    bench::throughput(rgba_size, m.encode),
+                    format!("{:.2?}", m.decode),
+                    bench::throughput(rgba_size, m.decode),
+                    megabytes(m.encode_memory),
+                    megabytes(m.decode_memory)
+                );
+            }
+
+            Ok(())
+        }
+        "hash" => {
+            let mut rest = args[2..].to_vec();
+            let algorithm = take_flag(&mut rest, "--algorithm")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run hash ~/image.bruh`"));
+            }
+
+            let hash: Option<fn(&RgbaImage) -> u64> = match algorithm.as_deref() {
+                None => None,
+                Some("ahash") => Some(analysis::ahash),
+                Some("dhash") => Some(analysis::dhash),
+                Some("phash") => Some(analysis::phash),
+                Some(_) => {
+                    return Err(BruhError::usage(
+                        "Unknown hash algorithm. Use one of: ahash, dhash, phash",
+                    ))
+                }
+            };
+
+            // Printed once everything is hashed, so the lines come in the order of the paths.
+            let (lines, result) = map_paths(&rest, |path| {
+                let img = read_bruh_image(path)?;
+
+                Ok(match hash {
+                    None => format!(
+                        "ahash:{:016x} dhash:{:016x} phash:{:016x}  {}",
+                        analysis::ahash(&img),
+                        analysis::dhash(&img),
+                        analysis::phash(&img),
+                        path.display()
+                    ),
+                    Some(hash) => format!("{:016x}  {}", hash(&img), path.display()),
+                })
+            });
+            for line in lines {
+                println!("{}", line);
+            }
+
+            result
+        }
+        "dedupe" => {
+            let mut rest = args[2..].to_vec();
+            let threshold = take_number(&mut rest, "--threshold")?.unwrap_or(5);
+            let delete = take_switch(&mut rest, "--delete");
+            let hardlink = take_switch(&mut rest, "--hardlink");
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('dir') not provided. Example: `cargo run dedupe ~/images --threshold 5`"));
+            }
+            if delete && hardlink {
+                return Err(BruhError::usage(
+                    "'--delete' and '--hardlink' can't be combined",
+                ));
+            }
+
+            let dir = Path::new(&rest[0]);
+            let files = find_bruh_files(dir).map_err(|e| BruhError::io(dir, e))?;
+            let total = files.len();
+            let mut failures = Vec::new();
+
+            // (path, perceptual hash, exact content hash) per image, grouped around the first
+            // image that every other member is within `threshold` of.
+            let mut groups: Vec<Vec<(PathBuf, u64, u64)>> = Vec::new();
+            for path in files {
+                let img = match read_bruh_image(&path) {
+                    Ok(img) => img,
+                    Err(e) => {
+                        e.report();
+                        failures.push(e.exit_code());
+                        continue;
+                    }
+                };
+                let entry = (path, analysis::phash(&img), analysis::content_hash(&img));
+                debug!(
+                    "{}: phash {:016x}, content hash {:016x}",
+                    entry.0.display(),
+                    entry.1,
+                    entry.2
+                );
+
+                match groups.iter_mut().find(|group| {
+                    analysis::hamming_distance(group[0].1, entry.1) <= threshold
+                        || group[0].2 == entry.2
+                }) {
+                    Some(group) => group.push(entry),
+                    None => groups.push(vec![entry]),
+                }
+            }
+
+            for group in groups.iter().filter(|group| group.len() > 1) {
+                let (original, original_phash, original_content) = &group[0];
+                println!("{}", original.display());
+
+                for (path, phash, content) in &group[1..] {
+                    if content != original_content {
+                        println!(
+                            "  near duplicate (distance {}): {}",
+                            analysis::hamming_distance(*original_phash, *phash),
+                            path.display()
+                        );
+                        continue;
+                    }
+                    println!("  exact duplicate: {}", path.display());
+                    if !(delete || hardlink) {
+                        continue;
+                    }
+
+                    // Make sure the content really is the same before touching anything.
+                    let result = match (read_bruh_image(path), read_bruh_image(original)) {
+                        (Ok(a), Ok(b)) if a != b => continue,
+                        (Ok(_), Ok(_)) if hardlink => {
+                            // Link next to the duplicate first so it's never missing. If both
+                            // already are the same file, rename leaves the link behind.
+                            let temp = storage::temp_path_for(path);
+                            let result = fs::hard_link(original, &temp)
+                                .and_then(|()| fs::rename(&temp, path));
+                            let _ = fs::remove_file(&temp);
+                            result.map_err(|e| BruhError::io(path, e))
+                        }
+                        (Ok(_), Ok(_)) => fs::remove_file(path).map_err(|e| BruhError::io(path, e)),
+                        (Err(e), _) | (_, Err(e)) => Err(e),
+                    };
+                    match result {
+                        Ok(()) if hardlink => println!("    hardlinked to {}", original.display()),
+                        Ok(()) => println!("    deleted"),
+                        Err(e) => {
+                            e.report();
+                            failures.push(e.exit_code());
+                        }
+                    }
+                }
+            }
+
+            error::batch_result(total, &failures)
+        }
+        "strip" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run strip ~/image.bruh`"));
+            }
+
+            let path = PathBuf::from(&rest[0]);
+            let out = output_path(out, &path)?;
+
+            let contents = read_file(&path)?;
+            if format::is_legacy(&contents) {
+                info!("Legacy BRUH files don't carry any metadata, nothing to strip");
+                return Ok(());
+            }
+
+            let mut file = BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?;
+            // Dropping sealed chunks would keep the rest from decrypting.
+            if file.header.encrypted {
+                return Err(BruhError::Failed(format!(
+                    "The metadata of {} is encrypted with the pixels, strip it before encrypting",
+                    path.display()
+                )));
+            }
+            let removed = file.strip();
+
+            write_bruh_file(&out, &file)?;
+            info!("Successfully stripped {} metadata chunk(s)", removed);
+
+            Ok(())
+        }
+        "repair" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run repair ~/broken.bruh -o ~/fixed.bruh`"));
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            if format::is_legacy(&contents)
+                && format::decode(&contents, config::get().max_pixels).is_ok()
+            {
+                return Err(BruhError::Failed(format!(
+                    "{} is a legacy BRUH file that decodes fine, `upgrade` turns it into a current one",
+                    path.display()
+                )));
+            }
+            let (file, fixes) =
+                format::repair(&contents, config::get().max_pixels).map_err(|e| {
+                    BruhError::Failed(format!("Can't repair {}: {}", path.display(), e))
+                })?;
+            if fixes.is_empty() {
+                info!("{} decodes fine, nothing to repair", path.display());
+                return Ok(());
+            }
+            // Next to the broken file rather than over it, in case the guesses were wrong.
+            let out = out.unwrap_or_else(|| with_suffix(&path, "-repaired"));
+            for fix in &fixes {
+                info!("{}", fix);
+            }
+            write_bruh_file(&out, &file)?;
+            info!(
+                "Repaired {} with {} fix(es), wrote {}",
+                path.display(),
+                fixes.len(),
+                out.display()
+            );
+
+            Ok(())
+        }
+        "meta" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            if rest.len() < 2 {
+                return Err(BruhError::usage("Arguments ('get|set|remove|list', 'path') not provided. Example: `cargo run meta set ~/image.bruh author me`"));
+            }
+
+            let path = PathBuf::from(&rest[1]);
+            let contents = read_file(&path)?;
+            if format::is_legacy(&contents) {
+                return Err(BruhError::Failed(
+                    "Legacy BRUH files can't hold metadata, run `upgrade` on it first".to_owned(),
+                ));
+            }
+            let file = BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?;
+            // The metadata of encrypted files is sealed, changes are sealed again with the same
+            // password.
+            let password = if file.header.encrypted {
+                Some(read_password(&format!(
+                    "Password for {}: ",
+                    path.display()
+                ))?)
+            } else {
+                None
+            };
+            let mut file = match &password {
+                Some(password) => encrypt::decrypt(&file, password).map_err(|e| {
+                    BruhError::Failed(format!("Can't decrypt {}: {}", path.display(), e))
+                })?,
+                None => file,
+            };
+            // Downloads and archive members can't be changed where they are.
+            let out = match out {
+                Some(out) => out,
+                None if storage::is_url(&path) || archive::split(&path).is_some() => {
+                    if rest[0] == "set" || rest[0] == "remove" {
+                        return Err(BruhError::usage(format!(
+                            "Can't write into {}, give '-o' for where the changed file goes",
+                            path.display()
+                        )));
+                    }
+                    path.clone()
+                }
+                None => path.clone(),
+            };
+            let write = |file: &BruhFile| match &password {
+                Some(password) => write_bruh_file(&out, &encrypt::encrypt(file, password)),
+                None => write_bruh_file(&out, file),
+            };
+
+            let key = rest.get(2).map(|key| text(key)).transpose()?;
+            let value = rest.get(3).map(|value| text(value)).transpose()?;
+            match (text(&rest[0])?, key, value) {
+                ("list", _, _) => {
+                    for (key, value) in file.metadata() {
+                        println!("{}: {}", key, value);
+                    }
+                }
+                ("get", Some(key), _) => match file.metadata_value(key) {
+                    Some(value) => println!("{}", value),
+                    None => return Err(BruhError::Failed(format!("No metadata key '{}'", key))),
+                },
+                ("set", Some(key), Some(value)) => {
+                    file.set_metadata_value(key, Some(value));
+                    write(&file)?;
+                }
+                ("remove", Some(key), _) => {
+                    file.set_metadata_value(key, None);
+                    write(&file)?;
+                }
+                _ => return Err(BruhError::usage("Usage: `cargo run meta list <path>`, `meta get <path> <key>`, `meta set <path> <key> <value> [-o out.bruh]` or `meta remove <path> <key> [-o out.bruh]`")),
+            }
+
+            Ok(())
+        }
+        "layer" => {
+            let mut rest = args[2..].to_vec();
+            let name = take_flag(&mut rest, "--name")?;
+            let at = take_flag(&mut rest, "--at")?;
+            let blend = take_flag(&mut rest, "--blend")?;
+            let opacity: Option<u8> = take_number(&mut rest, "--opacity")?;
+            let out = take_path(&mut rest, "-o")?;
+            let usage = || {
+                BruhError::usage("Usage: `cargo run layer list <path>`, `layer add <path> <layer.bruh> [--name name] [--at x,y] [--blend over|multiply|screen] [--opacity 0-100] [-o out.bruh]`, `layer extract <path> <index> [-o out.bruh]`, `layer hide <path> <index>` or `layer show <path> <index>`")
+            };
+            let (Some(operation), Some(path)) = (rest.first(), rest.get(1)) else {
+                return Err(usage());
+            };
+            let operation = text(operation)?;
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let file = BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?;
+            if file.is_animated() {
+                return Err(BruhError::Failed("Animations can't have layers".to_owned()));
+            }
+            // The layers are sealed with the pixels, changing them would mean encrypting again.
+            if file.header.encrypted {
+                return Err(BruhError::Failed(
+                    "Encrypted files can't have layers".to_owned(),
+                ));
+            }
+            let mut layers = decode_layers(&path, &file)?;
+            let index = rest
+                .get(2)
+                .filter(|_| operation != "add")
+                .map(|index| {
+                    text(index)?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&index| index < layers.len())
+                        .ok_or_else(|| {
+                            BruhError::usage(format!(
+                                "{} has {} layers, counting from 0",
+                                path.display(),
+                                layers.len()
+                            ))
+                        })
+                })
+                .transpose()?;
+
+            match (operation, rest.get(2), index) {
+                ("list", None, _) => {
+                    for (index, (layer, img)) in layers.iter().enumerate() {
+                        println!(
+                            "{}: {} {}x{} at {},{}, {}, {}% opacity{}",
+                            index,
+                            layer.name,
+                            img.width(),
+                            img.height(),
+                            layer.x,
+                            layer.y,
+                            layer.blend.name(),
+                            (layer.opacity as u32 * 100 + 127) / 255,
+                            if layer.visible { "" } else { ", hidden" }
+                        );
+                    }
+                }
+                ("add", Some(layer_path), _) => {
+                    let (x, y) = match at {
+                        Some(at) => parse_point(&at)
+                            .and_then(|(x, y)| {
+                                Some((i32::try_from(x).ok()?, i32::try_from(y).ok()?))
+                            })
+                            .ok_or_else(|| BruhError::usage("'--at' must look like x,y"))?,
+                        None => (0, 0),
+                    };
+                    let blend = match blend {
+                        Some(blend) => format::BlendMode::parse(&blend).ok_or_else(|| {
+                            BruhError::usage(
+                                "Unknown blend mode. Use one of: over, multiply, screen",
+                            )
+                        })?,
+                        None => format::BlendMode::Over,
+                    };
+                    let opacity = match opacity.unwrap_or(100) {
+                        percent @ 0..=100 => ((percent as u32 * 255 + 50) / 100) as u8,
+                        _ => return Err(BruhError::usage("'--opacity' must be 0 to 100")),
+                    };
+                    // The image so far becomes the bottom layer of files without any.
+                    if layers.is_empty() {
+                        let img = read_bruh_image(&path)?;
+                        let image = BruhFile::from_rgba(
+                            img.width(),
+                            img.height(),
+                            &img,
+                            config::get().compression,
+                        );
+                        let layer = Layer {
+                            name: "Background".to_owned(),
+                            x: 0,
+                            y: 0,
+                            blend: format::BlendMode::Over,
+                            opacity: 255,
+                            visible: true,
+                            image,
+                        };
+                        layers.push((layer, img));
+                    }
+
+                    let layer_path = Path::new(layer_path);
+                    let img = read_bruh_image(layer_path)?;
+                    let name = match name {
+                        Some(name) => name,
+                        None => layer_path
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned(),
+                    };
+                    let image = BruhFile::from_rgba(
+                        img.width(),
+                        img.height(),
+                        &img,
+                        config::get().compression,
+                    );
+                    let layer = Layer {
+                        name,
+                        x,
+                        y,
+                        blend,
+                        opacity,
+                        visible: true,
+                        image,
+                    };
+                    info!("Added layer {} ({})", layers.len(), layer.name);
+                    layers.push((layer, img));
+                    write_layered(&output_path(out, &path)?, &file, &layers)?;
+                }
+                ("extract", Some(_), Some(index)) => {
+                    let out = match out {
+                        Some(out) => out,
+                        None => {
+                            output_path(None, &with_suffix(&path, &format!("-layer-{}", index)))?
+                        }
+                    };
+                    write_bruh_file(&out, &layers[index].0.image)?;
+                    info!("Extracted layer {} to {}", index, out.display());
+                }
+                (operation @ ("hide" | "show"), Some(_), Some(index)) => {
+                    layers[index].0.visible = operation == "show";
+                    write_layered(&output_path(out, &path)?, &file, &layers)?;
+                }
+                _ => return Err(usage()),
+            }
+
+            Ok(())
+        }
+        "add" => {
+            if args.len() < 4 {
+                return Err(BruhError::usage("Arguments ('container', 'images') not provided. Example: `cargo run add ~/icons.bruh ~/16.bruh ~/32.bruh`"));
+            }
+
+            let read = |path: &Path| -> Result<BruhFile, BruhError> {
+                let contents = read_file(path)?;
+                if format::is_legacy(&contents) {
+                    let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                        .map_err(|e| BruhError::decode(path, e))?;
+                    Ok(BruhFile::from_rgba(
+                        width,
+                        height,
+                        &rgba,
+                        config::get().compression,
+                    ))
+                } else {
+                    let file =
+                        BruhFile::parse(&contents).map_err(|e| BruhError::decode(path, e))?;
+                    // The sealed chunks are authenticated with their count, so nothing can be
+                    // added to them, and sealed images can't be read to add them elsewhere.
+                    if file.header.encrypted {
+                        return Err(BruhError::Failed(format!(
+                            "{} is encrypted, containers can't hold encrypted images",
+                            path.display()
+                        )));
+                    }
+                    Ok(file)
+                }
+            };
+            let container = PathBuf::from(&args[2]);
+            let mut images = args[3..].iter().map(Path::new);
+            // A container that doesn't exist yet starts out as the first image.
+            let mut file = if container.exists() {
+                read(&container)?
+            } else {
+                read(images.next().unwrap())?
+            };
+            for image in images {
+                file.add_image(&read(image)?);
+            }
+            write_bruh_file(&container, &file)?;
+            info!(
+                "{} holds {} images",
+                container.display(),
+                file.image_count()
+            );
+
+            Ok(())
+        }
+        "list" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('container') not provided. Example: `cargo run list ~/icons.bruh`"));
+            }
+
+            let path = PathBuf::from(&args[2]);
+            let contents = read_file(&path)?;
+            let file = parse_bruh(&path, &contents)?;
+            for index in 0..file.image_count() {
+                let image = file
+                    .image(index)
+                    .map_err(|e| BruhError::decode(&path, e))?
+                    .unwrap();
+                let header = &image.header;
+                println!(
+                    "{}: {}x{} {}, {} compression{}",
+                    index,
+                    header.width,
+                    header.height,
+                    header.format.name(),
+                    header.compression.name(),
+                    if header.encrypted { ", encrypted" } else { "" }
+                );
+            }
+
+            Ok(())
+        }
+        "extract" => {
+            let mut rest = args[2..].to_vec();
+            let index: Option<usize> = take_number(&mut rest, "--index")?;
+            let out = take_path(&mut rest, "-o")?;
+            let (Some(index), Some(path)) = (index, rest.first()) else {
+                return Err(BruhError::usage("Arguments ('container', '--index') not provided. Example: `cargo run extract ~/icons.bruh --index 1 -o ~/32.bruh`"));
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let file = parse_bruh(&path, &contents)?;
+            let Some(image) = file.image(index).map_err(|e| BruhError::decode(&path, e))? else {
+                return Err(BruhError::usage(format!(
+                    "{} holds {} images, counting from 0",
+                    path.display(),
+                    file.image_count()
+                )));
+            };
+            let out = match out {
+                Some(out) => out,
+                None => output_path(None, &with_suffix(&path, &format!("-{}", index)))?,
+            };
+            write_bruh_file(&out, &image)?;
+            info!("Extracted image {} to {}", index, out.display());
+
+            Ok(())
+        }
+        "stego" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            let operation = rest.first().map(|arg| text(arg)).transpose()?;
+            match (operation, rest.get(1), rest.get(2)) {
+                (Some("embed"), Some(image), Some(secret)) => {
+                    let (image, secret) = (PathBuf::from(image), PathBuf::from(secret));
+                    let out = output_path(out, &image)?;
+                    let (mut img, original) = read_edit(&image)?;
+                    let data = fs::read(&secret).map_err(|e| BruhError::io(&secret, e))?;
+
+                    stego::embed(&mut img, &data).map_err(|capacity| {
+                        BruhError::Failed(format!(
+                            "{} is {} bytes, but {} only has room for {}",
+                            secret.display(),
+                            data.len(),
+                            image.display(),
+                            capacity
+                        ))
+                    })?;
+                    write_edit(&out, &original, &img)?;
+                    info!("Hid {} bytes in {}", data.len(), out.display());
+                }
+                (Some("extract"), Some(image), None) => {
+                    let image = PathBuf::from(image);
+                    let img = read_bruh_image(&image)?;
+                    let data = stego::extract(&img).map_err(|e| {
+                        BruhError::Failed(format!("Can't extract from {}: {}", image.display(), e))
+                    })?;
+
+                    match out {
+                        Some(out) => {
+                            Disk.write(&out, &data)
+                                .map_err(|e| BruhError::io(&out, e))?;
+                            info!("Extracted {} bytes to {}", data.len(), out.display());
+                        }
+                        None => io::stdout()
+                            .write_all(&data)
+                            .map_err(|e| BruhError::io(Path::new("stdout"), e))?,
+                    }
+                }
+                _ => return Err(BruhError::usage("Usage: `cargo run stego embed <image> <file> [-o out.bruh]` or `stego extract <image> [-o file]`")),
+            }
+
+            Ok(())
+        }
+        "sign" => {
+            let mut rest = args[2..].to_vec();
+            let key = take_path(&mut rest, "--key")?;
+            let new_key = take_path(&mut rest, "--new-key")?;
+            let out = take_path(&mut rest, "-o")?;
+            if let Some(new_key) = new_key {
+                let (public_path, public) = signature::generate_key(&new_key)?;
+                info!(
+                    "Wrote the secret key to {} and the public key {} to {}",
+                    new_key.display(),
+                    signature::to_hex(&public),
+                    public_path.display()
+                );
+                return Ok(());
+            }
+            let (Some(path), Some(key)) = (rest.first(), key) else {
+                return Err(BruhError::usage("Arguments ('path', '--key') not provided. Example: `cargo run sign ~/image.bruh --key ~/bruh.key`, create a key with `cargo run sign --new-key ~/bruh.key`"));
+            };
+
+            let path = PathBuf::from(path);
+            let secret = signature::read_key(&key)?;
+            let contents = read_file(&path)?;
+            if format::is_legacy(&contents) {
+                return Err(BruhError::usage(format!(
+                    "{} is a legacy file, run `bruh upgrade` before signing it",
+                    path.display()
+                )));
+            }
+            let mut file = BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?;
+
+            signature::sign(&mut file, &secret);
+            let out = output_path(out, &path)?;
+            write_bruh_file(&out, &file)?;
+            info!("Signed {}", out.display());
+
+            Ok(())
+        }
+        "verify-sig" => {
+            let mut rest = args[2..].to_vec();
+            let key = take_path(&mut rest, "--key")?;
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run verify-sig ~/image.bruh --key ~/bruh.key.pub`"));
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            if format::is_legacy(&contents) {
+                return Err(BruhError::Failed(format!(
+                    "{} isn't signed, legacy files can't be",
+                    path.display()
+                )));
+            }
+            let file = BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?;
+            let public = signature::verify(&file).map_err(|e| {
+                BruhError::Failed(format!("Bad signature on {}: {}", path.display(), e))
+            })?;
+
+            match key {
+                Some(key) => {
+                    if signature::read_key(&key)? != public {
+                        return Err(BruhError::Failed(format!(
+                            "{} was signed by {}, not by {}",
+                            path.display(),
+                            signature::to_hex(&public),
+                            key.display()
+                        )));
+                    }
+                    info!("Good signature on {} by {}", path.display(), key.display());
+                }
+                // Anyone can sign with a key of their own, only the key says who it was.
+                None => info!(
+                    "Good signature on {} by {}, pass `--key` to check that's who you expect",
+                    path.display(),
+                    signature::to_hex(&public)
+                ),
+            }
+
+            Ok(())
+        }
+        "inspect" => {
+            let mut rest = args[2..].to_vec();
+            let hex = take_switch(&mut rest, "--hex");
+            let bytes = take_number(&mut rest, "--bytes")?.unwrap_or(32);
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run inspect --hex ~/image.bruh`"));
+            }
+
+            let path = Path::new(&rest[0]);
+            let contents = read_file(path)?;
+            if let Some(image_format) = foreign_format(&contents) {
+                return Err(BruhError::usage(format!(
+                    "{} is a {} image, not a BRUH file",
+                    path.display(),
+                    image_format.extensions_str()[0].to_uppercase()
+                )));
+            }
+            print!("{}", inspect::inspection(&contents, hex.then_some(bytes)));
+
+            Ok(())
+        }
+        "identify" => {
+            let mut rest = args[2..].to_vec();
+            let template = take_flag(&mut rest, "--format")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('paths') not provided. Example: `cargo run identify --format '%f %wx%h\\n' ~/*.bruh`"));
+            }
+            let template = template.as_deref().unwrap_or(identify::DEFAULT_FORMAT);
+
+            let (lines, result) = map_paths(&rest, |path| {
+                let (prefix, size) = if storage::is_url(path) || archive::split(path).is_some() {
+                    let contents = read_file(path)?;
+                    let size = contents.len() as u64;
+                    (contents, size)
+                } else {
+                    let file = fs::File::open(path).map_err(|e| BruhError::io(path, e))?;
+                    let size = file.metadata().map_err(|e| BruhError::io(path, e))?.len();
+                    let mut prefix = Vec::new();
+                    io::Read::read_to_end(
+                        &mut io::Read::take(file, identify::PREFIX_SIZE as u64),
+                        &mut prefix,
+                    )
+                    .map_err(|e| BruhError::io(path, e))?;
+                    (prefix, size)
+                };
+                let identity = identify::identify(&prefix)
+                    .map_err(|e| BruhError::Failed(format!("{}: {}", path.display(), e)))?;
+                Ok(identify::render(template, path, size, &identity))
+            });
+            for line in lines {
+                print!("{}", line);
+            }
+            result
+        }
+        "upgrade" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('paths') not provided. Example: `cargo run upgrade ~/old.bruh ~/older.bruh`"));
+            }
+
+            for_each_path(&args[2..], |path| {
+                let contents = read_file(path)?;
+                if !format::is_legacy(&contents) {
+                    info!("{} is already up to date", path.display());
+                    return Ok(());
+                }
+
+                let (width, height, rgba) =
+                    format::decode_legacy(&contents, config::get().max_pixels)
+                        .map_err(|e| BruhError::decode(path, e))?;
+
+                let mut file = BruhFile::from_rgba(width, height, &rgba, config::get().compression);
+                let mut metadata = format::default_metadata();
+                metadata.push(("upgraded-from".to_owned(), "v0".to_owned()));
+                file.set_metadata(&metadata);
+
+                write_bruh_file(path, &file)?;
+                info!("Upgraded {} to version {}", path.display(), format::VERSION);
+
+                Ok(())
+            })
+        }
+        "optimize" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('paths') not provided. Example: `cargo run optimize ~/image.bruh`"));
+            }
+
+            for_each_path(&args[2..], |path| {
+                let contents = read_file(path)?;
+                let (width, height, mut rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(path, e))?;
+                let mut tile_size = None;
+                let mut interlaced = false;
+                let (mut linear, mut bgr) = (false, false);
+                let mut floats = None;
+                let (before, after) = if format::is_legacy(&contents) {
+                    (Vec::new(), Vec::new())
+                } else {
+                    let file =
+                        BruhFile::parse(&contents).map_err(|e| BruhError::decode(path, e))?;
+                    if file.is_animated() {
+                        info!("{}: skipping animation", path.display());
+                        return Ok(());
+                    }
+                    // Tiled and interlaced files stay that way, they were probably made that way
+                    // for a reason.
+                    tile_size = file.tile_size();
+                    interlaced = file.header.interlaced;
+                    // So do the color space and channel order, they say what the pixels mean.
+                    linear = file.header.linear;
+                    bgr = file.header.bgr;
+                    // Float pixels would lose everything brighter than white in an 8 bit format.
+                    if file.header.format.is_float() {
+                        let rgba = file
+                            .to_rgba_f32(config::get().max_pixels)
+                            .map_err(|e| BruhError::decode(path, e))?;
+                        floats = Some((file.header.format, rgba));
+                    }
+                    // The ancillary chunks stay on their side of `PXLS`, except the signature,
+                    // which doesn't match the new bytes.
+                    let pixels = file
+                        .chunks
+                        .iter()
+                        .position(|c| c.tag == format::PIXELS)
+                        .unwrap_or(0);
+                    let keep = |c: &&format::Chunk| !c.is_critical() && c.tag != format::SIGNATURE;
+                    let (before, after) = file.chunks.split_at(pixels);
+                    (
+                        before.iter().filter(keep).cloned().collect::<Vec<_>>(),
+                        after.iter().filter(keep).cloned().collect::<Vec<_>>(),
+                    )
+                };
+
+                if bgr {
+                    format::swap_red_blue(&mut rgba);
+                    if let Some((_, floats)) = &mut floats {
+                        floats
+                            .chunks_exact_mut(4)
+                            .for_each(|pixel| pixel.swap(0, 2));
+                    }
+                }
+
+                let formats = match &floats {
+                    Some((format, _)) => vec![*format],
+                    None => format::PixelFormat::all().to_vec(),
+                };
+                let mut best: Option<(BruhFile, usize)> = None;
+                for pixel_format in formats {
+                    for compression in format::Compression::all() {
+                        let candidate = match &floats {
+                            Some((_, floats)) => BruhFile::from_rgba_f32(
+                                width,
+                                height,
+                                floats,
+                                pixel_format,
+                                compression,
+                            ),
+                            None => {
+                                BruhFile::encode(width, height, &rgba, pixel_format, compression)
+                            }
+                        };
+                        let Some(mut candidate) = candidate else {
+                            continue;
+                        };
+                        candidate.header.linear = linear;
+                        candidate.header.bgr = bgr;
+                        if let Some((tile_width, tile_height)) = tile_size {
+                            candidate
+                                .tile(tile_width, tile_height, config::get().max_pixels)
+                                .map_err(|e| BruhError::decode(path, e))?;
+                        }
+                        if interlaced {
+                            candidate
+                                .interlace(config::get().max_pixels)
+                                .map_err(|e| BruhError::decode(path, e))?;
+                        }
+                        candidate.chunks.splice(0..0, before.iter().cloned());
+                        candidate.chunks.extend(after.iter().cloned());
+
+                        let size = candidate.to_bytes().len();
+                        debug!(
+                            "{} with {} compression: {} bytes",
+                            pixel_format.name(),
+                            compression.name(),
+                            size
+                        );
+                        if best.as_ref().is_none_or(|(_, best_size)| size < *best_size) {
+                            best = Some((candidate, size));
+                        }
+                    }
+                }
+
+                let (file, size) = best.unwrap();
+                if size >= contents.len() {
+                    info!(
+                        "{}: already optimal at {} bytes",
+                        path.display(),
+                        contents.len()
+                    );
+                    return Ok(());
+                }
+
+                write_bruh_file(path, &file)?;
+                info!(
+                    "{}: {} -> {} bytes ({:.1}% smaller) as {} with {} compression",
+                    path.display(),
+                    contents.len(),
+                    size,
+                    100.0 - size as f64 / contents.len() as f64 * 100.0,
+                    file.header.format.name(),
+                    file.header.compression.name()
+                );
+
+                Ok(())
+            })
+        }
+        "tile" => {
+            let mut rest = args[2..].to_vec();
+            let size = take_number(&mut rest, "--size")?.unwrap_or(format::DEFAULT_TILE_SIZE);
+            let out = take_path(&mut rest, "-o")?;
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run tile ~/huge.bruh --size 256`"));
+            };
+            if size == 0 {
+                return Err(BruhError::usage("'--size' must be at least 1"));
+            }
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let mut file = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                BruhFile::from_rgba(width, height, &rgba, config::get().compression)
+            } else {
+                BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?
+            };
+
+            file.tile(size, size, config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            // A signature wouldn't match the new bytes.
+            file.chunks.retain(|chunk| chunk.tag != format::SIGNATURE);
+            let out = output_path(out, &path)?;
+            write_bruh_file(&out, &file)?;
+            info!("Split {} into {}x{} tiles", out.display(), size, size);
+
+            Ok(())
+        }
+        "interlace" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run interlace ~/photo.bruh`"));
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let mut file = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                BruhFile::from_rgba(width, height, &rgba, config::get().compression)
+            } else {
+                BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?
+            };
+
+            file.interlace(config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            // A signature wouldn't match the new bytes.
+            file.chunks.retain(|chunk| chunk.tag != format::SIGNATURE);
+            let out = output_path(out, &path)?;
+            write_bruh_file(&out, &file)?;
+            info!("Interlaced {}", out.display());
+
+            Ok(())
+        }
+        "align" => {
+            let mut rest = args[2..].to_vec();
+            let out = take_path(&mut rest, "-o")?;
+            let [path, alignment] = &rest[..] else {
+                return Err(BruhError::usage("Expected a path and a row alignment. Example: `cargo run align ~/texture.bruh 4`"));
+            };
+            let alignment: usize = alignment.to_string_lossy().parse().map_err(|_| {
+                BruhError::usage("The row alignment must be a number of bytes, like 4 or 8")
+            })?;
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let mut file = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                BruhFile::from_rgba(width, height, &rgba, config::get().compression)
+            } else {
+                BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?
+            };
+            file.align_rows(alignment, config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            // A signature wouldn't match the new bytes.
+            file.chunks.retain(|chunk| chunk.tag != format::SIGNATURE);
+            let out = output_path(out, &path)?;
+            write_bruh_file(&out, &file)?;
+            info!(
+                "Padded the rows of {} to multiples of {} bytes",
+                out.display(),
+                alignment
+            );
+
+            Ok(())
+        }
+        "decode" => {
+            let mut rest = args[2..].to_vec();
+            let target = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "png".into());
+            let level: Option<usize> = take_number(&mut rest, "--level")?;
+            let tone_map = take_flag(&mut rest, "--tone-map")?;
+            let exposure: f32 = take_number(&mut rest, "--exposure")?.unwrap_or(0.0);
+            let out = take_path(&mut rest, "-o")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run decode ~/anim.bruh --format gif`"));
+            }
+            let tone_map = match tone_map {
+                Some(name) => ops::ToneMap::parse(&name).ok_or_else(|| {
+                    BruhError::usage("Unknown tone mapping. Use one of: clamp, reinhard, aces")
+                })?,
+                None => ops::ToneMap::Clamp,
+            };
+
+            let (extension, export): (&str, export::Export) = match target.as_str() {
+                "png" => ("png", export::png),
+                "apng" => ("png", export::apng),
+                "gif" => ("gif", export::gif),
+                "webp" => ("webp", export::webp),
+                _ => {
+                    return Err(BruhError::usage(
+                        "Unknown format. Use one of: png, apng, gif, webp",
+                    ))
+                }
+            };
+
+            let path = PathBuf::from(&rest[0]);
+            let contents = read_file(&path)?;
+            let mut pixels_per_inch = None;
+            let mut linear = false;
+            let mut exif = None;
+            let (width, height, frames, loops) = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                let frame = format::Frame { rgba, delay_ms: 0 };
+                (width, height, vec![frame], 0)
+            } else {
+                let file = parse_bruh(&path, &contents)?;
+                linear = file.header.linear;
+                exif = file.exif().map(<[u8]>::to_vec);
+                let decoded = match level {
+                    Some(level) => {
+                        let (width, height, rgba) = file
+                            .mip_level(level, config::get().max_pixels)
+                            .map_err(|e| BruhError::decode(&path, e))?
+                            .ok_or_else(|| {
+                                BruhError::usage(format!(
+                                    "{} has only {} mipmap levels, counting from 0",
+                                    path.display(),
+                                    file.mip_levels()
+                                ))
+                            })?;
+                        let frame = format::Frame { rgba, delay_ms: 0 };
+                        (width, height, vec![frame], 0)
+                    }
+                    // Every format we write has 8 bits per channel, so floats are tone mapped to
+                    // sRGB.
+                    None if file.header.format.is_float() => {
+                        let floats = file
+                            .to_rgba_f32(config::get().max_pixels)
+                            .map_err(|e| BruhError::decode(&path, e))?;
+                        linear = false;
+                        let rgba = ops::tone_map(&floats, tone_map, exposure);
+                        let frame = format::Frame { rgba, delay_ms: 0 };
+                        (file.header.width, file.header.height, vec![frame], 0)
+                    }
+                    None => {
+                        let frames = decode_frames(&path, &file)?;
+                        (file.header.width, file.header.height, frames, file.loops())
+                    }
+                };
+                // Mipmap levels print as big as the image, with fewer pixels per inch.
+                let scale = |ppi: u32, size: u32, full: u32| {
+                    (ppi as u64 * size as u64 / full.max(1) as u64).max(1) as u32
+                };
+                pixels_per_inch = file.pixels_per_inch().map(|(x, y)| {
+                    (
+                        scale(x, decoded.0, file.header.width),
+                        scale(y, decoded.1, file.header.height),
+                    )
+                });
+                decoded
+            };
+
+            let out = output_file(out, &path.with_extension(extension))?;
+            let mut bytes = export(width, height, &frames, loops).map_err(BruhError::Failed)?;
+            if let (Some(density), "png") = (pixels_per_inch, extension) {
+                export::set_png_pixels_per_inch(&mut bytes, density);
+            }
+            if let (Some(exif), "png") = (&exif, extension) {
+                export::set_png_exif(&mut bytes, exif);
+            }
+            if linear && extension == "png" {
+                export::set_png_linear(&mut bytes);
+            } else if linear {
+                warn!(
+                    "{} has linear colors, which {} can't say, they will look too dark",
+                    path.display(),
+                    target
+                );
+            }
+            Disk.write(&out, &bytes)
+                .map_err(|e| BruhError::io(&out, e))?;
+            info!("Decoded {} to {}", path.display(), out.display());
+
+            Ok(())
+        }
+        "show" => {
+            let mut rest = args[2..].to_vec();
+            let chosen: Vec<_> = terminal::Protocol::all_flags()
+                .into_iter()
+                .filter(|flag| take_switch(&mut rest, flag))
+                .collect();
+            let size = take_number(&mut rest, "--size")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run show --sixel ~/image.bruh`"));
+            }
+
+            let protocol = match chosen[..] {
+                [] => terminal::Protocol::detect(),
+                [flag] => terminal::Protocol::from_flag(flag).unwrap(),
+                _ => {
+                    return Err(BruhError::usage(
+                        "Only one of --sixel, --kitty, --iterm and --blocks can be given",
+                    ))
+                }
+            };
+            debug!("drawing with {:?}", protocol);
+
+            let mut img = read_bruh_image(Path::new(&rest[0]))?;
+            if let Some(size) = size {
+                img = ops::thumbnail(&img, size);
+            }
+            print!("{}", protocol.draw(&img));
+            if protocol != terminal::Protocol::Blocks {
+                println!();
+            }
+
+            Ok(())
+        }
+        "ascii" => {
+            let mut rest = args[2..].to_vec();
+            let width = take_number(&mut rest, "--width")?.unwrap_or(80);
+            let color = take_switch(&mut rest, "--color");
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run ascii ~/image.bruh --width 80 --color`"));
+            }
+            if width == 0 {
+                return Err(BruhError::usage("'--width' must be at least 1"));
+            }
+
+            let img = read_bruh_image(Path::new(&rest[0]))?;
+            print!("{}", terminal::ascii(&img, width, color));
+
+            Ok(())
+        }
+        "serve" => {
+            let mut rest = args[2..].to_vec();
+            let port = take_number(&mut rest, "--port")?.unwrap_or(8080);
+            let host = take_flag(&mut rest, "--host")?.unwrap_or_else(|| "127.0.0.1".to_owned());
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('directory') not provided. Example: `cargo run serve --port 8080 ~/images`"));
+            }
+
+            let dir = PathBuf::from(&rest[0]);
+            if !dir.is_dir() {
+                return Err(BruhError::usage(format!(
+                    "{} is not a directory",
+                    dir.display()
+                )));
+            }
+            serve::serve(&dir, &host, port)
+        }
+        "watch" => {
+            let mut rest = args[2..].to_vec();
+            let to = take_flag(&mut rest, "--to")?.unwrap_or_else(|| "bruh".into());
+            let interval = take_number(&mut rest, "--interval")?.unwrap_or(500);
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('directory') not provided. Example: `cargo run watch ~/assets --to bruh`"));
+            }
+
+            let dir = PathBuf::from(&rest[0]);
+            if !dir.is_dir() {
+                return Err(BruhError::usage(format!(
+                    "{} is not a directory",
+                    dir.display()
+                )));
+            }
+
+            type Output = fn(&Path) -> Result<PathBuf, BruhError>;
+            type Convert = fn(&Path, &Path) -> Result<(), BruhError>;
+            let (extensions, output, convert): (&'static [&'static str], Output, Convert) = match to
+                .as_str()
+            {
+                "bruh" => (
+                    COMPILE_EXTENSIONS,
+                    |path| output_path(None, &compiled_path(path)),
+                    |path, _| png_to_bruh(path.to_path_buf(), &CompileOptions::default()).map(drop),
+                ),
+                "png" => (
+                    &["bruh"],
+                    |path| output_file(None, &path.with_extension("png")),
+                    |path, out| write_png(out, &read_bruh_image(path)?),
+                ),
+                _ => {
+                    return Err(BruhError::usage(
+                        "Unknown target format. Use one of: bruh, png",
+                    ))
+                }
+            };
+
+            info!(
+                "Watching {} for .{} files, stop with Ctrl+C",
+                dir.display(),
+                extensions.join(" and .")
+            );
+            let mut watcher = watch::Watcher::new(&dir, extensions);
+            loop {
+                for path in watcher.poll().map_err(|e| BruhError::io(&dir, e))? {
+                    let result = output(&path).and_then(|out| {
+                        if watch::is_stale(&path, &out) {
+                            convert(&path, &out)?;
+                            info!("Converted {} to {}", path.display(), out.display());
+                        }
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        e.report();
+                    }
+                }
+                thread::sleep(Duration::from_millis(interval));
+            }
+        }
+        #[cfg(not(all(feature = "fuse", any(target_os = "linux", target_os = "macos"))))]
+        "mount" => Err(BruhError::usage(
+            "Mounting needs bruh built with the `fuse` feature, on Linux or macOS",
+        )),
+        #[cfg(all(feature = "fuse", any(target_os = "linux", target_os = "macos")))]
+        "mount" => {
+            let [dir, mountpoint] = &args[2..] else {
+                return Err(BruhError::usage("Arguments ('dir', 'mountpoint') not provided. Example: `cargo run --features fuse mount ~/images ~/mnt`"));
+            };
+            mount::run(Path::new(dir), Path::new(mountpoint))
+        }
+        #[cfg(not(feature = "ffmpeg"))]
+        "from-video" => Err(BruhError::usage(
+            "Converting videos needs bruh built with the `ffmpeg` feature",
+        )),
+        #[cfg(feature = "ffmpeg")]
+        "from-video" => {
+            let mut rest = args[2..].to_vec();
+            let fps: u32 = take_number(&mut rest, "--fps")?.unwrap_or(10);
+            let animated = take_path(&mut rest, "--animated")?;
+            if rest.is_empty() {
+                return Err(BruhError::usage("Secondary argument ('video') not provided. Example: `cargo run from-video ~/clip.mp4 --fps 10 ~/frames`"));
+            }
+            if !(1..=1000).contains(&fps) {
+                return Err(BruhError::usage("'--fps' must be between 1 and 1000"));
+            }
+
+            let input = PathBuf::from(&rest[0]);
+            let (width, height) = video::size(&input)?;
+            check_output_size(width, height)?;
+            let compression = config::get().compression;
+
+            if let Some(out) = animated {
+                let mut frames = Vec::new();
+                video::decode(&input, fps, (width, height), |rgba| {
+                    frames.push(format::Frame {
+                        rgba,
+                        delay_ms: 1000 / fps,
+                    });
+                    Ok(())
+                })?;
+                if frames.is_empty() {
+                    return Err(BruhError::Failed(format!(
+                        "{} has no frames",
+                        input.display()
+                    )));
+                }
+
+                let file = BruhFile::from_frames(width, height, &frames, 0, compression);
+                write_bruh_file(&out, &file)?;
+                info!(
+                    "Converted {} to {}, {} frames",
+                    input.display(),
+                    out.display(),
+                    frames.len()
+                );
+                return Ok(());
+            }
+
+            let dir = rest
+                .get(1)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| input.with_extension(""));
+            fs::create_dir_all(&dir).map_err(|e| BruhError::io(&dir, e))?;
+            let mut index = 0;
+            let count = video::decode(&input, fps, (width, height), |rgba| {
+                index += 1;
+                let path = dir.join(format!("frame-{:04}.bruh", index));
+                write_bruh(&path, width, height, &rgba)
+            })?;
+            info!(
+                "Converted {} to {} frames in {}",
+                input.display(),
+                count,
+                dir.display()
+            );
+
+            Ok(())
+        }
+        "append-frame" => {
+            let mut rest = args[2..].to_vec();
+            let delay_ms: u32 = take_number(&mut rest, "--delay")?.unwrap_or(100);
+            let [path, frame] = rest.as_slice() else {
+                return Err(BruhError::usage("Arguments ('animation', 'frame') not provided. Example: `cargo run append-frame ~/anim.bruh ~/frame.png --delay 100`"));
+            };
+
+            let path = PathBuf::from(path);
+            let (width, height, rgba) = read_bruh(Path::new(frame))?;
+            let frame = format::Frame { rgba, delay_ms };
+            // An animation that doesn't exist yet starts out as its first frame.
+            if !path.exists() {
+                let file =
+                    BruhFile::from_frames(width, height, &[frame], 0, config::get().compression);
+                write_bruh_file(&path, &file)?;
+                info!("Started {} with its first frame", path.display());
+                return Ok(());
+            }
+
+            let contents = read_file(&path)?;
+            let mut file = if format::is_legacy(&contents) {
+                let (width, height, rgba) = format::decode(&contents, config::get().max_pixels)
+                    .map_err(|e| BruhError::decode(&path, e))?;
+                BruhFile::from_rgba(width, height, &rgba, config::get().compression)
+            } else {
+                BruhFile::parse(&contents).map_err(|e| BruhError::decode(&path, e))?
+            };
+            if (width, height) != (file.header.width, file.header.height) {
+                return Err(BruhError::Failed(format!(
+                    "The frame is {}x{}, but {} is {}x{}",
+                    width,
+                    height,
+                    path.display(),
+                    file.header.width,
+                    file.header.height
+                )));
+            }
+            file.push_frame(frame, config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            write_bruh_file(&path, &file)?;
+            info!("{} has {} frames", path.display(), file.frame_count());
+
+            Ok(())
+        }
+        "extract-frames" => {
+            let mut rest = args[2..].to_vec();
+            let target = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "png".into());
+            let range = take_flag(&mut rest, "--range")?;
+            let out = take_path(&mut rest, "-o")?;
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run extract-frames ~/anim.bruh -o ~/frames --format png`"));
+            };
+            if !["png", "bruh", "gif", "webp"].contains(&target.as_str()) {
+                return Err(BruhError::usage(
+                    "Unknown format. Use one of: png, bruh, gif, webp",
+                ));
+            }
+            let range = match range {
+                Some(range) => Some(parse_range(&range).ok_or_else(|| {
+                    BruhError::usage(format!(
+                        "'{}' should be a range of frames like 2-5, 3- or 4, counting from 1",
+                        range
+                    ))
+                })?),
+                None => None,
+            };
+
+            let path = PathBuf::from(path);
+            let contents = read_file(&path)?;
+            let file = parse_bruh(&path, &contents)?;
+            let (width, height) = (file.header.width, file.header.height);
+            let count = file.frame_count();
+            let (first, last) = range.unwrap_or((1, None));
+            let last = last.unwrap_or(count);
+            if last > count || first > last {
+                return Err(BruhError::usage(format!(
+                    "{} has only {} frames, counting from 1",
+                    path.display(),
+                    count
+                )));
+            }
+            let frames = decode_frames(&path, &file)?;
+
+            let dir = out.unwrap_or_else(|| path.with_extension(""));
+            fs::create_dir_all(&dir).map_err(|e| BruhError::io(&dir, e))?;
+            for (index, frame) in frames.iter().enumerate().take(last).skip(first - 1) {
+                let out = dir.join(format!("frame-{:04}.{}", index + 1, target));
+                if target == "bruh" {
+                    let mut image =
+                        BruhFile::from_rgba(width, height, &frame.rgba, config::get().compression);
+                    image.header.linear = file.header.linear;
+                    write_bruh_file(&out, &image)?;
+                    continue;
+                }
+                let export = match target.as_str() {
+                    "gif" => export::gif,
+                    "webp" => export::webp,
+                    _ => export::png,
+                };
+                let mut bytes = export(width, height, std::slice::from_ref(frame), 0)
+                    .map_err(BruhError::Failed)?;
+                if target == "png" && file.header.linear {
+                    export::set_png_linear(&mut bytes);
+                }
+                Disk.write(&out, &bytes)
+                    .map_err(|e| BruhError::io(&out, e))?;
+            }
+            info!(
+                "Extracted {} of {} frames to {}",
+                last + 1 - first,
+                count,
+                dir.display()
+            );
+
+            Ok(())
+        }
+        "export-raw" => {
+            let mut rest = args[2..].to_vec();
+            let target = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "rgba8".into());
+            let Some(path) = rest.first() else {
+                return Err(BruhError::usage("Secondary argument ('path') not provided. Example: `cargo run export-raw ~/image.bruh ~/image.rgba --format rgba8`"));
+            };
+            let target = raw::RawFormat::parse(&target).ok_or_else(|| {
+                BruhError::usage(format!(
+                    "Unknown raw format. Use one of: {}",
+                    raw::RawFormat::all().map(raw::RawFormat::name).join(", ")
+                ))
+            })?;
+
+            let path = PathBuf::from(path);
+            let out = match rest.get(1) {
+                Some(out) => PathBuf::from(out),
+                None => output_file(None, &path.with_extension("raw"))?,
+            };
+            let contents = read_file(&path)?;
+            let file = parse_bruh(&path, &contents)?;
+            let bytes = raw::to_raw(&file, target, config::get().max_pixels)
+                .map_err(|e| BruhError::decode(&path, e))?;
+            Disk.write(&out, &bytes)
+                .map_err(|e| BruhError::io(&out, e))?;
+            info!(
+                "Wrote the {}x{} pixels of {} to {} as {}",
+                file.header.width,
+                file.header.height,
+                path.display(),
+                out.display(),
+                target.name()
+            );
+
+            Ok(())
+        }
+        "from-raw" => {
+            let mut rest = args[2..].to_vec();
+            let size = take_flag(&mut rest, "--size")?;
+            let source = take_flag(&mut rest, "--format")?.unwrap_or_else(|| "rgba8".into());
+            let (Some(path), Some(size)) = (rest.first(), size) else {
+                return Err(BruhError::usage("Arguments ('path', '--size') not provided. Example: `cargo run from-raw ~/capture.rgba --size 640x480 --format rgba8 ~/capture.bruh`"));
+            };
+            let (width, height) = parse_size(&size)
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .ok_or_else(|| BruhError::usage("'--size' must look like 640x480"))?;
+            check_output_size(width, height)?;
+            let source = raw::RawFormat::parse(&source).ok_or_else(|| {
+                BruhError::usage(format!(
+                    "Unknown raw format. Use one of: {}",
+                    raw::RawFormat::all().map(raw::RawFormat::name).join(", ")
+                ))
+            })?;
+
+            let path = PathBuf::from(path);
+            let out = match rest.get(1) {
+                Some(out) => PathBuf::from(out),
+                None => output_path(None, &compiled_path(&path))?,
+            };
+            let bytes = read_file(&path)?;
+            let file = raw::from_raw(width, height, &bytes, source, config::get().compression)
+                .map_err(|e| BruhError::Failed(format!("{}: {}", path.display(), e)))?;
+            write_bruh_file(&out, &file)?;
+            info!(
+                "Converted the {}x{} {} pixels of {} to {}",
+                width,
+                height,
+                source.name(),
+                path.display(),
+                out.display()
+            );
+
+            Ok(())
+        }
+        "generate" => {
+            let mut rest = args[2..].to_vec();
+            let pattern = take_flag(&mut rest, "--pattern")?.unwrap_or_else(|| "checker".into());
+            let size = take_flag(&mut rest, "--size")?.unwrap_or_else(|| "1024x768".into());
+            let color = match take_flag(&mut rest, "--color")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--color' must be a CSS color"))?,
+                None => image::Rgba([255, 255, 255, 255]),
+            };
+            let background = match take_flag(&mut rest, "--background")? {
+                Some(color) => parse_color(&color)
+                    .ok_or_else(|| BruhError::usage("'--background' must be a CSS color"))?,
+                None => image::Rgba([0, 0, 0, 255]),
+            };
+            let cell = take_number(&mut rest, "--cell")?.unwrap_or(32);
+            let seed = take_number(&mut rest, "--seed")?.unwrap_or(0);
+            let [out] = rest.as_slice() else {
+                return Err(BruhError::usage("Argument ('out') not provided. Example: `cargo run generate --pattern checker --size 1024x768 ~/checker.bruh`"));
+            };
+
+            let kind = ops::Pattern::parse(&pattern).ok_or_else(|| {
+                BruhError::usage("Unknown pattern. Use one of: checker, gradient, noise, solid")
+            })?;
+            let (width, height) = parse_size(&size)
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .ok_or_else(|| BruhError::usage("'--size' must look like 1024x768"))?;
+            if cell == 0 {
+                return Err(BruhError::usage("'--cell' must be at least 1"));
+            }
+            check_output_size(width, height)?;
+
+            let img = ops::generate(width, height, kind, background, color, cell, seed);
+            write_bruh_image(Path::new(out), &img)?;
+            info!("Generated a {}x{} {} image", width, height, pattern);
+
+            Ok(())
+        }
+        "screenshot" => {
+            let mut rest = args[2..].to_vec();
+            let region = take_flag(&mut rest, "--region")?
+                .map(|region| region.parse::<screenshot::Region>())
+                .transpose()
+                .map_err(BruhError::usage)?;
+            let [out] = rest.as_slice() else {
+                return Err(BruhError::usage("Argument ('out') not provided. Example: `cargo run screenshot --region 0,0,800,600 ~/screen.bruh`"));
+            };
+            let out = Path::new(out);
+
+            let started = Instant::now();
+            let img = screenshot::capture(region)
+                .map_err(|e| BruhError::Failed(format!("Can't take a screenshot: {}", e)))?;
+            debug!("captured the screen in {:.2?}", started.elapsed());
+            write_bruh_image(out, &img)?;
+            info!(
+                "Saved a {}x{} screenshot to {}",
+                img.width(),
+                img.height(),
+                out.display()
+            );
+
+            Ok(())
+        }
+        "thumbnailer" => {
+            let mut rest = args[2..].to_vec();
+            if take_switch(&mut rest, "--cache") {
+                if rest.is_empty() {
+                    return Err(BruhError::usage("Argument ('path') not provided. Example: `cargo run thumbnailer --cache ~/Pictures/*.bruh`"));
+                }
+                for_each_path(&rest, |path| {
+                    for (dir, size) in thumbnailer::SIZES {
+                        let out =
+                            thumbnailer::save(path, &file_manager_thumbnail(path, size)?, dir)?;
+                        debug!("cached {} as {}", path.display(), out.display());
+                    }
+                    Ok(())
+                })?;
+                info!("Cached the thumbnails of {} file(s)", rest.len());
+                return Ok(());
+            }
+
+            let size = take_number::<u32>(&mut rest, "-s")?.unwrap_or(128);
+            if size == 0 {
+                return Err(BruhError::usage("'-s' must be at least 1"));
+            }
+            let [input, output] = rest.as_slice() else {
+                return Err(BruhError::usage("Arguments ('input', 'output') not provided. Example: `cargo run thumbnailer -s 256 ~/image.bruh ~/thumbnail.png`"));
+            };
+            write_png(
+                Path::new(output),
+                &file_manager_thumbnail(Path::new(input), size)?,
+            )
+        }
+        "register" | "unregister" if !cfg!(windows) => Err(BruhError::usage(
+            "File associations can only be registered on Windows",
+        )),
+        "register" => {
+            let exe = env::current_exe().map_err(|e| BruhError::io(Path::new("bruh"), e))?;
+            association::register(&exe)?;
+            info!("Registered {} to open .bruh files", exe.display());
+
+            Ok(())
+        }
+        "unregister" => {
+            if association::unregister()? {
+                info!("Removed the .bruh file association");
+            } else {
+                info!(".bruh files weren't registered");
+            }
+
+            Ok(())
+        }
+        "install-desktop" if cfg!(windows) => Err(BruhError::usage(
+            "Desktop entries are for Linux, use `bruh register` on Windows",
+        )),
+        "install-desktop" => {
+            let exe = env::current_exe().map_err(|e| BruhError::io(Path::new("bruh"), e))?;
+            let (installed, failed) = association::install_desktop(&exe)?;
+            for path in &installed {
+                info!("Installed {}", path.display());
+            }
+            for program in &failed {
+                warn!(
+                    "Couldn't run {}, the desktop may only notice after logging in again",
+                    program
+                );
+            }
+
+            Ok(())
+        }
+        "completions" => {
+            if args.len() < 3 {
+                return Err(BruhError::usage("Secondary argument ('shell') not provided. Example: `cargo run completions bash`"));
+            }
+
+            let script = completions::script(text(&args[2])?).ok_or_else(|| {
+                BruhError::usage(format!(
+                    "Unknown shell. Use one of: {}",
+                    completions::SHELLS.join(", ")
+                ))
+            })?;
+            print!("{}", script);
+
+            Ok(())
+        }
+        _ => open_viewer(&file_path),
+    }
+}
+
+/// Opens `path` in `bruh-viewer`, next to this program or else wherever `PATH` finds it, with
+/// the same flags. Returns once its window is closed, exiting with its code if it failed, since
+/// it already reported why.
+fn open_viewer(path: &Path) -> Result<(), BruhError> {
+    let name = format!("bruh-viewer{}", env::consts::EXE_SUFFIX);
+    let program = env::current_exe()
+        .map(|exe| exe.with_file_name(&name))
+        .ok()
+        .filter(|program| program.is_file())
+        .unwrap_or_else(|| PathBuf::from(&name));
+    debug!("opening {} with {}", path.display(), program.display());
+
+    let status = process::Command::new(&program)
+        .args(env::args_os().skip(1))
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => BruhError::usage(format!(
+                "Unknown command '{}', and bruh-viewer isn't installed to open images",
+                path.display()
+            )),
+            _ => BruhError::io(&program, e),
+        })?;
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_parse() {
+        assert_eq!(parse_size("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_size("1920"), None);
+        assert_eq!(parse_size("-1x2"), None);
+    }
+
+    #[test]
+    fn rects_parse() {
+        assert_eq!(parse_rect("10,10,640,480"), Some((10, 10, 640, 480)));
+        assert_eq!(parse_rect("10,10,640"), None);
+        assert_eq!(parse_rect("10,10,640,-480"), None);
+    }
+
+    #[test]
+    fn positions_parse() {
+        assert_eq!(parse_point("100, -50"), Some((100, -50)));
+        assert_eq!(parse_point("100"), None);
+    }
+
+    #[test]
+    fn colors_parse_like_css() {
+        assert_eq!(
+            parse_color("white"),
+            Some(image::Rgba([255, 255, 255, 255]))
+        );
+        assert_eq!(
+            parse_color("#ff000080"),
+            Some(image::Rgba([255, 0, 0, 128]))
+        );
+        assert_eq!(
+            parse_color("#fff8"),
+            Some(image::Rgba([255, 255, 255, 136]))
+        );
+        assert_eq!(parse_color("#336"), Some(image::Rgba([51, 51, 102, 255])));
+        assert_eq!(parse_color("#ff00008"), None);
+        assert_eq!(parse_color("not a color"), None);
+    }
+
+    #[test]
+    fn flags_are_taken_out_of_the_arguments() {
+        let mut args: Vec<OsString> = ["in.png", "--size", "64", "--linear", "--out"]
+            .map(OsString::from)
+            .into();
+        assert!(take_switch(&mut args, "--linear"));
+        assert!(!take_switch(&mut args, "--linear"));
+        assert_eq!(take_number::<u32>(&mut args, "--size").unwrap(), Some(64));
+        assert_eq!(take_flag(&mut args, "--missing").unwrap(), None);
+        assert!(take_path(&mut args, "--out").is_err());
+        assert_eq!(args, ["in.png", "--out"]);
+
+        let mut args: Vec<OsString> = ["--size", "big"].map(OsString::from).into();
+        assert!(take_number::<u32>(&mut args, "--size").is_err());
+    }
+
+    #[test]
+    fn suffixes_go_before_the_last_extension() {
+        assert_eq!(
+            with_suffix(Path::new("dir/a.b.bruh"), "-x"),
+            Path::new("dir/a.b-x.bruh")
+        );
+        assert_eq!(
+            with_suffix(Path::new("README"), "-x"),
+            Path::new("README-x")
+        );
+        assert_eq!(
+            compiled_path(Path::new("dir/photo.png")),
+            Path::new("dir/photo.bruh")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_arent_utf8_keep_their_bytes() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let path = PathBuf::from(OsString::from_vec(b"dir/caf\xe9.png".to_vec()));
+        let suffixed = with_suffix(&path, "-x");
+        assert_eq!(suffixed.as_os_str().as_bytes(), b"dir/caf\xe9-x.png");
+        assert_eq!(
+            compiled_path(&path).as_os_str().as_bytes(),
+            b"dir/caf\xe9.bruh"
+        );
+        assert_eq!(
+            output_path(None, &path).unwrap().as_os_str().as_bytes(),
+            b"dir/caf\xe9.bruh"
+        );
+        assert_eq!(output_file(None, &path).unwrap(), path);
+    }
+
+    #[test]
+    fn ranges_parse() {
+        assert_eq!(parse_range("2-5"), Some((2, Some(5))));
+        assert_eq!(parse_range("3-"), Some((3, None)));
+        assert_eq!(parse_range("4"), Some((4, Some(4))));
+        for bad in ["0", "5-2", "0-3", "a-b", ""] {
+            assert_eq!(parse_range(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn scales_parse() {
+        assert!(matches!(parse_scale("50%"), Some(Scale::Percent(p)) if p == 50.0));
+        assert!(matches!(parse_scale("20x10"), Some(Scale::To(20, 10))));
+        for bad in ["0%", "-5%", "inf%", "0x10", "wide"] {
+            assert!(parse_scale(bad).is_none(), "{}", bad);
+        }
+        assert_eq!(Scale::Percent(50.0).of(101, 1), (51, 1));
+    }
+
+    #[test]
+    fn outputs_of_downloads_and_archive_members_stay_local() {
+        let out = PathBuf::from("given.bruh");
+        assert_eq!(
+            output_path(Some(out.clone()), Path::new("a.bruh")).unwrap(),
+            out
+        );
+        assert_eq!(
+            output_path(None, Path::new("https://example.com/img/a.bruh?v=2")).unwrap(),
+            Path::new("a.bruh")
+        );
+        assert_eq!(
+            output_path(None, Path::new("dir/images.zip!icons/a.bruh")).unwrap(),
+            Path::new("dir/a.bruh")
+        );
+    }
+
+    #[test]
+    fn edits_keep_metadata_and_color_flags() {
+        let dir = env::temp_dir().join(format!("bruh-edit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, out) = (dir.join("a.bruh"), dir.join("b.bruh"));
+
+        // Red then blue, stored blue first.
+        let mut stored = vec![255, 0, 0, 255, 0, 0, 255, 255];
+        format::swap_red_blue(&mut stored);
+        let mut file = BruhFile::from_rgba(2, 1, &stored, Compression::None);
+        file.header.linear = true;
+        file.header.bgr = true;
+        file.set_metadata(&[("author".to_owned(), "me".to_owned())]);
+        file.set_exif(Some(b"Exif\0\0".to_vec()));
+        file.set_pixels_per_inch(Some((300, 300)));
+        write_bruh_file(&path, &file).unwrap();
+
+        let (img, original) = read_edit(&path).unwrap();
+        write_edit(&out, &original, &ops::flip(&img, true, false)).unwrap();
+        let edited = parse_bruh(&out, &fs::read(&out).unwrap()).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(edited.header.linear && edited.header.bgr);
+        assert_eq!(
+            edited.to_rgba(u64::MAX).unwrap(),
+            [0, 0, 255, 255, 255, 0, 0, 255]
+        );
+        assert_eq!(edited.metadata_value("author").as_deref(), Some("me"));
+        assert_eq!(edited.exif(), Some(&b"Exif\0\0"[..]));
+        assert_eq!(edited.pixels_per_inch(), Some((300, 300)));
+    }
+}
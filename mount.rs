@@ -0,0 +1,505 @@
+//! `bruh mount dir mountpoint`: a read-only mirror of `dir` in which every .bruh file shows up as
+//! a PNG, converted when it is first read, so programs that know nothing about BRUH can open
+//! them. Everything else is passed through as it is. Built with the `fuse` feature on top of
+//! libfuse 2 (macFUSE on macOS), whose few functions and structs used are declared here.
+//!
+//! Converting takes a while, so listing a directory doesn't: until a file is read, its size is
+//! what its PNG would take at most, and the mount is made with `direct_io` so reads go on until
+//! the real end of the PNG whatever the size said.
+//!
+//! `photo.bruh` appears as `photo.png`, unless there is a real `photo.png` next to it, which wins
+//! and leaves `photo.bruh` under its own name. Encrypted files keep their name and stay
+//! encrypted, anything that can see the mount could read them otherwise.
+
+use std::{
+    collections::VecDeque,
+    ffi::{CStr, CString, OsStr},
+    fs,
+    io::{self, Read},
+    os::{
+        raw::{c_char, c_int, c_void},
+        unix::{ffi::OsStrExt, fs::FileExt},
+    },
+    path::{Component, Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use image::{codecs::png::PngEncoder, ImageEncoder};
+use log::{info, warn};
+
+use crate::{
+    error::BruhError,
+    export,
+    format::{self, BruhFile},
+    read_bruh_colors,
+};
+
+/// How many converted PNGs are kept, the most recently used last. Programs read files in many
+/// small pieces, which all need the whole PNG.
+const CACHED: usize = 16;
+
+/// A .bruh file as it was when it was converted: its path, modification time and size.
+type Key = (PathBuf, SystemTime, u64);
+
+struct Mount {
+    /// The mirrored directory, canonical so every path below it is too.
+    source: PathBuf,
+    cache: Mutex<VecDeque<(Key, Arc<Vec<u8>>)>>,
+}
+
+/// libfuse calls back without a way to pass state along, except through its context.
+static MOUNT: OnceLock<Mount> = OnceLock::new();
+
+/// What a path in the mount is in the source directory.
+enum Entry {
+    Real(PathBuf),
+    /// The .bruh file shown as a PNG.
+    Converted(PathBuf),
+}
+
+/// Mounts the mirror of `source` at `mountpoint` and serves it until it is unmounted.
+pub fn run(source: &Path, mountpoint: &Path) -> Result<(), BruhError> {
+    let source = fs::canonicalize(source).map_err(|e| BruhError::io(source, e))?;
+    if !source.is_dir() {
+        return Err(BruhError::usage(format!(
+            "{} isn't a directory",
+            source.display()
+        )));
+    }
+    let mount = Mount {
+        source,
+        cache: Mutex::new(VecDeque::new()),
+    };
+    if MOUNT.set(mount).is_err() {
+        panic!("Already mounted")
+    }
+
+    let args = [
+        OsStr::new("bruh"),
+        mountpoint.as_os_str(),
+        // In the foreground, so Ctrl+C unmounts.
+        OsStr::new("-f"),
+        OsStr::new("-o"),
+        OsStr::new("ro,direct_io,fsname=bruh"),
+    ]
+    .map(|arg| CString::new(arg.as_bytes()).unwrap());
+    let mut argv: Vec<*mut c_char> = args.iter().map(|arg| arg.as_ptr().cast_mut()).collect();
+    let operations = Operations {
+        getattr: Some(getattr),
+        unused_before_open: [None; 13],
+        open: Some(open),
+        read: Some(read),
+        unused_before_readdir: [None; 10],
+        readdir: Some(readdir),
+    };
+
+    info!(
+        "Mounting {} at {}, press Ctrl+C or unmount it to stop",
+        MOUNT.get().unwrap().source.display(),
+        mountpoint.display()
+    );
+    // SAFETY: `argv` and `operations` outlive the call, which returns once the file system is
+    // unmounted, and the callbacks only touch what libfuse hands them.
+    let status = unsafe {
+        fuse_main_real(
+            argv.len() as c_int,
+            argv.as_mut_ptr(),
+            &operations,
+            std::mem::size_of::<Operations>(),
+            ptr::null_mut(),
+        )
+    };
+    if status != 0 {
+        return Err(BruhError::Failed(format!(
+            "Couldn't mount {}, see above",
+            mountpoint.display()
+        )));
+    }
+    Ok(())
+}
+
+impl Mount {
+    /// What `path`, e.g. `/photos/cat.png`, is in the source directory.
+    fn resolve(&self, path: &CStr) -> Option<Entry> {
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+        let relative = path.strip_prefix("/").ok()?;
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let real = self.source.join(relative);
+        if real.exists() {
+            return Some(Entry::Real(real));
+        }
+        let bruh = real.with_extension("bruh");
+        (real.extension() == Some(OsStr::new("png")) && converts(&bruh))
+            .then_some(Entry::Converted(bruh))
+    }
+
+    /// The PNG of the .bruh file at `path`, from the cache if it didn't change since.
+    fn png(&self, path: &Path) -> Result<Arc<Vec<u8>>, c_int> {
+        let key = key(path)?;
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(index) = cache.iter().position(|(cached, _)| *cached == key) {
+            let entry = cache.remove(index).unwrap();
+            let png = entry.1.clone();
+            cache.push_back(entry);
+            return Ok(png);
+        }
+        // Converting takes a while, other files can be read meanwhile.
+        drop(cache);
+
+        let png = Arc::new(convert(path).map_err(|e| {
+            warn!("Can't convert {}: {}", path.display(), e);
+            libc::EIO
+        })?);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() == CACHED {
+            cache.pop_front();
+        }
+        cache.push_back((key, png.clone()));
+        Ok(png)
+    }
+
+    /// The size of the PNG of the .bruh file at `path` if it is in the cache, otherwise the most
+    /// it can take, without converting it.
+    fn png_size(&self, path: &Path) -> Result<u64, c_int> {
+        let key = key(path)?;
+        let cache = self.cache.lock().unwrap();
+        if let Some((_, png)) = cache.iter().find(|(cached, _)| *cached == key) {
+            return Ok(png.len() as u64);
+        }
+        drop(cache);
+        let header = read_header(path).map_err(|e| errno(&e))?;
+        let header = BruhFile::parse_header(&header).map_err(|_| libc::EIO)?;
+        Ok(max_png_size(header.width, header.height))
+    }
+}
+
+fn key(path: &Path) -> Result<Key, c_int> {
+    let metadata = fs::metadata(path).map_err(|e| errno(&e))?;
+    Ok((
+        path.to_path_buf(),
+        metadata.modified().map_err(|e| errno(&e))?,
+        metadata.len(),
+    ))
+}
+
+/// The most bytes a `width`x`height` RGBA PNG from `convert` takes: its rows with the filter
+/// byte in front of each, stored without compression in deflate blocks of at most 64 KiB, and
+/// the chunks around them.
+fn max_png_size(width: u32, height: u32) -> u64 {
+    let rows = (width as u64 * 4 + 1) * height as u64;
+    // The signature, `IHDR`, `gAMA`, `IEND`, the zlib header and checksum, and 12 bytes for each
+    // `IDAT` chunk, counting one per 8 KiB to be safe.
+    let chunks = 8 + 25 + 16 + 12 + 6 + (rows / 8192 + 1) * 12;
+    rows + (rows / 65535 + 1) * 5 + chunks
+}
+
+fn read_header(path: &Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(format::BRUH_HEADER_SIZE);
+    fs::File::open(path)?
+        .take(format::BRUH_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Whether `path` is a .bruh file shown as a PNG, which it is unless it is encrypted.
+fn converts(path: &Path) -> bool {
+    read_header(path)
+        .is_ok_and(|header| !BruhFile::parse_header(&header).is_ok_and(|header| header.encrypted))
+}
+
+fn convert(path: &Path) -> Result<Vec<u8>, BruhError> {
+    let (width, height, rgba, linear) = read_bruh_colors(path)?;
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(&rgba, width, height, image::ColorType::Rgba8)
+        .map_err(|source| BruhError::Image {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    if linear {
+        export::set_png_linear(&mut png);
+    }
+    Ok(png)
+}
+
+fn errno(e: &io::Error) -> c_int {
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+fn mount() -> &'static Mount {
+    MOUNT.get().unwrap()
+}
+
+/// `struct stat` as libfuse 2 has it, which is built with 64 bit file offsets and sizes. On
+/// 64 bit Linux and on macOS that's the plain one anyway.
+#[cfg(target_os = "linux")]
+type Stat = libc::stat64;
+#[cfg(target_os = "macos")]
+type Stat = libc::stat;
+
+#[cfg(target_os = "macos")]
+use libc::stat as stat_path;
+#[cfg(target_os = "linux")]
+use libc::stat64 as stat_path;
+
+/// The file status of `path` through `stat`, as libfuse wants it: 0 or a negative errno.
+unsafe fn stat(path: &Path, out: *mut Stat) -> c_int {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    if stat_path(path.as_ptr(), out) != 0 {
+        return -errno(&io::Error::last_os_error());
+    }
+    // Read-only, whatever the source allows.
+    (*out).st_mode &= !0o222;
+    0
+}
+
+unsafe extern "C" fn getattr(path: *const c_char, out: *mut Stat) -> c_int {
+    match mount().resolve(CStr::from_ptr(path)) {
+        Some(Entry::Real(real)) => stat(&real, out),
+        Some(Entry::Converted(bruh)) => {
+            let status = stat(&bruh, out);
+            if status != 0 {
+                return status;
+            }
+            match mount().png_size(&bruh) {
+                Ok(size) => {
+                    (*out).st_size = size as i64;
+                    0
+                }
+                Err(errno) => -errno,
+            }
+        }
+        None => -libc::ENOENT,
+    }
+}
+
+unsafe extern "C" fn open(path: *const c_char, info: *mut FileInfo) -> c_int {
+    if (*info).flags & libc::O_ACCMODE != libc::O_RDONLY {
+        return -libc::EROFS;
+    }
+    match mount().resolve(CStr::from_ptr(path)) {
+        Some(Entry::Real(real)) => match fs::File::open(real) {
+            Ok(_) => 0,
+            Err(e) => -errno(&e),
+        },
+        Some(Entry::Converted(bruh)) => match mount().png(&bruh) {
+            Ok(_) => 0,
+            Err(errno) => -errno,
+        },
+        None => -libc::ENOENT,
+    }
+}
+
+unsafe extern "C" fn read(
+    path: *const c_char,
+    buf: *mut c_char,
+    size: usize,
+    offset: i64,
+    _info: *mut FileInfo,
+) -> c_int {
+    let buf = std::slice::from_raw_parts_mut(buf.cast::<u8>(), size);
+    let offset = offset.max(0) as u64;
+    match mount().resolve(CStr::from_ptr(path)) {
+        Some(Entry::Real(real)) => {
+            let file = match fs::File::open(real) {
+                Ok(file) => file,
+                Err(e) => return -errno(&e),
+            };
+            let mut filled = 0;
+            while filled < size {
+                match file.read_at(&mut buf[filled..], offset + filled as u64) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return -errno(&e),
+                }
+            }
+            filled as c_int
+        }
+        Some(Entry::Converted(bruh)) => match mount().png(&bruh) {
+            Ok(png) => {
+                let start = (offset as usize).min(png.len());
+                let end = (start + size).min(png.len());
+                buf[..end - start].copy_from_slice(&png[start..end]);
+                (end - start) as c_int
+            }
+            Err(errno) => -errno,
+        },
+        None => -libc::ENOENT,
+    }
+}
+
+unsafe extern "C" fn readdir(
+    path: *const c_char,
+    buf: *mut c_void,
+    filler: FillDir,
+    _offset: i64,
+    _info: *mut FileInfo,
+) -> c_int {
+    let Some(Entry::Real(dir)) = mount().resolve(CStr::from_ptr(path)) else {
+        return -libc::ENOENT;
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => return -errno(&e),
+    };
+    let add = |name: &OsStr| {
+        let name = CString::new(name.as_bytes()).unwrap();
+        filler(buf, name.as_ptr(), ptr::null(), 0) == 0
+    };
+    if !add(OsStr::new(".")) || !add(OsStr::new("..")) {
+        return 0;
+    }
+    for entry in entries.flatten() {
+        let real = entry.path();
+        let png = real.with_extension("png");
+        let shown = if real.extension() == Some(OsStr::new("bruh"))
+            && !png.exists()
+            && real.is_file()
+            && converts(&real)
+        {
+            png.file_name().unwrap().to_owned()
+        } else {
+            entry.file_name()
+        };
+        if !add(&shown) {
+            break;
+        }
+    }
+    0
+}
+
+/// Only the start of `struct fuse_file_info`, the rest isn't used.
+#[repr(C)]
+struct FileInfo {
+    flags: c_int,
+}
+
+type FillDir = unsafe extern "C" fn(*mut c_void, *const c_char, *const Stat, i64) -> c_int;
+
+/// `struct fuse_operations` of the libfuse 2 API, up to `readdir`. libfuse takes its size and
+/// leaves out everything after it.
+#[repr(C)]
+struct Operations {
+    getattr: Option<unsafe extern "C" fn(*const c_char, *mut Stat) -> c_int>,
+    /// `readlink` to `utime`.
+    unused_before_open: [Option<unsafe extern "C" fn()>; 13],
+    open: Option<unsafe extern "C" fn(*const c_char, *mut FileInfo) -> c_int>,
+    read: Option<
+        unsafe extern "C" fn(*const c_char, *mut c_char, usize, i64, *mut FileInfo) -> c_int,
+    >,
+    /// `write` to `opendir`.
+    unused_before_readdir: [Option<unsafe extern "C" fn()>; 10],
+    readdir: Option<
+        unsafe extern "C" fn(*const c_char, *mut c_void, FillDir, i64, *mut FileInfo) -> c_int,
+    >,
+}
+
+#[link(name = "fuse")]
+extern "C" {
+    fn fuse_main_real(
+        argc: c_int,
+        argv: *mut *mut c_char,
+        operations: *const Operations,
+        operations_size: usize,
+        user_data: *mut c_void,
+    ) -> c_int;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::format::Compression;
+
+    fn mount(test: &str) -> Mount {
+        let source = env::temp_dir().join(format!("bruh-mount-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&source);
+        fs::create_dir_all(source.join("photos")).unwrap();
+        Mount {
+            source: source.canonicalize().unwrap(),
+            cache: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn resolved(mount: &Mount, path: &str) -> Option<(bool, PathBuf)> {
+        let path = CString::new(path).unwrap();
+        mount.resolve(&path).map(|entry| match entry {
+            Entry::Real(real) => (false, real),
+            Entry::Converted(bruh) => (true, bruh),
+        })
+    }
+
+    #[test]
+    fn bruh_files_resolve_as_pngs_unless_a_real_one_is_there() {
+        let mount = mount("resolve");
+        let bruh = BruhFile::from_rgba(1, 1, &[1, 2, 3, 4], Compression::None).to_bytes();
+        let mut encrypted = bruh.clone();
+        encrypted[7] |= format::FLAG_ENCRYPTED;
+        let photos = mount.source.join("photos");
+        for (name, contents) in [
+            ("cat.bruh", &bruh[..]),
+            ("dog.bruh", &bruh[..]),
+            ("dog.png", b"png"),
+            ("secret.bruh", &encrypted[..]),
+        ] {
+            fs::write(photos.join(name), contents).unwrap();
+        }
+
+        let cases = [
+            resolved(&mount, "/photos/cat.png"),
+            resolved(&mount, "/photos/dog.png"),
+            resolved(&mount, "/photos/dog.bruh"),
+            resolved(&mount, "/photos"),
+            resolved(&mount, "/photos/secret.png"),
+            resolved(&mount, "/photos/secret.bruh"),
+            resolved(&mount, "/photos/../photos/cat.png"),
+            resolved(&mount, "photos/cat.png"),
+            resolved(&mount, "/photos/bird.png"),
+        ];
+        let png_size = mount.png_size(&photos.join("cat.bruh"));
+        let png = mount.png(&photos.join("cat.bruh")).unwrap();
+        let cached_size = mount.png_size(&photos.join("cat.bruh"));
+        fs::remove_dir_all(&mount.source).unwrap();
+
+        assert_eq!(
+            cases,
+            [
+                Some((true, photos.join("cat.bruh"))),
+                Some((false, photos.join("dog.png"))),
+                Some((false, photos.join("dog.bruh"))),
+                Some((false, photos.clone())),
+                None,
+                Some((false, photos.join("secret.bruh"))),
+                None,
+                None,
+                None,
+            ]
+        );
+        assert!(png_size.unwrap() >= png.len() as u64);
+        assert_eq!(cached_size, Ok(png.len() as u64));
+    }
+
+    #[test]
+    fn pngs_never_outgrow_their_estimate() {
+        for (width, height) in [(1, 1), (7, 3), (300, 200)] {
+            let noise: Vec<u8> = (0..width * height * 4)
+                .map(|i: u32| i.wrapping_mul(2_654_435_761).to_be_bytes()[0])
+                .collect();
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png)
+                .write_image(&noise, width, height, image::ColorType::Rgba8)
+                .unwrap();
+            export::set_png_linear(&mut png);
+            assert!(png.len() as u64 <= max_png_size(width, height));
+        }
+    }
+}
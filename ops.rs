@@ -0,0 +1,1632 @@
+use std::{cmp::Reverse, ops::Range};
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use color_quant::NeuQuant;
+use image::{imageops, imageops::FilterType, ImageBuffer, Pixel, Rgba, RgbaImage};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+use crate::format::{BlendMode, Layer};
+
+/// Downscales `img` so that it fits into a `size`x`size` box, keeping its aspect ratio.
+pub fn thumbnail(img: &RgbaImage, size: u32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let scale = size as f32 / width.max(height) as f32;
+    if scale >= 1.0 {
+        return img.clone();
+    }
+
+    let new_width = ((width as f32 * scale).round() as u32).max(1);
+    let new_height = ((height as f32 * scale).round() as u32).max(1);
+    imageops::thumbnail(img, new_width, new_height)
+}
+
+/// The mipmap levels below `img`, each half as wide and high as the one above, rounded down but
+/// at least one pixel, down to 1x1. Every level is scaled from the one above with a triangle filter.
+pub fn mipmaps(img: &RgbaImage) -> Vec<RgbaImage> {
+    let mut levels: Vec<RgbaImage> = Vec::new();
+    loop {
+        let above = levels.last().unwrap_or(img);
+        let (width, height) = above.dimensions();
+        if width == 1 && height == 1 {
+            return levels;
+        }
+        let level = imageops::resize(
+            above,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            FilterType::Triangle,
+        );
+        levels.push(level);
+    }
+}
+
+/// Maps a filter name as given on the command line to the matching resampling filter.
+pub fn parse_filter(name: &str) -> Option<FilterType> {
+    match name {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" | "bilinear" => Some(FilterType::Triangle),
+        "catmull-rom" | "cubic" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos" | "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+/// Scales `img` to `width`x`height`. With `linear` the pixels are mixed in linear light with
+/// premultiplied alpha, so edges between bright and dark colors and around transparent pixels
+/// don't come out darker than they should. Otherwise the sRGB values are mixed as they are.
+pub fn resize(
+    img: &RgbaImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    linear: bool,
+) -> RgbaImage {
+    // Nearest neighbor mixes nothing.
+    if !linear || filter == FilterType::Nearest {
+        return imageops::resize(img, width, height, filter);
+    }
+    let mut to_linear = [0f32; 256];
+    for (i, entry) in to_linear.iter_mut().enumerate() {
+        *entry = srgb_to_linear(i as f32 / 255.0);
+    }
+    let light = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0;
+        let alpha = a as f32 / 255.0;
+        Rgba([
+            to_linear[r as usize] * alpha,
+            to_linear[g as usize] * alpha,
+            to_linear[b as usize] * alpha,
+            alpha,
+        ])
+    });
+    let light: ImageBuffer<Rgba<f32>, Vec<f32>> = imageops::resize(&light, width, height, filter);
+
+    let mut out = RgbaImage::new(width, height);
+    for (pixel, &Rgba([r, g, b, a])) in out.pixels_mut().zip(light.pixels()) {
+        *pixel = if a > 0.0 {
+            from_linear_unit([r / a, g / a, b / a, a])
+        } else {
+            Rgba([0; 4])
+        };
+    }
+    out
+}
+
+/// Rotates `img` clockwise by `degrees`, which has to be 90, 180 or 270.
+pub fn rotate(img: &RgbaImage, degrees: u32) -> Option<RgbaImage> {
+    match degrees {
+        90 => Some(imageops::rotate90(img)),
+        180 => Some(imageops::rotate180(img)),
+        270 => Some(imageops::rotate270(img)),
+        _ => None,
+    }
+}
+
+/// Turns `img` the way EXIF `orientation` says it should be shown, so it looks right without it.
+pub fn orient<P: Pixel + 'static>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    orientation: u16,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    match orientation {
+        2 => imageops::flip_horizontal(img),
+        3 => imageops::rotate180(img),
+        4 => imageops::flip_vertical(img),
+        5 => imageops::flip_horizontal(&imageops::rotate90(img)),
+        6 => imageops::rotate90(img),
+        7 => imageops::flip_horizontal(&imageops::rotate270(img)),
+        8 => imageops::rotate270(img),
+        _ => img.clone(),
+    }
+}
+
+pub fn flip(img: &RgbaImage, horizontal: bool, vertical: bool) -> RgbaImage {
+    let mut flipped = img.clone();
+    if horizontal {
+        imageops::flip_horizontal_in_place(&mut flipped);
+    }
+    if vertical {
+        imageops::flip_vertical_in_place(&mut flipped);
+    }
+    flipped
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Effect {
+    Grayscale,
+    Invert,
+    Sepia,
+}
+
+impl Effect {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "grayscale" | "greyscale" => Some(Self::Grayscale),
+            "invert" => Some(Self::Invert),
+            "sepia" => Some(Self::Sepia),
+            _ => None,
+        }
+    }
+}
+
+/// Applies `effect` to the color channels of every pixel, leaving alpha untouched.
+pub fn apply_effect(img: &RgbaImage, effect: Effect) -> RgbaImage {
+    let mut out = img.clone();
+    for pixel in out.pixels_mut() {
+        let [r, g, b, _] = pixel.0;
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+
+        let [new_r, new_g, new_b] = match effect {
+            Effect::Grayscale => {
+                let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+                [luma, luma, luma]
+            }
+            Effect::Invert => [255.0 - r, 255.0 - g, 255.0 - b],
+            Effect::Sepia => [
+                0.393 * r + 0.769 * g + 0.189 * b,
+                0.349 * r + 0.686 * g + 0.168 * b,
+                0.272 * r + 0.534 * g + 0.131 * b,
+            ],
+        };
+
+        pixel.0[0] = new_r.round().clamp(0.0, 255.0) as u8;
+        pixel.0[1] = new_g.round().clamp(0.0, 255.0) as u8;
+        pixel.0[2] = new_b.round().clamp(0.0, 255.0) as u8;
+    }
+    out
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// How float colors brighter than white are brought down to what a screen can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Cuts everything off at white.
+    Clamp,
+    /// `c / (1 + c)`, which keeps every highlight but makes the image flatter.
+    Reinhard,
+    /// The filmic curve of the ACES reference, fitted by Krzysztof Narkowicz.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|tone_map| tone_map.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clamp => "clamp",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces",
+        }
+    }
+
+    pub fn all() -> [Self; 3] {
+        [Self::Clamp, Self::Reinhard, Self::Aces]
+    }
+
+    /// The operator after this one, going back to the first after the last.
+    pub fn next(self) -> Self {
+        let all = Self::all();
+        all[(all.iter().position(|&tone_map| tone_map == self).unwrap() + 1) % all.len()]
+    }
+
+    fn apply(self, v: f32) -> f32 {
+        match self {
+            Self::Clamp => v,
+            Self::Reinhard => v / (1.0 + v),
+            Self::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }
+    }
+}
+
+/// Turns straight RGBA floats in linear light into 8 bit sRGB for showing or saving, brightening
+/// them by `exposure` stops first.
+pub fn tone_map(rgba: &[f32], tone_map: ToneMap, exposure: f32) -> Vec<u8> {
+    let scale = exposure.exp2();
+    rgba.chunks_exact(4)
+        .flat_map(|pixel| {
+            let color =
+                |c: f32| linear_to_srgb(tone_map.apply((c * scale).max(0.0)).clamp(0.0, 1.0));
+            [color(pixel[0]), color(pixel[1]), color(pixel[2]), pixel[3]]
+        })
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect()
+}
+
+/// Converts RGBA pixels holding linear light to sRGB in place, so they show right on screen.
+pub fn pixels_to_srgb(rgba: &mut [u8]) {
+    let mut lut = [0u8; 256];
+    for (i, entry) in lut.iter_mut().enumerate() {
+        *entry = (linear_to_srgb(i as f32 / 255.0) * 255.0).round() as u8;
+    }
+    for pixel in rgba.chunks_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = lut[*channel as usize];
+        }
+    }
+}
+
+/// The color vision deficiencies the viewer can show images through, so assets can be checked for
+/// colors that can't be told apart without all three kinds of cones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorVision {
+    Normal,
+    /// No red cones.
+    Protanopia,
+    /// No green cones, the most common.
+    Deuteranopia,
+    /// No blue cones.
+    Tritanopia,
+}
+
+impl ColorVision {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal vision",
+            Self::Protanopia => "protanopia",
+            Self::Deuteranopia => "deuteranopia",
+            Self::Tritanopia => "tritanopia",
+        }
+    }
+
+    /// The simulation after this one, going back to normal vision after the last.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Normal => Self::Protanopia,
+            Self::Protanopia => Self::Deuteranopia,
+            Self::Deuteranopia => Self::Tritanopia,
+            Self::Tritanopia => Self::Normal,
+        }
+    }
+
+    /// The matrix of Machado, Oliveira and Fernandes (2009) at full severity, for linear RGB.
+    fn matrix(self) -> Option<[[f32; 3]; 3]> {
+        match self {
+            Self::Normal => None,
+            Self::Protanopia => Some([
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ]),
+            Self::Deuteranopia => Some([
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ]),
+            Self::Tritanopia => Some([
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ]),
+        }
+    }
+}
+
+/// Changes sRGB pixels in place to how they look with `vision`. Alpha is left alone.
+pub fn simulate_color_vision(rgba: &mut [u8], vision: ColorVision) {
+    let Some(matrix) = vision.matrix() else {
+        return;
+    };
+    let tables = LinearTables::new();
+    for pixel in rgba.chunks_exact_mut(4) {
+        let linear = [
+            tables.linear(pixel[0]),
+            tables.linear(pixel[1]),
+            tables.linear(pixel[2]),
+        ];
+        for (channel, row) in pixel[..3].iter_mut().zip(&matrix) {
+            *channel = tables.srgb(row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]);
+        }
+    }
+}
+
+/// How the viewer's sliders change the image on screen, leaving the file as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewAdjustments {
+    /// Stops to brighten the linear light by.
+    pub exposure: f32,
+    /// Values above 1 brighten the midtones, values below 1 darken them, like in `adjust`.
+    pub gamma: f32,
+    /// 0 for gray, 1 for the colors as they are and above for more vivid ones.
+    pub saturation: f32,
+}
+
+impl Default for ViewAdjustments {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            gamma: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+/// Applies `adjustments` to sRGB pixels in place. Exposure and saturation work on linear light.
+pub fn apply_view_adjustments(rgba: &mut [u8], adjustments: ViewAdjustments) {
+    if adjustments == ViewAdjustments::default() {
+        return;
+    }
+    let tables = LinearTables::new();
+    let scale = adjustments.exposure.exp2();
+    let mut gamma = [0u8; 256];
+    for (i, entry) in gamma.iter_mut().enumerate() {
+        *entry = ((i as f32 / 255.0).powf(1.0 / adjustments.gamma) * 255.0).round() as u8;
+    }
+
+    for pixel in rgba.chunks_exact_mut(4) {
+        let [r, g, b] = [0, 1, 2].map(|c| tables.linear(pixel[c]) * scale);
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        for (channel, v) in pixel[..3].iter_mut().zip([r, g, b]) {
+            *channel = gamma[tables.srgb(luma + (v - luma) * adjustments.saturation) as usize];
+        }
+    }
+}
+
+/// Lookup tables from 8 bit sRGB to linear light and back, the way back fine enough that dark
+/// colors don't band.
+struct LinearTables {
+    to_linear: [f32; 256],
+    to_srgb: Vec<u8>,
+}
+
+impl LinearTables {
+    const STEPS: usize = 4096;
+
+    fn new() -> Self {
+        let mut to_linear = [0f32; 256];
+        for (i, entry) in to_linear.iter_mut().enumerate() {
+            *entry = srgb_to_linear(i as f32 / 255.0);
+        }
+        let to_srgb = (0..=Self::STEPS)
+            .map(|i| (linear_to_srgb(i as f32 / Self::STEPS as f32) * 255.0).round() as u8)
+            .collect();
+        Self { to_linear, to_srgb }
+    }
+
+    fn linear(&self, srgb: u8) -> f32 {
+        self.to_linear[srgb as usize]
+    }
+
+    /// Clamped to what sRGB can hold.
+    fn srgb(&self, linear: f32) -> u8 {
+        self.to_srgb[(linear.clamp(0.0, 1.0) * Self::STEPS as f32).round() as usize]
+    }
+}
+
+/// Replaces the colors of RGBA pixels with their alpha as gray and makes them opaque, so stray
+/// alpha shows up.
+pub fn alpha_to_gray(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[pixel[3], pixel[3], pixel[3], 255]);
+    }
+}
+
+/// Paints fully transparent RGBA pixels opaque `color`, so they stand out against any image.
+pub fn highlight_transparent(rgba: &mut [u8], color: [u8; 4]) {
+    for pixel in rgba.chunks_exact_mut(4).filter(|pixel| pixel[3] == 0) {
+        pixel.copy_from_slice(&color);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Adjustments {
+    /// Offset added to every channel, in 0-255 units.
+    pub brightness: f32,
+    /// Factor by which channels are pushed away from (or pulled towards) the midpoint.
+    pub contrast: f32,
+    /// Values above 1 brighten the midtones, values below 1 darken them.
+    pub gamma: f32,
+    /// Apply brightness and contrast to linear light instead of the sRGB encoded values.
+    pub linear: bool,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            linear: false,
+        }
+    }
+}
+
+pub fn adjust(img: &RgbaImage, adjustments: Adjustments) -> RgbaImage {
+    let Adjustments {
+        brightness,
+        contrast,
+        gamma,
+        linear,
+    } = adjustments;
+
+    let mut lut = [0u8; 256];
+    for (i, entry) in lut.iter_mut().enumerate() {
+        let mut v = i as f32 / 255.0;
+        if linear {
+            v = srgb_to_linear(v);
+        }
+
+        v += brightness / 255.0;
+        v = (v - 0.5) * contrast + 0.5;
+        v = v.clamp(0.0, 1.0).powf(1.0 / gamma);
+
+        if linear {
+            v = linear_to_srgb(v);
+        }
+        *entry = (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+
+    let mut out = img.clone();
+    for pixel in out.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = lut[*channel as usize];
+        }
+    }
+    out
+}
+
+/// Stretches every color channel so its darkest value becomes 0 and its brightest 255, which
+/// takes out color casts and makes dull images use the whole range.
+pub fn auto_levels(img: &RgbaImage) -> RgbaImage {
+    let luts = histograms(img).map(|histogram| {
+        let mut lut = [0u8; 256];
+        let low = histogram.iter().position(|&count| count > 0);
+        let high = histogram.iter().rposition(|&count| count > 0);
+        for (i, entry) in lut.iter_mut().enumerate() {
+            *entry = match (low, high) {
+                (Some(low), Some(high)) if low < high => {
+                    ((i.clamp(low, high) - low) as f32 * 255.0 / (high - low) as f32).round() as u8
+                }
+                // A single value, there's nothing to stretch.
+                _ => i as u8,
+            };
+        }
+        lut
+    });
+    apply_luts(img, &luts)
+}
+
+/// Spreads the values of every color channel out so each one is about as common as the others,
+/// which brings out detail in images that are mostly dark, bright or gray.
+pub fn equalize(img: &RgbaImage) -> RgbaImage {
+    let luts = histograms(img).map(|histogram| {
+        let mut lut: [u8; 256] = std::array::from_fn(|i| i as u8);
+        let total: u64 = histogram.iter().sum();
+        // The darkest value becomes black instead of staying above it.
+        let darkest = histogram
+            .iter()
+            .copied()
+            .find(|&count| count > 0)
+            .unwrap_or(0);
+        if total == darkest {
+            // A single value, there's nothing to spread.
+            return lut;
+        }
+        let mut below = 0;
+        for (entry, &count) in lut.iter_mut().zip(&histogram) {
+            below += count;
+            *entry = (below.saturating_sub(darkest) as f64 * 255.0 / (total - darkest) as f64)
+                .round() as u8;
+        }
+        lut
+    });
+    apply_luts(img, &luts)
+}
+
+/// How often every value of the red, green and blue channels occurs. Fully transparent pixels
+/// are left out, their colors never show.
+fn histograms(img: &RgbaImage) -> [[u64; 256]; 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in img.pixels().filter(|pixel| pixel.0[3] > 0) {
+        for (histogram, &channel) in histograms.iter_mut().zip(&pixel.0[..3]) {
+            histogram[channel as usize] += 1;
+        }
+    }
+    histograms
+}
+
+fn apply_luts(img: &RgbaImage, luts: &[[u8; 256]; 3]) -> RgbaImage {
+    let mut out = img.clone();
+    for pixel in out.pixels_mut() {
+        for (channel, lut) in pixel.0[..3].iter_mut().zip(luts) {
+            *channel = lut[*channel as usize];
+        }
+    }
+    out
+}
+
+/// Gaussian blur with the given standard deviation in pixels.
+pub fn blur(img: &RgbaImage, sigma: f32) -> RgbaImage {
+    imageops::blur(img, sigma)
+}
+
+/// Unsharp mask: adds `amount` times the difference between the image and a blurred copy of
+/// it back onto the image.
+pub fn sharpen(img: &RgbaImage, amount: f32) -> RgbaImage {
+    let blurred = imageops::blur(img, 1.0);
+
+    let mut out = img.clone();
+    for (pixel, blurred) in out.pixels_mut().zip(blurred.pixels()) {
+        for (channel, &blurred) in pixel.0[..3].iter_mut().zip(&blurred.0[..3]) {
+            let v = *channel as f32;
+            *channel = (v + amount * (v - blurred as f32))
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+    out
+}
+
+fn blend(mode: BlendMode, backdrop: f32, source: f32) -> f32 {
+    match mode {
+        BlendMode::Over => source,
+        BlendMode::Multiply => backdrop * source,
+        BlendMode::Screen => backdrop + source - backdrop * source,
+    }
+}
+
+/// Composites `source` over `backdrop`, both straight (non-premultiplied) RGBA in 0..=1.
+fn blend_pixel(backdrop: [f32; 4], source: [f32; 4], mode: BlendMode) -> [f32; 4] {
+    let (alpha_b, alpha_s) = (backdrop[3], source[3]);
+    let alpha = alpha_s + alpha_b * (1.0 - alpha_s);
+    if alpha <= 0.0 {
+        return [0.0; 4];
+    }
+
+    let mut out = [0.0, 0.0, 0.0, alpha];
+    for c in 0..3 {
+        let (cb, cs) = (backdrop[c], source[c]);
+        let mixed = (1.0 - alpha_b) * cs + alpha_b * blend(mode, cb, cs);
+        out[c] = (alpha_s * mixed + alpha_b * cb * (1.0 - alpha_s)) / alpha;
+    }
+    out
+}
+
+fn to_unit(pixel: &Rgba<u8>) -> [f32; 4] {
+    pixel.0.map(|channel| channel as f32 / 255.0)
+}
+
+fn from_unit(pixel: [f32; 4]) -> Rgba<u8> {
+    Rgba(pixel.map(|channel| (channel * 255.0).round().clamp(0.0, 255.0) as u8))
+}
+
+/// Like `to_unit`, but with the colors in linear light.
+fn to_linear_unit(pixel: &Rgba<u8>) -> [f32; 4] {
+    let [r, g, b, a] = to_unit(pixel);
+    [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+}
+
+fn from_linear_unit([r, g, b, a]: [f32; 4]) -> Rgba<u8> {
+    let srgb = |channel: f32| linear_to_srgb(channel.clamp(0.0, 1.0));
+    from_unit([srgb(r), srgb(g), srgb(b), a])
+}
+
+/// Draws `overlay` onto a copy of `base` with its top left corner at `x`,`y`. Parts of the
+/// overlay outside of `base` are dropped. With `linear` the colors are blended in linear light,
+/// so half transparent edges don't darken what's below them.
+pub fn composite(
+    base: &RgbaImage,
+    overlay: &RgbaImage,
+    x: i64,
+    y: i64,
+    mode: BlendMode,
+    linear: bool,
+) -> RgbaImage {
+    let mut out = base.clone();
+    draw(&mut out, overlay, x, y, mode, 1.0, linear);
+    out
+}
+
+/// Composites the visible `layers`, bottom first and each with its pixels, onto a transparent
+/// `width`x`height` image.
+pub fn composite_layers(width: u32, height: u32, layers: &[(Layer, RgbaImage)]) -> RgbaImage {
+    let mut out = RgbaImage::new(width, height);
+    for (layer, pixels) in layers.iter().filter(|(layer, _)| layer.visible) {
+        let opacity = layer.opacity as f32 / 255.0;
+        // Blended in the colors as they are stored, the way the editors these files come from
+        // show them. Linear files already are in linear light.
+        draw(
+            &mut out,
+            pixels,
+            layer.x.into(),
+            layer.y.into(),
+            layer.blend,
+            opacity,
+            false,
+        );
+    }
+    out
+}
+
+/// The font `text` uses unless it is given another one: Hack, a monospace font clear at small
+/// sizes. Its license is in `data/Hack-Regular.txt`.
+pub const DEFAULT_FONT: &[u8] = include_bytes!("data/Hack-Regular.ttf");
+
+/// `text` drawn in `color` with `font` at `size` pixels per line, on a transparent image just
+/// big enough to hold it. Every `\n` starts a new line.
+pub fn text(font: &FontRef, text: &str, size: f32, color: Rgba<u8>) -> RgbaImage {
+    let font = font.as_scaled(PxScale::from(size));
+    let line_height = font.height() + font.line_gap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    // Where every glyph goes, on the baseline of its line.
+    let mut glyphs = Vec::new();
+    let mut width = 0.0f32;
+    for (row, line) in lines.iter().enumerate() {
+        let baseline = font.ascent() + row as f32 * line_height;
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in line.chars().filter(|c| !c.is_control()) {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(font.scale(), point(caret, baseline)));
+            caret += font.h_advance(id);
+            previous = Some(id);
+        }
+        width = width.max(caret);
+    }
+    let height = lines.len().saturating_sub(1) as f32 * line_height + font.height();
+
+    let (width, height) = (width.ceil() as u32, height.ceil() as u32);
+    let mut coverage = vec![0.0f32; width as usize * height as usize];
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            // Spaces have nothing to draw.
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, amount| {
+            let (x, y) = (
+                bounds.min.x as i64 + x as i64,
+                bounds.min.y as i64 + y as i64,
+            );
+            if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                let covered = &mut coverage[y as usize * width as usize + x as usize];
+                // Where glyphs overlap, like in italics, neither lightens the other.
+                *covered = covered.max(amount.min(1.0));
+            }
+        });
+    }
+    RgbaImage::from_fn(width, height, |x, y| {
+        let covered = coverage[y as usize * width as usize + x as usize];
+        let mut pixel = color;
+        pixel[3] = (color[3] as f32 * covered).round() as u8;
+        pixel
+    })
+}
+
+/// Draws the straight `lines`, each between two points, `width` pixels wide onto `img` in
+/// `color`, with smooth edges. Where lines meet or cross, like along a pen stroke, the pixels
+/// are drawn once instead of twice as opaque.
+pub fn stroke(img: &mut RgbaImage, lines: &[[(f32, f32); 2]], width: f32, color: Rgba<u8>) {
+    let radius = width / 2.0;
+    let reach = radius + 1.0;
+    let bounds = |line: &[(f32, f32); 2]| {
+        let [(ax, ay), (bx, by)] = *line;
+        let clamp = |value: f32, size: u32| value.clamp(0.0, size as f32) as u32;
+        (
+            clamp((ax.min(bx) - reach).floor(), img.width()),
+            clamp((ay.min(by) - reach).floor(), img.height()),
+            clamp((ax.max(bx) + reach).ceil(), img.width()),
+            clamp((ay.max(by) + reach).ceil(), img.height()),
+        )
+    };
+    let Some((left, top, right, bottom)) = lines
+        .iter()
+        .map(bounds)
+        .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+    else {
+        return;
+    };
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let mut overlay = RgbaImage::new(right - left, bottom - top);
+    for line in lines {
+        let [(ax, ay), (bx, by)] = *line;
+        let (dx, dy) = (bx - ax, by - ay);
+        let length = dx * dx + dy * dy;
+        let (x0, y0, x1, y1) = bounds(line);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // How far the center of the pixel is from the closest point of the line.
+                let (px, py) = (x as f32 + 0.5 - ax, y as f32 + 0.5 - ay);
+                let t = if length > 0.0 {
+                    ((px * dx + py * dy) / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let distance = (px - t * dx).hypot(py - t * dy);
+                let covered = (radius + 0.5 - distance).clamp(0.0, 1.0);
+                let alpha = (color[3] as f32 * covered).round() as u8;
+                let pixel = overlay.get_pixel_mut(x - left, y - top);
+                if alpha > pixel[3] {
+                    *pixel = Rgba([color[0], color[1], color[2], alpha]);
+                }
+            }
+        }
+    }
+    draw(
+        img,
+        &overlay,
+        left.into(),
+        top.into(),
+        BlendMode::Over,
+        1.0,
+        false,
+    );
+}
+
+/// Where `watermark` puts the mark: at one of the corners, edges or the center, or repeated all
+/// over the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+    Tiled,
+}
+
+impl Placement {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "top-left" => Some(Self::TopLeft),
+            "top" => Some(Self::Top),
+            "top-right" => Some(Self::TopRight),
+            "left" => Some(Self::Left),
+            "center" => Some(Self::Center),
+            "right" => Some(Self::Right),
+            "bottom-left" => Some(Self::BottomLeft),
+            "bottom" => Some(Self::Bottom),
+            "bottom-right" => Some(Self::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+/// Draws `mark` onto a copy of `base` where `placement` says, `margin` pixels away from the
+/// edges it is placed at, or with `margin` pixels between the copies when tiled. Its alpha is
+/// scaled by `opacity`, from 0 to 1, and it is blended in linear light with `linear` like
+/// `composite`.
+pub fn watermark(
+    base: &RgbaImage,
+    mark: &RgbaImage,
+    placement: Placement,
+    margin: u32,
+    opacity: f32,
+    linear: bool,
+) -> RgbaImage {
+    let mut out = base.clone();
+    let (width, height) = (base.width() as i64, base.height() as i64);
+    let (mark_width, mark_height) = (mark.width() as i64, mark.height() as i64);
+    let margin = margin as i64;
+
+    if placement == Placement::Tiled {
+        let (step_x, step_y) = ((mark_width + margin).max(1), (mark_height + margin).max(1));
+        for y in (0..height).step_by(step_y as usize) {
+            for x in (0..width).step_by(step_x as usize) {
+                draw(&mut out, mark, x, y, BlendMode::Over, opacity, linear);
+            }
+        }
+        return out;
+    }
+
+    // 0 for the left or top, 1 for the middle and 2 for the right or bottom.
+    let (column, row) = match placement {
+        Placement::TopLeft => (0, 0),
+        Placement::Top => (1, 0),
+        Placement::TopRight => (2, 0),
+        Placement::Left => (0, 1),
+        Placement::Center | Placement::Tiled => (1, 1),
+        Placement::Right => (2, 1),
+        Placement::BottomLeft => (0, 2),
+        Placement::Bottom => (1, 2),
+        Placement::BottomRight => (2, 2),
+    };
+    let offset = |side: u8, space: i64, size: i64| match side {
+        0 => margin,
+        1 => (space - size) / 2,
+        _ => space - size - margin,
+    };
+    let x = offset(column, width, mark_width);
+    let y = offset(row, height, mark_height);
+    draw(&mut out, mark, x, y, BlendMode::Over, opacity, linear);
+    out
+}
+
+/// Blends `overlay` into `base` at `x`,`y`, with its alpha scaled by `opacity`, in linear light
+/// with `linear`.
+fn draw(
+    base: &mut RgbaImage,
+    overlay: &RgbaImage,
+    x: i64,
+    y: i64,
+    mode: BlendMode,
+    opacity: f32,
+    linear: bool,
+) {
+    let to_unit = if linear { to_linear_unit } else { to_unit };
+    let from_unit = if linear { from_linear_unit } else { from_unit };
+    for (ox, oy, source) in overlay.enumerate_pixels() {
+        let (bx, by) = (x + ox as i64, y + oy as i64);
+        if bx < 0 || by < 0 || bx >= base.width() as i64 || by >= base.height() as i64 {
+            continue;
+        }
+
+        let mut source = to_unit(source);
+        source[3] *= opacity;
+        let backdrop = base.get_pixel_mut(bx as u32, by as u32);
+        *backdrop = from_unit(blend_pixel(to_unit(backdrop), source, mode));
+    }
+}
+
+/// The dimensions of `montage`'s result, or `None` if they don't fit in a `u32`.
+pub fn montage_size(images: &[RgbaImage], columns: u32, gap: u32) -> Option<(u32, u32)> {
+    let columns = columns.clamp(1, images.len().max(1) as u32);
+    let rows = (images.len() as u32).div_ceil(columns);
+    let cell_width = images.iter().map(|img| img.width()).max().unwrap_or(0);
+    let cell_height = images.iter().map(|img| img.height()).max().unwrap_or(0);
+
+    let span = |cells: u32, cell: u32| {
+        cells
+            .checked_mul(cell)?
+            .checked_add(cells.saturating_sub(1).checked_mul(gap)?)
+    };
+    Some((span(columns, cell_width)?, span(rows, cell_height)?))
+}
+
+/// Tiles `images` into a grid with `columns` columns and `gap` pixels between the cells.
+/// Every cell is as large as the largest image, smaller images are centered in their cell and
+/// the remaining space is filled with `background`. Returns `None` if the result would be larger
+/// than `u32::MAX` in either direction.
+pub fn montage(
+    images: &[RgbaImage],
+    columns: u32,
+    gap: u32,
+    background: Rgba<u8>,
+) -> Option<RgbaImage> {
+    let (width, height) = montage_size(images, columns, gap)?;
+    let columns = columns.clamp(1, images.len().max(1) as u32);
+    let cell_width = images.iter().map(|img| img.width()).max().unwrap_or(0);
+    let cell_height = images.iter().map(|img| img.height()).max().unwrap_or(0);
+    let mut out = RgbaImage::from_pixel(width, height, background);
+
+    for (i, img) in images.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let x = column * (cell_width + gap) + (cell_width - img.width()) / 2;
+        let y = row * (cell_height + gap) + (cell_height - img.height()) / 2;
+        imageops::overlay(&mut out, img, x as i64, y as i64);
+    }
+    Some(out)
+}
+
+/// Cuts a sprite sheet into `tile_width`x`tile_height` tiles, row by row. `margin` pixels around
+/// the edge of the sheet and `spacing` pixels between tiles are left out, as are partial tiles at
+/// the right and bottom.
+pub fn slice(
+    img: &RgbaImage,
+    tile_width: u32,
+    tile_height: u32,
+    margin: u32,
+    spacing: u32,
+) -> Vec<RgbaImage> {
+    let count = |size: u32, tile: u32| {
+        let inner = size.saturating_sub(margin.saturating_mul(2));
+        let stride = tile.saturating_add(spacing);
+        if tile == 0 || inner < tile {
+            0
+        } else {
+            (inner - tile) / stride + 1
+        }
+    };
+    let columns = count(img.width(), tile_width);
+    let rows = count(img.height(), tile_height);
+
+    let mut tiles = Vec::with_capacity(columns as usize * rows as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let x = margin + column * (tile_width + spacing);
+            let y = margin + row * (tile_height + spacing);
+            tiles.push(imageops::crop_imm(img, x, y, tile_width, tile_height).to_image());
+        }
+    }
+    tiles
+}
+
+/// Where `pack` put everything.
+pub struct Packing {
+    pub width: u32,
+    pub height: u32,
+    /// The top left corner of every rectangle, in the order they were given.
+    pub positions: Vec<(u32, u32)>,
+}
+
+/// Places rectangles of the given sizes on shelves no wider than `max_width`: tallest first, left
+/// to right, starting a new shelf below when one is full. Returns `None` if a rectangle is wider
+/// than `max_width` or the atlas would be taller than `u32::MAX`.
+pub fn pack(sizes: &[(u32, u32)], max_width: u32) -> Option<Packing> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (Reverse(sizes[i].1), Reverse(sizes[i].0)));
+
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height, mut width) = (0u32, 0u32, 0u32, 0u32);
+    for i in order {
+        let (w, h) = sizes[i];
+        if w > max_width {
+            return None;
+        }
+        if x.saturating_add(w) > max_width {
+            y = y.checked_add(shelf_height)?;
+            x = 0;
+            shelf_height = 0;
+        }
+        positions[i] = (x, y);
+        x += w;
+        width = width.max(x);
+        shelf_height = shelf_height.max(h);
+    }
+    Some(Packing {
+        width,
+        height: y.checked_add(shelf_height)?,
+        positions,
+    })
+}
+
+/// Copies `images` into one atlas where `pack` placed them, transparent everywhere else.
+pub fn atlas(images: &[RgbaImage], packing: &Packing) -> RgbaImage {
+    let mut out = RgbaImage::new(packing.width, packing.height);
+    for (img, &(x, y)) in images.iter().zip(&packing.positions) {
+        imageops::replace(&mut out, img, x as i64, y as i64);
+    }
+    out
+}
+
+/// Reduces `img` to at most `colors` colors with NeuQuant, optionally spreading the error with
+/// Floyd-Steinberg dithering. Returns the palette and one palette index per pixel.
+pub fn quantize(img: &RgbaImage, colors: usize, dither: bool) -> (Vec<[u8; 4]>, Vec<u8>) {
+    let quant = NeuQuant::new(10, colors, img.as_raw());
+
+    let indices = if dither {
+        let mut dithered = img.clone();
+        imageops::dither(&mut dithered, &quant);
+        imageops::index_colors(&dithered, &quant).into_raw()
+    } else {
+        img.pixels()
+            .map(|pixel| quant.index_of(&pixel.0) as u8)
+            .collect()
+    };
+
+    let palette = quant
+        .color_map_rgba()
+        .chunks(4)
+        .map(|color| [color[0], color[1], color[2], color[3]])
+        .collect();
+    (palette, indices)
+}
+
+/// The `count` colors most of `img` is made of, found with median cut: the box around all
+/// colors is split at the median of its widest channel until there are `count` boxes, each
+/// giving its average color. Returns the colors with how many pixels they stand for, the most
+/// common first. Transparent pixels have no color to speak of and are left out.
+pub fn dominant_colors(img: &RgbaImage, count: usize) -> Vec<([u8; 3], usize)> {
+    let mut pixels: Vec<[u8; 3]> = img
+        .pixels()
+        .filter(|pixel| pixel[3] >= 128)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    /// The channel `colors` spread the most along, and how far.
+    fn widest(colors: &[[u8; 3]]) -> (usize, u8) {
+        (0..3)
+            .map(|channel| {
+                let (min, max) = colors.iter().fold((255, 0), |(min, max), color| {
+                    (color[channel].min(min), color[channel].max(max))
+                });
+                (channel, max - min)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap()
+    }
+
+    // Split points into `pixels`, each box its own range of it.
+    let mut boxes: Vec<Range<usize>> = Vec::with_capacity(count);
+    boxes.push(0..pixels.len());
+    while boxes.len() < count {
+        // The spread counts more than the pixels, or big areas of nearly one color would be
+        // split into shades of it while small but distinct colors vanish in an average.
+        let Some((index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, range)| {
+                let (channel, spread) = widest(&pixels[range.clone()]);
+                (
+                    index,
+                    channel,
+                    (range.len() as f64).sqrt() as u64 * (spread as u64).pow(2),
+                )
+            })
+            .filter(|&(_, _, score)| score > 0)
+            .max_by_key(|&(_, _, score)| score)
+            .map(|(index, channel, _)| (index, channel))
+        else {
+            // Every box is a single color already.
+            break;
+        };
+        let range = boxes.swap_remove(index);
+        let colors = &mut pixels[range.clone()];
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // Not right at the median if it falls among equal values, or both halves would share a
+        // color.
+        let median = colors[colors.len() / 2][channel];
+        let split = match colors.partition_point(|color| color[channel] < median) {
+            0 => colors.partition_point(|color| color[channel] <= median),
+            split => split,
+        };
+        boxes.push(range.start..range.start + split);
+        boxes.push(range.start + split..range.end);
+    }
+
+    let mut colors: Vec<([u8; 3], usize)> = boxes
+        .into_iter()
+        .map(|range| {
+            let colors = &pixels[range];
+            let mut sums = [0u64; 3];
+            for color in colors {
+                for (sum, &value) in sums.iter_mut().zip(color) {
+                    *sum += value as u64;
+                }
+            }
+            let len = colors.len() as u64;
+            (sums.map(|sum| ((sum + len / 2) / len) as u8), colors.len())
+        })
+        .collect();
+    colors.sort_by_key(|&(color, pixels)| (Reverse(pixels), color));
+    colors
+}
+
+/// A row of `size` pixel squares, one per color in `colors`.
+pub fn swatch(colors: &[[u8; 3]], size: u32) -> RgbaImage {
+    RgbaImage::from_fn(size * colors.len() as u32, size, |x, _| {
+        let [r, g, b] = colors[(x / size) as usize];
+        Rgba([r, g, b, 255])
+    })
+}
+
+/// Composites `img` over an opaque `background`, so the result has no transparency left, in
+/// linear light with `linear` like `composite`.
+pub fn flatten(img: &RgbaImage, background: Rgba<u8>, linear: bool) -> RgbaImage {
+    let background = Rgba([background[0], background[1], background[2], 255]);
+    let canvas = RgbaImage::from_pixel(img.width(), img.height(), background);
+    composite(&canvas, img, 0, 0, BlendMode::Over, linear)
+}
+
+/// Straight RGBA floats, 0 to 1 or brighter for HDR, with `channels` per pixel instead for
+/// `convert-depth`: 1 is the Rec. 709 luma and 3 is RGB, both composited over an opaque
+/// `background` first, 4 keeps the alpha. Luma and compositing are worked out in linear light.
+/// `from_linear` and `to_linear` say whether the colors are linear light or sRGB before and
+/// after.
+pub fn convert_channels(
+    rgba: &[f32],
+    channels: usize,
+    background: Rgba<u8>,
+    from_linear: bool,
+    to_linear: bool,
+) -> Vec<f32> {
+    if channels == 4 && from_linear == to_linear {
+        return rgba.to_vec();
+    }
+    let linear = |c: f32| if from_linear { c } else { srgb_to_linear(c) };
+    let encode = |c: f32| if to_linear { c } else { linear_to_srgb(c) };
+    let background = background.0.map(|c| srgb_to_linear(c as f32 / 255.0));
+    let mut out = Vec::with_capacity(rgba.len() / 4 * channels);
+    for pixel in rgba.chunks_exact(4) {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        let color: [f32; 3] = std::array::from_fn(|i| {
+            let c = linear(pixel[i]);
+            if channels == 4 {
+                c
+            } else {
+                c * alpha + background[i] * (1.0 - alpha)
+            }
+        });
+        match channels {
+            1 => out.push(encode(
+                0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2],
+            )),
+            _ => out.extend(color.map(encode)),
+        }
+        if channels == 4 {
+            out.push(pixel[3]);
+        }
+    }
+    out
+}
+
+/// Floats from 0 to 1 as bytes, `channels` to a pixel and `width` pixels to a row. With `dither`
+/// the rounding error of every channel is spread over the pixels right of and below it with
+/// Floyd-Steinberg, so gradients that had more than 8 bits don't turn into bands.
+pub fn dither_to_u8(values: &[f32], width: usize, channels: usize, dither: bool) -> Vec<u8> {
+    let to_u8 = |c: f32| (c * 255.0).round().clamp(0.0, 255.0);
+    if !dither {
+        return values.iter().map(|&c| to_u8(c) as u8).collect();
+    }
+    let mut values: Vec<f32> = values.to_vec();
+    let row = width * channels;
+    let mut out = Vec::with_capacity(values.len());
+    for i in 0..values.len() {
+        // Colors brighter than white only spread the part a byte can hold.
+        let value = (values[i] * 255.0).clamp(0.0, 255.0);
+        let rounded = to_u8(values[i]);
+        out.push(rounded as u8);
+        let error = (value - rounded) / 255.0;
+        let x = i % row / channels;
+        if x + 1 < width {
+            values[i + channels] += error * 7.0 / 16.0;
+        }
+        if i + row < values.len() {
+            if x > 0 {
+                values[i + row - channels] += error * 3.0 / 16.0;
+            }
+            values[i + row] += error * 5.0 / 16.0;
+            if x + 1 < width {
+                values[i + row + channels] += error / 16.0;
+            }
+        }
+    }
+    out
+}
+
+/// What `generate` draws.
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    Checker,
+    Gradient,
+    Noise,
+    Solid,
+}
+
+impl Pattern {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "checker" | "checkerboard" => Some(Self::Checker),
+            "gradient" => Some(Self::Gradient),
+            "noise" => Some(Self::Noise),
+            "solid" => Some(Self::Solid),
+            _ => None,
+        }
+    }
+}
+
+/// A synthetic image for benchmarks, fuzzing corpora and checking viewers. Checkers alternate
+/// `background` and `color` in squares of `cell` pixels, gradients go from `background` on the
+/// left to `color` on the right and solid images are all `color`. Noise is opaque random colors
+/// from `seed`, so the same seed always gives the same image.
+pub fn generate(
+    width: u32,
+    height: u32,
+    pattern: Pattern,
+    background: Rgba<u8>,
+    color: Rgba<u8>,
+    cell: u32,
+    seed: u64,
+) -> RgbaImage {
+    match pattern {
+        Pattern::Checker => {
+            let cell = cell.max(1);
+            RgbaImage::from_fn(width, height, |x, y| {
+                if (x / cell + y / cell).is_multiple_of(2) {
+                    background
+                } else {
+                    color
+                }
+            })
+        }
+        Pattern::Gradient => {
+            let last = width.saturating_sub(1).max(1) as f32;
+            RgbaImage::from_fn(width, height, |x, _| {
+                let t = x as f32 / last;
+                Rgba(std::array::from_fn(|c| {
+                    (background[c] as f32 + (color[c] as f32 - background[c] as f32) * t).round()
+                        as u8
+                }))
+            })
+        }
+        Pattern::Noise => {
+            let mut img = RgbaImage::new(width, height);
+            StdRng::seed_from_u64(seed).fill_bytes(&mut img);
+            for pixel in img.pixels_mut() {
+                pixel[3] = 255;
+            }
+            img
+        }
+        Pattern::Solid => RgbaImage::from_pixel(width, height, color),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Reference values worked out by hand: halfway between black and white is 50% linear light,
+    //! 188 in sRGB, not the 128 that mixing the sRGB values gives.
+
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn pair(left: Rgba<u8>, right: Rgba<u8>) -> RgbaImage {
+        RgbaImage::from_fn(2, 1, |x, _| if x == 0 { left } else { right })
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        let (width, height) = (64, 64);
+        let values = vec![100.3 / 255.0; width * height];
+        let rounded = dither_to_u8(&values, width, 1, false);
+        assert!(rounded.iter().all(|&c| c == 100));
+        let dithered = dither_to_u8(&values, width, 1, true);
+        let average = dithered.iter().map(|&c| c as f32).sum::<f32>() / dithered.len() as f32;
+        assert!((average - 100.3).abs() < 0.01, "{}", average);
+    }
+
+    #[test]
+    fn gray_is_the_luma_over_the_background() {
+        // Half transparent black over white is 50% linear light.
+        let gray = convert_channels(&[0.0, 0.0, 0.0, 0.5], 1, WHITE, false, false);
+        assert_eq!(dither_to_u8(&gray, 1, 1, false), [188]);
+        let green = convert_channels(&[0.0, 1.0, 0.0, 1.0], 1, WHITE, true, true);
+        assert!((green[0] - 0.7152).abs() < 1e-6);
+    }
+
+    #[test]
+    fn resizing_mixes_linear_light() {
+        let img = pair(BLACK, WHITE);
+        let linear = resize(&img, 1, 1, FilterType::Triangle, true);
+        assert_eq!(linear.get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+        let srgb = resize(&img, 1, 1, FilterType::Triangle, false);
+        assert_eq!(srgb.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn resizing_keeps_transparent_pixels_out_of_the_colors() {
+        let img = pair(Rgba([255, 0, 0, 255]), Rgba([0, 0, 0, 0]));
+        let linear = resize(&img, 1, 1, FilterType::Triangle, true);
+        assert_eq!(linear.get_pixel(0, 0), &Rgba([255, 0, 0, 128]));
+        // The dark fringe the linear path is there to avoid.
+        let srgb = resize(&img, 1, 1, FilterType::Triangle, false);
+        assert_eq!(srgb.get_pixel(0, 0), &Rgba([128, 0, 0, 128]));
+    }
+
+    #[test]
+    fn resizing_keeps_every_gray_level() {
+        let img = RgbaImage::from_fn(256, 1, |x, _| Rgba([x as u8, x as u8, x as u8, 255]));
+        // Stretched only vertically, so every pixel is mixed with copies of itself.
+        let tall = resize(&img, 256, 3, FilterType::Triangle, true);
+        for row in tall.rows() {
+            assert!(row.eq(img.pixels()));
+        }
+    }
+
+    #[test]
+    fn text_is_drawn_in_its_color() {
+        let font = FontRef::try_from_slice(DEFAULT_FONT).unwrap();
+        let red = Rgba([255, 0, 0, 255]);
+        let line = text(&font, "v1.2", 18.0, red);
+        let two_lines = text(&font, "v1.2\nv1.2", 18.0, red);
+        assert_eq!(two_lines.width(), line.width());
+        assert!(two_lines.height() > line.height() * 2 - 2);
+        assert!(line.pixels().any(|pixel| pixel[3] == 255));
+        assert!(line.pixels().all(|pixel| pixel.0[..3] == [255, 0, 0]));
+        // Hack is monospace, each glyph is just as wide.
+        assert_eq!(
+            text(&font, "ab", 18.0, red).width(),
+            text(&font, "..", 18.0, red).width()
+        );
+    }
+
+    #[test]
+    fn strokes_are_drawn_once_where_lines_meet() {
+        let mut img = RgbaImage::from_pixel(10, 10, BLACK);
+        let line = [(2.0, 5.0), (8.0, 5.0)];
+        stroke(&mut img, &[line, line], 2.0, Rgba([255, 255, 255, 128]));
+        assert_eq!(img.get_pixel(5, 4), &Rgba([128, 128, 128, 255]));
+        assert_eq!(img.get_pixel(5, 5), &Rgba([128, 128, 128, 255]));
+        assert_eq!(img.get_pixel(5, 3), &BLACK);
+        assert_eq!(img.get_pixel(5, 6), &BLACK);
+        // The ends are round, half a width past the points.
+        assert_eq!(img.get_pixel(0, 5), &BLACK);
+        assert_ne!(img.get_pixel(1, 5), &BLACK);
+    }
+
+    #[test]
+    fn watermarks_keep_their_margin() {
+        let base = RgbaImage::from_pixel(10, 8, BLACK);
+        let mark = RgbaImage::from_pixel(2, 2, WHITE);
+        let out = watermark(&base, &mark, Placement::BottomRight, 1, 0.5, false);
+        let marked: Vec<(u32, u32)> = out
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| **pixel != BLACK)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(marked, [(7, 5), (8, 5), (7, 6), (8, 6)]);
+        assert_eq!(out.get_pixel(7, 5), &Rgba([128, 128, 128, 255]));
+
+        let tiled = watermark(&base, &mark, Placement::Tiled, 2, 1.0, false);
+        let row: Vec<bool> = (0..10).map(|x| tiled.get_pixel(x, 0) == &WHITE).collect();
+        assert_eq!(
+            row,
+            [true, true, false, false, true, true, false, false, true, true]
+        );
+        assert_eq!(tiled.get_pixel(0, 2), &BLACK);
+        assert_eq!(tiled.get_pixel(0, 4), &WHITE);
+    }
+
+    #[test]
+    fn dominant_colors_come_most_common_first() {
+        let img = RgbaImage::from_fn(10, 10, |x, y| match (x, y) {
+            (0..=5, _) => Rgba([200, 10, 10, 255]),
+            (_, 0..=4) => Rgba([10, 10, 200, 255]),
+            (_, 5..=8) => Rgba([10, 200, 10, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        assert_eq!(
+            dominant_colors(&img, 8),
+            vec![
+                ([200, 10, 10], 60),
+                ([10, 10, 200], 20),
+                ([10, 200, 10], 16)
+            ]
+        );
+        assert_eq!(dominant_colors(&img, 1), vec![([129, 42, 50], 96)]);
+    }
+
+    #[test]
+    fn compositing_blends_linear_light() {
+        let base = RgbaImage::from_pixel(1, 1, BLACK);
+        let overlay = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128]));
+        let linear = composite(&base, &overlay, 0, 0, BlendMode::Over, true);
+        assert_eq!(linear.get_pixel(0, 0), &Rgba([188, 188, 188, 255]));
+        let srgb = composite(&base, &overlay, 0, 0, BlendMode::Over, false);
+        assert_eq!(srgb.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn compositing_opaque_pixels_is_exact_either_way() {
+        let base = pair(BLACK, WHITE);
+        let overlay = RgbaImage::from_pixel(1, 1, Rgba([10, 120, 240, 255]));
+        for linear in [true, false] {
+            let out = composite(&base, &overlay, 1, 0, BlendMode::Over, linear);
+            assert_eq!(out, pair(BLACK, Rgba([10, 120, 240, 255])));
+        }
+    }
+
+    #[test]
+    fn thumbnails_fit_the_box_and_keep_the_aspect_ratio() {
+        let img = RgbaImage::new(400, 100);
+        assert_eq!(thumbnail(&img, 64).dimensions(), (64, 16));
+        assert_eq!(
+            thumbnail(&RgbaImage::new(1000, 1), 10).dimensions(),
+            (10, 1)
+        );
+        // Smaller images aren't scaled up.
+        assert_eq!(thumbnail(&img, 1000).dimensions(), (400, 100));
+    }
+
+    /// A 3x2 image whose pixels all differ, so moving any of them shows.
+    fn numbered() -> RgbaImage {
+        RgbaImage::from_fn(3, 2, |x, y| Rgba([(y * 3 + x) as u8 * 40, 0, 0, 255]))
+    }
+
+    fn reds(img: &RgbaImage) -> Vec<u8> {
+        img.pixels().map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn rotating_and_flipping_move_every_pixel() {
+        let img = numbered();
+        let rotated = rotate(&img, 90).unwrap();
+        assert_eq!(rotated.dimensions(), (2, 3));
+        assert_eq!(reds(&rotated), [120, 0, 160, 40, 200, 80]);
+        assert_eq!(
+            reds(&rotate(&img, 180).unwrap()),
+            [200, 160, 120, 80, 40, 0]
+        );
+        assert_eq!(rotate(&rotate(&img, 90).unwrap(), 270).unwrap(), img);
+        assert_eq!(rotate(&img, 45), None);
+
+        assert_eq!(reds(&flip(&img, true, false)), [80, 40, 0, 200, 160, 120]);
+        assert_eq!(reds(&flip(&img, false, true)), [120, 160, 200, 0, 40, 80]);
+        assert_eq!(flip(&img, true, true), rotate(&img, 180).unwrap());
+    }
+
+    #[test]
+    fn effects_change_the_colors_but_not_the_alpha() {
+        let img = RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 77]));
+        let pixel = |effect| apply_effect(&img, effect).get_pixel(0, 0).0;
+        assert_eq!(pixel(Effect::Invert), [55, 155, 205, 77]);
+        assert_eq!(pixel(Effect::Grayscale), [124, 124, 124, 77]);
+        assert_eq!(pixel(Effect::Sepia), [165, 147, 114, 77]);
+        assert!(matches!(
+            Effect::parse("greyscale"),
+            Some(Effect::Grayscale)
+        ));
+        assert!(Effect::parse("negative").is_none());
+    }
+
+    #[test]
+    fn adjustments_move_the_colors_the_right_way() {
+        let img = RgbaImage::from_fn(3, 1, |x, _| Rgba([[64, 128, 192][x as usize], 0, 255, 9]));
+        assert_eq!(adjust(&img, Adjustments::default()), img);
+
+        let with = |adjustments| reds(&adjust(&img, adjustments));
+        let brighter = with(Adjustments {
+            brightness: 20.0,
+            ..Adjustments::default()
+        });
+        assert_eq!(brighter, [84, 148, 212]);
+        let contrast = with(Adjustments {
+            contrast: 2.0,
+            ..Adjustments::default()
+        });
+        assert_eq!(contrast, [1, 129, 255]);
+        let gamma = with(Adjustments {
+            gamma: 2.0,
+            ..Adjustments::default()
+        });
+        assert!(gamma
+            .iter()
+            .zip([64, 128, 192])
+            .all(|(&new, old)| new > old));
+        // Alpha is left alone, and so are channels that can't go further.
+        let adjusted = adjust(
+            &img,
+            Adjustments {
+                brightness: 50.0,
+                ..Adjustments::default()
+            },
+        );
+        assert!(adjusted
+            .pixels()
+            .all(|pixel| pixel[2] == 255 && pixel[3] == 9));
+    }
+
+    #[test]
+    fn blurring_spreads_edges_and_sharpening_steepens_them() {
+        let edge = RgbaImage::from_fn(8, 1, |x, _| if x < 4 { BLACK } else { WHITE });
+        let blurred = reds(&blur(&edge, 1.0));
+        assert!(blurred[3] > 0 && blurred[4] < 255, "{:?}", blurred);
+        assert!(blurred.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let soft = RgbaImage::from_fn(8, 1, |x, _| {
+            Rgba([[60, 60, 60, 100, 150, 190, 190, 190][x as usize], 0, 0, 255])
+        });
+        let sharpened = reds(&sharpen(&soft, 1.0));
+        assert!(sharpened[2] < 60 && sharpened[5] > 190, "{:?}", sharpened);
+
+        let flat = RgbaImage::from_pixel(5, 5, Rgba([90, 90, 90, 255]));
+        assert_eq!(blur(&flat, 2.0), flat);
+        assert_eq!(sharpen(&flat, 2.0), flat);
+    }
+
+    #[test]
+    fn montages_center_images_in_equal_cells() {
+        let images = [
+            RgbaImage::from_pixel(2, 2, WHITE),
+            RgbaImage::from_pixel(4, 2, WHITE),
+            RgbaImage::from_pixel(2, 1, WHITE),
+        ];
+        assert_eq!(montage_size(&images, 2, 1), Some((9, 5)));
+        assert_eq!(montage_size(&images, 10, 0), Some((12, 2)));
+
+        let grid = montage(&images, 2, 1, BLACK).unwrap();
+        let row = |y| reds(&imageops::crop_imm(&grid, 0, y, 9, 1).to_image());
+        assert_eq!(row(0), [0, 255, 255, 0, 0, 255, 255, 255, 255]);
+        // The 2x1 image sits in the middle of its 4x2 cell, below the gap.
+        assert_eq!(row(2), [0; 9]);
+        assert_eq!(row(3), [0, 255, 255, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(row(4), [0; 9]);
+    }
+
+    #[test]
+    fn quantizing_keeps_few_colors_exact() {
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 255]];
+        let img = RgbaImage::from_fn(120, 120, |x, _| Rgba(colors[x as usize / 40]));
+        for dither in [false, true] {
+            let (palette, indices) = quantize(&img, 16, dither);
+            assert!(palette.len() <= 16);
+            assert_eq!(indices.len(), 120 * 120);
+            for (pixel, &index) in img.pixels().zip(&indices) {
+                let color = palette[index as usize];
+                let distance: i32 = (0..3)
+                    .map(|c| (color[c] as i32 - pixel[c] as i32).abs())
+                    .sum();
+                assert!(distance <= 12, "{:?} for {:?}", color, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn flattening_leaves_no_transparency() {
+        let img = RgbaImage::from_fn(3, 1, |x, _| Rgba([0, 0, 0, [0, 128, 255][x as usize]]));
+        let background = Rgba([255, 255, 255, 0]);
+        let flat = flatten(&img, background, false);
+        assert!(flat.pixels().all(|pixel| pixel[3] == 255));
+        assert_eq!(reds(&flat), [255, 127, 0]);
+        // Half transparent black over white is 50% linear light.
+        assert_eq!(reds(&flatten(&img, background, true))[1], 187);
+    }
+
+    #[test]
+    fn color_vision_keeps_grays_and_merges_confused_colors() {
+        let mut vision = ColorVision::Normal;
+        for _ in 0..4 {
+            let mut grays: Vec<u8> = [0, 60, 128, 200, 255]
+                .iter()
+                .flat_map(|&v| [v, v, v, 77])
+                .collect();
+            let original = grays.clone();
+            simulate_color_vision(&mut grays, vision);
+            for (got, wanted) in grays.iter().zip(&original) {
+                assert!(
+                    got.abs_diff(*wanted) <= 1,
+                    "{} turned {:?} into {:?}",
+                    vision.name(),
+                    original,
+                    grays
+                );
+            }
+            vision = vision.next();
+        }
+        assert_eq!(vision, ColorVision::Normal);
+
+        let seen = |vision, rgb: [u8; 3]| {
+            let mut pixel = [rgb[0], rgb[1], rgb[2], 255];
+            simulate_color_vision(&mut pixel, vision);
+            pixel
+        };
+        let (red, green, blue) = ([255, 0, 0], [0, 255, 0], [0, 0, 255]);
+        assert_eq!(seen(ColorVision::Normal, red), [255, 0, 0, 255]);
+        // Without red or green cones both become yellowish, with the least blue, and without blue
+        // ones green and blue become cyanish, with the least red.
+        for vision in [ColorVision::Protanopia, ColorVision::Deuteranopia] {
+            for [r, g, b, _] in [seen(vision, red), seen(vision, green)] {
+                assert!(b < r.min(g), "{} {:?}", vision.name(), [r, g, b]);
+            }
+        }
+        for [r, g, b, _] in [
+            seen(ColorVision::Tritanopia, green),
+            seen(ColorVision::Tritanopia, blue),
+        ] {
+            assert!(r < g.min(b), "{:?}", [r, g, b]);
+        }
+    }
+}
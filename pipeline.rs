@@ -0,0 +1,166 @@
+//! `compile --async`, for batches of tens of thousands of files, possibly on network
+//! filesystems. Reading, encoding and writing each get their own threads and run at the same
+//! time, so a slow disk or network share doesn't leave the CPUs idle and the other way around.
+//! The queues between the stages only hold a few files each, so memory stays bounded no matter
+//! how many files there are.
+
+use std::{
+    ffi::OsString,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use crate::error::BruhError;
+
+/// The index of a path and what came of it, or the exit code of its error, which was already
+/// reported. Not every error can be sent to another thread.
+pub type Outcome<T> = (usize, Result<T, i32>);
+
+/// Runs every path through `read`, `encode` and `write`, with `jobs` threads per stage and at
+/// most `jobs` files waiting between two stages. The outcomes come in the order they finished.
+pub fn run<R: Send, E: Send, T: Send>(
+    paths: &[OsString],
+    jobs: usize,
+    read: impl Fn(&Path) -> Result<R, BruhError> + Sync,
+    encode: impl Fn(&Path, R) -> Result<E, BruhError> + Sync,
+    write: impl Fn(&Path, E) -> Result<T, BruhError> + Sync,
+) -> Vec<Outcome<T>> {
+    let jobs = jobs.max(1);
+    let next = AtomicUsize::new(0);
+    let (read_sender, read_queue) = mpsc::sync_channel(jobs);
+    let (encoded_sender, encoded_queue) = mpsc::sync_channel(jobs);
+    let (done, outcomes) = mpsc::channel();
+    let (read_queue, encoded_queue) = (Mutex::new(read_queue), Mutex::new(encoded_queue));
+    let (next, read, encode, write) = (&next, &read, &encode, &write);
+    let (read_queue, encoded_queue) = (&read_queue, &encoded_queue);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let (sender, finished) = (read_sender.clone(), done.clone());
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                match read(Path::new(path)) {
+                    Ok(contents) => {
+                        if sender.send((index, contents)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => fail(&finished, index, e),
+                }
+            });
+
+            let (sender, finished) = (encoded_sender.clone(), done.clone());
+            scope.spawn(move || {
+                while let Some((index, contents)) = receive(read_queue) {
+                    match encode(Path::new(&paths[index]), contents) {
+                        Ok(encoded) => {
+                            if sender.send((index, encoded)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => fail(&finished, index, e),
+                    }
+                }
+            });
+
+            let finished = done.clone();
+            scope.spawn(move || {
+                while let Some((index, encoded)) = receive(encoded_queue) {
+                    match write(Path::new(&paths[index]), encoded) {
+                        Ok(written) => {
+                            let _ = finished.send((index, Ok(written)));
+                        }
+                        Err(e) => fail(&finished, index, e),
+                    }
+                }
+            });
+        }
+        // Each stage ends once every thread of the one before it is done and has dropped its
+        // sender.
+        drop((read_sender, encoded_sender, done));
+    });
+
+    outcomes.into_iter().collect()
+}
+
+/// The next item from a queue shared by the threads of a stage, or `None` once the stage before
+/// it is done.
+fn receive<T>(queue: &Mutex<mpsc::Receiver<T>>) -> Option<T> {
+    queue.lock().unwrap_or_else(|e| e.into_inner()).recv().ok()
+}
+
+fn fail<T>(done: &mpsc::Sender<Outcome<T>>, index: usize, e: BruhError) {
+    e.report();
+    let _ = done.send((index, Err(e.exit_code())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn every_path_comes_out_once_with_what_became_of_it() {
+        let paths: Vec<OsString> = (0..60).map(|i| i.to_string().into()).collect();
+        let number = |path: &Path| path.to_str().unwrap().parse::<usize>().unwrap();
+        let jobs = 2;
+        // How many files were read and not written yet, and the most there ever were.
+        let (waiting, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+        let mut outcomes = run(
+            &paths,
+            jobs,
+            |path| {
+                if number(path) % 7 == 0 {
+                    return Err(BruhError::Failed("can't read".into()));
+                }
+                let now = waiting.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                Ok(number(path))
+            },
+            |_, n| {
+                if n % 5 == 0 {
+                    waiting.fetch_sub(1, Ordering::SeqCst);
+                    return Err(BruhError::usage("can't encode"));
+                }
+                Ok(n * 2)
+            },
+            |_, n| {
+                // A slow disk: reading has to wait instead of piling files up.
+                thread::sleep(Duration::from_millis(2));
+                waiting.fetch_sub(1, Ordering::SeqCst);
+                Ok(n + 1)
+            },
+        );
+
+        outcomes.sort_by_key(|(index, _)| *index);
+        assert_eq!(outcomes.len(), paths.len());
+        for (i, (index, outcome)) in outcomes.into_iter().enumerate() {
+            assert_eq!(index, i);
+            let expected = match i {
+                _ if i % 7 == 0 => Err(1),
+                _ if i % 5 == 0 => Err(2),
+                _ => Ok(i * 2 + 1),
+            };
+            assert_eq!(outcome, expected);
+        }
+        // Each thread of the three stages holds one, and each of the two queues `jobs` more.
+        assert!(most.into_inner() <= 5 * jobs);
+    }
+
+    #[test]
+    fn no_paths_and_no_jobs_still_finish() {
+        let read = |_: &Path| Ok(());
+        assert!(run(&[], 4, read, |_, ()| Ok(()), |_, ()| Ok(())).is_empty());
+        let outcomes = run(&["a".into()], 0, read, |_, ()| Ok(()), |_, ()| Ok(()));
+        assert_eq!(outcomes, [(0, Ok(()))]);
+    }
+}
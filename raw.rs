@@ -0,0 +1,241 @@
+//! `bruh export-raw` and `bruh from-raw`: pixels as bare bytes, row after row without a header or
+//! padding, for GPU captures and tools that only take a pointer and a size. Floats are little
+//! endian like everywhere else in BRUH.
+
+use crate::format::{f16_to_f32, f32_to_f16, BruhFile, Compression, DecodeError, PixelFormat};
+
+/// How the bytes of a raw dump are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Rgba8,
+    Bgra8,
+    Rgb8,
+    Gray8,
+    RgbaF16,
+    RgbaF32,
+}
+
+impl RawFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgba8 => "rgba8",
+            Self::Bgra8 => "bgra8",
+            Self::Rgb8 => "rgb8",
+            Self::Gray8 => "gray8",
+            Self::RgbaF16 => "rgba16f",
+            Self::RgbaF32 => "rgba32f",
+        }
+    }
+
+    pub fn all() -> [Self; 6] {
+        [
+            Self::Rgba8,
+            Self::Bgra8,
+            Self::Rgb8,
+            Self::Gray8,
+            Self::RgbaF16,
+            Self::RgbaF32,
+        ]
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8 | Self::Bgra8 => 4,
+            Self::Rgb8 => 3,
+            Self::Gray8 => 1,
+            Self::RgbaF16 => 8,
+            Self::RgbaF32 => 16,
+        }
+    }
+}
+
+/// The pixels of `file` as `format`. Gray is the Rec. 709 luma of the colors as they are stored,
+/// alpha is dropped for the formats without it.
+pub fn to_raw(file: &BruhFile, format: RawFormat, max_pixels: u64) -> Result<Vec<u8>, DecodeError> {
+    Ok(match format {
+        RawFormat::RgbaF16 => file
+            .to_rgba_f32(max_pixels)?
+            .into_iter()
+            .flat_map(|c| f32_to_f16(c).to_le_bytes())
+            .collect(),
+        RawFormat::RgbaF32 => file
+            .to_rgba_f32(max_pixels)?
+            .into_iter()
+            .flat_map(f32::to_le_bytes)
+            .collect(),
+        RawFormat::Rgba8 => file.to_rgba(max_pixels)?,
+        RawFormat::Bgra8 => file.to_bgra(max_pixels)?,
+        RawFormat::Rgb8 => file
+            .to_rgba(max_pixels)?
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        RawFormat::Gray8 => file
+            .to_rgba(max_pixels)?
+            .chunks_exact(4)
+            .map(|pixel| {
+                let luma =
+                    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+                luma.round() as u8
+            })
+            .collect(),
+    })
+}
+
+/// A `width`x`height` file of the raw `bytes` laid out as `format`, which have to be exactly as
+/// many as that takes. Float dumps become float files, which are marked linear.
+pub fn from_raw(
+    width: u32,
+    height: u32,
+    bytes: &[u8],
+    format: RawFormat,
+    compression: Compression,
+) -> Result<BruhFile, String> {
+    let expected = width as u64 * height as u64 * format.bytes_per_pixel() as u64;
+    if bytes.len() as u64 != expected {
+        return Err(format!(
+            "{} bytes don't make a {}x{} image, that takes {} as {}",
+            bytes.len(),
+            width,
+            height,
+            expected,
+            format.name()
+        ));
+    }
+
+    let floats = match format {
+        RawFormat::RgbaF16 => Some((
+            PixelFormat::RgbaF16,
+            bytes
+                .chunks_exact(2)
+                .map(|c| f16_to_f32(u16::from_le_bytes([c[0], c[1]])))
+                .collect::<Vec<_>>(),
+        )),
+        RawFormat::RgbaF32 => Some((
+            PixelFormat::RgbaF32,
+            bytes
+                .chunks_exact(4)
+                .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+        )),
+        _ => None,
+    };
+    if let Some((pixel_format, floats)) = floats {
+        return Ok(
+            BruhFile::from_rgba_f32(width, height, &floats, pixel_format, compression).unwrap(),
+        );
+    }
+
+    let rgba: Vec<u8> = match format {
+        RawFormat::Rgba8 => bytes.to_vec(),
+        RawFormat::Bgra8 => bytes
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+            .collect(),
+        RawFormat::Rgb8 => bytes
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        _ => bytes
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, 255])
+            .collect(),
+    };
+    if format == RawFormat::Gray8 {
+        if let Some(file) = BruhFile::encode(width, height, &rgba, PixelFormat::Gray8, compression)
+        {
+            return Ok(file);
+        }
+    }
+    Ok(BruhFile::from_rgba(width, height, &rgba, compression))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::DEFAULT_MAX_PIXELS;
+
+    /// A 3x2 dump in `format`, with floats that survive `f16` exactly.
+    fn dump(format: RawFormat) -> Vec<u8> {
+        let count = 6 * format.bytes_per_pixel();
+        match format {
+            RawFormat::RgbaF16 => (0..count / 2)
+                .flat_map(|i| f32_to_f16(i as f32 * 0.25).to_le_bytes())
+                .collect(),
+            RawFormat::RgbaF32 => (0..count / 4)
+                .flat_map(|i| (i as f32 * 0.1 - 0.5).to_le_bytes())
+                .collect(),
+            _ => (0..count).map(|i| (i * 37 % 256) as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for format in RawFormat::all() {
+            let bytes = dump(format);
+            let file = from_raw(3, 2, &bytes, format, Compression::Deflate).unwrap();
+            assert_eq!((file.header.width, file.header.height), (3, 2));
+            assert_eq!(
+                to_raw(&file, format, DEFAULT_MAX_PIXELS).unwrap(),
+                bytes,
+                "{}",
+                format.name()
+            );
+        }
+    }
+
+    #[test]
+    fn float_dumps_stay_floats() {
+        let file = from_raw(
+            3,
+            2,
+            &dump(RawFormat::RgbaF32),
+            RawFormat::RgbaF32,
+            Compression::None,
+        )
+        .unwrap();
+        assert_eq!(file.header.format, PixelFormat::RgbaF32);
+        let file = from_raw(
+            3,
+            2,
+            &dump(RawFormat::Gray8),
+            RawFormat::Gray8,
+            Compression::None,
+        )
+        .unwrap();
+        assert_eq!(file.header.format, PixelFormat::Gray8);
+    }
+
+    #[test]
+    fn byte_counts_must_match_the_size() {
+        for format in RawFormat::all() {
+            let mut bytes = dump(format);
+            bytes.pop();
+            let e = from_raw(3, 2, &bytes, format, Compression::None).unwrap_err();
+            assert!(
+                e.contains(&format!("that takes {}", 6 * format.bytes_per_pixel())),
+                "{}",
+                e
+            );
+            bytes.extend_from_slice(&[0; 2]);
+            assert!(from_raw(3, 2, &bytes, format, Compression::None).is_err());
+        }
+        assert!(
+            from_raw(0, 2, &[], RawFormat::Rgba8, Compression::None).is_ok_and(|file| file
+                .to_rgba(DEFAULT_MAX_PIXELS)
+                .is_ok_and(|rgba| rgba.is_empty()))
+        );
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for format in RawFormat::all() {
+            assert_eq!(RawFormat::parse(format.name()), Some(format));
+        }
+        assert_eq!(RawFormat::parse("rgba16"), None);
+    }
+}
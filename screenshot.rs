@@ -0,0 +1,238 @@
+//! `bruh screenshot`: capturing the screen, or a region of it, straight to a .bruh file. Each
+//! platform has its own backend: the X server on Linux and the BSDs (which includes XWayland,
+//! but not Wayland on its own), GDI on Windows and the `screencapture` program on macOS.
+
+use std::str::FromStr;
+
+use image::RgbaImage;
+
+/// A rectangle of the screen, in pixels from its top left corner.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Reads `x,y,width,height`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' should be x,y,width,height", text);
+        let parts = text
+            .split(',')
+            .map(|part| part.trim().parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let [x, y, width, height] = parts[..] else {
+            return Err(invalid());
+        };
+        let region = Region {
+            x: i32::try_from(x).map_err(|_| invalid())?,
+            y: i32::try_from(y).map_err(|_| invalid())?,
+            width: u32::try_from(width).map_err(|_| invalid())?,
+            height: u32::try_from(height).map_err(|_| invalid())?,
+        };
+        if region.width == 0 || region.height == 0 {
+            return Err("the region has to be at least 1x1".to_owned());
+        }
+        Ok(region)
+    }
+}
+
+/// `region`, or all of `screen` without one. Regions reaching past the screen are refused, there
+/// would be nothing to capture there.
+#[cfg(any(windows, all(unix, not(target_os = "macos"))))]
+fn within(region: Option<Region>, screen: Region) -> Result<Region, String> {
+    let Some(region) = region else {
+        return Ok(screen);
+    };
+    let inside = region.x >= screen.x
+        && region.y >= screen.y
+        && region.x as i64 + region.width as i64 <= screen.x as i64 + screen.width as i64
+        && region.y as i64 + region.height as i64 <= screen.y as i64 + screen.height as i64;
+    if !inside {
+        return Err(format!(
+            "the region {},{},{},{} doesn't fit on the {}x{} screen at {},{}",
+            region.x,
+            region.y,
+            region.width,
+            region.height,
+            screen.width,
+            screen.height,
+            screen.x,
+            screen.y
+        ));
+    }
+    Ok(region)
+}
+
+/// Captures `region` of the screen, or all of it, every monitor included.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn capture(region: Option<Region>) -> Result<RgbaImage, String> {
+    use x11rb::{
+        connection::Connection,
+        image::{Image, PixelLayout},
+    };
+
+    if std::env::var_os("DISPLAY").is_none() {
+        return Err("there is no X server, DISPLAY isn't set".to_owned());
+    }
+    let (connection, screen) =
+        x11rb::connect(None).map_err(|e| format!("can't connect to the X server: {}", e))?;
+    let screen = &connection.setup().roots[screen];
+    let region = within(
+        region,
+        Region {
+            x: 0,
+            y: 0,
+            width: screen.width_in_pixels as u32,
+            height: screen.height_in_pixels as u32,
+        },
+    )?;
+
+    let image = Image::get(
+        &connection,
+        screen.root,
+        region.x as i16,
+        region.y as i16,
+        region.width as u16,
+        region.height as u16,
+    )
+    .map_err(|e| e.to_string())?;
+    let visual = screen
+        .allowed_depths
+        .iter()
+        .flat_map(|depth| &depth.visuals)
+        .find(|visual| visual.visual_id == screen.root_visual)
+        .ok_or("the screen has no visual")?;
+    let layout = PixelLayout::from_visual_type(*visual).map_err(|e| e.to_string())?;
+
+    let mut img = RgbaImage::new(region.width, region.height);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let (red, green, blue) = layout.decode(image.get_pixel(x as u16, y as u16));
+        *pixel = image::Rgba([(red >> 8) as u8, (green >> 8) as u8, (blue >> 8) as u8, 255]);
+    }
+    Ok(img)
+}
+
+/// Captures `region` of the screen, or all of it, every monitor included.
+#[cfg(windows)]
+pub fn capture(region: Option<Region>) -> Result<RgbaImage, String> {
+    use std::{mem, ptr};
+    use winapi::um::{wingdi, winuser};
+
+    // SAFETY: every handle is checked before it is used and released before returning, and
+    // `bgra` has room for the whole region.
+    unsafe {
+        // Otherwise the coordinates and the pixels are scaled on displays with scaling.
+        winuser::SetProcessDPIAware();
+        let screen = Region {
+            x: winuser::GetSystemMetrics(winuser::SM_XVIRTUALSCREEN),
+            y: winuser::GetSystemMetrics(winuser::SM_YVIRTUALSCREEN),
+            width: winuser::GetSystemMetrics(winuser::SM_CXVIRTUALSCREEN) as u32,
+            height: winuser::GetSystemMetrics(winuser::SM_CYVIRTUALSCREEN) as u32,
+        };
+        let region = within(region, screen)?;
+        let (width, height) = (region.width as i32, region.height as i32);
+
+        let screen = winuser::GetDC(ptr::null_mut());
+        if screen.is_null() {
+            return Err("can't get the screen".to_owned());
+        }
+        let memory = wingdi::CreateCompatibleDC(screen);
+        let bitmap = wingdi::CreateCompatibleBitmap(screen, width, height);
+        let mut bgra = vec![0u8; region.width as usize * region.height as usize * 4];
+        let mut copied = 0;
+        if !memory.is_null() && !bitmap.is_null() {
+            let previous = wingdi::SelectObject(memory, bitmap.cast());
+            copied = wingdi::BitBlt(
+                memory,
+                0,
+                0,
+                width,
+                height,
+                screen,
+                region.x,
+                region.y,
+                wingdi::SRCCOPY | wingdi::CAPTUREBLT,
+            );
+            // The bitmap can't be read while it is selected.
+            wingdi::SelectObject(memory, previous);
+
+            let mut info: wingdi::BITMAPINFO = mem::zeroed();
+            info.bmiHeader.biSize = mem::size_of::<wingdi::BITMAPINFOHEADER>() as u32;
+            info.bmiHeader.biWidth = width;
+            // Negative for rows from top to bottom.
+            info.bmiHeader.biHeight = -height;
+            info.bmiHeader.biPlanes = 1;
+            info.bmiHeader.biBitCount = 32;
+            info.bmiHeader.biCompression = wingdi::BI_RGB;
+            if copied != 0 {
+                copied = wingdi::GetDIBits(
+                    memory,
+                    bitmap,
+                    0,
+                    region.height,
+                    bgra.as_mut_ptr().cast(),
+                    &mut info,
+                    wingdi::DIB_RGB_COLORS,
+                );
+            }
+        }
+        if !bitmap.is_null() {
+            wingdi::DeleteObject(bitmap.cast());
+        }
+        if !memory.is_null() {
+            wingdi::DeleteDC(memory);
+        }
+        winuser::ReleaseDC(ptr::null_mut(), screen);
+        if copied == 0 {
+            return Err("copying the screen failed".to_owned());
+        }
+
+        for pixel in bgra.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+            pixel[3] = 255;
+        }
+        Ok(RgbaImage::from_raw(region.width, region.height, bgra).unwrap())
+    }
+}
+
+/// Captures `region` of the main display, or all of it.
+#[cfg(target_os = "macos")]
+pub fn capture(region: Option<Region>) -> Result<RgbaImage, String> {
+    use std::{env, fs, process::Command};
+
+    let path = env::temp_dir().join(format!("bruh-screenshot-{}.png", std::process::id()));
+    let mut command = Command::new("screencapture");
+    // Without the shutter sound.
+    command.args(["-x", "-t", "png"]);
+    if let Some(region) = region {
+        command.arg(format!(
+            "-R{},{},{},{}",
+            region.x, region.y, region.width, region.height
+        ));
+    }
+    let status = command
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("can't run screencapture: {}", e))?;
+    let png = fs::read(&path);
+    let _ = fs::remove_file(&path);
+    if !status.success() {
+        return Err(format!("screencapture failed: {}", status));
+    }
+
+    let png = png.map_err(|e| e.to_string())?;
+    image::load_from_memory_with_format(&png, image::ImageFormat::Png)
+        .map(|img| img.to_rgba8())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn capture(_region: Option<Region>) -> Result<RgbaImage, String> {
+    Err("screenshots aren't supported on this platform".to_owned())
+}
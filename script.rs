@@ -0,0 +1,302 @@
+//! `bruh script transform.rhai files...`: runs a [Rhai](https://rhai.rs) script once per file,
+//! for batch transforms that need more than a chain of filters:
+//!
+//! ```text
+//! let img = load(path);
+//! if img.width > 1920 {
+//!     img = resize(img, 1920, round(img.height * 1920 / img.width));
+//! }
+//! img = filter(img, "sharpen:amount=0.5");
+//! save(img, dir + "/" + name + "-web.bruh");
+//! ```
+//!
+//! Every run starts with `path`, the file it is for, `name`, its file name without the
+//! extension, and `dir`, the directory it is in. Images have `width` and `height`, and the
+//! functions bruh adds are in `Script::new`. Sizes and positions can be integers or floats,
+//! floats are rounded to the nearest pixel.
+
+use std::{path::Path, sync::Arc};
+
+use image::{imageops, RgbaImage};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::{error::BruhError, filters, format::BlendMode, ops, read_bruh_image, write_bruh_image};
+
+/// Runs that take more operations than this are stopped, a script must not hang a batch.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+type Result<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+/// An image in a script. Functions make new ones, so sharing the pixels is fine.
+#[derive(Clone)]
+struct Image(Arc<RgbaImage>);
+
+impl Image {
+    fn new(img: RgbaImage) -> Self {
+        Image(Arc::new(img))
+    }
+}
+
+/// A parsed script, run once for every file.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    /// Parses `source`, with `registry` for `filter`. Besides what Rhai has, scripts can call:
+    ///
+    /// - `load(path)` reads a BRUH or PNG file, `save(img, path)` writes one, a PNG if the path
+    ///   ends in `.png`
+    /// - `resize(img, width, height)` in linear light, `crop(img, x, y, width, height)`,
+    ///   `rotate(img, degrees)` and `flip(img)`
+    /// - `composite(base, overlay, x, y)` draws the overlay onto the base
+    /// - `filter(img, "blur:sigma=2")` runs a filter of `bruh apply`
+    /// - `round`, `floor` and `ceil` on integers too, so sizes can be worked out either way
+    pub fn new(source: &str, registry: filters::Registry) -> std::result::Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine
+            .register_type_with_name::<Image>("image")
+            .register_get("width", |img: &mut Image| img.0.width() as INT)
+            .register_get("height", |img: &mut Image| img.0.height() as INT)
+            .register_fn("to_string", |img: &mut Image| {
+                format!("image {}x{}", img.0.width(), img.0.height())
+            });
+
+        engine.register_fn("load", |path: &str| -> Result<Image> {
+            Ok(Image::new(
+                read_bruh_image(Path::new(path)).map_err(failed)?,
+            ))
+        });
+        engine.register_fn("save", |img: Image, path: &str| -> Result<()> {
+            let path = Path::new(path);
+            if path.extension().is_some_and(|ext| ext == "png") {
+                crate::write_png(path, &img.0).map_err(failed)
+            } else {
+                write_bruh_image(path, &img.0).map_err(failed)
+            }
+        });
+        engine.register_fn(
+            "resize",
+            |img: Image, width: Dynamic, height: Dynamic| -> Result<Image> {
+                let (width, height) = (size(&width)?, size(&height)?);
+                if width == 0 || height == 0 {
+                    return Err("resize needs a size of at least 1x1".into());
+                }
+                crate::check_output_size(width, height).map_err(failed)?;
+                let filter = imageops::FilterType::Lanczos3;
+                Ok(Image::new(ops::resize(&img.0, width, height, filter, true)))
+            },
+        );
+        engine.register_fn(
+            "crop",
+            |img: Image,
+             x: Dynamic,
+             y: Dynamic,
+             width: Dynamic,
+             height: Dynamic|
+             -> Result<Image> {
+                let (x, y, width, height) = (size(&x)?, size(&y)?, size(&width)?, size(&height)?);
+                let fits = x
+                    .checked_add(width)
+                    .is_some_and(|right| right <= img.0.width())
+                    && y.checked_add(height)
+                        .is_some_and(|bottom| bottom <= img.0.height());
+                if !fits || width == 0 || height == 0 {
+                    return Err(format!(
+                        "crop {},{},{},{} doesn't fit inside the {}x{} image",
+                        x,
+                        y,
+                        width,
+                        height,
+                        img.0.width(),
+                        img.0.height()
+                    )
+                    .into());
+                }
+                let cropped = imageops::crop_imm(&*img.0, x, y, width, height).to_image();
+                Ok(Image::new(cropped))
+            },
+        );
+        engine.register_fn("rotate", |img: Image, degrees: Dynamic| -> Result<Image> {
+            let rotated = ops::rotate(&img.0, size(&degrees)?)
+                .ok_or("rotate takes 90, 180 or 270 degrees")?;
+            Ok(Image::new(rotated))
+        });
+        engine.register_fn("flip", |img: Image| {
+            Image::new(ops::flip(&img.0, true, false))
+        });
+        engine.register_fn(
+            "composite",
+            |base: Image, overlay: Image, x: Dynamic, y: Dynamic| -> Result<Image> {
+                let (x, y) = (number(&x)?.round() as i64, number(&y)?.round() as i64);
+                let composited = ops::composite(&base.0, &overlay.0, x, y, BlendMode::Over, true);
+                Ok(Image::new(composited))
+            },
+        );
+        let registry = Arc::new(registry);
+        engine.register_fn("filter", move |img: Image, step: &str| -> Result<Image> {
+            let step = registry.parse(step)?;
+            let mut img = (*img.0).clone();
+            crate::apply_step(&mut img, &step).map_err(failed)?;
+            Ok(Image::new(img))
+        });
+        for name in ["round", "floor", "ceil"] {
+            engine.register_fn(name, |n: INT| n);
+        }
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script for the file at `path`.
+    pub fn run(&self, path: &Path) -> std::result::Result<(), BruhError> {
+        let text = |text: &str| Dynamic::from(text.to_owned());
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_string_lossy(),
+            _ => ".".into(),
+        };
+        let mut scope = Scope::new();
+        scope.push("path", text(&path.to_string_lossy()));
+        scope.push(
+            "name",
+            text(&path.file_stem().unwrap_or_default().to_string_lossy()),
+        );
+        scope.push("dir", text(&dir));
+        self.engine
+            .run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| BruhError::Failed(format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// A failed read, write or filter as a script error, with everything that caused it.
+fn failed(e: BruhError) -> Box<EvalAltResult> {
+    e.message().into()
+}
+
+fn number(value: &Dynamic) -> Result<f64> {
+    if let Ok(n) = value.as_int() {
+        return Ok(n as f64);
+    }
+    value
+        .as_float()
+        .map_err(|kind| format!("expected a number, not {}", kind).into())
+}
+
+/// A number that has to be a size or position in pixels, rounded to the nearest one.
+fn size(value: &Dynamic) -> Result<u32> {
+    let n = number(value)?.round();
+    if !(0.0..=u32::MAX as f64).contains(&n) {
+        return Err(format!("{} isn't a size in pixels", n).into());
+    }
+    Ok(n as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use image::Rgba;
+
+    use super::*;
+
+    fn script(source: &str) -> std::result::Result<Script, String> {
+        Script::new(source, filters::Registry::new())
+    }
+
+    /// A fresh directory holding a 40x20 PNG called `in.png`.
+    fn dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bruh-script-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let img = RgbaImage::from_fn(40, 20, |x, _| Rgba([x as u8 * 6, 0, 0, 255]));
+        img.save(dir.join("in.png")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn syntax_errors_are_caught_before_running() {
+        let e = script("let img = load(path)\nif img.width > { }")
+            .err()
+            .unwrap();
+        assert!(e.contains("line 2"), "{}", e);
+    }
+
+    #[test]
+    fn runs_call_image_functions_with_the_variables_of_the_file() {
+        let dir = dir("calls");
+        let script = script(
+            r#"
+            let img = load(path);
+            if img.width > 30 {
+                img = resize(img, 30, round(img.height * 30 / img.width));
+            }
+            img = rotate(crop(img, 0, 0, 10, 15), 90);
+            save(img, dir + "/" + name + "-out.bruh");
+            "#,
+        )
+        .unwrap();
+        script.run(&dir.join("in.png")).unwrap();
+
+        let out = read_bruh_image(&dir.join("in-out.bruh")).unwrap();
+        assert_eq!(out.dimensions(), (15, 10));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loops_run_and_endless_ones_are_stopped() {
+        let dir = dir("loops");
+        let counted = script(
+            r#"
+            let img = load(path);
+            let i = 0;
+            while i < 3 {
+                img = flip(img);
+                i += 1;
+            }
+            for n in 0..2 {
+                img = resize(img, img.width / 2, img.height / 2.0);
+            }
+            if img.width != 10 || img.height != 5 {
+                throw "wrong size " + img.width + "x" + img.height;
+            }
+            "#,
+        )
+        .unwrap();
+        counted.run(&dir.join("in.png")).unwrap();
+
+        let endless = script("loop { }").unwrap();
+        let e = endless.run(&dir.join("in.png")).unwrap_err().to_string();
+        assert!(e.contains("operations"), "{}", e);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_of_image_functions_name_the_file_and_line() {
+        let dir = dir("errors");
+        let path = dir.join("in.png");
+        let cases = [
+            (
+                "let img = load(path);\ncrop(img, 30, 0, 20, 20);",
+                "doesn't fit",
+            ),
+            ("rotate(load(path), 45);", "90, 180 or 270"),
+            ("filter(load(path), \"no-such-filter\");", "no-such-filter"),
+            ("load(dir + \"/missing.png\");", "missing.png"),
+            ("resize(load(path), \"wide\", 2);", "expected a number"),
+        ];
+        for (source, expected) in cases {
+            let e = script(source).unwrap().run(&path).unwrap_err().to_string();
+            assert!(e.contains(expected), "{}: {}", source, e);
+            assert!(e.contains("in.png"));
+        }
+        let e = script("let img = load(path);\ncrop(img, 30, 0, 20, 20);")
+            .unwrap()
+            .run(&path)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("line 2"), "{}", e);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
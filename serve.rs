@@ -0,0 +1,436 @@
+//! `bruh serve`: a small HTTP server for a directory of .bruh files. Browsers get PNGs decoded on
+//! the fly, clients that send `Accept: image/bruh` the files as they are.
+//!
+//! Directory listings are a gallery: `?thumbnail` asks for a small copy of a file, made from its
+//! embedded thumbnail or the whole image for files without one. Both are kept in bruh's thumbnail
+//! cache, so browsing a directory again doesn't decode anything.
+//!
+//! Only `GET` and `HEAD` are understood, and only .bruh files and directory listings are served,
+//! nothing else in the directory is exposed. Symbolic links are followed only as long as they
+//! stay inside it, and never listed. Connections are handled on `jobs` threads, and
+//! clients that are slow to send their request or send a huge one are dropped.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+use log::{debug, info, warn};
+
+use crate::{
+    config,
+    error::BruhError,
+    format,
+    storage::{Disk, Storage},
+};
+
+pub const BRUH_MIME_TYPE: &str = "image/bruh";
+
+/// How big gallery thumbnails are on their longer side, twice what the page shows for sharp ones
+/// on high density displays.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// How long a client may take to send its request, and to take each part of the response.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the request line and the headers may be together. Browsers send a few KiB.
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", status).into_bytes(),
+        }
+    }
+}
+
+/// Serves `dir` on `host:port` until the process is stopped, handling up to `jobs` connections at
+/// once. The others wait for a free thread.
+pub fn serve(dir: &Path, host: &str, port: u16) -> Result<(), BruhError> {
+    let dir = &dir.canonicalize().map_err(|e| BruhError::io(dir, e))?;
+    let address = format!("{}:{}", host, port);
+    let listener =
+        TcpListener::bind(&address).map_err(|e| BruhError::io(Path::new(&address), e))?;
+    info!("Serving {} at http://{}/", dir.display(), address);
+
+    let jobs = config::get().jobs.max(1);
+    let (sender, queue) = mpsc::sync_channel::<TcpStream>(jobs);
+    let queue = Mutex::new(queue);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                // Only waiting for a connection holds the lock, not handling it.
+                let stream = match queue.lock().unwrap().recv() {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                if let Err(e) = handle(dir, stream) {
+                    debug!("connection failed: {}", e);
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => warn!("failed to accept a connection: {}", e),
+            }
+        }
+        drop(sender);
+    });
+
+    Ok(())
+}
+
+fn handle(dir: &Path, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    // A line without its end ran into the limit, or the client hung up in the middle of it.
+    let mut complete = reader.read_line(&mut request_line)? > 0 && request_line.ends_with('\n');
+
+    let mut accept = String::new();
+    while complete {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        complete = line.ends_with('\n');
+        if !complete || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("accept") {
+                accept = value.trim().to_owned();
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or("/"),
+    );
+    let response = match method {
+        _ if !complete => Response::error("400 Bad Request"),
+        "GET" | "HEAD" => respond(dir, target, &accept),
+        _ => Response::error("405 Method Not Allowed"),
+    };
+    debug!("{} {} {}", method, target, response.status);
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nVary: Accept\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+/// The response to a request for `target` in `dir`, which has to be canonical.
+fn respond(dir: &Path, target: &str, accept: &str) -> Response {
+    let Some(relative) = request_path(target) else {
+        return Response::error("400 Bad Request");
+    };
+    // Resolving the links tells where the path really leads, which has to be inside `dir`.
+    let path = match dir.join(&relative).canonicalize() {
+        Ok(path) if path.starts_with(dir) => path,
+        _ => return Response::error("404 Not Found"),
+    };
+
+    if path.is_dir() {
+        return match index(&path, &relative) {
+            Ok(page) => Response::ok("text/html; charset=utf-8", page.into_bytes()),
+            Err(e) => {
+                warn!("failed to list {}: {}", path.display(), e);
+                Response::error("500 Internal Server Error")
+            }
+        };
+    }
+    let is_bruh = path.is_file() && path.extension().is_some_and(|ext| ext == "bruh");
+    if !is_bruh {
+        return Response::error("404 Not Found");
+    }
+
+    if query(target) == Some("thumbnail") {
+        if let Some(png) = thumbnail_png(&path) {
+            return Response::ok("image/png", png);
+        }
+    }
+
+    let bytes = match Disk.read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("failed to read {}: {}", path.display(), e);
+            return Response::error("500 Internal Server Error");
+        }
+    };
+    if accept.contains(BRUH_MIME_TYPE) {
+        return Response::ok(BRUH_MIME_TYPE, bytes);
+    }
+
+    match to_png(&bytes) {
+        Ok(png) => Response::ok("image/png", png),
+        Err(e) => {
+            warn!("failed to transcode {}: {}", path.display(), e);
+            Response::error("500 Internal Server Error")
+        }
+    }
+}
+
+/// The gallery's thumbnail of the file at `path` as a PNG.
+fn thumbnail_png(path: &Path) -> Option<Vec<u8>> {
+    match crate::file_manager_thumbnail(path, THUMBNAIL_SIZE) {
+        Ok(thumbnail) => encode_png(thumbnail.width(), thumbnail.height(), thumbnail.as_raw()).ok(),
+        Err(e) => {
+            warn!("failed to make the thumbnail of {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn to_png(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (width, height, rgba) =
+        format::decode(bytes, config::get().max_pixels).map_err(|e| e.to_string())?;
+    encode_png(width, height, &rgba)
+}
+
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(rgba, width, height, ColorType::Rgba8)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// The part of `target` after the `?`.
+fn query(target: &str) -> Option<&str> {
+    let target = target.split('#').next().unwrap_or_default();
+    target.split_once('?').map(|(_, query)| query)
+}
+
+/// The path inside the served directory that `target` asks for, or `None` if it tries to leave
+/// the directory or reach hidden files.
+fn request_path(target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode(path)?;
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(part) if part.to_string_lossy().starts_with('.') => return None,
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A page linking the subdirectories and .bruh files of `path`, which is `relative` in the URL.
+fn index(path: &Path, relative: &Path) -> io::Result<String> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let kind = entry.file_type()?;
+        if name.starts_with('.') || kind.is_symlink() {
+            continue;
+        }
+        if kind.is_dir() {
+            dirs.push(name);
+        } else if kind.is_file() && name.ends_with(".bruh") {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+
+    let base = match relative.to_string_lossy().as_ref() {
+        "" => "/".to_owned(),
+        relative => format!("/{}/", relative),
+    };
+    let title = escape_html(&base);
+
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+         <body>\n<h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    if relative.parent().is_some() {
+        page += "<li><a href=\"../\">../</a></li>\n";
+    }
+    for dir in &dirs {
+        page += &format!(
+            "<li><a href=\"{}{}/\">{}/</a></li>\n",
+            percent_encode(&base),
+            percent_encode(dir),
+            escape_html(dir)
+        );
+    }
+    for file in &files {
+        let href = format!("{}{}", percent_encode(&base), percent_encode(file));
+        page += &format!(
+            "<li><a href=\"{0}\"><img src=\"{0}?thumbnail\" alt=\"\" height=\"64\" loading=\"lazy\"> {1}</a></li>\n",
+            href,
+            escape_html(file)
+        );
+    }
+    page += "</ul>\n</body>\n</html>\n";
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// A canonical directory of its own for `test`.
+    fn dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bruh-serve-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn request_paths_stay_inside_the_directory() {
+        assert_eq!(
+            request_path("/sprites/hero.bruh?thumbnail#top"),
+            Some(PathBuf::from("sprites/hero.bruh"))
+        );
+        assert_eq!(request_path("/a%20b.bruh"), Some(PathBuf::from("a b.bruh")));
+        assert_eq!(request_path("/"), Some(PathBuf::new()));
+        assert_eq!(request_path("/../secret.bruh"), None);
+        assert_eq!(request_path("/%2e%2e/secret.bruh"), None);
+        assert_eq!(request_path("/a/%2E%2E/%2E%2E/secret.bruh"), None);
+        assert_eq!(request_path("/.git/config"), None);
+    }
+
+    #[test]
+    fn percent_escapes_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%2e%2e").as_deref(), Some(".."));
+        assert_eq!(percent_decode("caf%C3%A9").as_deref(), Some("café"));
+        // Cut off, not hex, or not UTF-8.
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    #[test]
+    fn index_lists_subdirectories_and_bruh_files() {
+        let dir = dir("index");
+        fs::create_dir(dir.join("sprites")).unwrap();
+        for name in ["a <b>.bruh", "notes.txt", ".hidden.bruh"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let page = index(&dir, Path::new("")).unwrap();
+        let sub = index(&dir.join("sprites"), Path::new("sprites")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(page.contains("<a href=\"/sprites/\">sprites/</a>"));
+        assert!(page.contains("href=\"/a%20%3Cb%3E.bruh\""));
+        assert!(page.contains("a &lt;b&gt;.bruh"));
+        assert!(!page.contains("notes.txt") && !page.contains("hidden"));
+        assert!(!page.contains("../"));
+        assert!(sub.contains("<a href=\"../\">../</a>"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_out_of_the_directory_are_not_followed() {
+        let (dir, outside) = (dir("links"), dir("links-outside"));
+        fs::write(outside.join("secret.bruh"), b"secret").unwrap();
+        fs::write(dir.join("inside.bruh"), b"inside").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.bruh"), dir.join("secret.bruh")).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("inside.bruh"), dir.join("link.bruh")).unwrap();
+
+        let status = |target| respond(&dir, target, BRUH_MIME_TYPE).status;
+        let linked = respond(&dir, "/link.bruh", BRUH_MIME_TYPE);
+        let page = index(&dir, Path::new("")).unwrap();
+        let statuses = [
+            status("/secret.bruh"),
+            status("/outside/"),
+            status("/outside/secret.bruh"),
+        ];
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+
+        assert_eq!(statuses, ["404 Not Found"; 3]);
+        assert_eq!(
+            (linked.status, &linked.body[..]),
+            ("200 OK", &b"inside"[..])
+        );
+        assert!(!page.contains("secret") && !page.contains("outside"));
+    }
+}
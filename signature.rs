@@ -0,0 +1,108 @@
+//! Ed25519 signatures for `bruh sign` and `bruh verify-sig`. The signature lives in a `sign`
+//! chunk at the end of the file and covers every byte before it, so any change to the header,
+//! the pixels or the metadata after signing is caught. Keys are stored as hex text.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    crypto::{self, PUBLIC_KEY_SIZE, SECRET_KEY_SIZE, SIGNATURE_SIZE},
+    error::BruhError,
+    format::{BruhFile, Chunk, SIGNATURE},
+};
+
+/// Signs `file` with `secret`, replacing any earlier signature.
+pub fn sign(file: &mut BruhFile, secret: &[u8; SECRET_KEY_SIZE]) {
+    file.chunks.retain(|chunk| chunk.tag != SIGNATURE);
+    let signature = crypto::sign(secret, &file.to_bytes());
+
+    let mut data = crypto::public_key(secret).to_vec();
+    data.extend_from_slice(&signature);
+    file.chunks.push(Chunk::new(SIGNATURE, data));
+}
+
+/// Checks the signature of `file`, returning the public key that made it.
+pub fn verify(file: &BruhFile) -> Result<[u8; PUBLIC_KEY_SIZE], &'static str> {
+    let (last, signed) = file.chunks.split_last().ok_or("the file isn't signed")?;
+    if last.tag != SIGNATURE {
+        return Err(if file.chunk(SIGNATURE).is_some() {
+            "chunks were added after signing"
+        } else {
+            "the file isn't signed"
+        });
+    }
+    if last.data.len() != PUBLIC_KEY_SIZE + SIGNATURE_SIZE {
+        return Err("the sign chunk is damaged");
+    }
+
+    let (public, signature) = last.data.split_at(PUBLIC_KEY_SIZE);
+    let public: [u8; PUBLIC_KEY_SIZE] = public.try_into().unwrap();
+    let message = BruhFile {
+        header: file.header.clone(),
+        chunks: signed.to_vec(),
+    }
+    .to_bytes();
+    if !crypto::verify(&public, &message, signature.try_into().unwrap()) {
+        return Err("the signature doesn't match, the file was changed after signing");
+    }
+    Ok(public)
+}
+
+/// Writes a new secret key to `path` and its public key to `path.pub`, returning where the
+/// public key went and the key itself.
+pub fn generate_key(path: &Path) -> Result<(PathBuf, [u8; PUBLIC_KEY_SIZE]), BruhError> {
+    let mut secret = [0; SECRET_KEY_SIZE];
+    OsRng.fill_bytes(&mut secret);
+    let public = crypto::public_key(&secret);
+
+    write_secret(path, format!("{}\n", to_hex(&secret)).as_bytes())
+        .map_err(|e| BruhError::io(path, e))?;
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+    fs::write(&public_path, format!("{}\n", to_hex(&public)))
+        .map_err(|e| BruhError::io(&public_path, e))?;
+    Ok((public_path, public))
+}
+
+/// Reads a key written by `generate_key`.
+pub fn read_key<const N: usize>(path: &Path) -> Result<[u8; N], BruhError> {
+    let text = fs::read_to_string(path).map_err(|e| BruhError::io(path, e))?;
+    from_hex(text.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            BruhError::usage(format!(
+                "{} isn't a key, it should hold {} hex digits",
+                path.display(),
+                N * 2
+            ))
+        })
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Creates `path` readable only by its owner, so other users can't sign with the key.
+fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
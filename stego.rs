@@ -0,0 +1,140 @@
+//! Data hidden in the lowest bit of every color channel for `bruh stego`. Flipping those bits
+//! changes each channel by at most one, which nobody can see, and BRUH is lossless, so they
+//! survive as long as the pixels aren't edited. Alpha is left alone, so opaque images stay opaque.
+//!
+//! The hidden data starts with a small header: the magic `stg1`, the length as a little endian
+//! u32 and the CRC32 of the data, so extracting from an image without anything in it fails
+//! instead of returning noise.
+
+const MAGIC: &[u8; 4] = b"stg1";
+const HEADER_SIZE: usize = 12;
+
+/// How many bytes of data fit in an image with `pixels` pixels.
+pub fn capacity(pixels: usize) -> usize {
+    (pixels * 3 / 8).saturating_sub(HEADER_SIZE)
+}
+
+/// Hides `data` in the RGBA pixels, or returns the capacity if it doesn't fit.
+pub fn embed(rgba: &mut [u8], data: &[u8]) -> Result<(), usize> {
+    let capacity = capacity(rgba.len() / 4);
+    if data.len() > capacity || data.len() > u32::MAX as usize {
+        return Err(capacity);
+    }
+
+    let mut payload = MAGIC.to_vec();
+    payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    payload.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    payload.extend_from_slice(data);
+
+    let bits = payload
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1));
+    for (channel, bit) in color_channels(rgba).zip(bits) {
+        *channel = (*channel & !1) | bit;
+    }
+    Ok(())
+}
+
+/// The data hidden in the RGBA pixels by `embed`.
+pub fn extract(rgba: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut bits = rgba
+        .chunks(4)
+        .flat_map(|pixel| &pixel[..3])
+        .map(|channel| channel & 1);
+    let mut next_byte =
+        || -> Option<u8> { (0..8).try_fold(0, |byte, bit| Some(byte | bits.next()? << bit)) };
+
+    let mut header = [0; HEADER_SIZE];
+    for byte in &mut header {
+        *byte = next_byte().ok_or("the image is too small to hold any data")?;
+    }
+    if &header[..4] != MAGIC {
+        return Err("there is no hidden data in the image");
+    }
+
+    let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    if length > capacity(rgba.len() / 4) {
+        return Err("the hidden data is cut off");
+    }
+    let data: Vec<u8> = (0..length).map_while(|_| next_byte()).collect();
+    if crc32fast::hash(&data) != u32::from_le_bytes(header[8..].try_into().unwrap()) {
+        return Err("the hidden data is damaged, the pixels were changed after embedding");
+    }
+    Ok(data)
+}
+
+fn color_channels(rgba: &mut [u8]) -> impl Iterator<Item = &mut u8> {
+    rgba.chunks_mut(4).flat_map(|pixel| &mut pixel[..3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `pixels` pixels of a gradient, opaque or half transparent.
+    fn image(pixels: usize) -> Vec<u8> {
+        (0..pixels)
+            .flat_map(|i| {
+                [
+                    i as u8,
+                    (i * 3) as u8,
+                    255 - i as u8,
+                    128 + (i % 2) as u8 * 127,
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hidden_data_comes_back_and_barely_changes_the_pixels() {
+        let original = image(200);
+        let mut rgba = original.clone();
+        let data = b"meet me at the usual place";
+        embed(&mut rgba, data).unwrap();
+
+        assert_eq!(extract(&rgba).unwrap(), data);
+        for (pixel, original) in rgba.chunks(4).zip(original.chunks(4)) {
+            assert_eq!(pixel[3], original[3]);
+            assert!(pixel
+                .iter()
+                .zip(original)
+                .all(|(&a, &b)| a.abs_diff(b) <= 1));
+        }
+
+        // The capacity is exactly what fits.
+        let mut rgba = image(100);
+        let data = vec![0xa5; capacity(100)];
+        embed(&mut rgba, &data).unwrap();
+        assert_eq!(extract(&rgba).unwrap(), data);
+    }
+
+    #[test]
+    fn data_too_large_is_refused_with_the_capacity() {
+        let mut rgba = image(100);
+        let untouched = rgba.clone();
+        assert_eq!(
+            embed(&mut rgba, &vec![0; capacity(100) + 1]),
+            Err(capacity(100))
+        );
+        assert_eq!(rgba, untouched);
+        assert_eq!(embed(&mut image(4), b"x"), Err(0));
+    }
+
+    #[test]
+    fn images_without_hidden_data_are_told_apart() {
+        assert_eq!(
+            extract(&image(100)),
+            Err("there is no hidden data in the image")
+        );
+        assert_eq!(
+            extract(&image(8)),
+            Err("the image is too small to hold any data")
+        );
+
+        let mut rgba = image(100);
+        embed(&mut rgba, b"some secret").unwrap();
+        // A channel of the data changed by an edit.
+        rgba[4 * 40] ^= 1;
+        assert!(extract(&rgba).unwrap_err().contains("damaged"));
+    }
+}
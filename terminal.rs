@@ -0,0 +1,481 @@
+//! Previews drawn straight into the terminal, for machines without a display.
+
+use std::env;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::{
+    codecs::png::PngEncoder, imageops::FilterType, ColorType, ImageEncoder, Rgba, RgbaImage,
+};
+
+use crate::ops;
+
+/// The ways a terminal can be told to show an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Sixel,
+    /// The kitty graphics protocol, also understood by WezTerm and Konsole.
+    Kitty,
+    /// iTerm2's inline images, also understood by WezTerm and Tabby.
+    Iterm,
+    /// Colored half blocks, two pixels per character. Works in any terminal with 24-bit color,
+    /// at the cost of shrinking the image to the size of the window.
+    Blocks,
+}
+
+impl Protocol {
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag {
+            "--sixel" => Some(Self::Sixel),
+            "--kitty" => Some(Self::Kitty),
+            "--iterm" => Some(Self::Iterm),
+            "--blocks" => Some(Self::Blocks),
+            _ => None,
+        }
+    }
+
+    pub fn all_flags() -> [&'static str; 4] {
+        ["--sixel", "--kitty", "--iterm", "--blocks"]
+    }
+
+    /// Guesses the protocol from the variables terminals set, falling back to half blocks.
+    /// There's no reliable way to ask a terminal what it supports without reading its answer from
+    /// stdin, so this looks at the environment only.
+    pub fn detect() -> Self {
+        Self::detect_from(|name| env::var(name).unwrap_or_default())
+    }
+
+    /// `detect` with the environment variables from `var`.
+    fn detect_from(var: impl Fn(&str) -> String) -> Self {
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+
+        if !var("KITTY_WINDOW_ID").is_empty() || term == "xterm-kitty" || program == "ghostty" {
+            Self::Kitty
+        } else if program == "iTerm.app"
+            || program == "WezTerm"
+            || program == "Tabby"
+            || var("LC_TERMINAL") == "iTerm2"
+        {
+            Self::Iterm
+        } else if ["foot", "mlterm", "yaft", "contour"]
+            .iter()
+            .any(|name| term.starts_with(name))
+        {
+            Self::Sixel
+        } else {
+            Self::Blocks
+        }
+    }
+
+    pub fn draw(self, img: &RgbaImage) -> String {
+        match self {
+            Self::Sixel => sixel(img),
+            Self::Kitty => kitty(img),
+            Self::Iterm => iterm(img),
+            Self::Blocks => blocks(img),
+        }
+    }
+}
+
+/// Pixels with less alpha are left out and show the terminal background.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Encodes `img` as a sixel image for terminals like xterm, foot, mlterm or WezTerm. The colors
+/// are reduced to the 256 registers sixel terminals usually have, with dithering.
+pub fn sixel(img: &RgbaImage) -> String {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let (palette, indices) = ops::quantize(img, 256, true);
+
+    // Pixel aspect 1:1, transparent background, then the size as a hint for the terminal.
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for (i, [r, g, b, _]) in palette.iter().enumerate() {
+        let percent = |channel: u8| (channel as u32 * 100 + 127) / 255;
+        out += &format!("#{};2;{};{};{}", i, percent(*r), percent(*g), percent(*b));
+    }
+
+    // Every sixel character covers a column of 6 pixels, so the image is drawn in bands of six
+    // rows, one pass per color that occurs in the band.
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut columns: Vec<Option<Vec<u8>>> = vec![None; palette.len()];
+        for row in 0..rows {
+            for x in 0..width {
+                let pixel = (band + row) * width + x;
+                if img.as_raw()[pixel * 4 + 3] < ALPHA_THRESHOLD {
+                    continue;
+                }
+                let bits = columns[indices[pixel] as usize].get_or_insert_with(|| vec![0; width]);
+                bits[x] |= 1 << row;
+            }
+        }
+
+        for (color, bits) in columns.iter().enumerate() {
+            let Some(bits) = bits else {
+                continue;
+            };
+            // Trailing empty columns don't need to be drawn.
+            let end = bits.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            out += &format!("#{}", color);
+            push_run_length(&mut out, &bits[..end]);
+            // Back to the start of the band for the next color.
+            out.push('$');
+        }
+        out.push('-');
+    }
+
+    out += "\x1b\\";
+    out
+}
+
+/// Appends the sixel characters for `bits`, repeating runs with `!count`.
+fn push_run_length(out: &mut String, bits: &[u8]) {
+    let mut i = 0;
+    while i < bits.len() {
+        let run = bits[i..].iter().take_while(|&&b| b == bits[i]).count();
+        let character = (b'?' + bits[i]) as char;
+        if run > 3 {
+            out.push_str(&format!("!{}{}", run, character));
+        } else {
+            (0..run).for_each(|_| out.push(character));
+        }
+        i += run;
+    }
+}
+
+/// Sends `img` as zlib compressed RGBA with the kitty graphics protocol. Escape sequences are
+/// limited to 4096 bytes of payload, so the data goes out in chunks.
+pub fn kitty(img: &RgbaImage) -> String {
+    let data = BASE64.encode(miniz_oxide::deflate::compress_to_vec_zlib(img.as_raw(), 6));
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+
+    let mut out = String::with_capacity(data.len() + chunks.len() * 16 + 64);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            out += &format!(
+                "\x1b_Ga=T,f=32,o=z,s={},v={},m={};",
+                img.width(),
+                img.height(),
+                more
+            );
+        } else {
+            out += &format!("\x1b_Gm={};", more);
+        }
+        // Base64 is plain ASCII.
+        out += std::str::from_utf8(chunk).unwrap();
+        out += "\x1b\\";
+    }
+    out
+}
+
+/// Sends `img` as a PNG with iTerm2's inline image escape sequence.
+pub fn iterm(img: &RgbaImage) -> String {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(img.as_raw(), img.width(), img.height(), ColorType::Rgba8)
+        .expect("encoding a PNG in memory can't fail");
+
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={}px;height={}px;preserveAspectRatio=1:{}\x07",
+        png.len(),
+        img.width(),
+        img.height(),
+        BASE64.encode(&png)
+    )
+}
+
+/// The size of the terminal in characters, from `COLUMNS` and `LINES` or the terminal itself,
+/// 80x24 if neither is known.
+fn terminal_size() -> (u32, u32) {
+    let var = |name: &str| env::var(name).ok().and_then(|value| value.parse().ok());
+    if let (Some(columns), Some(lines)) = (var("COLUMNS"), var("LINES")) {
+        return (columns, lines);
+    }
+
+    #[cfg(unix)]
+    {
+        // SAFETY: TIOCGWINSZ only writes a winsize into the struct it is given.
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+            && size.ws_row > 0
+        {
+            return (size.ws_col as u32, size.ws_row as u32);
+        }
+    }
+
+    (80, 24)
+}
+
+/// Draws `img` with `▀` characters in 24-bit color, the foreground for the upper pixel and the
+/// background for the lower one. Larger images are shrunk to fit the window first.
+pub fn blocks(img: &RgbaImage) -> String {
+    let (columns, lines) = terminal_size();
+    // Leave a line for the prompt.
+    let (max_width, max_height) = (columns, lines.saturating_sub(1).max(1) * 2);
+    let scale = (max_width as f32 / img.width() as f32)
+        .min(max_height as f32 / img.height() as f32)
+        .min(1.0);
+    let resized;
+    let img = if scale < 1.0 {
+        let width = ((img.width() as f32 * scale).round() as u32).max(1);
+        let height = ((img.height() as f32 * scale).round() as u32).max(1);
+        resized = ops::resize(img, width, height, FilterType::Triangle, true);
+        &resized
+    } else {
+        img
+    };
+
+    let visible = |pixel: &Rgba<u8>| pixel[3] >= ALPHA_THRESHOLD;
+    let mut out = String::new();
+    for y in (0..img.height()).step_by(2) {
+        for x in 0..img.width() {
+            let top = img.get_pixel(x, y);
+            let bottom = (y + 1 < img.height()).then(|| img.get_pixel(x, y + 1));
+
+            match (visible(top), bottom.filter(|pixel| visible(pixel))) {
+                (true, Some(bottom)) => {
+                    out += &format!(
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m\u{2580}",
+                        top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                    )
+                }
+                (true, None) => {
+                    out += &format!("\x1b[0;38;2;{};{};{}m\u{2580}", top[0], top[1], top[2])
+                }
+                (false, Some(bottom)) => {
+                    out += &format!(
+                        "\x1b[0;38;2;{};{};{}m\u{2584}",
+                        bottom[0], bottom[1], bottom[2]
+                    )
+                }
+                (false, None) => out += "\x1b[0m ",
+            }
+        }
+        out += "\x1b[0m\n";
+    }
+    out
+}
+
+/// Characters from empty to full, for `ascii`.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Draws `img` as `width` characters per line, fuller ones for brighter pixels as they look on
+/// a dark terminal, with transparent pixels left empty. Characters are about twice as tall as
+/// they are wide, so every line covers two rows worth of pixels. With `color` every character
+/// also gets the color of its pixels in 24-bit ANSI.
+pub fn ascii(img: &RgbaImage, width: u32, color: bool) -> String {
+    let height = (img.height() as f32 * width as f32 / img.width() as f32 / 2.0).round() as u32;
+    let img = ops::resize(img, width, height.max(1), FilterType::Triangle, true);
+
+    let mut out = String::new();
+    for row in img.rows() {
+        let mut last = None;
+        for pixel in row {
+            let [r, g, b, a] = pixel.0;
+            let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) * a as f32 / 255.0;
+            let level = (luma / 256.0 * ASCII_RAMP.len() as f32) as usize;
+            let character = ASCII_RAMP[level.min(ASCII_RAMP.len() - 1)] as char;
+            // Only when the color changes, the escapes are most of the output otherwise.
+            if color && character != ' ' && last != Some([r, g, b]) {
+                out += &format!("\x1b[38;2;{};{};{}m", r, g, b);
+                last = Some([r, g, b]);
+            }
+            out.push(character);
+        }
+        if color && last.is_some() {
+            out += "\x1b[0m";
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    /// Which color register every pixel of a sixel image was drawn with, and the registers in
+    /// percent.
+    fn read_sixel(
+        out: &str,
+        width: usize,
+        height: usize,
+    ) -> (Vec<Option<usize>>, HashMap<usize, Vec<u32>>) {
+        let body = out
+            .strip_prefix("\x1bP0;1;0q")
+            .and_then(|body| body.strip_suffix("\x1b\\"))
+            .unwrap();
+        let mut chars = body.chars().peekable();
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut n = 0;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                n = n * 10 + digit;
+                chars.next();
+            }
+            n
+        };
+
+        let (mut pixels, mut registers) = (vec![None; width * height], HashMap::new());
+        let (mut x, mut band, mut color) = (0, 0, 0);
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    for _ in 0..4 {
+                        number(&mut chars);
+                        chars.next_if_eq(&';');
+                    }
+                }
+                '#' => {
+                    color = number(&mut chars) as usize;
+                    if chars.next_if_eq(&';').is_some() {
+                        assert_eq!(number(&mut chars), 2);
+                        let rgb = (0..3)
+                            .map(|_| {
+                                chars.next_if_eq(&';').unwrap();
+                                number(&mut chars)
+                            })
+                            .collect();
+                        registers.insert(color, rgb);
+                    }
+                }
+                '$' => x = 0,
+                '-' => (x, band) = (0, band + 6),
+                '!' | '?'..='~' => {
+                    let run = if c == '!' { number(&mut chars) } else { 1 };
+                    let c = if c == '!' { chars.next().unwrap() } else { c };
+                    let bits = c as u8 - b'?';
+                    for _ in 0..run {
+                        for row in (0..6).filter(|row| bits & 1 << row != 0) {
+                            let pixel = &mut pixels[(band + row) * width + x];
+                            assert!(pixel.is_none(), "drawn twice");
+                            *pixel = Some(color);
+                        }
+                        x += 1;
+                    }
+                }
+                _ => panic!("unexpected {:?}", c),
+            }
+        }
+        assert_eq!(band, height.div_ceil(6) * 6);
+        (pixels, registers)
+    }
+
+    #[test]
+    fn flags_and_environments_pick_protocols() {
+        for flag in Protocol::all_flags() {
+            assert!(Protocol::from_flag(flag).is_some());
+        }
+        assert_eq!(Protocol::from_flag("--ascii"), None);
+
+        let detect = |vars: &[(&str, &str)]| {
+            Protocol::detect_from(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map_or_else(String::new, |(_, value)| value.to_string())
+            })
+        };
+        assert_eq!(detect(&[("KITTY_WINDOW_ID", "1")]), Protocol::Kitty);
+        assert_eq!(detect(&[("TERM", "xterm-kitty")]), Protocol::Kitty);
+        assert_eq!(detect(&[("TERM_PROGRAM", "WezTerm")]), Protocol::Iterm);
+        assert_eq!(detect(&[("LC_TERMINAL", "iTerm2")]), Protocol::Iterm);
+        assert_eq!(detect(&[("TERM", "foot-extra")]), Protocol::Sixel);
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), Protocol::Blocks);
+        assert_eq!(detect(&[]), Protocol::Blocks);
+    }
+
+    #[test]
+    fn sixels_draw_every_visible_pixel_once() {
+        let (width, height) = (9, 8);
+        let img = RgbaImage::from_fn(width, height, |x, y| match (x, y) {
+            (7, 7) => Rgba([0, 0, 255, 0]),
+            (0..=5, _) => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 255, 0, 255]),
+        });
+        let out = sixel(&img);
+        assert!(out.contains("!6~"));
+
+        let (pixels, registers) = read_sixel(&out, width as usize, height as usize);
+        for (pixel, drawn) in img.pixels().zip(pixels) {
+            let Some(register) = drawn else {
+                assert!(pixel[3] < ALPHA_THRESHOLD);
+                continue;
+            };
+            // Quantizing can move colors a little, but not to another one.
+            for (&drawn, &channel) in registers[&register].iter().zip(&pixel.0[..3]) {
+                assert!(drawn.abs_diff(channel as u32 * 100 / 255) < 25);
+            }
+        }
+    }
+
+    #[test]
+    fn kitty_and_iterm_carry_the_whole_image() {
+        let img = RgbaImage::from_fn(120, 80, |x, y| {
+            let hash = (x * 7919 + y * 104_729).wrapping_mul(2_654_435_761);
+            Rgba(hash.to_le_bytes())
+        });
+
+        // Noise doesn't compress, so it takes several escape sequences.
+        let out = kitty(&img);
+        let sequences: Vec<&str> = out.split_terminator("\x1b\\").collect();
+        assert!(sequences.len() > 1);
+        let mut data = String::new();
+        for (i, sequence) in sequences.iter().enumerate() {
+            let (control, payload) = sequence
+                .strip_prefix("\x1b_G")
+                .unwrap()
+                .split_once(';')
+                .unwrap();
+            let more = if i + 1 < sequences.len() {
+                "m=1"
+            } else {
+                "m=0"
+            };
+            assert!(control.ends_with(more));
+            assert_eq!(control.starts_with("a=T,f=32,o=z,s=120,v=80,"), i == 0);
+            assert!(payload.len() <= 4096);
+            data += payload;
+        }
+        let compressed = BASE64.decode(data).unwrap();
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap(),
+            *img.as_raw()
+        );
+
+        let out = iterm(&img);
+        let (control, payload) = out
+            .strip_prefix("\x1b]1337;File=")
+            .and_then(|out| out.strip_suffix('\x07'))
+            .and_then(|out| out.split_once(':'))
+            .unwrap();
+        let png = BASE64.decode(payload).unwrap();
+        assert!(control.contains(&format!("size={};width=120px;height=80px", png.len())));
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), img);
+    }
+
+    #[test]
+    fn blocks_follow_the_pixels() {
+        let (red, blue, clear) = (Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255]), Rgba([0; 4]));
+        let column = |pixels: &[Rgba<u8>]| {
+            RgbaImage::from_fn(1, pixels.len() as u32, |_, y| pixels[y as usize])
+        };
+        assert_eq!(
+            blocks(&column(&[red, blue, clear])),
+            "\x1b[38;2;255;0;0;48;2;0;0;255m\u{2580}\x1b[0m\n\x1b[0m \x1b[0m\n"
+        );
+        assert_eq!(
+            blocks(&column(&[clear, blue, red])),
+            "\x1b[0;38;2;0;0;255m\u{2584}\x1b[0m\n\x1b[0;38;2;255;0;0m\u{2580}\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn ascii_gets_fuller_and_colored_where_it_is_bright() {
+        let white = RgbaImage::from_pixel(4, 4, Rgba([255; 4]));
+        assert_eq!(ascii(&white, 2, false), "@@\n");
+        assert_eq!(ascii(&white, 2, true), "\x1b[38;2;255;255;255m@@\x1b[0m\n");
+        assert_eq!(ascii(&RgbaImage::new(4, 4), 2, true), "  \n");
+    }
+}
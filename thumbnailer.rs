@@ -0,0 +1,170 @@
+//! `bruh thumbnailer`: previews of .bruh files in Linux file managers. `data/bruh.thumbnailer`
+//! tells them to run `bruh thumbnailer -s SIZE INPUT OUTPUT` for files of type `image/x-bruh`,
+//! and they keep the PNG it writes in their cache. `--cache` fills that cache ahead of time,
+//! following the freedesktop thumbnail specification: a thumbnail is named after the MD5 of the
+//! file's URI and remembers the URI and modification time, so it's made again once they change.
+//!
+//! `cached` keeps bruh's own copies in `thumbnails/` of its cache directory, for the `serve`
+//! gallery and for file managers that don't cache what they're given. They are named after the
+//! file's path, modification time and size, so a changed file never gets an old thumbnail.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use log::debug;
+
+use image::{codecs::png::PngEncoder, ImageEncoder, RgbaImage};
+
+use crate::{config, crypto, error::BruhError, export, signature, storage};
+
+/// The sizes with a directory in the cache. File managers ask for others, but only cache these.
+pub const SIZES: [(&str, u32); 2] = [("normal", 128), ("large", 256)];
+
+/// The `file://` URI of `path`, the way file managers write it to look thumbnails up.
+pub fn uri(path: &Path) -> io::Result<String> {
+    let path = fs::canonicalize(path)?;
+    let mut uri = "file://".to_owned();
+    for &byte in path_bytes(&path).iter() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Ok(uri)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// `$XDG_CACHE_HOME/thumbnails`, or `~/.cache/thumbnails` if that isn't set.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("thumbnails"))
+}
+
+/// Stores `img` as the thumbnail of `path` in the cache directory `dir`, e.g. `normal`,
+/// returning where it went.
+pub fn save(path: &Path, img: &RgbaImage, dir: &str) -> Result<PathBuf, BruhError> {
+    let uri = uri(path).map_err(|e| BruhError::io(path, e))?;
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| BruhError::io(path, e))?;
+    let mtime = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+
+    let dir = cache_dir()
+        .ok_or_else(|| BruhError::Failed("Can't find the thumbnail cache, set HOME".to_owned()))?
+        .join(dir);
+    let out = dir.join(format!(
+        "{}.png",
+        signature::to_hex(&crypto::md5(uri.as_bytes()))
+    ));
+
+    let mut png = encode(img).map_err(|source| BruhError::Image {
+        path: out.clone(),
+        source,
+    })?;
+    export::set_png_text(&mut png, "Software", "bruh");
+    export::set_png_text(&mut png, "Thumb::MTime", &mtime.to_string());
+    export::set_png_text(&mut png, "Thumb::URI", &uri);
+    create_private_dir(&dir).map_err(|e| BruhError::io(&dir, e))?;
+    write_private(&out, &png).map_err(|e| BruhError::io(&out, e))?;
+    Ok(out)
+}
+
+/// The thumbnail of `path` at `size` pixels from bruh's cache, or the one `make` makes, which is
+/// cached for next time. Failing to use the cache only means making the thumbnail every time.
+pub fn cached(
+    path: &Path,
+    size: u32,
+    make: impl FnOnce() -> Result<RgbaImage, BruhError>,
+) -> Result<RgbaImage, BruhError> {
+    let Some(out) = cached_path(path, size) else {
+        return make();
+    };
+    if let Ok(png) = fs::read(&out) {
+        match image::load_from_memory_with_format(&png, image::ImageFormat::Png) {
+            Ok(img) => return Ok(img.to_rgba8()),
+            Err(e) => debug!("ignoring the broken thumbnail {}: {}", out.display(), e),
+        }
+    }
+
+    let img = make()?;
+    let stored = encode(&img).map_err(io::Error::other).and_then(|png| {
+        let dir = out.parent().unwrap();
+        create_private_dir(dir)?;
+        write_private(&out, &png)
+    });
+    if let Err(e) = stored {
+        debug!("can't cache the thumbnail of {}: {}", path.display(), e);
+    }
+    Ok(img)
+}
+
+/// Where `cached` keeps the thumbnail of `path` at `size` pixels, named after what makes it
+/// outdated: the file's canonical path, modification time and size.
+fn cached_path(path: &Path, size: u32) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let mut key = path_bytes(&path);
+    key.push(0);
+    key.extend_from_slice(&modified.as_nanos().to_le_bytes());
+    key.extend_from_slice(&metadata.len().to_le_bytes());
+    let name = format!("{}-{}.png", signature::to_hex(&crypto::md5(&key)), size);
+    Some(config::cache_dir()?.join("thumbnails").join(name))
+}
+
+fn encode(img: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        image::ColorType::Rgba8,
+    )?;
+    Ok(png)
+}
+
+/// The cache is only for its owner to see, it shows what's in their files.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Writes `path` readable only by its owner, through a temporary file so file managers never
+/// see half a thumbnail.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = storage::temp_path_for(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
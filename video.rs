@@ -0,0 +1,107 @@
+//! Frames decoded from video files for `bruh from-video`. The decoding is left to the `ffmpeg`
+//! and `ffprobe` programs, which have to be on the `PATH`, and which handle more containers and
+//! codecs than anything that could be linked in.
+
+use std::{
+    io::{self, Read},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::error::BruhError;
+
+/// The size of the first video stream in `input`.
+pub fn size(input: &Path) -> Result<(u32, u32), BruhError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height", "-of", "csv=p=0:s=x"])
+        .arg(input)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| BruhError::io(Path::new("ffprobe"), e))?;
+    if !output.status.success() {
+        return Err(BruhError::Failed(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    let line = output.lines().next().unwrap_or_default().trim();
+    line.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| BruhError::Failed(format!("{} has no video stream", input.display())))
+}
+
+/// Decodes `input` at `fps` frames per second, handing every frame to `each` as straight RGBA of
+/// the given size. Frames are read one at a time, so long videos don't have to fit in memory.
+pub fn decode(
+    input: &Path,
+    fps: u32,
+    (width, height): (u32, u32),
+    mut each: impl FnMut(Vec<u8>) -> Result<(), BruhError>,
+) -> Result<usize, BruhError> {
+    let mut child = Command::new("ffmpeg")
+        .args(["-v", "error", "-nostdin", "-i"])
+        .arg(input)
+        .args(["-vf", &format!("fps={}", fps)])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| BruhError::io(Path::new("ffmpeg"), e))?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    let frame_size = width as usize * height as usize * 4;
+    let mut count = 0;
+    let result = loop {
+        let mut frame = vec![0; frame_size];
+        match read_frame(&mut stdout, &mut frame) {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(BruhError::io(input, e)),
+        }
+        if let Err(e) = each(frame) {
+            break Err(e);
+        }
+        count += 1;
+    };
+
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    drop(stdout);
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    let status = child
+        .wait()
+        .map_err(|e| BruhError::io(Path::new("ffmpeg"), e))?;
+    result?;
+
+    if !status.success() {
+        return Err(BruhError::Failed(format!(
+            "ffmpeg failed: {}",
+            stderr.trim()
+        )));
+    }
+    Ok(count)
+}
+
+/// Fills `frame`, returning `false` if the stream ended before the frame started.
+fn read_frame(reader: &mut impl Read, frame: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < frame.len() {
+        match reader.read(&mut frame[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,175 @@
+//! Drawing images in the viewer with egui's painter. Images are uploaded as a grid of textures
+//! no bigger than the GPU allows, so any size displays correctly, and `View` zooms with the
+//! scroll wheel and pans by dragging by only moving where the tiles are drawn.
+
+use eframe::egui::{self, ColorImage, TextureHandle, TextureOptions};
+
+/// The longest side of a tile, lowered to what the GPU supports.
+const TILE_SIZE: usize = 2048;
+/// Screen points per image pixel when zoomed in all the way.
+const MAX_ZOOM: f32 = 16.0;
+
+/// An image split into textures, uploaded the first time it is drawn.
+pub struct TiledTexture {
+    name: String,
+    size: [usize; 2],
+    pending: Option<ColorImage>,
+    /// Each tile and where it is in the image, in pixels.
+    tiles: Vec<(egui::Rect, TextureHandle)>,
+}
+
+impl TiledTexture {
+    pub fn new(name: impl Into<String>, image: ColorImage) -> Self {
+        Self {
+            name: name.into(),
+            size: image.size,
+            pending: Some(image),
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Draws the image stretched over `rect` on the screen.
+    pub fn paint(&mut self, ui: &egui::Ui, painter: &egui::Painter, rect: egui::Rect) {
+        if let Some(image) = self.pending.take() {
+            self.tiles = upload(ui.ctx(), &self.name, &image);
+        }
+        let scale = rect.size() / egui::vec2(self.size[0] as f32, self.size[1] as f32);
+        let whole = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+        for (tile, texture) in &self.tiles {
+            let screen = egui::Rect::from_min_max(
+                rect.min + tile.min.to_vec2() * scale,
+                rect.min + tile.max.to_vec2() * scale,
+            );
+            if painter.clip_rect().intersects(screen) {
+                painter.image(texture.id(), screen, whole, egui::Color32::WHITE);
+            }
+        }
+    }
+}
+
+fn upload(ctx: &egui::Context, name: &str, image: &ColorImage) -> Vec<(egui::Rect, TextureHandle)> {
+    let tile_size = ctx.input(|i| i.max_texture_side).min(TILE_SIZE);
+    // Sharp pixels when zoomed in, smooth ones when zoomed out.
+    let options = TextureOptions {
+        magnification: egui::TextureFilter::Nearest,
+        minification: egui::TextureFilter::Linear,
+    };
+    let [width, height] = image.size;
+    let mut tiles = Vec::new();
+    for top in (0..height).step_by(tile_size) {
+        for left in (0..width).step_by(tile_size) {
+            let rect = egui::Rect::from_min_max(
+                egui::pos2(left as f32, top as f32),
+                egui::pos2(
+                    (left + tile_size).min(width) as f32,
+                    (top + tile_size).min(height) as f32,
+                ),
+            );
+            let texture = ctx.load_texture(name, image.region(&rect, None), options);
+            tiles.push((rect, texture));
+        }
+    }
+    tiles
+}
+
+/// How far the viewer is zoomed in and where it looks, fitting the whole image at first.
+#[derive(Default)]
+pub struct View {
+    /// Screen points per image pixel, unset while the image is fitted into the view.
+    zoom: Option<f32>,
+    /// The image position at the top left corner of the view.
+    offset: egui::Vec2,
+    /// Keeps dragging from panning, while the viewer draws with the pointer.
+    pub still: bool,
+}
+
+/// Where an image goes on the screen for one frame.
+pub struct Placement {
+    /// The part of the screen the view takes up.
+    pub rect: egui::Rect,
+    pub zoom: f32,
+    pub offset: egui::Vec2,
+    /// How the pointer used the view this frame.
+    pub response: egui::Response,
+}
+
+impl View {
+    /// A view zoomed in to `zoom`, or fitting the image with `None`.
+    // Only the desktop viewer remembers its zoom, the web viewer leaves these two out.
+    #[allow(dead_code)]
+    pub fn with_zoom(zoom: Option<f32>) -> Self {
+        Self {
+            zoom,
+            ..Self::default()
+        }
+    }
+
+    /// The zoom picked by scrolling, `None` while the image is fitted into the view.
+    #[allow(dead_code)]
+    pub fn zoom(&self) -> Option<f32> {
+        self.zoom
+    }
+
+    /// Takes the rest of `ui` for an image of `width`x`height` pixels, zooming around the
+    /// pointer when scrolled and panning when dragged.
+    pub fn show(&mut self, ui: &mut egui::Ui, width: u32, height: u32) -> Placement {
+        let size = egui::vec2(width as f32, height as f32);
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+        let fit = (rect.width() / size.x).min(rect.height() / size.y);
+        let mut zoom = self
+            .zoom
+            .map_or(fit.min(1.0), |zoom| zoom.clamp(fit.min(1.0), MAX_ZOOM));
+        if let Some(pointer) = response.hover_pos() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            if scroll != 0.0 {
+                // Keep the pixel under the pointer in place.
+                let anchor = self.offset + (pointer - rect.min) / zoom;
+                zoom = (zoom * (scroll / 200.0).exp()).clamp(fit.min(1.0), MAX_ZOOM);
+                self.offset = anchor - (pointer - rect.min) / zoom;
+                self.zoom = Some(zoom);
+            }
+        }
+        // Dragging with Shift held is left to the caller, the viewer selects with it.
+        if !self.still && !ui.input(|i| i.modifiers.shift) {
+            self.offset -= response.drag_delta() / zoom;
+        }
+        let visible = rect.size() / zoom;
+        self.offset.x = clamp_offset(self.offset.x, visible.x, size.x);
+        self.offset.y = clamp_offset(self.offset.y, visible.y, size.y);
+        Placement {
+            rect,
+            zoom,
+            offset: self.offset,
+            response,
+        }
+    }
+}
+
+impl Placement {
+    /// Where the `width`x`height` rectangle of image pixels at `x`,`y` is on the screen.
+    pub fn screen_rect(&self, x: f32, y: f32, width: f32, height: f32) -> egui::Rect {
+        let to_screen = |x, y| self.rect.min + (egui::vec2(x, y) - self.offset) * self.zoom;
+        egui::Rect::from_min_max(to_screen(x, y), to_screen(x + width, y + height))
+    }
+
+    /// The image position under the screen position `pos`.
+    #[allow(dead_code)]
+    pub fn image_pos(&self, pos: egui::Pos2) -> egui::Vec2 {
+        (pos - self.rect.min) / self.zoom + self.offset
+    }
+
+    /// How many image pixels fit in the view.
+    pub fn visible(&self) -> egui::Vec2 {
+        self.rect.size() / self.zoom
+    }
+}
+
+/// Keeps the view at `offset` with `visible` image pixels inside an image `size` pixels long,
+/// or centers the image when it is smaller than the view.
+fn clamp_offset(offset: f32, visible: f32, size: f32) -> f32 {
+    if visible >= size {
+        (size - visible) / 2.0
+    } else {
+        offset.clamp(0.0, size - visible)
+    }
+}
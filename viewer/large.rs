@@ -0,0 +1,275 @@
+//! The viewer for images too big to show as one texture. A background thread reads a downscaled
+//! proxy first, so there is something on screen right away, and then only the part of the image
+//! in view at full resolution whenever the view is zoomed in further than the proxy can show.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use bruh_cli::{config, error::BruhError, ops};
+use bruh_core::{BruhDecoder, DecodeError, Header};
+use eframe::egui::{self, ColorImage};
+
+use crate::canvas::{TiledTexture, View};
+
+/// Images with a side longer than this are shown through a proxy.
+pub const MAX_TEXTURE_SIZE: u32 = 4096;
+/// The longest side of the proxy.
+const PROXY_SIZE: u32 = 2048;
+/// The longest side of the window.
+const WINDOW_SIZE: f32 = 1024.0;
+/// How many pixels the proxy is read in at once, so the whole image is never in memory.
+const BAND_PIXELS: u64 = 16 * 1024 * 1024;
+
+/// A rectangle of the image as x, y, width and height, to read scaled down by `factor`.
+struct Request {
+    rect: [u32; 4],
+    factor: u32,
+}
+
+enum Loaded {
+    /// An embedded thumbnail to show until the proxy is read.
+    Thumbnail(ColorImage),
+    Proxy(ColorImage),
+    Detail([u32; 4], ColorImage),
+}
+
+pub struct LargeImage {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    proxy: Option<TiledTexture>,
+    /// Proxy pixels per image pixel, or 0 while only the thumbnail is there.
+    proxy_scale: f32,
+    /// The part of the image last read at full resolution.
+    detail: Option<([u32; 4], TiledTexture)>,
+    view: View,
+    requested: Option<[u32; 4]>,
+    requests: mpsc::Sender<Request>,
+    loaded: mpsc::Receiver<Loaded>,
+}
+
+impl LargeImage {
+    /// A view of the local file at `path`, along with the work for the background thread that
+    /// fills it in.
+    pub fn open(path: &Path, header: &Header) -> (Self, impl FnOnce(egui::Context) + Send) {
+        let (requests, incoming) = mpsc::channel();
+        let (outgoing, loaded) = mpsc::channel();
+        let image = Self {
+            path: path.to_path_buf(),
+            width: header.width,
+            height: header.height,
+            proxy: None,
+            proxy_scale: 0.0,
+            detail: None,
+            view: View::default(),
+            requested: None,
+            requests,
+            loaded,
+        };
+        let path = path.to_path_buf();
+        let load = move |ctx: egui::Context| {
+            if let Err(e) = load(&path, &ctx, incoming, outgoing) {
+                e.report();
+            }
+        };
+        (image, load)
+    }
+
+    /// The image scaled down to fit a window of a sensible size.
+    pub fn window_size(&self) -> egui::Vec2 {
+        let scale = WINDOW_SIZE / self.width.max(self.height) as f32;
+        egui::vec2(self.width as f32, self.height as f32) * scale
+    }
+
+    /// The whole image at full resolution, for copying it.
+    pub fn decode(&self) -> Result<Vec<u8>, BruhError> {
+        let path = &self.path;
+        let file = fs::File::open(path).map_err(|e| BruhError::io(path, e))?;
+        let mut decoder = BruhDecoder::new(file).map_err(|e| BruhError::decode(path, e))?;
+        let linear = decoder.header().linear;
+        let mut rgba = decoder
+            .decode_region(0, 0, self.width, self.height, config::get().max_pixels)
+            .map_err(|e| BruhError::decode(path, e))?;
+        if linear {
+            ops::pixels_to_srgb(&mut rgba);
+        }
+        Ok(rgba)
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        for loaded in self.loaded.try_iter() {
+            match loaded {
+                Loaded::Thumbnail(image) => {
+                    self.proxy = Some(TiledTexture::new("thumbnail", image));
+                }
+                Loaded::Proxy(image) => {
+                    self.proxy_scale = image.size[0] as f32 / self.width as f32;
+                    self.proxy = Some(TiledTexture::new("proxy", image));
+                }
+                Loaded::Detail(rect, image) => {
+                    self.detail = Some((rect, TiledTexture::new("detail", image)));
+                }
+            }
+        }
+
+        let placement = self.view.show(ui, self.width, self.height);
+        let painter = ui.painter_at(placement.rect);
+        let (width, height) = (self.width as f32, self.height as f32);
+        match &mut self.proxy {
+            Some(proxy) => {
+                proxy.paint(ui, &painter, placement.screen_rect(0.0, 0.0, width, height))
+            }
+            None => {
+                ui.put(placement.rect, egui::Spinner::new());
+            }
+        }
+        if let Some(([x, y, w, h], detail)) = &mut self.detail {
+            let rect = placement.screen_rect(*x as f32, *y as f32, *w as f32, *h as f32);
+            detail.paint(ui, &painter, rect);
+        }
+
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let (zoom, offset, visible) = (placement.zoom, placement.offset, placement.visible());
+        if self.proxy_scale == 0.0 || zoom * pixels_per_point <= self.proxy_scale {
+            return;
+        }
+        let left = (offset.x.floor().max(0.0) as u32).min(self.width - 1);
+        let top = (offset.y.floor().max(0.0) as u32).min(self.height - 1);
+        let right = ((offset.x + visible.x).ceil() as u32).clamp(left + 1, self.width);
+        let bottom = ((offset.y + visible.y).ceil() as u32).clamp(top + 1, self.height);
+        let view = [left, top, right - left, bottom - top];
+        if self.requested != Some(view) {
+            let factor = (1.0 / (zoom * pixels_per_point)) as u32;
+            let request = Request {
+                rect: view,
+                factor: factor.max(1),
+            };
+            if self.requests.send(request).is_ok() {
+                self.requested = Some(view);
+            }
+        }
+    }
+}
+
+/// Sends the proxy, then the parts of the image the view asks for until it closes.
+fn load(
+    path: &Path,
+    ctx: &egui::Context,
+    requests: mpsc::Receiver<Request>,
+    loaded: mpsc::Sender<Loaded>,
+) -> Result<(), BruhError> {
+    let file = fs::File::open(path).map_err(|e| BruhError::io(path, e))?;
+    let mut decoder = BruhDecoder::new(file).map_err(|e| BruhError::decode(path, e))?;
+    let header = decoder.header().clone();
+    let max_pixels = config::get().max_pixels;
+    let send = |image| {
+        let sent = loaded.send(image).is_ok();
+        ctx.request_repaint();
+        sent
+    };
+
+    // An embedded thumbnail or mipmap level is there almost at once. The mipmap is sharp enough
+    // to keep, the thumbnail only has to do until the proxy is read.
+    let level = (1..decoder.mip_levels()).find_map(|level| {
+        let (width, height, rgba) = decoder.mip_level(level, max_pixels).ok()??;
+        (width.max(height) <= PROXY_SIZE).then_some((width, height, rgba))
+    });
+    let proxy = match level {
+        Some((width, height, rgba)) => {
+            let size = [width as usize, height as usize];
+            Loaded::Proxy(ColorImage::from_rgba_unmultiplied(size, &rgba))
+        }
+        None => {
+            if let Ok(Some((width, height, rgba))) = decoder.thumbnail(max_pixels) {
+                let size = [width as usize, height as usize];
+                if !send(Loaded::Thumbnail(ColorImage::from_rgba_unmultiplied(
+                    size, &rgba,
+                ))) {
+                    return Ok(());
+                }
+            }
+            let factor = header.width.max(header.height).div_ceil(PROXY_SIZE);
+            let whole = [0, 0, header.width, header.height];
+            let image = read_shrunk(&mut decoder, &header, whole, factor)
+                .map_err(|e| BruhError::decode(path, e))?;
+            Loaded::Proxy(image)
+        }
+    };
+    if !send(proxy) {
+        return Ok(());
+    }
+
+    while let Ok(mut request) = requests.recv() {
+        // Only the latest view matters, the ones before it were already scrolled past.
+        while let Ok(newer) = requests.try_recv() {
+            request = newer;
+        }
+        let image = read_shrunk(&mut decoder, &header, request.rect, request.factor)
+            .map_err(|e| BruhError::decode(path, e))?;
+        if !send(Loaded::Detail(request.rect, image)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The rectangle `[x, y, width, height]` scaled down by `factor`, read in bands of rows.
+fn read_shrunk(
+    decoder: &mut BruhDecoder<fs::File>,
+    header: &Header,
+    [x, y, width, height]: [u32; 4],
+    factor: u32,
+) -> Result<ColorImage, DecodeError> {
+    let band = ((BAND_PIXELS / width as u64) as u32 / factor * factor).max(factor);
+    let mut rgba = Vec::new();
+    for top in (y..y + height).step_by(band as usize) {
+        let rows = band.min(y + height - top);
+        let pixels = decoder.decode_region(x, top, width, rows, config::get().max_pixels)?;
+        rgba.extend(shrink(&pixels, width, rows, factor));
+    }
+    if header.linear {
+        ops::pixels_to_srgb(&mut rgba);
+    }
+    let size = [
+        width.div_ceil(factor) as usize,
+        height.div_ceil(factor) as usize,
+    ];
+    Ok(ColorImage::from_rgba_unmultiplied(size, &rgba))
+}
+
+/// Scales the RGBA pixels down by `factor`, averaging each block of pixels weighted by alpha so
+/// the colors of transparent pixels don't bleed in. Blocks at the right and bottom edges may be
+/// cut off.
+fn shrink(rgba: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    if factor == 1 {
+        return rgba.to_vec();
+    }
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let mut shrunk = Vec::with_capacity(width.div_ceil(factor) * height.div_ceil(factor) * 4);
+    for top in (0..height).step_by(factor) {
+        for left in (0..width).step_by(factor) {
+            let mut sums = [0u64; 4];
+            let mut count = 0;
+            for y in top..(top + factor).min(height) {
+                for x in left..(left + factor).min(width) {
+                    let pixel = &rgba[(y * width + x) * 4..][..4];
+                    let alpha = pixel[3] as u64;
+                    for (sum, &channel) in sums.iter_mut().zip(&pixel[..3]) {
+                        *sum += channel as u64 * alpha;
+                    }
+                    sums[3] += alpha;
+                    count += 1;
+                }
+            }
+            let alpha = sums[3];
+            for &sum in &sums[..3] {
+                shrunk.push(sum.checked_div(alpha).unwrap_or(0) as u8);
+            }
+            shrunk.push((alpha / count) as u8);
+        }
+    }
+    shrunk
+}
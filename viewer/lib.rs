@@ -0,0 +1,15 @@
+//! The image viewer `bruh path/to/image.bruh` opens. It is a crate of its own so the commands and
+//! the codec build without egui and skia.
+
+pub mod canvas;
+#[cfg(not(target_arch = "wasm32"))]
+mod large;
+#[cfg(not(target_arch = "wasm32"))]
+mod markup;
+#[cfg(not(target_arch = "wasm32"))]
+mod session;
+#[cfg(not(target_arch = "wasm32"))]
+mod viewer;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use viewer::open;
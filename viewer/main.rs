@@ -0,0 +1,22 @@
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+
+use std::{env, ffi::OsString, path::Path};
+
+use bruh_cli::error::BruhError;
+
+/// Takes the same flags as `bruh`, which runs this with its own arguments.
+fn main() {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    bruh_cli::init_logging(&mut args);
+
+    let result = bruh_cli::load_config(&mut args).and_then(|()| match &args[1..] {
+        [path] => bruh_viewer::open(Path::new(path)),
+        _ => Err(BruhError::usage(
+            "Give the path of one image to open. Example: `bruh-viewer ~/image.bruh`",
+        )),
+    });
+    if let Err(e) = result {
+        e.report();
+        std::process::exit(e.exit_code());
+    }
+}
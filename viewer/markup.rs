@@ -0,0 +1,360 @@
+//! The viewer's drawing tools for marking up screenshots: a pen, rectangles and arrows in any
+//! color. They draw onto an overlay above the image, which is only flattened into the pixels
+//! when saving, so the image itself is never changed. Every edit is kept, so Ctrl+Z takes
+//! them back one at a time and Ctrl+Shift+Z brings them back.
+
+use eframe::egui::{self, Color32};
+use image::{Rgba, RgbaImage};
+
+use bruh_cli::ops;
+
+use crate::canvas;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Pen,
+    Rectangle,
+    Arrow,
+}
+
+/// Which file the toolbar was asked to save the marked up image as.
+#[derive(Debug, Clone, Copy)]
+pub enum Save {
+    Bruh,
+    Png,
+}
+
+/// Something drawn, its points in image pixels: every point of a pen stroke, or the two
+/// corners of a rectangle or the start and tip of an arrow.
+struct Shape {
+    tool: Tool,
+    color: Color32,
+    width: f32,
+    points: Vec<egui::Vec2>,
+}
+
+impl Shape {
+    /// The straight lines the shape is made of, in image pixels.
+    fn lines(&self) -> Vec<[egui::Vec2; 2]> {
+        let (first, last) = (self.points[0], *self.points.last().unwrap());
+        match self.tool {
+            // A click without moving still leaves a dot.
+            Tool::Pen if self.points.len() == 1 => vec![[first, first]],
+            Tool::Pen => self
+                .points
+                .windows(2)
+                .map(|pair| [pair[0], pair[1]])
+                .collect(),
+            Tool::Rectangle => {
+                let corners = [
+                    first,
+                    egui::vec2(last.x, first.y),
+                    last,
+                    egui::vec2(first.x, last.y),
+                ];
+                (0..4).map(|i| [corners[i], corners[(i + 1) % 4]]).collect()
+            }
+            Tool::Arrow => {
+                // A click without dragging has no direction to point the head in.
+                if first == last {
+                    return vec![[first, first]];
+                }
+                let direction = (last - first).normalized();
+                // The head's sides go back from the tip at 30 degrees either way.
+                let head = (self.width * 4.0).max(10.0);
+                let (sin, cos) = (0.5, 0.75f32.sqrt());
+                let side = |sin: f32| {
+                    let back = -direction * head;
+                    last + egui::vec2(back.x * cos - back.y * sin, back.x * sin + back.y * cos)
+                };
+                vec![[first, last], [side(sin), last], [side(-sin), last]]
+            }
+        }
+    }
+}
+
+/// One step `undo` takes back. Only what changed is kept, not the whole drawing.
+enum Edit {
+    Draw(Shape),
+    Clear,
+}
+
+pub struct Markup {
+    pub tool: Tool,
+    pub color: Color32,
+    /// How wide lines are, in image pixels.
+    pub width: f32,
+    /// Everything done to the drawing, oldest first.
+    edits: Vec<Edit>,
+    /// How many of `edits` are in effect, the rest were undone and can be redone.
+    applied: usize,
+    /// The shape being dragged out, added to `shapes` on release.
+    drawing: Option<Shape>,
+}
+
+impl Default for Markup {
+    fn default() -> Self {
+        Self {
+            tool: Tool::Pen,
+            color: Color32::RED,
+            width: 4.0,
+            edits: Vec::new(),
+            applied: 0,
+            drawing: None,
+        }
+    }
+}
+
+impl Markup {
+    /// The shapes in effect, drawn since the last time the drawing was cleared.
+    fn shapes(&self) -> impl Iterator<Item = &Shape> {
+        let edits = &self.edits[..self.applied];
+        let cleared = edits.iter().rposition(|edit| matches!(edit, Edit::Clear));
+        edits[cleared.map_or(0, |index| index + 1)..]
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Draw(shape) => Some(shape),
+                Edit::Clear => None,
+            })
+    }
+
+    /// Does `edit`, dropping what was undone before, which can't be redone anymore.
+    fn push(&mut self, edit: Edit) {
+        self.edits.truncate(self.applied);
+        self.edits.push(edit);
+        self.applied += 1;
+    }
+
+    /// Takes back the last edit, returning whether there was one.
+    pub fn undo(&mut self) -> bool {
+        self.drawing = None;
+        let undone = self.applied > 0;
+        self.applied = self.applied.saturating_sub(1);
+        undone
+    }
+
+    /// Does the last undone edit again, returning whether there was one.
+    pub fn redo(&mut self) -> bool {
+        let redone = self.applied < self.edits.len();
+        self.applied = (self.applied + 1).min(self.edits.len());
+        redone
+    }
+
+    /// Removes everything drawn, which can be undone.
+    pub fn clear(&mut self) {
+        self.drawing = None;
+        if self.shapes().next().is_some() {
+            self.push(Edit::Clear);
+        }
+    }
+
+    /// Forgets the drawing and its edits, for another image.
+    pub fn reset(&mut self) {
+        self.edits.clear();
+        self.applied = 0;
+        self.drawing = None;
+    }
+
+    /// The row with the tools, the color, the line width and the buttons to save. Returns which
+    /// file to save if one of those was clicked.
+    pub fn toolbar(&mut self, ui: &mut egui::Ui) -> Option<Save> {
+        let mut save = None;
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Pen, "Pen");
+            ui.selectable_value(&mut self.tool, Tool::Rectangle, "Rectangle");
+            ui.selectable_value(&mut self.tool, Tool::Arrow, "Arrow");
+            ui.separator();
+            egui::color_picker::color_edit_button_srgba(
+                ui,
+                &mut self.color,
+                egui::color_picker::Alpha::OnlyBlend,
+            );
+            ui.add(egui::Slider::new(&mut self.width, 1.0..=32.0).text("Width"));
+            ui.separator();
+            if ui
+                .add_enabled(self.applied > 0, egui::Button::new("Undo"))
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(self.applied < self.edits.len(), egui::Button::new("Redo"))
+                .clicked()
+            {
+                self.redo();
+            }
+            if ui.button("Clear").clicked() {
+                self.clear();
+            }
+            if ui.button("Save .bruh").clicked() {
+                save = Some(Save::Bruh);
+            }
+            if ui.button("Save PNG").clicked() {
+                save = Some(Save::Png);
+            }
+        });
+        save
+    }
+
+    /// Draws with the current tool while the pointer is dragged over the image when `active`,
+    /// and paints everything drawn so far over it. Drags with Shift held are left to the
+    /// selection.
+    pub fn update(&mut self, ui: &egui::Ui, placement: &canvas::Placement, active: bool) {
+        let response = &placement.response;
+        let pointer = response
+            .interact_pointer_pos()
+            .map(|pos| placement.image_pos(pos));
+        if active && response.drag_started() && !ui.input(|i| i.modifiers.shift) {
+            self.drawing = pointer.map(|pos| Shape {
+                tool: self.tool,
+                color: self.color,
+                width: self.width,
+                points: match self.tool {
+                    Tool::Pen => vec![pos],
+                    Tool::Rectangle | Tool::Arrow => vec![pos, pos],
+                },
+            });
+        }
+        if let (Some(shape), Some(pos)) = (&mut self.drawing, pointer) {
+            match shape.tool {
+                // Points closer than half a pixel add nothing but work.
+                Tool::Pen if (*shape.points.last().unwrap() - pos).length() >= 0.5 => {
+                    shape.points.push(pos)
+                }
+                Tool::Pen => {}
+                Tool::Rectangle | Tool::Arrow => *shape.points.last_mut().unwrap() = pos,
+            }
+        }
+        if response.drag_released() {
+            if let Some(shape) = self.drawing.take() {
+                self.push(Edit::Draw(shape));
+            }
+        }
+
+        let painter = ui.painter_at(placement.rect);
+        let screen = |pos: egui::Vec2| placement.screen_rect(pos.x, pos.y, 0.0, 0.0).min;
+        for shape in self.shapes().chain(&self.drawing) {
+            let width = shape.width * placement.zoom;
+            for [from, to] in shape.lines() {
+                let (from, to) = (screen(from), screen(to));
+                painter.line_segment([from, to], (width, shape.color));
+                // Round joints and ends, like the saved pixels have.
+                painter.circle_filled(from, width / 2.0, shape.color);
+                painter.circle_filled(to, width / 2.0, shape.color);
+            }
+        }
+    }
+
+    /// `img` with everything drawn on it.
+    pub fn flatten(&self, img: &RgbaImage) -> RgbaImage {
+        let mut out = img.clone();
+        for shape in self.shapes() {
+            let lines: Vec<[(f32, f32); 2]> = shape
+                .lines()
+                .into_iter()
+                .map(|line| line.map(|point| (point.x, point.y)))
+                .collect();
+            let color = Rgba(shape.color.to_srgba_unmultiplied());
+            ops::stroke(&mut out, &lines, shape.width, color);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(markup: &mut Markup, tool: Tool, points: &[(f32, f32)]) {
+        markup.push(Edit::Draw(Shape {
+            tool,
+            color: markup.color,
+            width: markup.width,
+            points: points.iter().map(|&(x, y)| egui::vec2(x, y)).collect(),
+        }));
+    }
+
+    fn tools(markup: &Markup) -> Vec<Tool> {
+        markup.shapes().map(|shape| shape.tool).collect()
+    }
+
+    #[test]
+    fn edits_are_undone_and_redone_in_order() {
+        let mut markup = Markup::default();
+        assert!(!markup.undo() && !markup.redo());
+
+        draw(&mut markup, Tool::Pen, &[(1.0, 1.0)]);
+        draw(&mut markup, Tool::Rectangle, &[(1.0, 1.0), (5.0, 5.0)]);
+        markup.clear();
+        assert!(tools(&markup).is_empty());
+        // Clearing nothing isn't an edit of its own.
+        markup.clear();
+
+        assert!(markup.undo());
+        assert_eq!(tools(&markup), [Tool::Pen, Tool::Rectangle]);
+        assert!(markup.undo());
+        assert_eq!(tools(&markup), [Tool::Pen]);
+        assert!(markup.redo());
+        assert_eq!(tools(&markup), [Tool::Pen, Tool::Rectangle]);
+
+        // Drawing after undoing drops what could have been redone.
+        assert!(markup.undo() && markup.undo());
+        draw(&mut markup, Tool::Arrow, &[(0.0, 0.0), (9.0, 0.0)]);
+        assert!(!markup.redo());
+        assert_eq!(tools(&markup), [Tool::Arrow]);
+
+        markup.reset();
+        assert!(tools(&markup).is_empty() && !markup.undo() && !markup.redo());
+    }
+
+    #[test]
+    fn shapes_are_made_of_lines() {
+        let shape = |tool, points: &[(f32, f32)]| Shape {
+            tool,
+            color: Color32::RED,
+            width: 2.0,
+            points: points.iter().map(|&(x, y)| egui::vec2(x, y)).collect(),
+        };
+        assert_eq!(shape(Tool::Pen, &[(1.0, 2.0)]).lines().len(), 1);
+        assert_eq!(
+            shape(Tool::Pen, &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)])
+                .lines()
+                .len(),
+            2
+        );
+        let rectangle = shape(Tool::Rectangle, &[(0.0, 0.0), (4.0, 3.0)]).lines();
+        assert_eq!(rectangle.len(), 4);
+        assert!(rectangle
+            .iter()
+            .all(|[from, to]| from.x == to.x || from.y == to.y));
+
+        // Both sides of the head end at the tip and point back along the shaft.
+        let arrow = shape(Tool::Arrow, &[(0.0, 0.0), (20.0, 0.0)]).lines();
+        assert_eq!(arrow.len(), 3);
+        for [side, tip] in &arrow[1..] {
+            assert_eq!(*tip, egui::vec2(20.0, 0.0));
+            assert!(side.x < 20.0 && side.y != 0.0);
+        }
+        assert_eq!(
+            shape(Tool::Arrow, &[(3.0, 3.0), (3.0, 3.0)]).lines().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn flattening_draws_only_what_is_in_effect() {
+        let img = RgbaImage::from_pixel(10, 10, Rgba([255; 4]));
+        let mut markup = Markup {
+            color: Color32::BLUE,
+            width: 2.0,
+            ..Markup::default()
+        };
+        draw(&mut markup, Tool::Pen, &[(1.0, 5.0), (8.0, 5.0)]);
+
+        let flat = markup.flatten(&img);
+        assert_eq!(*flat.get_pixel(5, 5), Rgba([0, 0, 255, 255]));
+        assert_eq!(*flat.get_pixel(5, 0), Rgba([255; 4]));
+        markup.undo();
+        assert_eq!(markup.flatten(&img), img);
+    }
+}
@@ -0,0 +1,111 @@
+//! What the viewer remembers between runs, in `bruh/viewer.toml` next to the configuration
+//! file: where its window was and how large, how far it was zoomed in, the background picked
+//! with `G` and whether the slider panel was open. Read when the viewer opens and written when
+//! it closes, so the next image opens the way the last one was left.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use bruh_cli::config;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// The window's top left corner and size as `[x, y, width, height]`, in points.
+    pub window: Option<[f32; 4]>,
+    /// Screen points per image pixel, `None` to fit the image into the window.
+    pub zoom: Option<f32>,
+    /// RGBA, `None` for the background from the configuration.
+    pub background: Option<[u8; 4]>,
+    pub sliders: bool,
+}
+
+/// `bruh/viewer.toml` in the platform's configuration directory.
+fn path() -> Option<PathBuf> {
+    Some(config::dir()?.join("viewer.toml"))
+}
+
+/// The last session, or a fresh one if there is none or it can't be read.
+pub fn load() -> Session {
+    path().map(|path| load_from(&path)).unwrap_or_default()
+}
+
+fn load_from(path: &Path) -> Session {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Session::default(),
+        Err(e) => {
+            warn!("Can't read {}: {}", path.display(), e);
+            return Session::default();
+        }
+    };
+    debug!("restoring the viewer from {}", path.display());
+    toml::from_str(&contents).unwrap_or_else(|e| {
+        warn!("Ignoring {}: {}", path.display(), e);
+        Session::default()
+    })
+}
+
+/// Stores `session` for the next run. Failing to is only worth a warning.
+pub fn save(session: &Session) {
+    if let Some(path) = path() {
+        save_to(&path, session);
+    }
+}
+
+fn save_to(path: &Path, session: &Session) {
+    let written = toml::to_string(session)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        .and_then(|contents| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, contents)
+        });
+    match written {
+        Ok(()) => debug!("saved the viewer session to {}", path.display()),
+        Err(e) => warn!("Can't save the viewer session to {}: {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn sessions_survive_a_restart() {
+        let dir = env::temp_dir().join(format!("bruh-session-{}", std::process::id()));
+        let path = dir.join("bruh").join("viewer.toml");
+        assert_eq!(load_from(&path), Session::default());
+
+        let session = Session {
+            window: Some([10.0, 20.5, 800.0, 600.0]),
+            zoom: Some(2.0),
+            background: Some([1, 2, 3, 255]),
+            sliders: true,
+        };
+        save_to(&path, &session);
+        assert_eq!(load_from(&path), session);
+
+        // Fields missing from older versions get their defaults, broken files are ignored.
+        fs::write(&path, "sliders = true\n").unwrap();
+        assert_eq!(
+            load_from(&path),
+            Session {
+                sliders: true,
+                ..Session::default()
+            }
+        );
+        fs::write(&path, "zoom = \"close\"\n").unwrap();
+        assert_eq!(load_from(&path), Session::default());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,873 @@
+//! The viewer's window: loading the image, the keys, the panels and everything they save.
+
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use eframe::egui::{self, ColorImage};
+use image::RgbaImage;
+use log::{debug, error, info};
+use skia_safe::{
+    AlphaType, Color4f, ColorType, EncodedImageFormat, ImageInfo, Paint, Rect, Surface,
+};
+
+use bruh_cli::{
+    archive, config, copy_to_clipboard, decode_layers, error::BruhError, ops, paste_from_clipboard,
+    read_bruh_colors, read_file, write_bruh, write_png,
+};
+use bruh_core::{self as format, storage, BruhFile, DecodeError, Layer};
+
+use crate::{canvas, large, markup, session};
+
+static TEMP_RESULT_PATH: &str = "temp.png";
+
+/// Images with at least this many pixels are shown while they are read, a few rows at a time.
+const STREAMED_PIXELS: u64 = 2048 * 2048;
+
+/// Opens the viewer for the image at `path` and blocks until its window is closed.
+pub fn open(path: &Path) -> Result<(), BruhError> {
+    type Load = Box<dyn FnOnce(egui::Context) + Send>;
+    let (preview, load): (ImagePreview, Option<Load>) = match local_header(path) {
+        Some(header) if header.width.max(header.height) > large::MAX_TEXTURE_SIZE => {
+            let (image, load) = large::LargeImage::open(path, &header);
+            (ImagePreview::large(path, image), Some(Box::new(load)))
+        }
+        Some(header) if streams(&header) => {
+            let (sender, receiver) = mpsc::channel();
+            let preview = ImagePreview::streaming(path, &header, receiver);
+            let path = path.to_path_buf();
+            let load = move |ctx| stream(&path, ctx, sender);
+            (preview, Some(Box::new(load)))
+        }
+        _ => {
+            let (width, height, rgba) = bruh_to_png(path)?;
+            let preview = ImagePreview::new(path, width, height, rgba)?;
+            (preview.with_file(path), None)
+        }
+    };
+    debug!("opening a {}x{} preview", preview.width, preview.height);
+    let preview = preview.restore(session::load());
+    let window = preview.session.window;
+    let options = eframe::NativeOptions {
+        initial_window_pos: window.map(|[x, y, _, _]| egui::pos2(x, y)),
+        initial_window_size: Some(
+            window.map_or(preview.window_size(), |[_, _, width, height]| {
+                egui::vec2(width, height)
+            }),
+        ),
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "Image preview",
+        options,
+        Box::new(move |cc| {
+            cc.egui_ctx.set_visuals(match config::get().theme {
+                config::Theme::Dark => egui::Visuals::dark(),
+                config::Theme::Light => egui::Visuals::light(),
+            });
+            if let Some(load) = load {
+                let ctx = cc.egui_ctx.clone();
+                thread::spawn(move || load(ctx));
+            }
+            Box::new(preview)
+        }),
+    )
+    .map_err(|e| BruhError::Failed(format!("image viewer failed: {}", e)))
+}
+
+/// The header of the local file at `path`, so the viewer can pick how to read it: interlaced
+/// and big files are streamed in, see `streams`, and huge ones are shown through a proxy.
+/// Encrypted files and everything else are read whole as before. Files in archives can only be
+/// streamed, the proxy needs to jump around in them.
+fn local_header(path: &Path) -> Option<format::Header> {
+    if storage::is_url(path) {
+        return None;
+    }
+    let mut bytes = [0; format::BRUH_HEADER_SIZE];
+    io::Read::read_exact(&mut open_local(path).ok()?, &mut bytes).ok()?;
+    let header = BruhFile::parse_header(&bytes).ok()?;
+    let size = format::check_dimensions(header.width, header.height, config::get().max_pixels);
+    let proxied = header.width.max(header.height) > large::MAX_TEXTURE_SIZE;
+    let in_archive = archive::split(path).is_some();
+    (!header.encrypted && size.is_ok() && !(in_archive && proxied)).then_some(header)
+}
+
+/// Reads the file at `path`, or the member of an archive as `archive.zip!member` names it.
+fn open_local(path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    match archive::split(path) {
+        Some((archive, member)) => archive::open(&archive, &member),
+        None => Ok(Box::new(fs::File::open(path)?)),
+    }
+}
+
+/// Whether the file with `header` is shown while it is read: interlaced ones pass by pass, and
+/// big ones row by row as they are decompressed instead of leaving the window empty until then.
+/// Float files need all their pixels for the tone mapping, and tiled and lossy ones only come
+/// out whole.
+fn streams(header: &format::Header) -> bool {
+    header.interlaced
+        || (!header.format.is_float()
+            && !header.tiled
+            && !header.lossy
+            && header.width as u64 * header.height as u64 >= STREAMED_PIXELS)
+}
+
+/// Feeds the file at `path` to a `ProgressiveDecoder` on a thread of its own, sending every
+/// image with more to see to the viewer.
+fn stream(path: &Path, ctx: egui::Context, images: mpsc::Sender<Vec<u8>>) {
+    let mut file = match open_local(path) {
+        Ok(file) => file,
+        Err(e) => return BruhError::io(path, e).report(),
+    };
+    let mut decoder = format::ProgressiveDecoder::new(config::get().max_pixels);
+    let mut buffer = vec![0; 64 * 1024];
+    while !decoder.is_complete() {
+        let read = match io::Read::read(&mut file, &mut buffer) {
+            Ok(0) => return error!("{} ends before its pixels do", path.display()),
+            Ok(read) => read,
+            Err(e) => return BruhError::io(path, e).report(),
+        };
+        match decoder.push(&buffer[..read]) {
+            Ok(Some(mut rgba)) => {
+                if decoder.header().is_some_and(|header| header.linear) {
+                    ops::pixels_to_srgb(&mut rgba);
+                }
+                if images.send(rgba).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+            }
+            Ok(None) => {}
+            Err(e) => return BruhError::decode(path, e).report(),
+        }
+    }
+    debug!("streamed {}", path.display());
+}
+
+fn bruh_to_png(path: &Path) -> Result<(u32, u32, Vec<u8>), BruhError> {
+    let (width, height, mut rgba, linear) = read_bruh_colors(path)?;
+    if linear {
+        ops::pixels_to_srgb(&mut rgba);
+    }
+
+    let info = ImageInfo::new(
+        (width as i32, height as i32),
+        ColorType::RGBA8888,
+        AlphaType::Opaque,
+        None,
+    );
+
+    let mut surface = Surface::new_raster(&info, None, None)
+        .ok_or_else(|| BruhError::Failed(format!("Can't preview a {}x{} image", width, height)))?;
+    let canvas = surface.canvas();
+
+    for (i, pixel) in rgba.chunks(4).enumerate() {
+        let color4f = Color4f::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, 0.004);
+        let paint = Paint::new(color4f, None);
+        if i == 0 {
+            debug!("first pixel paint: {:?}", paint)
+        }
+        let x = i % width as usize;
+        let y = i / width as usize;
+
+        let rect = Rect::from_point_and_size((x as f32, y as f32), (1.0, 1.0));
+        canvas.draw_rect(rect, &paint);
+    }
+
+    let image = surface.image_snapshot();
+
+    if let Some(data) = image.encode(None, EncodedImageFormat::PNG, 100) {
+        fs::write(TEMP_RESULT_PATH, &*data)
+            .map_err(|e| BruhError::io(Path::new(TEMP_RESULT_PATH), e))?;
+    }
+
+    Ok((width, height, rgba))
+}
+
+/// Picks `<dir>/<name>.<extension>`, or `<dir>/<name>-N.<extension>` if that is already taken.
+fn unused_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", name, extension));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", name, n, extension));
+        n += 1;
+    }
+    path
+}
+
+struct ImagePreview {
+    image: canvas::TiledTexture,
+    view: canvas::View,
+    dir: PathBuf,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    /// Sharper or fuller versions of an image that is still being read.
+    updates: Option<mpsc::Receiver<Vec<u8>>>,
+    /// The layers of a layered file, shown and hidden with the number keys.
+    layers: Vec<(Layer, RgbaImage)>,
+    /// Whether the layers are in linear light and have to be converted for showing.
+    linear: bool,
+    /// The pixels of a float image, tone mapped again whenever the exposure or operator change.
+    floats: Option<Vec<f32>>,
+    tone_map: ops::ToneMap,
+    /// Stops to brighten the float image by.
+    exposure: f32,
+    /// A huge image shown through a proxy, drawn instead of `image`.
+    large: Option<large::LargeImage>,
+    /// The color vision deficiency `image` is shown through, `rgba` is kept as it is.
+    vision: ops::ColorVision,
+    /// Whether `image` shows only the alpha channel, as gray.
+    alpha_only: bool,
+    /// Whether fully transparent pixels are painted magenta.
+    highlight_transparent: bool,
+    /// What the sliders in the side panel are set to, applied to `image` but not `rgba`.
+    adjustments: ops::ViewAdjustments,
+    /// Whether the side panel with the sliders is open.
+    sliders: bool,
+    /// What is written for the next run when the window closes.
+    session: session::Session,
+    /// The rectangle of image pixels selected by dragging with Shift, as `[x, y, width, height]`,
+    /// which Ctrl+C copies and Ctrl+Shift+S saves instead of the whole image.
+    selection: Option<[u32; 4]>,
+    /// Where the drag making the selection started, in image pixels.
+    selecting_from: Option<egui::Vec2>,
+    /// Whether the drawing toolbar is open, dragging draws instead of panning then.
+    drawing: bool,
+    /// What was drawn over the image, kept apart from `rgba` until it is saved.
+    markup: markup::Markup,
+}
+
+impl ImagePreview {
+    /// Picks up the PNG `bruh_to_png` left at `TEMP_RESULT_PATH` for the decoded `path`.
+    fn new(path: &Path, width: u32, height: u32, rgba: Vec<u8>) -> Result<Self, BruhError> {
+        let temp_path = Path::new(TEMP_RESULT_PATH);
+        let image_data = read_file(temp_path)?;
+
+        fs::remove_file(temp_path).map_err(|e| BruhError::io(temp_path, e))?;
+
+        let image = egui_extras::image::load_image_bytes(&image_data)
+            .map_err(|e| BruhError::decode(temp_path, DecodeError::Invalid(e)))?;
+        let image = canvas::TiledTexture::new(TEMP_RESULT_PATH, image);
+
+        Ok(Self {
+            image,
+            view: canvas::View::default(),
+            dir: preview_dir(path),
+            width,
+            height,
+            rgba,
+            updates: None,
+            layers: Vec::new(),
+            linear: false,
+            floats: None,
+            tone_map: ops::ToneMap::Clamp,
+            exposure: 0.0,
+            large: None,
+            vision: ops::ColorVision::Normal,
+            alpha_only: false,
+            highlight_transparent: false,
+            adjustments: ops::ViewAdjustments::default(),
+            sliders: false,
+            session: session::Session::default(),
+            selection: None,
+            selecting_from: None,
+            drawing: false,
+            markup: markup::Markup::default(),
+        })
+    }
+
+    /// Picks up the layers of `path` for the number keys to toggle, and the float pixels of HDR
+    /// images for the tone mapping keys.
+    fn with_file(mut self, path: &Path) -> Self {
+        if storage::is_url(path) {
+            return self;
+        }
+        let file = read_file(path).and_then(|contents| {
+            BruhFile::parse(&contents).map_err(|e| BruhError::decode(path, e))
+        });
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("not reading the layers of {}: {}", path.display(), e);
+                return self;
+            }
+        };
+        match decode_layers(path, &file) {
+            Ok(layers) => {
+                if !layers.is_empty() {
+                    info!("Press 1 to 9 to show or hide the {} layers", layers.len());
+                }
+                self.layers = layers;
+                self.linear = file.header.linear;
+            }
+            Err(e) => debug!("not reading the layers of {}: {}", path.display(), e),
+        }
+        if file.header.format.is_float() {
+            match file.to_rgba_f32(config::get().max_pixels) {
+                Ok(floats) => {
+                    info!("Press + and - to change the exposure, T to change the tone mapping");
+                    self.floats = Some(floats);
+                }
+                Err(e) => debug!("not reading the floats of {}: {}", path.display(), e),
+            }
+        }
+        self
+    }
+
+    /// Tone maps the float image again after the exposure or operator changed.
+    fn retone(&mut self) {
+        let Some(floats) = &self.floats else {
+            return;
+        };
+        info!(
+            "Tone mapping with {} at {:+} stops",
+            self.tone_map.name(),
+            self.exposure
+        );
+        let rgba = ops::tone_map(floats, self.tone_map, self.exposure);
+        self.show_pixels("tone mapped", rgba);
+    }
+
+    /// Shows or hides layer `index` and draws the visible ones again.
+    fn toggle_layer(&mut self, index: usize) {
+        let Some((layer, _)) = self.layers.get_mut(index) else {
+            return;
+        };
+        layer.visible = !layer.visible;
+        self.floats = None;
+        info!(
+            "{} layer {} ({})",
+            if layer.visible { "Showing" } else { "Hiding" },
+            index,
+            layer.name
+        );
+
+        let mut rgba = ops::composite_layers(self.width, self.height, &self.layers).into_raw();
+        if self.linear {
+            ops::pixels_to_srgb(&mut rgba);
+        }
+        self.show_pixels("layers", rgba);
+    }
+
+    /// Shows the next color vision simulation, or normal vision again after the last.
+    fn next_vision(&mut self) {
+        if !self.can_change_display() {
+            return;
+        }
+        self.vision = self.vision.next();
+        info!("Showing the image with {}", self.vision.name());
+        self.redisplay("color vision");
+    }
+
+    /// Switches between the image and its alpha channel as gray.
+    fn toggle_alpha_only(&mut self) {
+        if !self.can_change_display() {
+            return;
+        }
+        self.alpha_only = !self.alpha_only;
+        info!(
+            "Showing {}",
+            if self.alpha_only {
+                "only the alpha channel"
+            } else {
+                "the colors"
+            }
+        );
+        self.redisplay("alpha");
+    }
+
+    /// Switches painting fully transparent pixels magenta on or off.
+    fn toggle_highlight_transparent(&mut self) {
+        if !self.can_change_display() {
+            return;
+        }
+        self.highlight_transparent = !self.highlight_transparent;
+        info!(
+            "{} fully transparent pixels",
+            if self.highlight_transparent {
+                "Highlighting"
+            } else {
+                "Not highlighting"
+            }
+        );
+        self.redisplay("transparency");
+    }
+
+    /// Whether how the image is shown can change. Huge images are drawn by `large` as they are.
+    fn can_change_display(&self) -> bool {
+        if self.large.is_some() {
+            info!("Huge images can only be shown as they are");
+        }
+        self.large.is_none()
+    }
+
+    /// The side panel with the exposure, gamma and saturation sliders.
+    fn show_sliders(&mut self, ui: &mut egui::Ui) {
+        let before = self.adjustments;
+        ui.heading("Adjustments");
+        ui.add(egui::Slider::new(&mut self.adjustments.exposure, -5.0..=5.0).text("Exposure"));
+        ui.add(
+            egui::Slider::new(&mut self.adjustments.gamma, 0.2..=5.0)
+                .logarithmic(true)
+                .text("Gamma"),
+        );
+        ui.add(egui::Slider::new(&mut self.adjustments.saturation, 0.0..=2.0).text("Saturation"));
+        ui.horizontal(|ui| {
+            if ui.button("Reset").clicked() {
+                self.adjustments = ops::ViewAdjustments::default();
+            }
+            if ui.button("Export adjusted").clicked() {
+                self.export_adjusted();
+            }
+        });
+        if self.adjustments != before {
+            self.redisplay("adjusted");
+        }
+    }
+
+    /// Saves the image with the sliders applied next to the opened file.
+    fn export_adjusted(&self) {
+        let mut rgba = self.rgba.clone();
+        ops::apply_view_adjustments(&mut rgba, self.adjustments);
+        let path = unused_path(&self.dir, "adjusted", "bruh");
+        match write_bruh(&path, self.width, self.height, &rgba) {
+            Ok(()) => info!("Saved the adjusted image to {}", path.display()),
+            Err(e) => e.report(),
+        }
+    }
+
+    /// Opens or closes the drawing toolbar. What was drawn stays either way.
+    fn toggle_drawing(&mut self) {
+        if !self.can_change_display() {
+            return;
+        }
+        self.drawing = !self.drawing;
+        if self.drawing {
+            info!("Drag to draw, with Shift held to select instead, D closes the toolbar");
+        }
+    }
+
+    /// Saves the image as it is shown, with everything drawn on it, next to the opened file.
+    fn save_markup(&self, save: markup::Save) -> Result<(), BruhError> {
+        let img = RgbaImage::from_raw(self.width, self.height, self.rgba.clone()).unwrap();
+        let img = self.markup.flatten(&img);
+        let path = match save {
+            markup::Save::Bruh => {
+                let path = unused_path(&self.dir, "markup", "bruh");
+                write_bruh(&path, img.width(), img.height(), &img)?;
+                path
+            }
+            markup::Save::Png => {
+                let path = unused_path(&self.dir, "markup", "png");
+                write_png(&path, &img)?;
+                path
+            }
+        };
+        info!("Saved the marked up image to {}", path.display());
+        Ok(())
+    }
+
+    /// Selects from where a drag with Shift held started to where the pointer is now, clamped
+    /// to the image. Escape drops the selection.
+    fn update_selection(&mut self, ui: &egui::Ui, placement: &canvas::Placement) {
+        let response = &placement.response;
+        let shift = ui.input(|i| i.modifiers.shift);
+        let pointer = response.interact_pointer_pos();
+        if response.drag_started() && shift {
+            self.selecting_from = pointer.map(|pos| placement.image_pos(pos));
+            self.selection = None;
+        }
+        if let (Some(from), Some(pointer)) = (self.selecting_from, pointer) {
+            let to = placement.image_pos(pointer);
+            let size = egui::vec2(self.width as f32, self.height as f32);
+            let min = from.min(to).clamp(egui::Vec2::ZERO, size).floor();
+            let max = from.max(to).clamp(egui::Vec2::ZERO, size).ceil();
+            let [width, height] = [max.x - min.x, max.y - min.y].map(|side| side as u32);
+            self.selection = Some([min.x as u32, min.y as u32, width, height])
+                .filter(|_| width > 0 && height > 0);
+        }
+        if response.drag_released() && self.selecting_from.take().is_some() {
+            if let Some([x, y, width, height]) = self.selection {
+                info!(
+                    "Selected {}x{} at {},{}, Ctrl+C copies it and Ctrl+Shift+S saves it",
+                    width, height, x, y
+                );
+            }
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.selection = None;
+        }
+
+        if let Some([x, y, width, height]) = self.selection {
+            let rect = placement.screen_rect(x as f32, y as f32, width as f32, height as f32);
+            let painter = ui.painter_at(placement.rect);
+            painter.rect_stroke(rect, 0.0, (1.0, egui::Color32::WHITE));
+            painter.rect_stroke(rect.expand(1.0), 0.0, (1.0, egui::Color32::BLACK));
+        }
+    }
+
+    /// The selected pixels, or all of them without a selection.
+    fn selected(&self) -> Result<(u32, u32, Cow<'_, [u8]>), BruhError> {
+        if let Some(large) = &self.large {
+            return Ok((self.width, self.height, Cow::Owned(large.decode()?)));
+        }
+        let Some([x, y, width, height]) = self.selection else {
+            return Ok((self.width, self.height, Cow::Borrowed(&self.rgba)));
+        };
+        let row = self.width as usize * 4;
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for line in self
+            .rgba
+            .chunks_exact(row)
+            .skip(y as usize)
+            .take(height as usize)
+        {
+            rgba.extend_from_slice(&line[x as usize * 4..(x + width) as usize * 4]);
+        }
+        Ok((width, height, Cow::Owned(rgba)))
+    }
+
+    /// Saves the selection, or the whole image, as a PNG next to the opened file.
+    fn save_selection(&self) -> Result<(), BruhError> {
+        let (width, height, rgba) = self.selected()?;
+        let path = unused_path(&self.dir, "selection", "png");
+        let img = RgbaImage::from_raw(width, height, rgba.into_owned()).unwrap();
+        write_png(&path, &img)?;
+        info!("Saved the selection to {}", path.display());
+        Ok(())
+    }
+
+    fn redisplay(&mut self, name: &str) {
+        let rgba = std::mem::take(&mut self.rgba);
+        self.show_pixels(name, rgba);
+    }
+
+    /// Makes `rgba`, at the size of the image, what is shown, through the color vision
+    /// simulation and the alpha views that are on.
+    fn show_pixels(&mut self, name: &str, rgba: Vec<u8>) {
+        let size = [self.width as usize, self.height as usize];
+        let image = if self.adjustments == ops::ViewAdjustments::default()
+            && self.vision == ops::ColorVision::Normal
+            && !self.alpha_only
+            && !self.highlight_transparent
+        {
+            ColorImage::from_rgba_unmultiplied(size, &rgba)
+        } else {
+            let mut shown = rgba.clone();
+            ops::apply_view_adjustments(&mut shown, self.adjustments);
+            ops::simulate_color_vision(&mut shown, self.vision);
+            if self.alpha_only {
+                ops::alpha_to_gray(&mut shown);
+            }
+            if self.highlight_transparent {
+                ops::highlight_transparent(&mut shown, [255, 0, 255, 255]);
+            }
+            ColorImage::from_rgba_unmultiplied(size, &shown)
+        };
+        self.image = canvas::TiledTexture::new(name, image);
+        self.rgba = rgba;
+    }
+
+    /// An empty preview for the streamed `path`, filled in by the images from `updates`.
+    fn streaming(path: &Path, header: &format::Header, updates: mpsc::Receiver<Vec<u8>>) -> Self {
+        let size = [header.width as usize, header.height as usize];
+        Self {
+            image: canvas::TiledTexture::new(
+                path.display().to_string(),
+                ColorImage::new(size, egui::Color32::TRANSPARENT),
+            ),
+            view: canvas::View::default(),
+            dir: preview_dir(path),
+            width: header.width,
+            height: header.height,
+            rgba: vec![0; size[0] * size[1] * 4],
+            updates: Some(updates),
+            layers: Vec::new(),
+            linear: false,
+            floats: None,
+            tone_map: ops::ToneMap::Clamp,
+            exposure: 0.0,
+            large: None,
+            vision: ops::ColorVision::Normal,
+            alpha_only: false,
+            highlight_transparent: false,
+            adjustments: ops::ViewAdjustments::default(),
+            sliders: false,
+            session: session::Session::default(),
+            selection: None,
+            selecting_from: None,
+            drawing: false,
+            markup: markup::Markup::default(),
+        }
+    }
+
+    /// A preview of the huge `path` that leaves the drawing to `large`.
+    fn large(path: &Path, large: large::LargeImage) -> Self {
+        Self {
+            image: canvas::TiledTexture::new(
+                path.display().to_string(),
+                ColorImage::new([1, 1], egui::Color32::TRANSPARENT),
+            ),
+            view: canvas::View::default(),
+            dir: preview_dir(path),
+            width: large.width,
+            height: large.height,
+            rgba: Vec::new(),
+            updates: None,
+            layers: Vec::new(),
+            linear: false,
+            floats: None,
+            tone_map: ops::ToneMap::Clamp,
+            exposure: 0.0,
+            large: Some(large),
+            vision: ops::ColorVision::Normal,
+            alpha_only: false,
+            highlight_transparent: false,
+            adjustments: ops::ViewAdjustments::default(),
+            sliders: false,
+            session: session::Session::default(),
+            selection: None,
+            selecting_from: None,
+            drawing: false,
+            markup: markup::Markup::default(),
+        }
+    }
+
+    /// Opens the way the last run was left. Huge images keep their own zoom and can't have the
+    /// sliders.
+    fn restore(mut self, session: session::Session) -> Self {
+        if self.large.is_none() {
+            self.view = canvas::View::with_zoom(session.zoom);
+            self.sliders = session.sliders;
+        }
+        self.session = session;
+        self
+    }
+
+    /// Switches to the next background, going back to the configured one after the last.
+    fn next_background(&mut self) {
+        const BACKGROUNDS: [Option<[u8; 4]>; 4] = [
+            None,
+            Some([0, 0, 0, 255]),
+            Some([128, 128, 128, 255]),
+            Some([255, 255, 255, 255]),
+        ];
+        let index = BACKGROUNDS
+            .iter()
+            .position(|&background| background == self.session.background)
+            .map_or(0, |index| (index + 1) % BACKGROUNDS.len());
+        self.session.background = BACKGROUNDS[index];
+        match self.session.background {
+            Some([r, g, b, _]) => info!("Background #{:02x}{:02x}{:02x}", r, g, b),
+            None => info!("Background from the configuration"),
+        }
+    }
+
+    fn window_size(&self) -> egui::Vec2 {
+        match &self.large {
+            Some(large) => large.window_size(),
+            None => egui::vec2(self.width as f32, self.height as f32),
+        }
+    }
+}
+
+/// Where pasted images go.
+fn preview_dir(path: &Path) -> PathBuf {
+    match &config::get().output_dir {
+        Some(dir) => dir.clone(),
+        None if storage::is_url(path) => PathBuf::new(),
+        None => match archive::split(path) {
+            Some((archive, _)) => archive.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        },
+    }
+}
+
+/// The key for an uppercase ASCII letter, as stored in the configuration.
+fn letter_key(letter: char) -> egui::Key {
+    use egui::Key::*;
+    const LETTERS: [egui::Key; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+
+    LETTERS[(letter as u8 - b'A') as usize]
+}
+
+impl eframe::App for ImagePreview {
+    fn on_close_event(&mut self) -> bool {
+        if self.large.is_none() {
+            self.session.zoom = self.view.zoom();
+            self.session.sliders = self.sliders;
+        }
+        session::save(&self.session);
+        true
+    }
+
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let config = config::get();
+
+        if let Some(updates) = &self.updates {
+            if let Some(rgba) = updates.try_iter().last() {
+                self.show_pixels("progressive", rgba);
+            }
+        }
+
+        // Keys typed into a slider's number field are no shortcuts.
+        let typing = ctx.wants_keyboard_input();
+        let shortcut = |key| !typing && ctx.input(|i| !i.modifiers.command && i.key_pressed(key));
+
+        const NUMBERS: [egui::Key; 9] = [
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+            egui::Key::Num8,
+            egui::Key::Num9,
+        ];
+        for (index, key) in NUMBERS.into_iter().enumerate() {
+            if shortcut(key) {
+                self.toggle_layer(index);
+            }
+        }
+
+        if self.floats.is_some() {
+            let (brighter, darker) = ctx.input(|i| {
+                (
+                    !typing && i.key_pressed(egui::Key::PlusEquals),
+                    !typing && i.key_pressed(egui::Key::Minus),
+                )
+            });
+            let next = shortcut(egui::Key::T);
+            if brighter || darker || next {
+                self.exposure += match (brighter, darker) {
+                    (true, false) => 0.5,
+                    (false, true) => -0.5,
+                    _ => 0.0,
+                };
+                if next {
+                    self.tone_map = self.tone_map.next();
+                }
+                self.retone();
+            }
+        }
+
+        if shortcut(egui::Key::B) {
+            self.next_vision();
+        }
+        if shortcut(egui::Key::A) {
+            self.toggle_alpha_only();
+        }
+        if shortcut(egui::Key::H) {
+            self.toggle_highlight_transparent();
+        }
+        if shortcut(egui::Key::G) {
+            self.next_background();
+        }
+        if shortcut(egui::Key::S) && self.can_change_display() {
+            self.sliders = !self.sliders;
+        }
+        if shortcut(egui::Key::D) {
+            self.toggle_drawing();
+        }
+
+        if ctx.input(|i| i.modifiers.command && i.key_pressed(letter_key(config.copy_key))) {
+            match self.selected() {
+                Ok((width, height, rgba)) => {
+                    if let Err(e) = copy_to_clipboard(width, height, &rgba) {
+                        error!("Failed to copy image to clipboard: {}", e);
+                    }
+                }
+                Err(e) => e.report(),
+            }
+        }
+
+        if !typing && ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::Z)) {
+            let (done, what) = if ctx.input(|i| i.modifiers.shift) {
+                (self.markup.redo(), "redo")
+            } else {
+                (self.markup.undo(), "undo")
+            };
+            if !done {
+                info!("Nothing to {}", what);
+            }
+        }
+
+        if ctx.input(|i| i.modifiers.command && i.modifiers.shift && i.key_pressed(egui::Key::S)) {
+            if let Err(e) = self.save_selection() {
+                e.report();
+            }
+        }
+
+        if ctx.input(|i| i.modifiers.command && i.key_pressed(letter_key(config.paste_key))) {
+            match paste_from_clipboard() {
+                Ok((width, height, rgba)) => {
+                    let path = unused_path(&self.dir, "clipboard", "bruh");
+                    match write_bruh(&path, width, height, &rgba) {
+                        Ok(()) => info!("Saved clipboard image to {}", path.display()),
+                        Err(e) => e.report(),
+                    }
+
+                    self.width = width;
+                    self.height = height;
+                    self.show_pixels(&path.display().to_string(), rgba);
+                    self.updates = None;
+                    self.layers.clear();
+                    self.floats = None;
+                    self.large = None;
+                    self.selection = None;
+                    self.markup.reset();
+                    self.view = canvas::View::default();
+                    frame.set_window_size(egui::vec2(width as f32, height as f32));
+                }
+                Err(e) => error!("Failed to read image from clipboard: {}", e),
+            }
+        }
+
+        let window = frame.info().window_info;
+        if let Some(position) = window
+            .position
+            .filter(|_| !window.maximized && !window.fullscreen)
+        {
+            self.session.window = Some([position.x, position.y, window.size.x, window.size.y]);
+        }
+
+        // No margin, so the window fits the image exactly and it is shown at its real size.
+        let mut panel_frame = egui::Frame::central_panel(&ctx.style()).inner_margin(0.0);
+        if let Some([r, g, b, a]) = self.session.background.or(config.background) {
+            panel_frame = panel_frame.fill(egui::Color32::from_rgba_unmultiplied(r, g, b, a));
+        }
+        if self.sliders {
+            egui::SidePanel::right("adjustments").show(ctx, |ui| self.show_sliders(ui));
+        }
+        if self.drawing {
+            let save = egui::TopBottomPanel::top("markup")
+                .show(ctx, |ui| self.markup.toolbar(ui))
+                .inner;
+            if let Some(Err(e)) = save.map(|save| self.save_markup(save)) {
+                e.report();
+            }
+        }
+        let panel = egui::CentralPanel::default().frame(panel_frame);
+        panel.show(ctx, |ui| match &mut self.large {
+            Some(large) => large.show(ui),
+            None => {
+                self.view.still = self.drawing;
+                let placement = self.view.show(ui, self.width, self.height);
+                let (width, height) = (self.width as f32, self.height as f32);
+                let rect = placement.screen_rect(0.0, 0.0, width, height);
+                self.image.paint(ui, &ui.painter_at(placement.rect), rect);
+                self.markup.update(ui, &placement, self.drawing);
+                self.update_selection(ui, &placement);
+            }
+        });
+    }
+}
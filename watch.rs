@@ -0,0 +1,158 @@
+//! Polling for changed files for `bruh watch`. Polling needs nothing from the platform and is
+//! plenty fast for directories of assets.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// What a file looked like when it was last looked at.
+type Snapshot = (SystemTime, u64);
+
+pub struct Watcher {
+    dir: PathBuf,
+    extensions: &'static [&'static str],
+    /// The files as of the previous poll.
+    previous: HashMap<PathBuf, Snapshot>,
+    /// The files as they were when they were last handed out.
+    reported: HashMap<PathBuf, Snapshot>,
+}
+
+impl Watcher {
+    /// Watches the files ending in any of `extensions`, in any case, anywhere below `dir`.
+    pub fn new(dir: &Path, extensions: &'static [&'static str]) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            extensions,
+            previous: HashMap::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// The files that appeared or changed since they were last returned. A file is only returned
+    /// once it looked the same for two polls in a row, so files that are still being written
+    /// aren't picked up halfway. The first two polls return every file there is.
+    pub fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut current = HashMap::new();
+        self.scan(&self.dir.clone(), &mut current)?;
+
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, snapshot)| {
+                self.previous.get(*path) == Some(snapshot)
+                    && self.reported.get(*path) != Some(snapshot)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.sort();
+
+        for path in &changed {
+            self.reported.insert(path.clone(), current[path]);
+        }
+        self.reported.retain(|path, _| current.contains_key(path));
+        self.previous = current;
+        Ok(changed)
+    }
+
+    fn scan(&self, dir: &Path, files: &mut HashMap<PathBuf, Snapshot>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            // Skips hidden files, like the temporary ones outputs are written to.
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            // Files can disappear between listing and looking at them.
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                self.scan(&path, files)?;
+            } else if path.extension().is_some_and(|ext| {
+                let ext = ext.to_string_lossy();
+                self.extensions.iter().any(|e| ext.eq_ignore_ascii_case(e))
+            }) {
+                files.insert(path, (metadata.modified()?, metadata.len()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `output` is missing or older than `input`.
+pub fn is_stale(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output < input,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs::File, slice, time::Duration};
+
+    #[test]
+    fn files_are_reported_once_they_stop_changing() {
+        let dir = env::temp_dir().join(format!("bruh-watch-stable-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let image = dir.join("sub").join("a.PNG");
+        fs::write(&image, "1").unwrap();
+        fs::write(dir.join("notes.txt"), "1").unwrap();
+        fs::write(dir.join(".b.png"), "1").unwrap();
+
+        let mut watcher = Watcher::new(&dir, &["png"]);
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(watcher.poll().unwrap(), slice::from_ref(&image));
+        assert!(watcher.poll().unwrap().is_empty());
+
+        // Still being written: it only comes back after a poll where it stayed the same.
+        fs::write(&image, "12").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        fs::write(&image, "123").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(watcher.poll().unwrap(), slice::from_ref(&image));
+        assert!(watcher.poll().unwrap().is_empty());
+
+        // Coming back after being removed counts as new.
+        fs::remove_file(&image).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        fs::write(&image, "123").unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+        assert_eq!(watcher.poll().unwrap(), [image]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outputs_older_than_their_input_are_stale() {
+        let dir = env::temp_dir().join(format!("bruh-watch-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.png"), dir.join("out.bruh"));
+        fs::write(&input, "").unwrap();
+        assert!(is_stale(&input, &output));
+
+        fs::write(&output, "").unwrap();
+        let now = SystemTime::now();
+        let set = |path: &Path, time| {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        set(&input, now);
+        set(&output, now - Duration::from_secs(10));
+        assert!(is_stale(&input, &output));
+        set(&output, now + Duration::from_secs(10));
+        assert!(!is_stale(&input, &output));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! The viewer for the browser: drop a .bruh file onto the page to look at it. Build with
+//! `trunk serve` (or `trunk build --release`) from this directory.
+
+#[cfg(not(target_arch = "wasm32"))]
+compile_error!("the web viewer only builds for wasm32, use `trunk build` in web/");
+
+use std::path::{Path, PathBuf};
+
+use bruh_core::storage::{Memory, Storage};
+use bruh_viewer::canvas;
+use eframe::egui::{self, ColorImage};
+
+static CANVAS_ID: &str = "bruh_canvas";
+
+#[derive(Default)]
+struct WebViewer {
+    files: Memory,
+    image: Option<canvas::TiledTexture>,
+    size: (u32, u32),
+    view: canvas::View,
+    /// Why the last dropped file couldn't be shown.
+    error: Option<String>,
+}
+
+impl WebViewer {
+    fn open(&mut self, path: &Path) {
+        let decoded = self
+            .files
+            .read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                bruh_core::decode(&bytes, bruh_core::DEFAULT_MAX_PIXELS).map_err(|e| e.to_string())
+            });
+
+        match decoded {
+            Ok((width, height, rgba)) => {
+                log::info!("opened {}: {}x{}", path.display(), width, height);
+                self.image = Some(canvas::TiledTexture::new(
+                    path.display().to_string(),
+                    ColorImage::from_rgba_unmultiplied([width as usize, height as usize], &rgba),
+                ));
+                self.size = (width, height);
+                self.view = canvas::View::default();
+                self.error = None;
+            }
+            Err(e) => {
+                log::error!("couldn't open {}: {}", path.display(), e);
+                self.error = Some(format!("Couldn't open {}: {}", path.display(), e));
+            }
+        }
+    }
+}
+
+impl eframe::App for WebViewer {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        for file in dropped {
+            let Some(bytes) = file.bytes else {
+                continue;
+            };
+            let path = PathBuf::from(file.name);
+            if let Err(e) = self.files.write(&path, &bytes) {
+                self.error = Some(e.to_string());
+                continue;
+            }
+            self.open(&path);
+        }
+
+        let hovering = ctx.input(|i| !i.raw.hovered_files.is_empty());
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            match &mut self.image {
+                Some(image) if !hovering => {
+                    let (width, height) = self.size;
+                    let placement = self.view.show(ui, width, height);
+                    let rect = placement.screen_rect(0.0, 0.0, width as f32, height as f32);
+                    image.paint(ui, &ui.painter_at(placement.rect), rect);
+                }
+                _ => {
+                    ui.centered_and_justified(|ui| {
+                        ui.heading(if hovering {
+                            "Drop to open"
+                        } else {
+                            "Drop a .bruh file here"
+                        });
+                    });
+                }
+            }
+        });
+    }
+}
+
+fn main() {
+    eframe::WebLogger::init(log::LevelFilter::Info).ok();
+
+    wasm_bindgen_futures::spawn_local(async {
+        eframe::WebRunner::new()
+            .start(
+                CANVAS_ID,
+                eframe::WebOptions::default(),
+                Box::new(|_cc| Box::<WebViewer>::default()),
+            )
+            .await
+            .expect("failed to start the viewer");
+    });
+}